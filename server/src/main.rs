@@ -1,4 +1,5 @@
 use chrono::Utc;
+use flate2::{Compression, write::GzEncoder};
 use http_body_util::{BodyExt, Full, combinators::BoxBody};
 use hyper::{
     Request, Response,
@@ -8,10 +9,24 @@ use hyper::{
     service::service_fn,
 };
 use hyper_util::rt::TokioIo;
-use rusqlite::{Connection, params};
+use rusqlite::{Connection, OpenFlags, params};
+use rust_xlsxwriter::{ExcelDateTime, Format, Workbook, XlsxError};
 use serde::{Deserialize, Serialize};
-use std::{env, fs, io::Read, net::SocketAddr};
+use sha2::{Digest, Sha256};
+use std::{
+    env, fs,
+    hash::BuildHasher,
+    io::Write,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 use tokio::net::TcpListener;
+use tracing::{error, info, warn};
+use tracing_subscriber::{EnvFilter, Registry, prelude::*, reload};
 
 /**
  * server
@@ -22,33 +37,158 @@ use tokio::net::TcpListener;
     "name": "item name",
     "barcode": "42",
     "location": "location",
-    "last-seen": 1234567890 // unix timestamp
+    "last_seen": 1234567890 // unix timestamp ("last-seen" is accepted too, but deprecated)
 }
 ```
 
 table creation:
 ```sql
 CREATE TABLE items (
+    id INTEGER PRIMARY KEY,
     name VARCHAR NOT NULL,
     barcode INTEGER NOT NULL UNIQUE,
     location VARCHAR NOT NULL,
-    last_seen TIMESTAMP NOT NULL
+    last_seen TIMESTAMP NOT NULL,
+    version INTEGER NOT NULL DEFAULT 1,
+    status TEXT NOT NULL DEFAULT 'ok',
+    purchase_date TEXT,
+    value_pence INTEGER,
+    parent_id INTEGER REFERENCES items(id) ON DELETE SET NULL,
+    quantity INTEGER NOT NULL DEFAULT 1
 );
 ````
  */
 
-#[cfg(test)]
-const DB_NAME: &str = "test.db";
-
-#[cfg(not(test))]
 const DB_NAME: &str = "barcode.db";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)] // so typos like "locaton" are reported instead of silently dropped
 pub struct Item {
     name: String,
+    /// accepted as a number or a string of digits, see `barcodes_as_strings` for output
+    #[serde(deserialize_with = "barcode_from_number_or_string")]
     barcode: u64,
+    /// may be left out (or empty) when the field policy doesn't require it
+    #[serde(default)]
     location: String,
+    /// `last-seen` is accepted as well, as these docs once spelled it (see `DEPRECATED_FIELDS`)
+    #[serde(alias = "last-seen")]
     last_seen: Option<u64>,
+    /// incremented on every modify, for optimistic concurrency (`ETag`/`If-Match`)
+    #[serde(default)]
+    version: u64,
+    /// condition, one of `allowed_statuses()`; left out of a request it means `ok` for new items
+    /// and unchanged for modified ones, and it's always present on loaded items
+    #[serde(default)]
+    status: Option<String>,
+    /// when it was bought, as `YYYY-MM-DD`
+    #[serde(default)]
+    purchase_date: Option<String>,
+    /// replacement value in minor units (pence), so sums are exact; never negative
+    #[serde(default)]
+    value_pence: Option<i64>,
+    /// the case or kit this is packed in, see `/item/{barcode}/parent`;
+    /// left out of `/modify` it's unchanged
+    #[serde(default, deserialize_with = "optional_barcode")]
+    parent_barcode: Option<u64>,
+    /// a note to add to the item's notes (see `/note/{barcode}`) when it's created or modified;
+    /// notes are kept apart from the item, so this is never sent back
+    #[serde(default, skip_serializing)]
+    notes: Option<String>,
+    /// how many identical units the barcode stands for, for consumables kept as a stock;
+    /// left out it's 1 for new items and unchanged for modified ones
+    #[serde(default)]
+    quantity: Option<u64>,
+}
+
+/// item fields and the SQL selecting each, in the order `Item::from_row` expects
+///
+/// parents are stored by id, so the barcode is looked up
+const ITEM_FIELDS: [(&str, &str); 10] = [
+    ("name", "name"),
+    ("barcode", "barcode"),
+    ("location", "location"),
+    ("last_seen", "last_seen"),
+    ("version", "version"),
+    ("status", "status"),
+    ("purchase_date", "purchase_date"),
+    ("value_pence", "value_pence"),
+    (
+        "parent_barcode",
+        "(SELECT parent.barcode FROM items AS parent WHERE parent.id = items.parent_id)",
+    ),
+    ("quantity", "quantity"),
+];
+
+/// the select list for every item field, for queries `FROM items`
+fn item_columns() -> String {
+    ITEM_FIELDS
+        .iter()
+        .map(|(_, sql)| *sql)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// names of every item field, for error messages
+fn item_field_names() -> String {
+    ITEM_FIELDS
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// like `barcode_from_number_or_string`, for barcodes that may be null or left out
+fn optional_barcode<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Barcode(#[serde(deserialize_with = "barcode_from_number_or_string")] u64);
+
+    Ok(Option::<Barcode>::deserialize(deserializer)?.map(|Barcode(barcode)| barcode))
+}
+
+/// accept a barcode as a JSON number or a string of digits, since clients like the webclient
+/// can't represent barcodes above 2^53 exactly as numbers
+fn barcode_from_number_or_string<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    struct BarcodeVisitor;
+
+    impl serde::de::Visitor<'_> for BarcodeVisitor {
+        type Value = u64;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(
+                f,
+                "u64 up to {} as a number or a string of digits",
+                MAX_BARCODE
+            )
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<u64, E> {
+            // anything larger can't be stored, so it's refused here rather than failing in SQLite
+            if v > MAX_BARCODE {
+                return Err(E::invalid_value(serde::de::Unexpected::Unsigned(v), &self));
+            }
+            Ok(v)
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<u64, E> {
+            u64::try_from(v).map_err(|_| E::invalid_value(serde::de::Unexpected::Signed(v), &self))
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<u64, E> {
+            match v.parse() {
+                Ok(barcode) => self.visit_u64(barcode),
+                Err(_) => Err(E::invalid_value(serde::de::Unexpected::Str(v), &self)),
+            }
+        }
+    }
+
+    deserializer.deserialize_any(BarcodeVisitor)
 }
 
 impl Item {
@@ -58,682 +198,12664 @@ impl Item {
             barcode,
             location,
             last_seen: Some(Utc::now().timestamp() as u64),
+            version: 1,
+            status: Some("ok".to_string()),
+            purchase_date: None,
+            value_pence: None,
+            parent_barcode: None,
+            notes: None,
+            quantity: Some(1),
         }
     }
 
-    pub fn save(&self) -> Result<(), String> {
-        let conn = Connection::open(DB_NAME).map_err(|e| e.to_string())?;
-        conn.execute(
-            "INSERT INTO items (name, barcode, location, last_seen) VALUES (?1, ?2, ?3, ?4)",
-            params![self.name, self.barcode, self.location, self.last_seen],
-        )
-        .map_err(|e| e.to_string())?;
-        Ok(())
+    /// the note given with the item, if there's anything in it
+    fn note(&self) -> Option<&str> {
+        self.notes
+            .as_deref()
+            .map(str::trim)
+            .filter(|note| !note.is_empty())
     }
-}
 
-pub fn load_items() -> Result<Vec<Item>, String> {
-    let conn = Connection::open(DB_NAME).map_err(|e| e.to_string())?;
-    let mut stmt = conn
-        .prepare("SELECT name, barcode, location, last_seen FROM items")
-        .map_err(|e| e.to_string())?;
-    let items = stmt
-        .query_map(params![], |row| {
-            Ok(Item {
-                name: row.get(0)?,
-                barcode: row.get(1)?,
-                location: row.get(2)?,
-                last_seen: row.get(3)?,
-            })
+    /// an item from a row selected with `item_columns()` first
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            name: row.get(0)?,
+            barcode: row.get(1)?,
+            location: row.get(2)?,
+            last_seen: row.get(3)?,
+            version: row.get(4)?,
+            status: row.get(5)?,
+            purchase_date: row.get(6)?,
+            value_pence: row.get(7)?,
+            parent_barcode: row.get(8)?,
+            notes: None,
+            quantity: row.get(9)?,
         })
-        .map_err(|e| e.to_string())?
-        .map(|r| r.map_err(|e| e.to_string()))
-        .collect::<Result<Vec<_>, _>>()?;
-    Ok(items)
-}
+    }
 
-pub fn load_item(barcode: u64) -> Result<Item, String> {
-    let conn = Connection::open(DB_NAME).map_err(|e| e.to_string())?;
-    let mut stmt = conn
-        .prepare("SELECT name, barcode, location, last_seen FROM items WHERE barcode = ?1")
-        .map_err(|e| e.to_string())?;
-    let item = stmt
-        .query_map(params![barcode], |row| {
-            Ok(Item {
-                name: row.get(0)?,
-                barcode: row.get(1)?,
-                location: row.get(2)?,
-                last_seen: row.get(3)?,
-            })
-        })
-        .map_err(|e| e.to_string())?
-        .map(|r| r.map_err(|e| e.to_string()))
-        .collect::<Result<Vec<_>, _>>()?;
-    if item.len() == 0 {
-        return Err("Item not found".to_string());
+    pub fn save(&self, conn: &mut Connection) -> Result<(), String> {
+        let _timer = QueryTimer::start("save");
+        with_retry(conn, |conn| {
+            let parent_id = match self.parent_barcode {
+                Some(parent) => match parent_id(conn, self.barcode, parent)? {
+                    Ok(id) => Some(id),
+                    Err(err) => return Ok(Err(err)),
+                },
+                None => None,
+            };
+            if let Err(err) = check_archived_barcode(conn, self.barcode, reuse_archived_barcodes())?
+            {
+                return Ok(Err(err));
+            }
+            let tx = conn.transaction()?;
+            let location = canonical_location(&tx, &self.location)?;
+            tx.execute(
+                "INSERT INTO items
+                    (name, barcode, location, last_seen, version, status, purchase_date, value_pence,
+                     parent_id, quantity)
+                 VALUES (?1, ?2, ?3, ?4, 1, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    self.name,
+                    self.barcode,
+                    location,
+                    self.last_seen,
+                    self.status.as_deref().unwrap_or("ok"),
+                    self.purchase_date,
+                    self.value_pence,
+                    parent_id,
+                    self.quantity.unwrap_or(1)
+                ],
+            )?;
+            if let Some(note) = self.note() {
+                insert_note(&tx, tx.last_insert_rowid(), note)?;
+            }
+            tx.commit()?;
+            Ok(Ok(()))
+        })?
     }
-    Ok(item[0].clone()) // UNIQUE constraint on barcode, so there will be only one item
 }
 
-pub fn delete_item(barcode: &str) -> Result<(), String> {
-    let conn = Connection::open(DB_NAME).map_err(|e| e.to_string())?;
-    let rows_affected = conn
-        .execute("DELETE FROM items WHERE barcode = ?1", params![barcode])
-        .map_err(|e| e.to_string())?;
-    if rows_affected == 0 {
-        return Err("Item not found".to_string());
+/// how many idle connections are kept for reuse, each of the main database and the read database,
+/// from BARCODE_DB_POOL (default 4, 0 to open a new one every time)
+fn db_pool_size() -> usize {
+    match env::var("BARCODE_DB_POOL") {
+        Ok(size) => match size.parse::<usize>() {
+            Ok(size) => size,
+            Err(_) => {
+                warn!("Invalid BARCODE_DB_POOL: {}, using 4", size);
+                4
+            }
+        },
+        Err(_) => 4,
     }
-    Ok(())
 }
 
-pub fn modify_item(item: Item) -> Result<(), String> {
-    let conn = Connection::open(DB_NAME).map_err(|e| e.to_string())?;
-    let rows_affected = conn
-        .execute(
-            "UPDATE items SET name = ?1, location = ?2, last_seen = ?3 WHERE barcode = ?4",
-            params![item.name, item.location, item.last_seen, item.barcode],
-        )
-        .map_err(|e| e.to_string())?;
+/// idle connections to one database, so requests don't reopen the file and re-read the schema
+/// every time
+pub struct Pool {
+    idle: std::sync::Mutex<Vec<Connection>>,
+    /// the most idle connections kept, 0 to open a new one every time
+    size: usize,
+    open: Box<dyn Fn() -> rusqlite::Result<Connection> + Send + Sync>,
+}
 
-    if rows_affected == 0 {
-        return Err("Item not found".to_string());
+impl Pool {
+    fn new(
+        size: usize,
+        open: impl Fn() -> rusqlite::Result<Connection> + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(Pool {
+            idle: std::sync::Mutex::new(Vec::new()),
+            size,
+            open: Box::new(open),
+        })
     }
 
-    Ok(())
-}
+    /// an idle connection, or a new one
+    fn take(self: &Arc<Self>) -> rusqlite::Result<PooledConnection> {
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => (self.open)()?,
+        };
+        Ok(PooledConnection {
+            conn: Some(conn),
+            pool: Arc::clone(self),
+        })
+    }
 
-fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
-    Full::new(chunk.into())
-        .map_err(|never| match never {})
-        .boxed()
+    /// close every idle connection
+    fn clear(&self) {
+        self.idle.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
 }
 
-fn ok() -> BoxBody<Bytes, hyper::Error> {
-    full("OK")
+/// a connection taken from a `Pool`, put back for the next request when dropped; one left inside
+/// a transaction is closed instead
+pub struct PooledConnection {
+    conn: Option<Connection>,
+    pool: Arc<Pool>,
 }
 
-/// remove all non-alphanumeric characters from a string (all fields can have this applied)
-fn sanitize(s: &str) -> String {
-    s.replace(
-        |c: char| !c.is_ascii_alphanumeric() && !c.is_ascii_whitespace(),
-        "",
-    )
+impl std::ops::Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+            .as_ref()
+            .expect("connection is only taken on drop")
+    }
 }
 
-impl Item {
-    /// remove all non-alphanumeric characters from all fields
-    fn sanitize(&mut self) {
-        self.name = sanitize(&self.name);
-        self.location = sanitize(&self.location);
+impl std::ops::DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn
+            .as_mut()
+            .expect("connection is only taken on drop")
     }
 }
 
-// endpoint for new item (hyper)
-async fn new_item(
-    req: Request<Incoming>,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    let max = req.body().size_hint().upper().unwrap_or(u64::MAX);
-    if max > 1024 * 64 {
-        let mut resp = Response::new(full("Body too big"));
-        *resp.status_mut() = hyper::StatusCode::PAYLOAD_TOO_LARGE;
-        return Ok(resp);
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        let Some(conn) = self.conn.take() else {
+            return;
+        };
+        if !conn.is_autocommit() {
+            return;
+        }
+        let mut idle = self.pool.idle.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < self.pool.size {
+            idle.push(conn);
+        }
     }
+}
 
-    let whole_body = req.collect().await?.to_bytes().to_vec();
+/// the databases requests use, opened once in `main` and handed down to every handler; cloning
+/// shares the pools
+#[derive(Clone)]
+pub struct Db {
+    /// the main database's file, for `/get_database` and `/status`
+    path: Arc<str>,
+    main: Arc<Pool>,
+    /// read-only connections to BARCODE_READ_DB, if it's set
+    read: Option<Arc<Pool>>,
+}
 
-    let str_body = std::str::from_utf8(&whole_body);
+impl Db {
+    /// the database at `path`, with reads going to a read-only connection to `read_path` if given
+    /// (usually the main database, with WAL so reads never wait on writers); each pool keeps up to
+    /// `pool_size` idle connections
+    fn open(path: &str, read_path: Option<&str>, pool_size: usize) -> Self {
+        let main = {
+            let path = path.to_string();
+            // foreign keys are enforced, since SQLite leaves them off on every new connection
+            Pool::new(pool_size, move || {
+                let conn = Connection::open(&path)?;
+                conn.pragma_update(None, "foreign_keys", "ON")?;
+                Ok(conn)
+            })
+        };
+        let read = read_path.map(|read_path| {
+            let read_path = read_path.to_string();
+            Pool::new(pool_size, move || {
+                let conn = Connection::open_with_flags(
+                    &read_path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY
+                        | OpenFlags::SQLITE_OPEN_URI
+                        | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )?;
+                conn.pragma_update(None, "foreign_keys", "ON")?;
+                Ok(conn)
+            })
+        });
 
-    let item: Result<Item, serde_json::Error> = serde_json::from_str(str_body.unwrap());
+        Db {
+            path: path.into(),
+            main,
+            read,
+        }
+    }
 
-    if item.is_err() {
-        let mut resp = Response::new(full("Invalid JSON"));
-        *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
-        return Ok(resp);
+    /// a connection to the main database
+    fn conn(&self) -> Result<PooledConnection, String> {
+        self.main.take().map_err(|e| e.to_string())
     }
 
-    // now give it a last seen time of now
-    let mut item = item.unwrap(); // unwrap is safe because we checked it above
-    item.sanitize();
-    item.last_seen = Some(Utc::now().timestamp() as u64);
+    /// a connection for reads, the read-only one if there is one, otherwise the main database
+    fn read_conn(&self) -> Result<PooledConnection, String> {
+        match &self.read {
+            Some(read) => read.take().map_err(|e| e.to_string()),
+            None => self.conn(),
+        }
+    }
 
-    let res = item.save();
+    /// run reads on a connection from `read_conn`
+    fn read<T>(&self, op: impl FnOnce(&mut Connection) -> Result<T, String>) -> Result<T, String> {
+        let mut conn = self.read_conn()?;
+        op(&mut conn)
+    }
 
-    if let Err(err) = res {
-        let mut resp = if err.contains("UNIQUE constraint failed") {
-            Response::new(full("Item already exists"))
-        } else {
-            Response::new(full(err.clone()))
-        };
-        *resp.status_mut() = if err.contains("UNIQUE constraint failed") {
-            hyper::StatusCode::CONFLICT
-        } else {
-            hyper::StatusCode::INTERNAL_SERVER_ERROR
-        };
-        return Ok(resp);
+    /// run writes (or reads that must see them) on a connection to the main database
+    fn write<T>(&self, op: impl FnOnce(&mut Connection) -> Result<T, String>) -> Result<T, String> {
+        let mut conn = self.conn()?;
+        op(&mut conn)
     }
 
-    Ok(Response::new(ok()))
-}
+    /// `read` on tokio's blocking threads, see `blocking`
+    async fn read_blocking<T: Send + 'static>(
+        &self,
+        op: impl FnOnce(&mut Connection) -> Result<T, String> + Send + 'static,
+    ) -> Result<T, String> {
+        let db = self.clone();
+        blocking(move || db.read(op)).await
+    }
 
-// endpoint for all items (hyper)
-async fn all_items(
-    _req: Request<Incoming>,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    let items = load_items();
+    /// `write` on tokio's blocking threads, see `blocking`
+    async fn write_blocking<T: Send + 'static>(
+        &self,
+        op: impl FnOnce(&mut Connection) -> Result<T, String> + Send + 'static,
+    ) -> Result<T, String> {
+        let db = self.clone();
+        blocking(move || db.write(op)).await
+    }
 
-    if items.is_err() {
-        let mut resp = Response::new(full(items.unwrap_err()));
-        *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
-        return Ok(resp);
+    /// close every idle connection, so none is carried across `daemonize`'s fork (SQLite
+    /// connections mustn't be used on both sides of a fork)
+    fn close_idle(&self) {
+        self.main.clear();
+        if let Some(read) = &self.read {
+            read.clear();
+        }
     }
+}
 
-    let items: Vec<Item> = items
-        .unwrap()
-        .iter_mut()
-        .map(|i| {
-            i.sanitize();
-            i.clone()
-        })
-        .collect();
+/// how many writes had to be retried because the database was busy, reported by `/health`
+static DB_RETRIES: AtomicU64 = AtomicU64::new(0);
 
-    let items_json = serde_json::to_string(&items);
+/// how long each write attempt waits on a lock before giving up and backing off
+const DB_BUSY_TIMEOUT: Duration = Duration::from_millis(250);
 
-    if items_json.is_err() {
-        let mut resp = Response::new(full(items_json.unwrap_err().to_string()));
-        *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
-        return Ok(resp);
-    }
+/// how many times a write is attempted while the database is busy, from BARCODE_DB_RETRIES (default 5)
+fn db_retry_attempts() -> u32 {
+    static DB_RETRY_ATTEMPTS: std::sync::OnceLock<u32> = std::sync::OnceLock::new();
 
-    Ok(Response::new(full(items_json.unwrap()))) // unwrap is safe because we checked it above
+    *DB_RETRY_ATTEMPTS.get_or_init(|| match env::var("BARCODE_DB_RETRIES") {
+        Ok(attempts) => match attempts.parse::<u32>() {
+            Ok(attempts) if attempts > 0 => attempts,
+            _ => {
+                warn!("Invalid BARCODE_DB_RETRIES: {}, using 5", attempts);
+                5
+            }
+        },
+        Err(_) => 5,
+    })
 }
 
-// endpoint for item (hyper)
-async fn item(
-    req: Request<Incoming>,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    let barcode = req.uri().path().split('/').last();
+/// whether a database error is worth retrying (another connection holds the lock), as opposed to
+/// constraint violations, schema errors and the like
+fn is_transient(err: &rusqlite::Error) -> bool {
+    matches!(
+        err.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
 
-    let barcode = match barcode {
-        Some(barcode) => match barcode.parse::<u64>() {
-            Ok(barcode) => barcode,
-            Err(_) => {
-                let mut resp = Response::new(full("Invalid barcode"));
-                *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
-                return Ok(resp);
-            }
-        },
-        None => {
-            let mut resp = Response::new(full("No barcode"));
-            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
-            return Ok(resp);
-        }
-    };
+/// run a write on `conn`, retrying with jittered backoff while the database is busy or locked,
+/// so bursts of scans don't surface SQLITE_BUSY to clients
+fn with_retry<T>(
+    conn: &mut Connection,
+    mut op: impl FnMut(&mut Connection) -> rusqlite::Result<T>,
+) -> Result<T, String> {
+    let attempts = db_retry_attempts();
+    let mut attempt = 0;
 
-    let item = load_item(barcode); // unwrap is safe because we checked it above
+    conn.busy_timeout(DB_BUSY_TIMEOUT)
+        .map_err(|e| e.to_string())?;
+    loop {
+        attempt += 1;
 
-    if let Err(err) = item {
-        let mut resp = if err == "Item not found" {
-            Response::new(full("Item not found"))
-        } else {
-            Response::new(full(err.clone()))
-        };
-        *resp.status_mut() = if err == "Item not found" {
-            hyper::StatusCode::NOT_FOUND
-        } else {
-            hyper::StatusCode::INTERNAL_SERVER_ERROR
-        };
-        return Ok(resp);
-    }
+        let result = op(conn);
 
-    let mut item = item.unwrap(); // unwrap is safe because we checked it above
-    item.sanitize();
+        match result {
+            Err(e) if is_transient(&e) && attempt < attempts => {
+                DB_RETRIES.fetch_add(1, Ordering::Relaxed);
 
-    let item_json = serde_json::to_string(&item);
+                // 25ms, 50ms, 100ms... capped at a second, with jitter so retries don't line up again
+                let backoff =
+                    Duration::from_millis(25 << attempt.min(6)).min(Duration::from_secs(1));
+                let half = backoff.as_millis() as u64 / 2;
+                let delay = Duration::from_millis(
+                    half + std::collections::hash_map::RandomState::new().hash_one(attempt)
+                        % (half + 1),
+                );
 
-    if item_json.is_err() {
-        let mut resp = Response::new(full(item_json.unwrap_err().to_string()));
-        *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
-        return Ok(resp);
+                warn!(
+                    "database busy ({}), retrying in {}ms (attempt {} of {})",
+                    e,
+                    delay.as_millis(),
+                    attempt,
+                    attempts
+                );
+                std::thread::sleep(delay);
+            }
+            result => return result.map_err(|e| e.to_string()),
+        }
     }
+}
 
-    Ok(Response::new(full(item_json.unwrap()))) // unwrap is safe because we checked it above
+tokio::task_local! {
+    /// the slowest database operation of the request being handled, for slow-request warnings
+    static SLOWEST_QUERY: std::cell::Cell<Option<(&'static str, Duration)>>;
 }
 
-// endpoint to modify item (hyper)
-// expected format:
-/*
-```
-{
-    "name": "item name",
-    "barcode": "42",
-    "location": "location",
+/// times a database operation until dropped, remembering it if it's the slowest of the request so far
+struct QueryTimer {
+    operation: &'static str,
+    start: std::time::Instant,
 }
-```
-*/
-async fn modify_item_endpoint(
-    req: Request<Incoming>,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    let max = req.body().size_hint().upper().unwrap_or(u64::MAX);
-    if max > 1024 * 64 {
-        let mut resp = Response::new(full("Body too big"));
-        *resp.status_mut() = hyper::StatusCode::PAYLOAD_TOO_LARGE;
-        return Ok(resp);
+
+impl QueryTimer {
+    fn start(operation: &'static str) -> Self {
+        Self {
+            operation,
+            start: std::time::Instant::now(),
+        }
     }
+}
 
-    let whole_body = req.collect().await?.to_bytes().to_vec();
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
 
-    let str_body = std::str::from_utf8(&whole_body);
+        // outside a request (startup, tests) there is nothing to report to
+        let _ = SLOWEST_QUERY.try_with(|slowest| {
+            if slowest.get().is_none_or(|(_, longest)| elapsed > longest) {
+                slowest.set(Some((self.operation, elapsed)));
+            }
+        });
+    }
+}
 
-    let item: Result<Item, serde_json::Error> = serde_json::from_str(str_body.unwrap());
+/// how locations are spelled once normalized, from BARCODE_LOCATION_CASE
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LocationCase {
+    /// reuse whichever spelling is already stored ("rig" becomes "Rig" if "Rig" exists), the default
+    Existing,
+    /// "levi fox hall" becomes "Levi Fox Hall"
+    Title,
+    /// everything lowercase
+    Lower,
+}
 
-    if item.is_err() {
-        let mut resp = Response::new(full("Invalid JSON"));
-        *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
-        return Ok(resp);
-    }
+fn location_case() -> LocationCase {
+    static LOCATION_CASE: std::sync::OnceLock<LocationCase> = std::sync::OnceLock::new();
 
-    let mut item = item.unwrap(); // unwrap is safe because we checked it above
-    item.sanitize();
-    item.last_seen = Some(Utc::now().timestamp() as u64);
+    *LOCATION_CASE.get_or_init(|| match env::var("BARCODE_LOCATION_CASE").as_deref() {
+        Ok("title") => LocationCase::Title,
+        Ok("lower") => LocationCase::Lower,
+        Ok("existing") | Err(_) => LocationCase::Existing,
+        Ok(other) => {
+            warn!(
+                "Invalid BARCODE_LOCATION_CASE: {}, expected existing, title or lower",
+                other
+            );
+            LocationCase::Existing
+        }
+    })
+}
 
-    let res = modify_item(item);
+/// trim a location and collapse runs of whitespace inside it, then apply `case`
+/// (`Existing` is left as typed here, see `canonical_location`)
+fn normalize_location(location: &str, case: LocationCase) -> String {
+    let words = location.split_whitespace();
 
-    if let Err(err) = res {
-        let mut resp = if err == "Item not found" {
-            Response::new(full("Item not found"))
-        } else {
-            Response::new(full(err.clone()))
-        };
-        *resp.status_mut() = if err == "Item not found" {
-            hyper::StatusCode::NOT_FOUND
-        } else {
-            hyper::StatusCode::INTERNAL_SERVER_ERROR
-        };
-        return Ok(resp);
+    match case {
+        LocationCase::Existing => words.collect::<Vec<_>>().join(" "),
+        LocationCase::Lower => words.collect::<Vec<_>>().join(" ").to_lowercase(),
+        LocationCase::Title => words
+            .map(|word| {
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first
+                        .to_uppercase()
+                        .chain(chars.flat_map(char::to_lowercase))
+                        .collect(),
+                    None => String::new(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" "),
     }
-
-    Ok(Response::new(ok()))
 }
 
-// endpoint to delete item (hyper)
-async fn delete_item_endpoint(
-    req: Request<Incoming>,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    let barcode = req.uri().path().split('/').last();
+/// the spelling a location is stored under, so "rig", "Rig" and "RIG " end up as one location
+fn canonical_location(conn: &Connection, location: &str) -> rusqlite::Result<String> {
+    use rusqlite::OptionalExtension;
 
-    if barcode.is_none() {
-        let mut resp = Response::new(full("No barcode"));
-        *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
-        return Ok(resp);
+    let case = location_case();
+    let location = normalize_location(location, case);
+    if case != LocationCase::Existing {
+        return Ok(location);
     }
 
-    let res = delete_item(barcode.unwrap()); // unwrap is safe because we checked it above
+    let existing = conn
+        .query_row(
+            "SELECT location FROM items WHERE location = ?1 COLLATE NOCASE ORDER BY id LIMIT 1",
+            params![location],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(existing.unwrap_or(location))
+}
 
-    if let Err(err) = res {
-        let mut resp = if err == "Item not found" {
-            Response::new(full("Item not found"))
-        } else {
-            Response::new(full(err.clone()))
-        };
-        *resp.status_mut() = if err == "Item not found" {
-            hyper::StatusCode::NOT_FOUND
+pub fn load_items(conn: &Connection) -> Result<Vec<Item>, String> {
+    let _timer = QueryTimer::start("load_items");
+    let mut stmt = conn
+        .prepare(&format!("SELECT {} FROM items", item_columns()))
+        .map_err(|e| e.to_string())?;
+    let items = stmt
+        .query_map(params![], Item::from_row)
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(items)
+}
+
+/// items at a location, matched case-insensitively
+pub fn load_items_at(conn: &Connection, location: &str) -> Result<Vec<Item>, String> {
+    let _timer = QueryTimer::start("load_items_at");
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM items WHERE location = ?1 COLLATE NOCASE",
+            item_columns()
+        ))
+        .map_err(|e| e.to_string())?;
+    let items = stmt
+        .query_map(
+            params![normalize_location(location, LocationCase::Existing)],
+            Item::from_row,
+        )
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(items)
+}
+
+/// items whose name and/or location contain the given text (and, for `anywhere`, whose name or
+/// location does), ignoring case, ordered by name; `%` and `_` in the text match themselves rather
+/// than acting as wildcards
+pub fn search_items(
+    conn: &Connection,
+    name: Option<&str>,
+    location: Option<&str>,
+    anywhere: Option<&str>,
+) -> Result<Vec<Item>, String> {
+    let _timer = QueryTimer::start("search_items");
+    let pattern = |text: Option<&str>| {
+        text.map(|text| {
+            format!(
+                "%{}%",
+                text.replace('\\', "\\\\")
+                    .replace('%', "\\%")
+                    .replace('_', "\\_")
+            )
+        })
+    };
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM items
+             WHERE (?1 IS NULL OR name LIKE ?1 ESCAPE '\\')
+               AND (?2 IS NULL OR location LIKE ?2 ESCAPE '\\')
+               AND (?3 IS NULL OR name LIKE ?3 ESCAPE '\\' OR location LIKE ?3 ESCAPE '\\')
+             ORDER BY name COLLATE NOCASE, barcode",
+            item_columns()
+        ))
+        .map_err(|e| e.to_string())?;
+    let items = stmt
+        .query_map(
+            params![pattern(name), pattern(location), pattern(anywhere)],
+            Item::from_row,
+        )
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(items)
+}
+
+/// every location with how many items are there, variants differing only in case counted as one
+pub fn load_locations(conn: &Connection) -> Result<Vec<(String, u64)>, String> {
+    let _timer = QueryTimer::start("load_locations");
+    let mut stmt = conn
+        .prepare(
+            "SELECT MIN(location), COUNT(*) FROM items
+             GROUP BY location COLLATE NOCASE ORDER BY location COLLATE NOCASE",
+        )
+        .map_err(|e| e.to_string())?;
+    let locations = stmt
+        .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(locations)
+}
+
+/// the item fields named in `?fields=barcode,name`, in the order given, or `None` to mean all of them
+fn requested_fields(query: Option<&str>) -> Result<Option<Vec<&'static str>>, String> {
+    let Some(requested) = query_param(query, "fields") else {
+        return Ok(None);
+    };
+
+    let mut fields = Vec::new();
+    for field in requested
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+    {
+        match ITEM_FIELDS.iter().find(|(name, _)| *name == field) {
+            Some((name, _)) if !fields.contains(name) => fields.push(*name),
+            Some(_) => {}
+            None => {
+                return Err(format!(
+                    "Unknown field {}, expected some of {}",
+                    sanitize(field),
+                    item_field_names()
+                ));
+            }
+        }
+    }
+
+    if fields.is_empty() {
+        return Err(format!(
+            "fields can't be empty, expected some of {}",
+            item_field_names()
+        ));
+    }
+    Ok(Some(fields))
+}
+
+/// how many items match an SQL condition
+pub fn count_items(
+    conn: &Connection,
+    condition: &str,
+    params: &[&dyn rusqlite::ToSql],
+) -> Result<u64, String> {
+    let _timer = QueryTimer::start("count_items");
+    conn.query_row(
+        &format!("SELECT COUNT(*) FROM items WHERE {}", condition),
+        params,
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// the change counter, bumped by every insert, update or delete of an item (see `upgrade_schema`)
+pub fn change_counter(conn: &Connection) -> Result<u64, String> {
+    let _timer = QueryTimer::start("change_counter");
+    conn.query_row(
+        "SELECT value FROM meta WHERE key = 'change_counter'",
+        params![],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// what changed after the counter was at `since`: the counter now, the items changed since
+/// (as they are now) and the barcodes deleted since, both in the order they changed
+///
+/// read in one transaction, so the counter matches the changes
+pub fn load_changes(
+    conn: &mut Connection,
+    since: u64,
+) -> Result<(u64, Vec<Item>, Vec<u64>), String> {
+    let _timer = QueryTimer::start("load_changes");
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    let counter: u64 = tx
+        .query_row(
+            "SELECT value FROM meta WHERE key = 'change_counter'",
+            params![],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if since > counter {
+        return Err("Counter is ahead of the server".to_string());
+    }
+
+    let mut stmt = tx
+        .prepare(&format!(
+            "SELECT {} FROM items JOIN item_changes USING (barcode)
+            WHERE item_changes.counter > ?1 AND NOT item_changes.deleted
+            ORDER BY item_changes.counter",
+            item_columns()
+        ))
+        .map_err(|e| e.to_string())?;
+    let changed = stmt
+        .query_map(params![since], Item::from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut stmt = tx
+        .prepare("SELECT barcode FROM item_changes WHERE counter > ?1 AND deleted ORDER BY counter")
+        .map_err(|e| e.to_string())?;
+    let deleted = stmt
+        .query_map(params![since], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok((counter, changed, deleted))
+}
+
+/// the barcodes that changed after the cursor was at `since`, for `/sync`
+#[derive(Debug, Serialize)]
+pub struct SyncDelta {
+    /// the change counter now, to send as `since` next time
+    cursor: u64,
+    /// barcodes that came into use after `since`
+    created: Vec<u64>,
+    /// barcodes that were in use at `since` and have changed since
+    updated: Vec<u64>,
+    /// barcodes that were in use at `since` and have been deleted (or archived) since
+    deleted: Vec<u64>,
+}
+
+/// what changed after the cursor was at `since`, each list in the order things changed;
+/// a barcode that came and went after `since` is in none of them
+pub fn load_sync(conn: &mut Connection, since: u64) -> Result<SyncDelta, String> {
+    let _timer = QueryTimer::start("load_sync");
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+
+    let cursor: u64 = tx
+        .query_row(
+            "SELECT value FROM meta WHERE key = 'change_counter'",
+            params![],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if since > cursor {
+        return Err("Counter is ahead of the server".to_string());
+    }
+
+    let mut stmt = tx
+        .prepare(
+            "SELECT barcode, deleted, created > ?1 FROM item_changes
+            WHERE counter > ?1 ORDER BY counter",
+        )
+        .map_err(|e| e.to_string())?;
+    let changes = stmt
+        .query_map(params![since], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, bool>(1)?,
+                row.get::<_, bool>(2)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut delta = SyncDelta {
+        cursor,
+        created: Vec::new(),
+        updated: Vec::new(),
+        deleted: Vec::new(),
+    };
+    for (barcode, deleted, new) in changes {
+        match (deleted, new) {
+            (true, true) => {}
+            (true, false) => delta.deleted.push(barcode),
+            (false, true) => delta.created.push(barcode),
+            (false, false) => delta.updated.push(barcode),
+        }
+    }
+    Ok(delta)
+}
+
+/// how many items were created, modified, deleted and scanned on one day, for `/activity`
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DayActivity {
+    /// `YYYY-MM-DD`, in the timezone asked for
+    date: String,
+    created: u64,
+    modified: u64,
+    deleted: u64,
+    scanned: u64,
+}
+
+/// most days `/activity` goes back
+const MAX_ACTIVITY_DAYS: u64 = 366;
+
+/// what happened on each of the last `days` days up to `now` (today included, oldest first, quiet
+/// days as zeros), with days starting at midnight `offset` seconds east of UTC
+///
+/// activity is only recorded from when the `activity` table was added, so earlier days are empty
+pub fn load_activity(
+    conn: &Connection,
+    days: u64,
+    offset: i64,
+    now: i64,
+) -> Result<Vec<DayActivity>, String> {
+    let _timer = QueryTimer::start("load_activity");
+
+    let today = (now + offset).div_euclid(86400);
+    let first = today - days as i64 + 1;
+    let mut activity: Vec<DayActivity> = (first..=today)
+        .map(|day| DayActivity {
+            date: chrono::DateTime::from_timestamp(day * 86400, 0)
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+            ..Default::default()
+        })
+        .collect();
+
+    // one range scan of the index, however many days
+    let mut stmt = conn
+        .prepare(
+            "SELECT (at + ?2) / 86400 AS day, operation, COUNT(*) FROM activity
+            WHERE at >= ?1 AND at < ?3 GROUP BY day, operation",
+        )
+        .map_err(|e| e.to_string())?;
+    let counts = stmt
+        .query_map(
+            params![first * 86400 - offset, offset, (today + 1) * 86400 - offset],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u64>(2)?,
+                ))
+            },
+        )
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    for (day, operation, count) in counts {
+        let Some(bucket) = activity.get_mut((day - first) as usize) else {
+            continue;
+        };
+        match operation.as_str() {
+            "created" => bucket.created += count,
+            "modified" => bucket.modified += count,
+            "deleted" => bucket.deleted += count,
+            "scanned" => bucket.scanned += count,
+            _ => {}
+        }
+    }
+    Ok(activity)
+}
+
+/// seconds east of UTC for an offset like `+01:00`, `-0530`, `+2` or `UTC`
+fn parse_utc_offset(offset: &str) -> Result<i64, String> {
+    let invalid = || {
+        format!(
+            "Invalid timezone {}, expected an offset like +01:00 or UTC",
+            offset
+        )
+    };
+
+    let offset = offset.trim();
+    if offset.eq_ignore_ascii_case("utc") || offset == "Z" {
+        return Ok(0);
+    }
+    let (sign, rest) = match offset.split_at_checked(1) {
+        Some(("+", rest)) => (1, rest),
+        Some(("-", rest)) => (-1, rest),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if rest.len() == 4 => rest.split_at(2),
+        None => (rest, "0"),
+    };
+    match (hours.parse::<i64>(), minutes.parse::<i64>()) {
+        (Ok(hours), Ok(minutes)) if hours <= 14 && minutes < 60 => {
+            Ok(sign * (hours * 3600 + minutes * 60))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// the timezone `/activity` starts its days in unless asked for another, from BARCODE_TIMEZONE
+/// (a fixed offset like `+01:00`; default UTC)
+fn activity_offset() -> i64 {
+    static ACTIVITY_OFFSET: std::sync::OnceLock<i64> = std::sync::OnceLock::new();
+
+    *ACTIVITY_OFFSET.get_or_init(|| match env::var("BARCODE_TIMEZONE") {
+        Ok(offset) => parse_utc_offset(&offset).unwrap_or_else(|err| {
+            warn!("{}, using UTC", err);
+            0
+        }),
+        Err(_) => 0,
+    })
+}
+
+/// how many items `/all` sends when the client doesn't give a `?limit=`, from BARCODE_DEFAULT_LIMIT
+/// (default 100); 0 sends them all, as `/all` always used to
+fn default_limit() -> Option<u64> {
+    static DEFAULT_LIMIT: std::sync::OnceLock<Option<u64>> = std::sync::OnceLock::new();
+
+    *DEFAULT_LIMIT.get_or_init(|| match env::var("BARCODE_DEFAULT_LIMIT") {
+        Ok(limit) => match limit.parse::<u64>() {
+            Ok(0) => None,
+            Ok(limit) => Some(limit),
+            Err(_) => {
+                warn!("Invalid BARCODE_DEFAULT_LIMIT: {}, using 100", limit);
+                Some(100)
+            }
+        },
+        Err(_) => Some(100),
+    })
+}
+
+/// just the given fields of the items matching an SQL condition, as JSON objects
+///
+/// only those columns are selected, so a list of names and barcodes doesn't read everything else;
+/// `fields` are names from `ITEM_FIELDS`, as `requested_fields` returns
+///
+/// with `page` (limit, offset) only that page of them, most recently seen first
+pub fn load_item_fields(
+    conn: &Connection,
+    fields: &[&str],
+    condition: &str,
+    params: &[&dyn rusqlite::ToSql],
+    page: Option<(u64, u64)>,
+) -> Result<Vec<serde_json::Map<String, serde_json::Value>>, String> {
+    use rusqlite::types::ValueRef;
+
+    let _timer = QueryTimer::start("load_item_fields");
+    let columns: Vec<&str> = fields
+        .iter()
+        .filter_map(|field| ITEM_FIELDS.iter().find(|(name, _)| name == field))
+        .map(|(_, sql)| *sql)
+        .collect();
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM items WHERE {}{}",
+            columns.join(", "),
+            condition,
+            match page {
+                Some((limit, offset)) => format!(
+                    " ORDER BY last_seen DESC, barcode LIMIT {} OFFSET {}",
+                    limit, offset
+                ),
+                None => String::new(),
+            }
+        ))
+        .map_err(|e| e.to_string())?;
+    let items = stmt
+        .query_map(params, |row| {
+            let mut item = serde_json::Map::new();
+            for (i, field) in fields.iter().enumerate() {
+                let value = match row.get_ref(i)? {
+                    ValueRef::Null => serde_json::Value::Null,
+                    ValueRef::Integer(n) => n.into(),
+                    ValueRef::Real(n) => n.into(),
+                    // free text is sanitized as it is for whole items
+                    ValueRef::Text(text) if ["name", "location"].contains(field) => {
+                        sanitize(&String::from_utf8_lossy(text)).into()
+                    }
+                    ValueRef::Text(text) => String::from_utf8_lossy(text).into(),
+                    ValueRef::Blob(_) => serde_json::Value::Null,
+                };
+                item.insert(field.to_string(), value);
+            }
+            Ok(item)
+        })
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(items)
+}
+
+/// total value of the items at one location
+#[derive(Debug, Serialize, PartialEq)]
+pub struct LocationValue {
+    location: String,
+    value_pence: i64,
+    items: u64,
+    missing_value: u64,
+}
+
+/// what everything is worth, overall and by location, for insurance
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Valuation {
+    value_pence: i64,
+    items: u64,
+    /// items without a `value_pence`, which the totals leave out
+    missing_value: u64,
+    locations: Vec<LocationValue>,
+}
+
+/// total up `(location, value_pence)` rows sorted by location, locations differing only in case counted as one
+///
+/// fails rather than wrapping if a total doesn't fit in 64 bits
+fn value_by_location(
+    rows: impl IntoIterator<Item = (String, Option<i64>)>,
+) -> Result<Valuation, String> {
+    let overflow = || "Valuation total is too large to represent".to_string();
+    let mut valuation = Valuation {
+        value_pence: 0,
+        items: 0,
+        missing_value: 0,
+        locations: Vec::new(),
+    };
+
+    for (location, value) in rows {
+        let same_location = valuation
+            .locations
+            .last()
+            .is_some_and(|last| last.location.eq_ignore_ascii_case(&location));
+        if !same_location {
+            valuation.locations.push(LocationValue {
+                location,
+                value_pence: 0,
+                items: 0,
+                missing_value: 0,
+            });
+        }
+        let at = valuation.locations.last_mut().unwrap(); // pushed above if there wasn't one
+
+        at.items += 1;
+        valuation.items += 1;
+        match value {
+            Some(value) => {
+                at.value_pence = at.value_pence.checked_add(value).ok_or_else(overflow)?;
+                valuation.value_pence = valuation
+                    .value_pence
+                    .checked_add(value)
+                    .ok_or_else(overflow)?;
+            }
+            None => {
+                at.missing_value += 1;
+                valuation.missing_value += 1;
+            }
+        }
+    }
+
+    Ok(valuation)
+}
+
+/// the value of every item that isn't retired, overall and by location
+pub fn load_valuation(conn: &Connection) -> Result<Valuation, String> {
+    let _timer = QueryTimer::start("load_valuation");
+    let mut stmt = conn
+        .prepare(
+            "SELECT location, value_pence FROM items WHERE status != 'retired'
+             ORDER BY location COLLATE NOCASE",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    value_by_location(rows)
+}
+
+pub fn load_item(conn: &Connection, barcode: u64) -> Result<Item, String> {
+    let _timer = QueryTimer::start("load_item");
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM items WHERE barcode = ?1",
+            item_columns()
+        ))
+        .map_err(|e| e.to_string())?;
+    let item = stmt
+        .query_map(params![barcode], Item::from_row)
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    if item.len() == 0 {
+        return Err("Item not found".to_string());
+    }
+    Ok(item[0].clone()) // UNIQUE constraint on barcode, so there will be only one item
+}
+
+pub fn delete_item(conn: &mut Connection, barcode: &str) -> Result<(), String> {
+    let _timer = QueryTimer::start("delete_item");
+    let rows_affected = with_retry(conn, |conn| {
+        conn.execute("DELETE FROM items WHERE barcode = ?1", params![barcode])
+    })?;
+    if rows_affected == 0 {
+        return Err("Item not found".to_string());
+    }
+    Ok(())
+}
+
+/// whether `/new` may give an archived item's barcode to a new item, from
+/// BARCODE_REUSE_ARCHIVED (default true); an archived item can't come back while its barcode is in use
+fn reuse_archived_barcodes() -> bool {
+    static REUSE_ARCHIVED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+    *REUSE_ARCHIVED
+        .get_or_init(|| !env::var("BARCODE_REUSE_ARCHIVED").is_ok_and(|reuse| reuse == "false"))
+}
+
+/// fails with "Barcode is archived" if `barcode` belongs to an archived item and can't be reused
+fn check_archived_barcode(
+    conn: &Connection,
+    barcode: u64,
+    reuse: bool,
+) -> rusqlite::Result<Result<(), String>> {
+    if reuse {
+        return Ok(Ok(()));
+    }
+
+    let archived: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM archived_items WHERE barcode = ?1)",
+        params![barcode],
+        |row| row.get(0),
+    )?;
+    Ok(if archived {
+        Err("Barcode is archived".to_string())
+    } else {
+        Ok(())
+    })
+}
+
+/// an item moved out of the inventory by `/archive`, kept for the asset register
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchivedItem {
+    #[serde(flatten)]
+    item: Item,
+    archived_at: u64,
+}
+
+impl ArchivedItem {
+    /// the select list for archived items, `archived_items` stores every item field as a column
+    fn columns() -> String {
+        format!("{}, archived_at", item_field_names())
+    }
+
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            item: Item::from_row(row)?,
+            archived_at: row.get(ITEM_FIELDS.len())?,
+        })
+    }
+}
+
+/// move an item into the archive, with its maintenance log, location trail and notes if `history`
+///
+/// reservations and aliases never move; they go with the item, as does the history if it's left behind
+pub fn archive_item(
+    conn: &mut Connection,
+    barcode: u64,
+    history: bool,
+) -> Result<ArchivedItem, String> {
+    let _timer = QueryTimer::start("archive_item");
+    let archived_at = Utc::now().timestamp() as u64;
+
+    with_retry(conn, |conn| {
+        let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        let id = match item_id(&tx, barcode) {
+            Ok(id) => id,
+            Err(err) => return Ok(Err(err)),
+        };
+
+        // a reused barcode can't be archived over the item it was taken from
+        let already: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM archived_items WHERE barcode = ?1)",
+            params![barcode],
+            |row| row.get(0),
+        )?;
+        if already {
+            return Ok(Err("Already archived".to_string()));
+        }
+
+        tx.execute(
+            &format!(
+                "INSERT INTO archived_items ({}) SELECT {}, ?2 FROM items WHERE id = ?1",
+                ArchivedItem::columns(),
+                item_columns()
+            ),
+            params![id, archived_at],
+        )?;
+        let archived_id = tx.last_insert_rowid();
+
+        if history {
+            tx.execute(
+                "INSERT INTO archived_maintenance
+                    (archived_id, recorded_at, type, description, recorded_by)
+                 SELECT ?1, recorded_at, type, description, recorded_by FROM maintenance
+                 WHERE item_id = ?2 ORDER BY id",
+                params![archived_id, id],
+            )?;
+            tx.execute(
+                "INSERT INTO archived_location_log
+                    (archived_id, moved_at, from_location, to_location)
+                 SELECT ?1, moved_at, from_location, to_location FROM location_log
+                 WHERE item_id = ?2 ORDER BY id",
+                params![archived_id, id],
+            )?;
+            tx.execute(
+                "INSERT INTO archived_item_history (archived_id, seen_at, location)
+                 SELECT ?1, seen_at, location FROM item_history WHERE item_id = ?2 ORDER BY id",
+                params![archived_id, id],
+            )?;
+            tx.execute(
+                "INSERT INTO archived_scan_log (archived_id, scanned_at, location)
+                 SELECT ?1, scanned_at, location FROM scan_log WHERE item_id = ?2 ORDER BY id",
+                params![archived_id, id],
+            )?;
+            tx.execute(
+                "INSERT INTO archived_notes (archived_id, noted_at, text)
+                 SELECT ?1, noted_at, text FROM item_notes WHERE item_id = ?2 ORDER BY id",
+                params![archived_id, id],
+            )?;
+        }
+
+        tx.execute("DELETE FROM items WHERE id = ?1", params![id])?;
+        let archived = tx.query_row(
+            &format!(
+                "SELECT {} FROM archived_items WHERE id = ?1",
+                ArchivedItem::columns()
+            ),
+            params![archived_id],
+            ArchivedItem::from_row,
+        )?;
+        tx.commit()?;
+        Ok(Ok(archived))
+    })?
+}
+
+/// bring an archived item back into the inventory, with any history archived with it
+///
+/// fails with "Barcode reused" if another item (or alias) has its barcode now
+pub fn unarchive_item(conn: &mut Connection, barcode: u64) -> Result<Item, String> {
+    use rusqlite::OptionalExtension;
+
+    let _timer = QueryTimer::start("unarchive_item");
+    with_retry(conn, |conn| {
+        let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        let archived_id: Option<i64> = tx
+            .query_row(
+                "SELECT id FROM archived_items WHERE barcode = ?1",
+                params![barcode],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(archived_id) = archived_id else {
+            return Ok(Err("Archived item not found".to_string()));
+        };
+
+        let reused: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM items WHERE barcode = ?1)
+                 OR EXISTS (SELECT 1 FROM item_aliases WHERE alias = ?1)",
+            params![barcode],
+            |row| row.get(0),
+        )?;
+        if reused {
+            return Ok(Err("Barcode reused".to_string()));
+        }
+
+        // repacked into its old case if that's still around
+        tx.execute(
+            "INSERT INTO items
+                (name, barcode, location, last_seen, version, status, purchase_date, value_pence,
+                 parent_id, quantity)
+             SELECT name, barcode, location, last_seen, version, status, purchase_date, value_pence,
+                 (SELECT parent.id FROM items AS parent
+                  WHERE parent.barcode = archived_items.parent_barcode),
+                 quantity
+             FROM archived_items WHERE id = ?1",
+            params![archived_id],
+        )?;
+        let id = tx.last_insert_rowid();
+
+        tx.execute(
+            "INSERT INTO maintenance (item_id, recorded_at, type, description, recorded_by)
+             SELECT ?1, recorded_at, type, description, recorded_by FROM archived_maintenance
+             WHERE archived_id = ?2 ORDER BY rowid",
+            params![id, archived_id],
+        )?;
+        tx.execute(
+            "INSERT INTO location_log (item_id, moved_at, from_location, to_location)
+             SELECT ?1, moved_at, from_location, to_location FROM archived_location_log
+             WHERE archived_id = ?2 ORDER BY rowid",
+            params![id, archived_id],
+        )?;
+        // the row the insert just added is already the newest archived one
+        tx.execute(
+            "DELETE FROM item_history WHERE item_id = ?1
+                AND EXISTS (SELECT 1 FROM archived_item_history WHERE archived_id = ?2)",
+            params![id, archived_id],
+        )?;
+        tx.execute(
+            "INSERT INTO item_history (item_id, seen_at, location)
+             SELECT ?1, seen_at, location FROM archived_item_history
+             WHERE archived_id = ?2 ORDER BY rowid",
+            params![id, archived_id],
+        )?;
+        tx.execute(
+            "INSERT INTO scan_log (item_id, scanned_at, location)
+             SELECT ?1, scanned_at, location FROM archived_scan_log
+             WHERE archived_id = ?2 ORDER BY rowid",
+            params![id, archived_id],
+        )?;
+        tx.execute(
+            "INSERT INTO item_notes (item_id, noted_at, text)
+             SELECT ?1, noted_at, text FROM archived_notes WHERE archived_id = ?2 ORDER BY rowid",
+            params![id, archived_id],
+        )?;
+        tx.execute(
+            "DELETE FROM archived_items WHERE id = ?1",
+            params![archived_id],
+        )?;
+
+        let item = tx.query_row(
+            &format!("SELECT {} FROM items WHERE id = ?1", item_columns()),
+            params![id],
+            Item::from_row,
+        )?;
+        tx.commit()?;
+        Ok(Ok(item))
+    })?
+}
+
+/// archived items, most recently archived first, `limit` at a time (all of them if `None`)
+/// after skipping `offset`, and how many there are in all
+pub fn load_archived(
+    conn: &Connection,
+    limit: Option<u64>,
+    offset: u64,
+) -> Result<(Vec<ArchivedItem>, u64), String> {
+    let _timer = QueryTimer::start("load_archived");
+    let total: u64 = conn
+        .query_row("SELECT COUNT(*) FROM archived_items", params![], |row| {
+            row.get(0)
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM archived_items ORDER BY archived_at DESC, id DESC LIMIT ?1 OFFSET ?2",
+            ArchivedItem::columns()
+        ))
+        .map_err(|e| e.to_string())?;
+    let archived = stmt
+        .query_map(
+            params![limit.map_or(-1, |limit| limit as i64), offset],
+            ArchivedItem::from_row,
+        )
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((archived, total))
+}
+
+/// update an item, bumping its version
+///
+/// if `expected_version` is given the update only happens when the stored version matches,
+/// otherwise it fails with "Version mismatch" so the client can refetch
+pub fn modify_item(
+    conn: &mut Connection,
+    item: Item,
+    expected_version: Option<u64>,
+) -> Result<(), String> {
+    let _timer = QueryTimer::start("modify_item");
+    let (rows_affected, exists) = with_retry(conn, |conn| {
+        let tx = conn.transaction()?;
+        let parent_id = match item.parent_barcode {
+            Some(parent) => match parent_id(&tx, item.barcode, parent)? {
+                Ok(id) => Some(id),
+                Err(err) => return Ok(Err(err)),
+            },
+            None => None,
+        };
+        let location = canonical_location(&tx, &item.location)?;
+        let rows_affected = tx.execute(
+            "UPDATE items SET name = ?1, location = ?2, last_seen = ?3, version = version + 1,
+                status = COALESCE(?6, status),
+                purchase_date = COALESCE(?7, purchase_date),
+                value_pence = COALESCE(?8, value_pence),
+                parent_id = COALESCE(?9, parent_id),
+                quantity = COALESCE(?10, quantity)
+             WHERE barcode = ?4 AND (?5 IS NULL OR version = ?5)",
+            params![
+                item.name,
+                location,
+                item.last_seen,
+                item.barcode,
+                expected_version,
+                item.status,
+                item.purchase_date,
+                item.value_pence,
+                parent_id,
+                item.quantity
+            ],
+        )?;
+
+        if rows_affected > 0 {
+            if let Some(note) = item.note() {
+                let id = tx.query_row(
+                    "SELECT id FROM items WHERE barcode = ?1",
+                    params![item.barcode],
+                    |row| row.get(0),
+                )?;
+                insert_note(&tx, id, note)?;
+            }
+            tx.commit()?;
+            return Ok(Ok((rows_affected, true)));
+        }
+
+        let exists = tx.query_row(
+            "SELECT COUNT(*) FROM items WHERE barcode = ?1",
+            params![item.barcode],
+            |row| row.get::<_, u64>(0),
+        )? > 0;
+        Ok(Ok((rows_affected, exists)))
+    })??;
+
+    if rows_affected == 0 {
+        return Err(if exists && expected_version.is_some() {
+            "Version mismatch".to_string()
         } else {
-            hyper::StatusCode::INTERNAL_SERVER_ERROR
+            "Item not found".to_string()
+        });
+    }
+
+    Ok(())
+}
+
+/// the id of item `parent`, checked to be a valid parent for item `child`
+///
+/// the inner error is "Parent not found", or "Parent cycle" if `parent` is `child`
+/// or already packed (however deeply) inside it
+fn parent_id(conn: &Connection, child: u64, parent: u64) -> rusqlite::Result<Result<i64, String>> {
+    use rusqlite::OptionalExtension;
+
+    let id: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM items WHERE barcode = ?1",
+            params![parent],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(id) = id else {
+        return Ok(Err("Parent not found".to_string()));
+    };
+
+    // walk up from the new parent; meeting the child means the chain would loop
+    let cycle: bool = conn.query_row(
+        "WITH RECURSIVE chain(id) AS (
+            SELECT ?1
+            UNION
+            SELECT items.parent_id FROM items JOIN chain ON items.id = chain.id
+            WHERE items.parent_id IS NOT NULL
+        )
+        SELECT EXISTS (SELECT 1 FROM chain JOIN items ON items.id = chain.id WHERE items.barcode = ?2)",
+        params![id, child],
+        |row| row.get(0),
+    )?;
+    if cycle {
+        return Ok(Err("Parent cycle".to_string()));
+    }
+    Ok(Ok(id))
+}
+
+/// pack an item inside another (or unpack it with `None`), bumping its version
+pub fn set_parent(conn: &mut Connection, barcode: u64, parent: Option<u64>) -> Result<(), String> {
+    let _timer = QueryTimer::start("set_parent");
+    let rows_affected = with_retry(conn, |conn| {
+        let tx = conn.transaction()?;
+        let parent_id = match parent {
+            Some(parent) => match parent_id(&tx, barcode, parent)? {
+                Ok(id) => Some(id),
+                Err(err) => return Ok(Err(err)),
+            },
+            None => None,
+        };
+        let rows_affected = tx.execute(
+            "UPDATE items SET parent_id = ?1, version = version + 1 WHERE barcode = ?2",
+            params![parent_id, barcode],
+        )?;
+        tx.commit()?;
+        Ok(Ok(rows_affected))
+    })??;
+
+    if rows_affected == 0 {
+        return Err("Item not found".to_string());
+    }
+    Ok(())
+}
+
+/// add `delta` units to an item's quantity (take them away if it's negative), stopping at zero,
+/// and return the new quantity
+pub fn adjust_quantity(conn: &mut Connection, barcode: u64, delta: i64) -> Result<u64, String> {
+    use rusqlite::OptionalExtension;
+
+    let _timer = QueryTimer::start("adjust_quantity");
+    let quantity = with_retry(conn, |conn| {
+        let tx = conn.transaction()?;
+        tx.execute(
+            "UPDATE items SET quantity = MAX(0, quantity + ?2), version = version + 1
+             WHERE barcode = ?1",
+            params![barcode, delta],
+        )?;
+        let quantity = tx
+            .query_row(
+                "SELECT quantity FROM items WHERE barcode = ?1",
+                params![barcode],
+                |row| row.get(0),
+            )
+            .optional()?;
+        tx.commit()?;
+        Ok(quantity)
+    })?;
+
+    quantity.ok_or_else(|| "Item not found".to_string())
+}
+
+/// the barcode of the item a scanned barcode is an alias of, or `None` if it isn't an alias
+pub fn resolve_alias(conn: &Connection, scanned: u64) -> Result<Option<u64>, String> {
+    use rusqlite::OptionalExtension;
+
+    let _timer = QueryTimer::start("resolve_alias");
+    conn.query_row(
+        "SELECT items.barcode FROM item_aliases JOIN items ON items.id = item_aliases.item_id
+         WHERE item_aliases.alias = ?1",
+        params![scanned],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+/// why `barcode` can't be given to a new item: an item or an alias has it, or it belonged to an
+/// archived item and BARCODE_REUSE_ARCHIVED=false; `None` if it's free
+pub fn barcode_unavailable(conn: &Connection, barcode: u64) -> Result<Option<String>, String> {
+    let _timer = QueryTimer::start("barcode_unavailable");
+    let (used, alias_of, archived): (bool, Option<u64>, bool) = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM items WHERE barcode = ?1),
+                (SELECT items.barcode FROM item_aliases JOIN items ON items.id = item_aliases.item_id
+                 WHERE item_aliases.alias = ?1),
+                EXISTS (SELECT 1 FROM archived_items WHERE barcode = ?1)",
+            params![barcode],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .map_err(|e| e.to_string())?;
+
+    Ok(if used {
+        Some("Item already exists".to_string())
+    } else if let Some(item) = alias_of {
+        Some(format!("Barcode is an alias of {}", item))
+    } else if archived && !reuse_archived_barcodes() {
+        Some("Barcode is archived".to_string())
+    } else {
+        None
+    })
+}
+
+/// the largest barcode there can be, since SQLite integers are signed 64-bit
+const MAX_BARCODE: u64 = i64::MAX as u64;
+
+/// a barcode from a request path, telling "not a number" apart from "too large" in the error
+fn path_barcode(segment: &str) -> Result<u64, String> {
+    let too_large = || {
+        format!(
+            "Invalid barcode: too large, barcodes go up to {}",
+            MAX_BARCODE
+        )
+    };
+    match segment.parse::<u64>() {
+        Ok(barcode) if barcode <= MAX_BARCODE => Ok(barcode),
+        Ok(_) => Err(too_large()),
+        Err(e) if *e.kind() == std::num::IntErrorKind::PosOverflow => Err(too_large()),
+        Err(_) => Err("Invalid barcode: not a number".to_string()),
+    }
+}
+
+/// the item a scanned barcode means, and the alias it was scanned by if it wasn't the item's own barcode
+///
+/// barcodes that aren't numbers can't be aliases, so they're passed through for the caller to reject
+fn canonical_barcode(conn: &Connection, scanned: &str) -> Result<(String, Option<u64>), String> {
+    match scanned.parse::<u64>() {
+        Ok(alias) => Ok(match resolve_alias(conn, alias)? {
+            Some(barcode) => (barcode.to_string(), Some(alias)),
+            None => (scanned.to_string(), None),
+        }),
+        Err(_) => Ok((scanned.to_string(), None)),
+    }
+}
+
+/// an item's alternate barcodes
+pub fn load_aliases(conn: &Connection, barcode: u64) -> Result<Vec<u64>, String> {
+    let _timer = QueryTimer::start("load_aliases");
+    let id = item_id(conn, barcode)?;
+    let mut stmt = conn
+        .prepare("SELECT alias FROM item_aliases WHERE item_id = ?1 ORDER BY alias")
+        .map_err(|e| e.to_string())?;
+    let aliases = stmt
+        .query_map(params![id], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(aliases)
+}
+
+/// give an item another barcode it can be scanned by
+///
+/// fails with "Item not found", or with a UNIQUE constraint error if the alias is already
+/// an alias or an item's own barcode (the schema's triggers check both tables)
+pub fn add_alias(conn: &mut Connection, barcode: u64, alias: u64) -> Result<(), String> {
+    let _timer = QueryTimer::start("add_alias");
+    let rows_affected = with_retry(conn, |conn| {
+        conn.execute(
+            "INSERT INTO item_aliases (alias, item_id) SELECT ?2, id FROM items WHERE barcode = ?1",
+            params![barcode, alias],
+        )
+    })?;
+    if rows_affected == 0 {
+        return Err("Item not found".to_string());
+    }
+    Ok(())
+}
+
+/// stop an item being scanned by an alias
+pub fn remove_alias(conn: &mut Connection, barcode: u64, alias: u64) -> Result<(), String> {
+    let _timer = QueryTimer::start("remove_alias");
+    let rows_affected = with_retry(conn, |conn| {
+        conn.execute(
+            "DELETE FROM item_aliases
+             WHERE alias = ?2 AND item_id = (SELECT id FROM items WHERE barcode = ?1)",
+            params![barcode, alias],
+        )
+    })?;
+    if rows_affected == 0 {
+        return Err("Alias not found".to_string());
+    }
+    Ok(())
+}
+
+/// one change of an item's location, recorded by the `location_log` trigger
+#[derive(Debug, Clone, Serialize)]
+pub struct Move {
+    moved_at: u64,
+    from: String,
+    to: String,
+}
+
+/// move an item to a location, seeing it there; with `cascade` everything packed inside it
+/// (however deeply) moves with it, all in one transaction
+///
+/// returns how many items moved
+pub fn move_item(
+    conn: &mut Connection,
+    barcode: u64,
+    location: &str,
+    cascade: bool,
+) -> Result<usize, String> {
+    let _timer = QueryTimer::start("move_item");
+    let rows_affected = with_retry(conn, |conn| {
+        let tx = conn.transaction()?;
+        let location = canonical_location(&tx, location)?;
+        let rows_affected = tx.execute(
+            "WITH RECURSIVE tree(id) AS (
+                SELECT id FROM items WHERE barcode = ?3
+                UNION
+                SELECT items.id FROM items JOIN tree ON items.parent_id = tree.id WHERE ?4
+            )
+            UPDATE items SET location = ?1, last_seen = ?2, version = version + 1
+            WHERE id IN tree",
+            params![location, Utc::now().timestamp() as u64, barcode, cascade],
+        )?;
+        tx.commit()?;
+        Ok(rows_affected)
+    })?;
+
+    if rows_affected == 0 {
+        return Err("Item not found".to_string());
+    }
+    Ok(rows_affected)
+}
+
+/// an item's most recent moves, newest first
+pub fn load_trail(conn: &Connection, barcode: u64, limit: u64) -> Result<Vec<Move>, String> {
+    let _timer = QueryTimer::start("load_trail");
+    let id = item_id(conn, barcode)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT moved_at, from_location, to_location FROM location_log
+             WHERE item_id = ?1 ORDER BY moved_at DESC, id DESC LIMIT ?2",
+        )
+        .map_err(|e| e.to_string())?;
+    let trail = stmt
+        .query_map(params![id, limit], |row| {
+            Ok(Move {
+                moved_at: row.get(0)?,
+                from: row.get(1)?,
+                to: row.get(2)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(trail)
+}
+
+/// a time an item was added, seen or moved, recorded by the `item_history` triggers
+#[derive(Debug, Clone, Serialize)]
+pub struct Sighting {
+    id: i64,
+    seen_at: u64,
+    location: String,
+}
+
+/// where a page of history starts: before a time (unix seconds), or with the id of the oldest
+/// sighting on the last page too, after that sighting, since several can share a second
+pub type HistoryCursor = (u64, Option<i64>);
+
+/// an item's history, newest first; with `before` only what's older than that
+pub fn load_history(
+    conn: &Connection,
+    barcode: u64,
+    limit: u64,
+    before: Option<HistoryCursor>,
+) -> Result<Vec<Sighting>, String> {
+    let _timer = QueryTimer::start("load_history");
+    let id = item_id(conn, barcode)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, seen_at, location FROM item_history
+             WHERE item_id = ?1
+                AND (?3 IS NULL OR seen_at < ?3 OR (seen_at = ?3 AND id < ?4))
+             ORDER BY seen_at DESC, id DESC LIMIT ?2",
+        )
+        .map_err(|e| e.to_string())?;
+    let (before, before_id) = before.unzip();
+    let history = stmt
+        .query_map(params![id, limit, before, before_id.flatten()], |row| {
+            Ok(Sighting {
+                id: row.get(0)?,
+                seen_at: row.get(1)?,
+                location: row.get(2)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(history)
+}
+
+/// the longest a reservation can be, in days, from BARCODE_MAX_RESERVATION_DAYS (default 90)
+fn max_reservation_days() -> u64 {
+    static MAX_RESERVATION_DAYS: std::sync::OnceLock<u64> = std::sync::OnceLock::new();
+
+    *MAX_RESERVATION_DAYS.get_or_init(|| match env::var("BARCODE_MAX_RESERVATION_DAYS") {
+        Ok(days) => match days.parse::<u64>() {
+            Ok(days) if days > 0 => days,
+            _ => {
+                warn!("Invalid BARCODE_MAX_RESERVATION_DAYS: {}, using 90", days);
+                90
+            }
+        },
+        Err(_) => 90,
+    })
+}
+
+/// whether an item can be booked twice at once, from BARCODE_ALLOW_DOUBLE_BOOKING (default false);
+/// `/reservations/conflicts` lists any double bookings this lets through
+fn allow_double_booking() -> bool {
+    static ALLOW_DOUBLE_BOOKING: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+    *ALLOW_DOUBLE_BOOKING
+        .get_or_init(|| env::var("BARCODE_ALLOW_DOUBLE_BOOKING").is_ok_and(|allow| allow == "true"))
+}
+
+/// whether two reservations overlap; each runs from its start up to but not including its end,
+/// so one ending as another starts doesn't count
+fn overlaps(a: (u64, u64), b: (u64, u64)) -> bool {
+    a.0 < b.1 && b.0 < a.1
+}
+
+/// a reservation as posted to `/item/{barcode}/reservations`, times in unix seconds
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewReservation {
+    starts_at: u64,
+    ends_at: u64,
+    reserved_by: String,
+    note: Option<String>,
+}
+
+impl NewReservation {
+    /// why the reservation can't be made, if it can't
+    fn validate(&self) -> Result<(), String> {
+        if self.ends_at <= self.starts_at {
+            return Err("ends_at must be after starts_at".to_string());
+        }
+        if self.ends_at - self.starts_at > max_reservation_days().saturating_mul(24 * 60 * 60) {
+            return Err(format!(
+                "reservations can't be longer than {} days",
+                max_reservation_days()
+            ));
+        }
+        if self.reserved_by.trim().is_empty() {
+            return Err("reserved_by can't be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// a booking of an item for a time range
+#[derive(Debug, Clone, Serialize)]
+pub struct Reservation {
+    id: i64,
+    barcode: u64,
+    name: String,
+    starts_at: u64,
+    ends_at: u64,
+    reserved_by: String,
+    note: Option<String>,
+}
+
+/// the select list `Reservation::from_row` expects, for `FROM reservations JOIN items`
+const RESERVATION_COLUMNS: &str =
+    "reservations.id, items.barcode, items.name, reservations.starts_at,
+    reservations.ends_at, reservations.reserved_by, reservations.note";
+
+impl Reservation {
+    /// a reservation from `RESERVATION_COLUMNS`, starting at column `first`
+    fn from_row(row: &rusqlite::Row, first: usize) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(first)?,
+            barcode: row.get(first + 1)?,
+            name: row.get(first + 2)?,
+            starts_at: row.get(first + 3)?,
+            ends_at: row.get(first + 4)?,
+            reserved_by: row.get(first + 5)?,
+            note: row.get(first + 6)?,
+        })
+    }
+
+    fn sanitize(&mut self) {
+        self.name = sanitize(&self.name);
+        self.reserved_by = sanitize(&self.reserved_by);
+        self.note = self.note.as_deref().map(sanitize);
+    }
+}
+
+/// book an item, unless that would double-book it
+///
+/// the inner error is the existing reservation it clashes with; the check and the booking
+/// share one write transaction, so two clashing requests can't both get in
+pub fn add_reservation(
+    conn: &mut Connection,
+    barcode: u64,
+    new: &NewReservation,
+) -> Result<Result<Reservation, Reservation>, String> {
+    let _timer = QueryTimer::start("add_reservation");
+    with_retry(conn, |conn| {
+        let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+        let id = match item_id(&tx, barcode) {
+            Ok(id) => id,
+            Err(err) => return Ok(Err(err)),
+        };
+
+        if !allow_double_booking() {
+            // only reservations still running when this one starts can clash
+            let mut stmt = tx.prepare(&format!(
+                "SELECT {} FROM reservations JOIN items ON items.id = reservations.item_id
+                 WHERE reservations.item_id = ?1 AND reservations.ends_at > ?2
+                 ORDER BY reservations.starts_at",
+                RESERVATION_COLUMNS
+            ))?;
+            let clash = stmt
+                .query_map(params![id, new.starts_at], |row| {
+                    Reservation::from_row(row, 0)
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?
+                .into_iter()
+                .find(|existing| {
+                    overlaps(
+                        (existing.starts_at, existing.ends_at),
+                        (new.starts_at, new.ends_at),
+                    )
+                });
+            if let Some(clash) = clash {
+                return Ok(Ok(Err(clash)));
+            }
+        }
+
+        tx.execute(
+            "INSERT INTO reservations (item_id, starts_at, ends_at, reserved_by, note)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                id,
+                new.starts_at,
+                new.ends_at,
+                new.reserved_by.trim(),
+                new.note
+            ],
+        )?;
+        let reservation = tx.query_row(
+            &format!(
+                "SELECT {} FROM reservations JOIN items ON items.id = reservations.item_id
+                 WHERE reservations.id = ?1",
+                RESERVATION_COLUMNS
+            ),
+            params![tx.last_insert_rowid()],
+            |row| Reservation::from_row(row, 0),
+        )?;
+        tx.commit()?;
+        Ok(Ok(Ok(reservation)))
+    })?
+}
+
+/// cancel one of an item's reservations
+pub fn cancel_reservation(conn: &mut Connection, barcode: u64, id: i64) -> Result<(), String> {
+    let _timer = QueryTimer::start("cancel_reservation");
+    let rows_affected = with_retry(conn, |conn| {
+        conn.execute(
+            "DELETE FROM reservations
+             WHERE id = ?2 AND item_id = (SELECT id FROM items WHERE barcode = ?1)",
+            params![barcode, id],
+        )
+    })?;
+    if rows_affected == 0 {
+        return Err("Reservation not found".to_string());
+    }
+    Ok(())
+}
+
+/// reservations matching an SQL condition on `reservations` and `items`, in start order
+fn load_reservations_where(
+    conn: &Connection,
+    condition: &str,
+    params: &[&dyn rusqlite::ToSql],
+) -> Result<Vec<Reservation>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM reservations JOIN items ON items.id = reservations.item_id
+             WHERE {} ORDER BY reservations.starts_at, reservations.id",
+            RESERVATION_COLUMNS, condition
+        ))
+        .map_err(|e| e.to_string())?;
+    let reservations = stmt
+        .query_map(params, |row| Reservation::from_row(row, 0))
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(reservations)
+}
+
+/// every reservation overlapping `from` up to `to`, for a calendar
+pub fn load_reservations(
+    conn: &Connection,
+    from: u64,
+    to: u64,
+) -> Result<Vec<Reservation>, String> {
+    let _timer = QueryTimer::start("load_reservations");
+    load_reservations_where(
+        conn,
+        "reservations.starts_at < ?2 AND ?1 < reservations.ends_at",
+        &[&from, &to],
+    )
+}
+
+/// an item's reservations that haven't finished yet
+pub fn item_reservations(conn: &Connection, barcode: u64) -> Result<Vec<Reservation>, String> {
+    let _timer = QueryTimer::start("item_reservations");
+    item_id(conn, barcode)?;
+    load_reservations_where(
+        conn,
+        "items.barcode = ?1 AND reservations.ends_at > ?2",
+        &[&barcode, &(Utc::now().timestamp() as u64)],
+    )
+}
+
+/// the reservation an item is out on right now, if any
+pub fn active_reservation(conn: &Connection, barcode: u64) -> Result<Option<Reservation>, String> {
+    let _timer = QueryTimer::start("active_reservation");
+    let now = Utc::now().timestamp() as u64;
+    Ok(load_reservations_where(
+        conn,
+        "items.barcode = ?1 AND reservations.starts_at <= ?2 AND ?2 < reservations.ends_at",
+        &[&barcode, &now],
+    )?
+    .into_iter()
+    .next())
+}
+
+/// pairs of reservations double-booking the same item
+pub fn reservation_conflicts(conn: &Connection) -> Result<Vec<(Reservation, Reservation)>, String> {
+    let _timer = QueryTimer::start("reservation_conflicts");
+    let other_columns = RESERVATION_COLUMNS.replace("reservations.", "other.");
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {}, {} FROM reservations
+             JOIN reservations AS other ON other.item_id = reservations.item_id
+                AND other.id > reservations.id
+                AND reservations.starts_at < other.ends_at AND other.starts_at < reservations.ends_at
+             JOIN items ON items.id = reservations.item_id
+             ORDER BY reservations.starts_at, reservations.id, other.id",
+            RESERVATION_COLUMNS, other_columns
+        ))
+        .map_err(|e| e.to_string())?;
+    let conflicts = stmt
+        .query_map(params![], |row| {
+            Ok((
+                Reservation::from_row(row, 0)?,
+                Reservation::from_row(row, 7)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(conflicts)
+}
+
+/// the items packed directly inside an item
+pub fn load_children(conn: &Connection, barcode: u64) -> Result<Vec<Item>, String> {
+    let _timer = QueryTimer::start("load_children");
+    let id = item_id(conn, barcode)?;
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM items WHERE parent_id = ?1 ORDER BY barcode",
+            item_columns()
+        ))
+        .map_err(|e| e.to_string())?;
+    let items = stmt
+        .query_map(params![id], Item::from_row)
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(items)
+}
+
+/// delete every item in one transaction, returning how many were removed
+pub fn reset_items(conn: &mut Connection) -> Result<usize, String> {
+    let _timer = QueryTimer::start("reset_items");
+    with_retry(conn, |conn| {
+        let tx = conn.transaction()?;
+        let rows_affected = tx.execute("DELETE FROM items", params![])?;
+        tx.commit()?;
+        Ok(rows_affected)
+    })
+}
+
+/// update an item's last_seen timestamp to now, recording the scan in `scan_log`
+///
+/// with `cascade`, everything packed inside it (however deeply) is seen too, in the same transaction
+pub fn touch_item(conn: &mut Connection, barcode: &str, cascade: bool) -> Result<(), String> {
+    let _timer = QueryTimer::start("touch_item");
+    // the item, and with ?3 everything inside it
+    const SEEN: &str = "WITH RECURSIVE tree(id) AS (
+        SELECT id FROM items WHERE barcode = ?2
+        UNION
+        SELECT items.id FROM items JOIN tree ON items.parent_id = tree.id WHERE ?3
+    )";
+    let now = Utc::now().timestamp() as u64;
+    let rows_affected = with_retry(conn, |conn| {
+        let tx = conn.transaction()?;
+        let rows_affected = tx.execute(
+            &format!("{} UPDATE items SET last_seen = ?1 WHERE id IN tree", SEEN),
+            params![now, barcode, cascade],
+        )?;
+        tx.execute(
+            &format!(
+                "{} INSERT INTO scan_log (item_id, scanned_at, location)
+                    SELECT id, ?1, location FROM items WHERE id IN tree",
+                SEEN
+            ),
+            params![now, barcode, cascade],
+        )?;
+        tx.commit()?;
+        Ok(rows_affected)
+    })?;
+
+    if rows_affected == 0 {
+        return Err("Item not found".to_string());
+    }
+
+    Ok(())
+}
+
+/// one time an item was scanned, from `scan_log`
+#[derive(Debug, Clone, Serialize)]
+pub struct Scan {
+    scanned_at: u64,
+    location: String,
+}
+
+/// an item's scans, oldest first, a page at a time
+pub fn load_scans(
+    conn: &Connection,
+    barcode: u64,
+    limit: Option<u64>,
+    offset: u64,
+) -> Result<Vec<Scan>, String> {
+    let _timer = QueryTimer::start("load_scans");
+    let id = item_id(conn, barcode)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT scanned_at, location FROM scan_log WHERE item_id = ?1
+             ORDER BY scanned_at, id LIMIT ?2 OFFSET ?3",
+        )
+        .map_err(|e| e.to_string())?;
+    let scans = stmt
+        .query_map(
+            params![id, limit.map_or(-1, |limit| limit as i64), offset],
+            |row| {
+                Ok(Scan {
+                    scanned_at: row.get(0)?,
+                    location: row.get(1)?,
+                })
+            },
+        )
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(scans)
+}
+
+/// what an import did (or, for a dry run, would do)
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    created: usize,
+    updated: usize,
+    /// unchanged rows and rows with errors
+    skipped: usize,
+    errors: Vec<ImportRowError>,
+    dry_run: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportRowError {
+    /// 1-based, counting the header as row 1
+    row: usize,
+    /// every violation's message in one line
+    error: String,
+    errors: Vec<Violation>,
+}
+
+/// split CSV text into rows of fields, handling quoted fields with `""` escapes and embedded newlines
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    rows
+}
+
+/// the message of a failed constraint (such as a barcode that's already an alias), or `err`
+/// back for anything else
+fn constraint_failure(err: rusqlite::Error) -> Result<String, rusqlite::Error> {
+    match err {
+        rusqlite::Error::SqliteFailure(e, message)
+            if e.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            Ok(message.unwrap_or_else(|| e.to_string()))
+        }
+        err => Err(err),
+    }
+}
+
+/// import items from CSV with a header row naming (in any order) name, barcode, location and
+/// optionally last_seen: new barcodes are created, changed ones updated, the rest skipped
+///
+/// the whole import is one transaction, which a dry run rolls back
+pub fn import_items(
+    conn: &mut Connection,
+    csv: &str,
+    dry_run: bool,
+) -> Result<ImportReport, String> {
+    use rusqlite::OptionalExtension;
+
+    let _timer = QueryTimer::start("import_items");
+    let rows = parse_csv(csv);
+    let (header, rows) = rows
+        .split_first()
+        .ok_or_else(|| "Invalid CSV: no header row".to_string())?;
+
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|field| field.trim().eq_ignore_ascii_case(name))
+    };
+    // a location column is only needed when the field policy requires locations
+    let location_required = field_policy().contains(&("location", FieldPolicy::Required));
+    let (name_col, barcode_col, location_col, last_seen_col) = match (
+        column("name"),
+        column("barcode"),
+        column("location"),
+        column("last_seen").or_else(|| column("last-seen")),
+    ) {
+        (Some(name), Some(barcode), location, last_seen)
+            if location.is_some() || !location_required =>
+        {
+            (name, barcode, location, last_seen)
+        }
+        _ => {
+            return Err(
+                "Invalid CSV: the header must name the name, barcode and location columns"
+                    .to_string(),
+            );
+        }
+    };
+
+    // every problem with a row at once, so a bad file takes one round of fixes
+    let parse_row = |row: &[String]| -> Result<(String, u64, String, Option<u64>), Vec<Violation>> {
+        let field = |col: usize| row.get(col).map(|field| field.trim()).unwrap_or("");
+        let mut violations = Vec::new();
+
+        let barcode = match path_barcode(field(barcode_col)) {
+            Ok(barcode) => Some(barcode),
+            Err(err) if err.contains("too large") => {
+                violations.push(Violation::new(
+                    "barcode",
+                    "too_large",
+                    format!(
+                        "barcode {} is too large, barcodes go up to {}",
+                        field(barcode_col),
+                        MAX_BARCODE
+                    ),
+                ));
+                None
+            }
+            Err(_) => {
+                violations.push(Violation::new(
+                    "barcode",
+                    "invalid",
+                    format!("invalid barcode \"{}\"", field(barcode_col)),
+                ));
+                None
+            }
+        };
+        let (name, location) = (field(name_col), location_col.map(field).unwrap_or(""));
+        if name.is_empty() {
+            violations.push(Violation::new("name", "empty", "name can't be empty"));
+        }
+        let item = Item::new(name.to_string(), barcode.unwrap_or(0), location.to_string());
+        violations.extend(policy_violations(&item, field_policy()));
+        let last_seen = match last_seen_col.map(field) {
+            Some(last_seen) if !last_seen.is_empty() => {
+                let parsed = last_seen.parse::<u64>().ok();
+                if parsed.is_none() {
+                    violations.push(Violation::new(
+                        "last_seen",
+                        "invalid",
+                        format!("invalid last_seen \"{}\"", last_seen),
+                    ));
+                }
+                parsed
+            }
+            _ => None,
+        };
+
+        match barcode {
+            // stored as /new and /modify would store it, so re-importing the same file finds
+            // nothing changed
+            Some(barcode) if violations.is_empty() => {
+                Ok((sanitize(name), barcode, sanitize(location), last_seen))
+            }
+            _ => Err(violations),
+        }
+    };
+
+    with_retry(conn, |conn| {
+        let tx = conn.transaction()?;
+        let mut report = ImportReport {
+            dry_run,
+            ..Default::default()
+        };
+
+        for (i, row) in rows.iter().enumerate() {
+            if row.iter().all(|field| field.trim().is_empty()) {
+                continue; // blank line
+            }
+
+            let (name, barcode, location, last_seen) = match parse_row(row) {
+                Ok(parsed) => parsed,
+                Err(errors) => {
+                    report.skipped += 1;
+                    report.errors.push(ImportRowError {
+                        row: i + 2,
+                        error: errors
+                            .iter()
+                            .map(|violation| violation.message.as_str())
+                            .collect::<Vec<_>>()
+                            .join(", "),
+                        errors,
+                    });
+                    continue;
+                }
+            };
+
+            let location = canonical_location(&tx, &location)?;
+            let existing: Option<(String, String, u64)> = tx
+                .query_row(
+                    "SELECT name, location, last_seen FROM items WHERE barcode = ?1",
+                    params![barcode],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .optional()?;
+
+            match existing {
+                None => {
+                    if let Err(error) =
+                        check_archived_barcode(&tx, barcode, reuse_archived_barcodes())?
+                    {
+                        report.skipped += 1;
+                        report.errors.push(ImportRowError {
+                            row: i + 2,
+                            errors: vec![Violation::new("barcode", "archived", error.clone())],
+                            error,
+                        });
+                        continue;
+                    }
+                    match tx.execute(
+                        "INSERT INTO items (name, barcode, location, last_seen, version) VALUES (?1, ?2, ?3, ?4, 1)",
+                        params![
+                            name,
+                            barcode,
+                            location,
+                            last_seen.unwrap_or(Utc::now().timestamp() as u64)
+                        ],
+                    ) {
+                        Ok(_) => report.created += 1,
+                        Err(e) => {
+                            // only this row is refused, the statement having been undone alone
+                            let error = constraint_failure(e)?;
+                            report.skipped += 1;
+                            report.errors.push(ImportRowError {
+                                row: i + 2,
+                                errors: vec![Violation::new("barcode", "conflict", error.clone())],
+                                error,
+                            });
+                        }
+                    }
+                }
+                Some(existing)
+                    if existing.0 == name
+                        && existing.1 == location
+                        && last_seen.is_none_or(|last_seen| last_seen == existing.2) =>
+                {
+                    report.skipped += 1;
+                }
+                Some(_) => {
+                    tx.execute(
+                        "UPDATE items SET name = ?1, location = ?2, last_seen = COALESCE(?3, last_seen),
+                         version = version + 1 WHERE barcode = ?4",
+                        params![name, location, last_seen, barcode],
+                    )?;
+                    report.updated += 1;
+                }
+            }
+        }
+
+        if dry_run {
+            tx.rollback()?;
+        } else {
+            tx.commit()?;
+        }
+
+        Ok(report)
+    })
+}
+
+/// longest maintenance description accepted, in characters
+const MAX_MAINTENANCE_DESCRIPTION: usize = 2000;
+
+/// kinds of maintenance that can be recorded, from BARCODE_MAINTENANCE_TYPES
+/// (comma separated, default `pat,repair,inspection,service`)
+fn maintenance_types() -> &'static [String] {
+    static MAINTENANCE_TYPES: std::sync::OnceLock<Vec<String>> = std::sync::OnceLock::new();
+
+    MAINTENANCE_TYPES.get_or_init(|| {
+        env::var("BARCODE_MAINTENANCE_TYPES")
+            .unwrap_or_else(|_| "pat,repair,inspection,service".to_string())
+            .split(',')
+            .map(|kind| kind.trim().to_lowercase())
+            .filter(|kind| !kind.is_empty())
+            .collect()
+    })
+}
+
+/// a maintenance entry as posted to `/item/{barcode}/maintenance`
+#[derive(Debug, Deserialize)]
+pub struct NewMaintenance {
+    #[serde(rename = "type")]
+    kind: String,
+    description: String,
+    recorded_by: Option<String>,
+}
+
+impl NewMaintenance {
+    /// why the entry can't be recorded, if it can't
+    fn validate(&self) -> Result<(), String> {
+        if !maintenance_types().contains(&self.kind.to_lowercase()) {
+            return Err(format!(
+                "Unknown maintenance type {}, expected one of {}",
+                sanitize(&self.kind),
+                maintenance_types().join(", ")
+            ));
+        }
+        if self.description.trim().is_empty() {
+            return Err("description can't be empty".to_string());
+        }
+        if self.description.chars().count() > MAX_MAINTENANCE_DESCRIPTION {
+            return Err(format!(
+                "description is longer than {} characters",
+                MAX_MAINTENANCE_DESCRIPTION
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// a recorded maintenance entry, which never changes once written
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceEntry {
+    id: i64,
+    recorded_at: u64,
+    #[serde(rename = "type")]
+    kind: String,
+    description: String,
+    recorded_by: Option<String>,
+}
+
+impl MaintenanceEntry {
+    fn sanitize(&mut self) {
+        self.description = sanitize(&self.description);
+        self.recorded_by = self.recorded_by.as_deref().map(sanitize);
+    }
+}
+
+/// longest note accepted, in characters
+const MAX_NOTE_LENGTH: usize = 2000;
+
+/// a note as posted to `/note/{barcode}`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewNote {
+    text: String,
+}
+
+/// why a note can't be added, if it can't
+fn invalid_note(text: &str) -> Option<String> {
+    if text.trim().is_empty() {
+        return Some("text can't be empty".to_string());
+    }
+    if text.chars().count() > MAX_NOTE_LENGTH {
+        return Some(format!("notes are at most {} characters", MAX_NOTE_LENGTH));
+    }
+    None
+}
+
+/// a note left on an item; notes are only ever added, so earlier ones are never lost
+#[derive(Debug, Clone, Serialize)]
+pub struct Note {
+    id: i64,
+    noted_at: u64,
+    text: String,
+}
+
+impl Note {
+    fn sanitize(&mut self) {
+        self.text = sanitize(&self.text);
+    }
+}
+
+/// append a note to the item with id `item_id`, dated now
+fn insert_note(conn: &Connection, item_id: i64, text: &str) -> rusqlite::Result<Note> {
+    let noted_at = Utc::now().timestamp() as u64;
+    conn.execute(
+        "INSERT INTO item_notes (item_id, noted_at, text) VALUES (?1, ?2, ?3)",
+        params![item_id, noted_at, text.trim()],
+    )?;
+    Ok(Note {
+        id: conn.last_insert_rowid(),
+        noted_at,
+        text: text.trim().to_string(),
+    })
+}
+
+/// append a note to an item
+pub fn add_note(conn: &mut Connection, barcode: u64, text: &str) -> Result<Note, String> {
+    let _timer = QueryTimer::start("add_note");
+    with_retry(conn, |conn| {
+        let id = match item_id(conn, barcode) {
+            Ok(id) => id,
+            Err(err) => return Ok(Err(err)),
+        };
+        insert_note(conn, id, text).map(Ok)
+    })?
+}
+
+/// an item's notes, newest first
+pub fn load_notes(conn: &Connection, barcode: u64) -> Result<Vec<Note>, String> {
+    let _timer = QueryTimer::start("load_notes");
+    let id = item_id(conn, barcode)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, noted_at, text FROM item_notes
+             WHERE item_id = ?1 ORDER BY noted_at DESC, id DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let notes = stmt
+        .query_map(params![id], |row| {
+            Ok(Note {
+                id: row.get(0)?,
+                noted_at: row.get(1)?,
+                text: row.get(2)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(notes)
+}
+
+/// the id of the item with a barcode, or "Item not found"
+fn item_id(conn: &Connection, barcode: u64) -> Result<i64, String> {
+    use rusqlite::OptionalExtension;
+
+    conn.query_row(
+        "SELECT id FROM items WHERE barcode = ?1",
+        params![barcode],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "Item not found".to_string())
+}
+
+/// append a maintenance entry to an item's log, dated now
+pub fn add_maintenance(
+    conn: &mut Connection,
+    barcode: u64,
+    entry: &NewMaintenance,
+) -> Result<MaintenanceEntry, String> {
+    let _timer = QueryTimer::start("add_maintenance");
+    let recorded_at = Utc::now().timestamp() as u64;
+    let kind = entry.kind.to_lowercase();
+
+    let id = with_retry(conn, |conn| {
+        conn.execute(
+            "INSERT INTO maintenance (item_id, recorded_at, type, description, recorded_by)
+             SELECT id, ?2, ?3, ?4, ?5 FROM items WHERE barcode = ?1",
+            params![
+                barcode,
+                recorded_at,
+                kind,
+                entry.description.trim(),
+                entry.recorded_by
+            ],
+        )
+        .map(|inserted| (inserted > 0).then(|| conn.last_insert_rowid()))
+    })?
+    .ok_or_else(|| "Item not found".to_string())?;
+
+    Ok(MaintenanceEntry {
+        id,
+        recorded_at,
+        kind,
+        description: entry.description.trim().to_string(),
+        recorded_by: entry.recorded_by.clone(),
+    })
+}
+
+/// an item's maintenance log, newest first
+pub fn load_maintenance(conn: &Connection, barcode: u64) -> Result<Vec<MaintenanceEntry>, String> {
+    let _timer = QueryTimer::start("load_maintenance");
+    let id = item_id(conn, barcode)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, recorded_at, type, description, recorded_by FROM maintenance
+             WHERE item_id = ?1 ORDER BY recorded_at DESC, id DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let entries = stmt
+        .query_map(params![id], |row| {
+            Ok(MaintenanceEntry {
+                id: row.get(0)?,
+                recorded_at: row.get(1)?,
+                kind: row.get(2)?,
+                description: row.get(3)?,
+                recorded_by: row.get(4)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(entries)
+}
+
+/// items (other than retired ones) whose latest maintenance of `kind` was before `before`,
+/// or which have never had it, with when that latest entry was
+pub fn maintenance_due(
+    conn: &Connection,
+    kind: &str,
+    before: u64,
+) -> Result<Vec<(Item, Option<u64>)>, String> {
+    let _timer = QueryTimer::start("maintenance_due");
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {},
+                (SELECT MAX(recorded_at) FROM maintenance
+                 WHERE maintenance.item_id = items.id AND maintenance.type = ?1) AS last_done
+             FROM items
+             WHERE status != 'retired' AND (last_done IS NULL OR last_done < ?2)
+             ORDER BY last_done IS NOT NULL, last_done, barcode",
+            item_columns()
+        ))
+        .map_err(|e| e.to_string())?;
+    let due = stmt
+        .query_map(params![kind.to_lowercase(), before], |row| {
+            Ok((Item::from_row(row)?, row.get(ITEM_FIELDS.len())?))
+        })
+        .map_err(|e| e.to_string())?
+        .map(|r| r.map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(due)
+}
+
+/// how long a stored Idempotency-Key response is replayed for, from BARCODE_IDEMPOTENCY_TTL (seconds)
+fn idempotency_ttl() -> i64 {
+    env::var("BARCODE_IDEMPOTENCY_TTL")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .unwrap_or(24 * 60 * 60)
+}
+
+/// what an Idempotency-Key was sent with, so reusing it for something else isn't mistaken for a retry
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdempotentRequest {
+    pub method: String,
+    /// with the query, since that can change what a request does (e.g. `?touch=true`)
+    pub path: String,
+    /// `sha256_hex` of the body
+    pub body_hash: String,
+}
+
+pub enum IdempotencyClaim {
+    /// first time this key has been seen, go ahead
+    New,
+    /// another request with this key hasn't finished yet
+    InProgress,
+    /// already done, replay this status, content type and body
+    Done(u16, Option<String>, Vec<u8>),
+    /// the key was used for a different request
+    Mismatch,
+}
+
+/// claim an Idempotency-Key for `request`, or find out what happened to it last time
+///
+/// the key is the table's primary key, so two concurrent requests can't both claim it
+pub fn claim_idempotency_key(
+    conn: &mut Connection,
+    key: &str,
+    request: &IdempotentRequest,
+) -> Result<IdempotencyClaim, String> {
+    let now = Utc::now().timestamp();
+
+    with_retry(conn, |conn| {
+        // expired keys are evicted lazily, whenever a new one comes in
+        conn.execute(
+            "DELETE FROM idempotency_keys WHERE created_at < ?1",
+            params![now - idempotency_ttl()],
+        )?;
+
+        match conn.execute(
+            "INSERT INTO idempotency_keys (key, method, path, body_hash, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![key, request.method, request.path, request.body_hash, now],
+        ) {
+            Ok(_) => return Ok(IdempotencyClaim::New),
+            Err(e) if e.to_string().contains("UNIQUE constraint failed") => {}
+            Err(e) => return Err(e),
+        }
+
+        let (claimed, status, content_type, body): (
+            IdempotentRequest,
+            Option<u16>,
+            Option<String>,
+            Option<Vec<u8>>,
+        ) = conn.query_row(
+            "SELECT method, path, body_hash, status, content_type, body FROM idempotency_keys
+             WHERE key = ?1",
+            params![key],
+            |row| {
+                let claimed = IdempotentRequest {
+                    method: row.get(0)?,
+                    path: row.get(1)?,
+                    body_hash: row.get(2)?,
+                };
+                Ok((claimed, row.get(3)?, row.get(4)?, row.get(5)?))
+            },
+        )?;
+
+        if claimed != *request {
+            return Ok(IdempotencyClaim::Mismatch);
+        }
+        Ok(match status {
+            Some(status) => IdempotencyClaim::Done(status, content_type, body.unwrap_or_default()),
+            None => IdempotencyClaim::InProgress,
+        })
+    })
+}
+
+/// remember the response for a claimed Idempotency-Key
+pub fn store_idempotent_response(
+    conn: &mut Connection,
+    key: &str,
+    status: u16,
+    content_type: Option<&str>,
+    body: &[u8],
+) -> Result<(), String> {
+    with_retry(conn, |conn| {
+        conn.execute(
+            "UPDATE idempotency_keys SET status = ?1, content_type = ?2, body = ?3 WHERE key = ?4",
+            params![status, content_type, body, key],
+        )
+    })?;
+    Ok(())
+}
+
+/// give up a claimed Idempotency-Key so the request can be retried
+pub fn release_idempotency_key(conn: &mut Connection, key: &str) -> Result<(), String> {
+    with_retry(conn, |conn| {
+        conn.execute(
+            "DELETE FROM idempotency_keys WHERE key = ?1 AND status IS NULL",
+            params![key],
+        )
+    })?;
+    Ok(())
+}
+
+/// unix timestamp of the last `PRAGMA optimize`, 0 if it hasn't run yet
+static LAST_OPTIMIZE: AtomicU64 = AtomicU64::new(0);
+
+/// refresh the query planner's statistics, see https://sqlite.org/pragma.html#pragma_optimize
+pub fn optimize_db(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch("PRAGMA optimize;")
+        .map_err(|e| e.to_string())?;
+    LAST_OPTIMIZE.store(Utc::now().timestamp() as u64, Ordering::Relaxed);
+    Ok(())
+}
+
+/// parse an `If-Match` header into the version it requires
+///
+/// `Ok(None)` means any version is fine (no header, or `*`)
+fn if_match_version<B>(req: &Request<B>) -> Result<Option<u64>, String> {
+    let if_match = match req.headers().get(hyper::header::IF_MATCH) {
+        Some(if_match) => if_match
+            .to_str()
+            .map_err(|_| "Invalid If-Match".to_string())?,
+        None => return Ok(None),
+    };
+
+    let if_match = if_match.trim();
+    if if_match == "*" {
+        return Ok(None);
+    }
+
+    if_match
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse::<u64>()
+        .map(Some)
+        .map_err(|_| "Invalid If-Match, expected an item version".to_string())
+}
+
+/// no body limit can be configured above this
+const MAX_BODY_CEILING: u64 = 256 * 1024 * 1024;
+
+/// how big a request body each endpoint accepts
+#[derive(Debug, Clone, PartialEq)]
+pub struct BodyLimits {
+    default: u64,
+    routes: Vec<(String, u64)>,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self {
+            default: 64 * 1024,
+            routes: vec![
+                ("/decode".to_string(), 10 * 1024 * 1024),
+                ("/import.csv".to_string(), 10 * 1024 * 1024),
+            ],
+        }
+    }
+}
+
+impl BodyLimits {
+    /// build limits from BARCODE_MAX_BODY (the global default) and
+    /// BARCODE_MAX_BODY_ROUTES (per-route overrides like `/decode=10MiB,/new=16KiB`)
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(
+            env::var("BARCODE_MAX_BODY").ok().as_deref(),
+            env::var("BARCODE_MAX_BODY_ROUTES").ok().as_deref(),
+        )
+    }
+
+    pub fn parse(default: Option<&str>, routes: Option<&str>) -> Result<Self, String> {
+        let mut limits = Self::default();
+
+        if let Some(default) = default {
+            limits.default = parse_size(default)?;
+        }
+
+        for route in routes
+            .unwrap_or("")
+            .split(',')
+            .filter(|r| !r.trim().is_empty())
+        {
+            let (path, size) = route.split_once('=').ok_or_else(|| {
+                format!("Invalid body limit override {}, expected /path=size", route)
+            })?;
+            let (path, size) = (path.trim(), parse_size(size)?);
+
+            if !path.starts_with('/') {
+                return Err(format!("Invalid body limit override path {}", path));
+            }
+
+            limits.routes.retain(|(p, _)| p != path);
+            limits.routes.push((path.to_string(), size));
+        }
+
+        Ok(limits)
+    }
+
+    /// the limit for a request path, the longest matching override wins
+    pub fn limit(&self, path: &str) -> u64 {
+        self.routes
+            .iter()
+            .filter(|(route, _)| path == route || path.starts_with(&format!("{}/", route)))
+            .max_by_key(|(route, _)| route.len())
+            .map(|(_, limit)| *limit)
+            .unwrap_or(self.default)
+    }
+}
+
+/// parse a size like `65536`, `64KiB` or `10MiB`, which must be non-zero and under the ceiling
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let (number, multiplier) = if let Some(n) = size.strip_suffix("KiB") {
+        (n, 1024)
+    } else if let Some(n) = size.strip_suffix("MiB") {
+        (n, 1024 * 1024)
+    } else {
+        (size, 1)
+    };
+
+    let bytes = number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid size {}", size))?;
+
+    if bytes == 0 {
+        return Err(format!("Body limit {} must be more than zero", size));
+    }
+    if bytes > MAX_BODY_CEILING {
+        return Err(format!(
+            "Body limit {} is above the ceiling of {} bytes",
+            size, MAX_BODY_CEILING
+        ));
+    }
+
+    Ok(bytes)
+}
+
+/// body limits in use, set once at startup
+static BODY_LIMITS: std::sync::OnceLock<BodyLimits> = std::sync::OnceLock::new();
+
+fn body_limits() -> &'static BodyLimits {
+    BODY_LIMITS.get_or_init(BodyLimits::default)
+}
+
+/// why a request body couldn't be read
+enum BodyError {
+    TooLarge(u64),
+    Hyper(hyper::Error),
+}
+
+/// read a whole request body, refusing anything over the limit for its route
+async fn read_body(req: Request<BoxBody<Bytes, hyper::Error>>) -> Result<Bytes, BodyError> {
+    let limit = body_limits().limit(req.uri().path());
+    read_limited(req.into_body(), limit).await
+}
+
+/// read a whole body, refusing anything over `limit` bytes
+async fn read_limited(body: BoxBody<Bytes, hyper::Error>, limit: u64) -> Result<Bytes, BodyError> {
+    // a Content-Length over the limit can be refused without reading anything
+    if body.size_hint().lower() > limit {
+        return Err(BodyError::TooLarge(limit));
+    }
+
+    match http_body_util::Limited::new(body, limit as usize)
+        .collect()
+        .await
+    {
+        Ok(body) => Ok(body.to_bytes()),
+        Err(err) => match err.downcast::<hyper::Error>() {
+            Ok(err) => Err(BodyError::Hyper(*err)),
+            Err(_) => Err(BodyError::TooLarge(limit)),
+        },
+    }
+}
+
+/// 413 response naming the limit that was exceeded
+fn too_large(limit: u64) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut resp = text_response(format!(
+        "Body too big, the limit for this endpoint is {} bytes",
+        limit
+    ));
+    *resp.status_mut() = hyper::StatusCode::PAYLOAD_TOO_LARGE;
+    resp
+}
+
+/// whether a deployment asks for a field, see `field_policy()`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum FieldPolicy {
+    Required,
+    Optional,
+    Disabled,
+}
+
+/// fields a deployment can make optional or turn off, with their default policy
+const POLICY_FIELDS: [(&str, FieldPolicy); 2] = [
+    ("location", FieldPolicy::Required),
+    ("notes", FieldPolicy::Optional),
+];
+
+/// parse a field policy like `location=optional`, starting from the defaults in `POLICY_FIELDS`
+fn parse_field_policy(config: &str) -> Result<Vec<(&'static str, FieldPolicy)>, String> {
+    let mut policy = POLICY_FIELDS.to_vec();
+
+    for entry in config
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (field, setting) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("expected field=policy, got {}", entry))?;
+        let setting = match setting.trim() {
+            "required" => FieldPolicy::Required,
+            "optional" => FieldPolicy::Optional,
+            "disabled" => FieldPolicy::Disabled,
+            other => {
+                return Err(format!(
+                    "unknown policy {} for {}, expected required, optional or disabled",
+                    other,
+                    field.trim()
+                ));
+            }
+        };
+        match policy.iter_mut().find(|(name, _)| *name == field.trim()) {
+            Some((_, current)) => *current = setting,
+            None => return Err(format!("{} has no field policy", field.trim())),
+        }
+    }
+
+    Ok(policy)
+}
+
+/// which optional-capable fields this deployment requires, allows or has turned off,
+/// from BARCODE_FIELD_POLICY (e.g. `location=disabled`; default every field required)
+fn field_policy() -> &'static [(&'static str, FieldPolicy)] {
+    static FIELD_POLICY: std::sync::OnceLock<Vec<(&'static str, FieldPolicy)>> =
+        std::sync::OnceLock::new();
+
+    FIELD_POLICY.get_or_init(|| {
+        let configured = env::var("BARCODE_FIELD_POLICY").unwrap_or_default();
+        parse_field_policy(&configured).unwrap_or_else(|err| {
+            warn!(
+                "Invalid BARCODE_FIELD_POLICY: {}, requiring every field",
+                err
+            );
+            POLICY_FIELDS.to_vec()
+        })
+    })
+}
+
+/// one thing wrong with an item, as listed in a 422's `errors`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    field: &'static str,
+    /// stable, for clients to act on; `message` is for people
+    code: &'static str,
+    message: String,
+}
+
+impl Violation {
+    fn new(field: &'static str, code: &'static str, message: impl Into<String>) -> Self {
+        Violation {
+            field,
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// every way an item breaks a field policy, naming the field and its policy
+///
+/// an item without a location has it stored as an empty string
+fn policy_violations(item: &Item, policy: &[(&'static str, FieldPolicy)]) -> Vec<Violation> {
+    policy
+        .iter()
+        .filter_map(|(field, setting)| {
+            let given = match *field {
+                "location" => !item.location.trim().is_empty(),
+                "notes" => item.note().is_some(),
+                _ => return None,
+            };
+            match (setting, given) {
+                (FieldPolicy::Required, false) => Some(Violation::new(
+                    field,
+                    "required",
+                    format!("{} is required", field),
+                )),
+                (FieldPolicy::Disabled, true) => Some(Violation::new(
+                    field,
+                    "disabled",
+                    format!("{} is disabled", field),
+                )),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// statuses an item may have, from BARCODE_STATUSES (comma separated, default
+/// `ok,needs_repair,missing,retired`); `ok` and `retired` are always allowed since the server relies on them
+fn allowed_statuses() -> &'static [String] {
+    static ALLOWED_STATUSES: std::sync::OnceLock<Vec<String>> = std::sync::OnceLock::new();
+
+    ALLOWED_STATUSES.get_or_init(|| {
+        let configured = env::var("BARCODE_STATUSES")
+            .unwrap_or_else(|_| "ok,needs_repair,missing,retired".to_string());
+        let mut statuses: Vec<String> = configured
+            .split(',')
+            .map(|status| status.trim().to_string())
+            .filter(|status| !status.is_empty())
+            .collect();
+
+        for required in ["ok", "retired"] {
+            if !statuses.iter().any(|status| status == required) {
+                statuses.push(required.to_string());
+            }
+        }
+        statuses
+    })
+}
+
+/// everything wrong with an item: an empty name, a status that isn't one of `allowed_statuses()`,
+/// a negative value, a purchase date that isn't a real `YYYY-MM-DD` date, a note that's too long
+/// and anything breaking the `field_policy()`
+fn item_violations(item: &Item) -> Vec<Violation> {
+    let mut violations = Vec::new();
+
+    if item.name.trim().is_empty() {
+        violations.push(Violation::new("name", "empty", "name can't be empty"));
+    }
+    if let Some(status) = item
+        .status
+        .as_ref()
+        .filter(|status| !allowed_statuses().contains(status))
+    {
+        violations.push(Violation::new(
+            "status",
+            "unknown",
+            format!(
+                "Unknown status {}, expected one of {}",
+                sanitize(status),
+                allowed_statuses().join(", ")
+            ),
+        ));
+    }
+    if item.value_pence.is_some_and(|value| value < 0) {
+        violations.push(Violation::new(
+            "value_pence",
+            "negative",
+            "value_pence can't be negative",
+        ));
+    }
+    if let Some(date) = item.purchase_date.as_ref().filter(|date| {
+        chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(|parsed| parsed.format("%Y-%m-%d").to_string())
+            .as_deref()
+            != Ok(date.as_str())
+    }) {
+        violations.push(Violation::new(
+            "purchase_date",
+            "invalid",
+            format!(
+                "purchase_date {} isn't a date like 2024-03-31",
+                sanitize(date)
+            ),
+        ));
+    }
+    if item
+        .note()
+        .is_some_and(|note| note.chars().count() > MAX_NOTE_LENGTH)
+    {
+        violations.push(Violation::new(
+            "notes",
+            "too_long",
+            format!("notes are at most {} characters", MAX_NOTE_LENGTH),
+        ));
+    }
+    violations.extend(policy_violations(item, field_policy()));
+
+    violations
+}
+
+/// 422 response listing everything wrong with an item (see `item_violations`), if anything is,
+/// as `{"errors": [{"field": "name", "code": "empty", "message": ..}, ..]}`
+fn invalid_item(item: &Item) -> Option<Response<BoxBody<Bytes, hyper::Error>>> {
+    let violations = item_violations(item);
+    if violations.is_empty() {
+        return None;
+    }
+
+    let body = serde_json::json!({ "errors": violations });
+    let mut resp = json_response(body.to_string());
+    *resp.status_mut() = hyper::StatusCode::UNPROCESSABLE_ENTITY;
+    Some(resp)
+}
+
+fn full<T: Into<Bytes>>(chunk: T) -> BoxBody<Bytes, hyper::Error> {
+    Full::new(chunk.into())
+        .map_err(|never| match never {})
+        .boxed()
+}
+
+/// a JSON response, labelled as such
+fn json_response<T: Into<Bytes>>(body: T) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut resp = Response::new(full(body));
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    resp
+}
+
+/// a plain text response, such as an error message
+fn text_response<T: Into<Bytes>>(body: T) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut resp = Response::new(full(body));
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    resp
+}
+
+fn ok() -> Response<BoxBody<Bytes, hyper::Error>> {
+    text_response("OK")
+}
+
+/// explain why a JSON payload was rejected: serde's message and position, plus a hint for common mistakes
+fn describe_json_error(err: &serde_json::Error) -> serde_json::Value {
+    let detail = err.to_string();
+
+    let sent_as_string =
+        detail.contains("invalid type: string") || detail.contains("invalid value: string");
+    let hint = if sent_as_string && detail.contains("expected u64") {
+        Some(format!(
+            "a barcode sent as a string must be digits only, between \"0\" and \"{}\"",
+            MAX_BARCODE
+        ))
+    } else if detail.contains("expected u64") {
+        Some(format!(
+            "barcode must be a whole number between 0 and {}",
+            MAX_BARCODE
+        ))
+    } else if let Some(field) = detail
+        .strip_prefix("unknown field `")
+        .and_then(|rest| rest.split('`').next())
+    {
+        Some(format!(
+            "unknown field \"{}\", check its spelling (allowed: {})",
+            field,
+            item_field_names()
+        ))
+    } else if err.is_eof() {
+        Some("the body ended early, is the JSON complete?".to_string())
+    } else {
+        None
+    };
+
+    serde_json::json!({
+        "error": "Invalid JSON",
+        "detail": detail,
+        "line": err.line(),
+        "column": err.column(),
+        "hint": hint,
+    })
+}
+
+/// 400 response for a JSON payload that couldn't be deserialized
+fn invalid_json(err: &serde_json::Error) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut resp = json_response(describe_json_error(err).to_string());
+    *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+    resp
+}
+
+/// remove all non-alphanumeric characters from a string (all fields can have this applied)
+fn sanitize(s: &str) -> String {
+    s.replace(
+        |c: char| !c.is_ascii_alphanumeric() && !c.is_ascii_whitespace(),
+        "",
+    )
+}
+
+impl Item {
+    /// remove all non-alphanumeric characters from all fields
+    fn sanitize(&mut self) {
+        self.name = sanitize(&self.name);
+        self.location = sanitize(&self.location);
+    }
+}
+
+// endpoint for the settings the webclient needs, as a script defining them (hyper)
+//
+// BASE_PATH is where the API is mounted, see `mounted_at`
+async fn config_js(
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let policy: serde_json::Map<String, serde_json::Value> = field_policy()
+        .iter()
+        .map(|(field, policy)| {
+            // plain enum, always serializes
+            (field.to_string(), serde_json::to_value(policy).unwrap())
+        })
+        .collect();
+
+    let mut resp = Response::new(full(format!(
+        "const FIELD_POLICY = {};\nconst BASE_PATH = {};\n",
+        serde_json::Value::Object(policy),
+        serde_json::Value::from(mounted_at(&req))
+    )));
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/javascript"),
+    );
+    Ok(resp)
+}
+
+/// old spellings of item fields that are still accepted, with the one to use instead;
+/// responses always use the new one
+const DEPRECATED_FIELDS: [(&str, &str); 1] = [("last-seen", "last_seen")];
+
+/// the deprecated field spellings a JSON item uses, logging each so old clients can be found
+fn deprecated_fields(body: &[u8]) -> Vec<(&'static str, &'static str)> {
+    let Ok(serde_json::Value::Object(fields)) = serde_json::from_slice(body) else {
+        return Vec::new();
+    };
+
+    DEPRECATED_FIELDS
+        .iter()
+        .filter(|(old, _)| fields.contains_key(*old))
+        .inspect(|(old, new)| warn!("Request used deprecated field {}, use {} instead", old, new))
+        .copied()
+        .collect()
+}
+
+/// add a `Warning` header for each deprecated field spelling a request used
+fn with_deprecated_fields(
+    mut resp: Response<BoxBody<Bytes, hyper::Error>>,
+    deprecated: &[(&str, &str)],
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    for (old, new) in deprecated {
+        let warning = format!("299 - \"{} is deprecated, use {}\"", old, new);
+        resp.headers_mut().append(
+            hyper::header::WARNING,
+            hyper::header::HeaderValue::from_str(&warning).unwrap(), // field names are ASCII
+        );
+    }
+    resp
+}
+
+// endpoint for new item (hyper)
+async fn new_item(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let whole_body = match read_body(req).await {
+        Ok(whole_body) => whole_body,
+        Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+        Err(BodyError::Hyper(err)) => return Err(err),
+    };
+
+    let item: Result<Item, serde_json::Error> = serde_json::from_slice(&whole_body);
+
+    if let Err(err) = item.as_ref() {
+        return Ok(invalid_json(err));
+    }
+    let deprecated = deprecated_fields(&whole_body);
+
+    // now give it a last seen time of now
+    let mut item = item.unwrap(); // unwrap is safe because we checked it above
+    if let Some(resp) = invalid_item(&item) {
+        return Ok(resp);
+    }
+    item.sanitize();
+    item.last_seen = Some(Utc::now().timestamp() as u64);
+
+    let res = db.write_blocking(move |conn| item.save(conn)).await;
+
+    if let Err(err) = res {
+        if err.starts_with("Parent ") {
+            return Ok(parent_error(err));
+        }
+        if err == "Barcode is archived" {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::CONFLICT;
+            return Ok(resp);
+        }
+
+        let mut resp = if err.contains("UNIQUE constraint failed") {
+            text_response("Item already exists")
+        } else {
+            text_response(err.clone())
+        };
+        *resp.status_mut() = if err.contains("UNIQUE constraint failed") {
+            hyper::StatusCode::CONFLICT
+        } else {
+            hyper::StatusCode::INTERNAL_SERVER_ERROR
+        };
+        return Ok(resp);
+    }
+
+    Ok(with_deprecated_fields(ok(), &deprecated))
+}
+
+// endpoint for all items (hyper)
+async fn all_items(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    // ?status=needs_repair filters by status; retired items only show up when asked for
+    let status = query_param(req.uri().query(), "status");
+    let include_retired = query_param(req.uri().query(), "include_retired")
+        .is_some_and(|include| include == "true")
+        || status.as_deref() == Some("retired");
+
+    // ?fields=barcode,name returns only those fields
+    let fields = match requested_fields(req.uri().query()) {
+        Ok(fields) => fields,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+    // the list only changes when the change counter does, so the counter is the ETag for
+    // every view of it; read first, so a change made while loading can't be missed
+    let etag = match db.read_blocking(|conn| change_counter(conn)).await {
+        Ok(counter) => hyper::header::HeaderValue::from_str(&format!("\"{}\"", counter)).unwrap(), // always a plain number
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+    if etag_matches(req.headers(), etag.to_str().unwrap()) {
+        let mut resp = Response::new(full(Bytes::new()));
+        *resp.status_mut() = hyper::StatusCode::NOT_MODIFIED;
+        resp.headers_mut().insert(hyper::header::ETAG, etag);
+        return Ok(resp);
+    }
+    // a page of the most recently seen, BARCODE_DEFAULT_LIMIT of them unless ?limit= says otherwise;
+    // ?offset= only applies with a limit
+    let page = match page(&req, default_limit()) {
+        Ok((limit, offset)) => limit.map(|limit| (limit, offset)),
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    if let Some(fields) = fields {
+        let condition = "(?1 IS NULL OR status = ?1) AND (?2 OR status != 'retired')";
+        let status = status.clone();
+        let items = db
+            .read_blocking(move |conn| {
+                count_items(conn, condition, &[&status, &include_retired]).and_then(|total| {
+                    load_item_fields(conn, &fields, condition, &[&status, &include_retired], page)
+                        .map(|items| (items, total))
+                })
+            })
+            .await;
+        return Ok(match items {
+            Ok((items, total)) => {
+                let mut resp = json_response(
+                    to_json(&items, barcodes_as_strings(&req)).unwrap(), // plain JSON, always serializes
+                );
+                resp.headers_mut()
+                    .insert("x-total-count", hyper::header::HeaderValue::from(total));
+                resp.headers_mut().insert(hyper::header::ETAG, etag);
+                resp
+            }
+            Err(err) => {
+                let mut resp = text_response(err);
+                *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+                resp
+            }
+        });
+    }
+
+    let items = db.read_blocking(|conn| load_items(conn)).await;
+
+    if items.is_err() {
+        let mut resp = text_response(items.unwrap_err());
+        *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+        return Ok(resp);
+    }
+
+    let mut items: Vec<Item> = items
+        .unwrap()
+        .iter_mut()
+        .filter(|i| {
+            let item_status = i.status.as_deref().unwrap_or("ok");
+            status.as_deref().is_none_or(|status| status == item_status)
+                && (include_retired || item_status != "retired")
+        })
+        .map(|i| {
+            i.sanitize();
+            i.clone()
+        })
+        .collect();
+
+    let total = items.len() as u64;
+    if let Some((limit, offset)) = page {
+        items.sort_by(|a, b| {
+            b.last_seen
+                .cmp(&a.last_seen)
+                .then(a.barcode.cmp(&b.barcode))
+        });
+        items = items
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+    }
+
+    let items_json = to_json(&items, barcodes_as_strings(&req));
+
+    if items_json.is_err() {
+        let mut resp = text_response(items_json.unwrap_err().to_string());
+        *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+        return Ok(resp);
+    }
+
+    let mut resp = json_response(items_json.unwrap()); // unwrap is safe because we checked it above
+    resp.headers_mut()
+        .insert("x-total-count", hyper::header::HeaderValue::from(total));
+    resp.headers_mut().insert(hyper::header::ETAG, etag);
+    Ok(resp)
+}
+
+// endpoint for offline clients: the barcodes created, updated and deleted after
+// `?since=<cursor>`, and the cursor to send next time (hyper)
+// a client starting from nothing sends `since=0`, then fetches the items it's told about
+async fn sync(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let since = match query_param(req.uri().query(), "since").map(|since| since.parse::<u64>()) {
+        Some(Ok(since)) => since,
+        _ => {
+            let mut resp = text_response("since must be a whole number");
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    match db.read_blocking(move |conn| load_sync(conn, since)).await {
+        Ok(delta) => Ok(json_response(
+            serde_json::to_string(&delta).unwrap(), // plain data, always serializes
+        )),
+        // the database was reset or restored from a backup, so the client must start over
+        Err(err) if err == "Counter is ahead of the server" => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::GONE;
+            Ok(resp)
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for what happened each day: `?days=30` (the default) of created, modified, deleted
+// and scanned counts, oldest first, with `?tz=+01:00` to start days somewhere other than the
+// configured timezone (hyper)
+async fn activity(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let days = match query_param(req.uri().query(), "days").map(|days| days.parse::<u64>()) {
+        None => 30,
+        Some(Ok(days)) if (1..=MAX_ACTIVITY_DAYS).contains(&days) => days,
+        Some(_) => {
+            let mut resp = text_response(format!(
+                "days must be a whole number from 1 to {}",
+                MAX_ACTIVITY_DAYS
+            ));
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+    let offset = match query_param(req.uri().query(), "tz") {
+        None => activity_offset(),
+        Some(tz) => match parse_utc_offset(&tz) {
+            Ok(offset) => offset,
+            Err(_) => {
+                let mut resp = text_response("tz must be an offset like +01:00 or UTC");
+                *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+                return Ok(resp);
+            }
+        },
+    };
+
+    match db.read(|conn| load_activity(conn, days, offset, Utc::now().timestamp())) {
+        Ok(activity) => Ok(json_response(
+            serde_json::to_string(&activity).unwrap(), // plain data, always serializes
+        )),
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for incremental sync: what changed after `?since=<counter>` (hyper)
+// the counter comes from `/all`'s ETag or an earlier call; items come back as they are now,
+// deleted (or archived) ones as bare barcodes
+async fn changes(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let since = match query_param(req.uri().query(), "since").map(|since| since.parse::<u64>()) {
+        Some(Ok(since)) => since,
+        _ => {
+            let mut resp = text_response("since must be a whole number");
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    match db
+        .read_blocking(move |conn| load_changes(conn, since))
+        .await
+    {
+        Ok((counter, mut changed, deleted)) => {
+            changed.iter_mut().for_each(Item::sanitize);
+            let body = serde_json::json!({
+                "counter": counter,
+                "changed": changed,
+                "deleted": deleted,
+            });
+            Ok(json_response(
+                to_json(&body, barcodes_as_strings(&req)).unwrap(), // plain data, always serializes
+            ))
+        }
+        // the database was reset or restored from a backup, so the client must start over
+        Err(err) if err == "Counter is ahead of the server" => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::GONE;
+            Ok(resp)
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for the items at one location, matched case-insensitively (hyper)
+async fn location_items(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let location = percent_decode(req.uri().path().trim_start_matches("/location/"));
+
+    let mut items = match db
+        .read_blocking(move |conn| load_items_at(conn, &location))
+        .await
+    {
+        Ok(items) => items,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+    items.iter_mut().for_each(Item::sanitize);
+
+    match to_json(&items, barcodes_as_strings(&req)) {
+        Ok(items_json) => Ok(json_response(items_json)),
+        Err(err) => {
+            let mut resp = text_response(err.to_string());
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint to find items by part of their name and/or location, ignoring case (hyper)
+// e.g. /search?name=cable&location=rig, or /search?q=cable for either, an empty array if nothing matches
+async fn search(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let param = |key| query_param(req.uri().query(), key).filter(|value| !value.is_empty());
+    let (name, location, anywhere) = (param("name"), param("location"), param("q"));
+    if name.is_none() && location.is_none() && anywhere.is_none() {
+        let mut resp = text_response("Give q, name or location to search by");
+        *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+        return Ok(resp);
+    }
+
+    let mut items = match db
+        .read_blocking(move |conn| {
+            search_items(
+                conn,
+                name.as_deref(),
+                location.as_deref(),
+                anywhere.as_deref(),
+            )
+        })
+        .await
+    {
+        Ok(items) => items,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+    items.iter_mut().for_each(Item::sanitize);
+
+    match to_json(&items, barcodes_as_strings(&req)) {
+        Ok(items_json) => Ok(json_response(items_json)),
+        Err(err) => {
+            let mut resp = text_response(err.to_string());
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint listing every location with its item count (hyper)
+async fn locations(
+    db: &Db,
+    _req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    match db.read(|conn| load_locations(conn)) {
+        Ok(locations) => {
+            let locations: Vec<serde_json::Value> = locations
+                .into_iter()
+                .map(|(location, items)| {
+                    serde_json::json!({ "location": sanitize(&location), "items": items })
+                })
+                .collect();
+            Ok(json_response(
+                serde_json::Value::from(locations).to_string(),
+            ))
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for the insurance valuation: total value overall and per location,
+// plus how many items have no value recorded (hyper)
+async fn valuation(
+    db: &Db,
+    _req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    match db.read(|conn| load_valuation(conn)) {
+        Ok(mut valuation) => {
+            for location in valuation.locations.iter_mut() {
+                location.location = sanitize(&location.location);
+            }
+            Ok(json_response(
+                serde_json::to_string(&valuation).unwrap(), // plain data, always serializes
+            ))
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for the items needing attention, in one call (hyper):
+// stale ones (not seen in `?stale_days=`, default 30) and ones whose status isn't ok, grouped by status;
+// retired items are never included
+async fn attention(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let stale_days = match query_param(req.uri().query(), "stale_days") {
+        Some(days) => match days.parse::<u64>() {
+            Ok(days) => days,
+            Err(_) => {
+                let mut resp = text_response("stale_days must be a whole number of days");
+                *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+                return Ok(resp);
+            }
+        },
+        None => 30,
+    };
+
+    let items = match db.read_blocking(|conn| load_items(conn)).await {
+        Ok(items) => items,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+
+    let cutoff =
+        (Utc::now().timestamp() as u64).saturating_sub(stale_days.saturating_mul(24 * 60 * 60));
+    let mut stale = Vec::new();
+    let mut by_status: std::collections::BTreeMap<String, Vec<Item>> = Default::default();
+
+    for mut item in items {
+        let status = item.status.clone().unwrap_or_else(|| "ok".to_string());
+        if status == "retired" {
+            continue;
+        }
+
+        item.sanitize();
+        if item.last_seen.is_none_or(|last_seen| last_seen < cutoff) {
+            stale.push(item.clone());
+        }
+        if status != "ok" {
+            by_status.entry(status).or_default().push(item);
+        }
+    }
+
+    let report = serde_json::json!({
+        "stale_days": stale_days,
+        "stale": stale,
+        "status": by_status,
+    });
+
+    match to_json(&report, barcodes_as_strings(&req)) {
+        Ok(report) => Ok(json_response(report)),
+        Err(err) => {
+            let mut resp = text_response(err.to_string());
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for the case or kit an item is packed in (hyper):
+// POST `{"parent_barcode": 42}` packs it inside 42, DELETE unpacks it
+async fn parent_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    #[derive(Deserialize)]
+    struct NewParent {
+        #[serde(deserialize_with = "optional_barcode")]
+        parent_barcode: Option<u64>,
+    }
+
+    let parent = match *req.method() {
+        hyper::Method::DELETE => None,
+        hyper::Method::POST => {
+            let whole_body = match read_body(req).await {
+                Ok(whole_body) => whole_body,
+                Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+                Err(BodyError::Hyper(err)) => return Err(err),
+            };
+            match serde_json::from_slice::<NewParent>(&whole_body) {
+                Ok(new_parent) => new_parent.parent_barcode,
+                Err(err) => return Ok(invalid_json(&err)),
+            }
+        }
+        _ => {
+            let mut resp = text_response("Use POST to set the parent or DELETE to clear it");
+            *resp.status_mut() = hyper::StatusCode::METHOD_NOT_ALLOWED;
+            resp.headers_mut().insert(
+                hyper::header::ALLOW,
+                hyper::header::HeaderValue::from_static("POST, DELETE"),
+            );
+            return Ok(resp);
+        }
+    };
+
+    match db
+        .write_blocking(move |conn| set_parent(conn, barcode, parent))
+        .await
+    {
+        Ok(()) => Ok(ok()),
+        Err(err) => Ok(parent_error(err)),
+    }
+}
+
+/// the response for a failed change of parent, from `set_parent` or `modify_item`
+fn parent_error(err: String) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let (status, message) = match err.as_str() {
+        "Item not found" => (hyper::StatusCode::NOT_FOUND, err),
+        "Parent not found" => (hyper::StatusCode::UNPROCESSABLE_ENTITY, err),
+        "Parent cycle" => (
+            hyper::StatusCode::CONFLICT,
+            "That parent is already inside this item, so it would end up inside itself".to_string(),
+        ),
+        _ => (hyper::StatusCode::INTERNAL_SERVER_ERROR, err),
+    };
+
+    let mut resp = text_response(message);
+    *resp.status_mut() = status;
+    resp
+}
+
+// endpoint for the items packed directly inside an item (hyper)
+async fn children(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    match db.read(|conn| load_children(conn, barcode)) {
+        Ok(mut items) => {
+            items.iter_mut().for_each(Item::sanitize);
+            Ok(json_response(
+                to_json(&items, barcodes_as_strings(&req)).unwrap(), // plain data, always serializes
+            ))
+        }
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Item not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+/// mark a response as being for the item an alias stands for, if the barcode used was one
+fn with_matched_alias(
+    mut resp: Response<BoxBody<Bytes, hyper::Error>>,
+    alias: Option<u64>,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    if let Some(alias) = alias {
+        resp.headers_mut()
+            .insert("x-matched-alias", hyper::header::HeaderValue::from(alias));
+    }
+    resp
+}
+
+// endpoint for an item's alternate barcodes (hyper):
+// GET lists them, POST `{"alias": 5012345678900}` adds one and DELETE with the same body removes it
+async fn aliases(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    #[derive(Deserialize)]
+    struct Alias {
+        #[serde(deserialize_with = "barcode_from_number_or_string")]
+        alias: u64,
+    }
+
+    let method = req.method().clone();
+    let result = match method {
+        hyper::Method::GET => db.read(|conn| load_aliases(conn, barcode)).map(|aliases| {
+            let aliases = serde_json::json!(aliases);
+            to_json(&aliases, barcodes_as_strings(&req)).unwrap() // a Value always serializes
+        }),
+        hyper::Method::POST | hyper::Method::DELETE => {
+            let whole_body = match read_body(req).await {
+                Ok(whole_body) => whole_body,
+                Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+                Err(BodyError::Hyper(err)) => return Err(err),
+            };
+            let alias = match serde_json::from_slice::<Alias>(&whole_body) {
+                Ok(Alias { alias }) => alias,
+                Err(err) => return Ok(invalid_json(&err)),
+            };
+
+            let changed = if method == hyper::Method::POST {
+                db.write_blocking(move |conn| add_alias(conn, barcode, alias))
+                    .await
+            } else {
+                db.write_blocking(move |conn| remove_alias(conn, barcode, alias))
+                    .await
+            };
+            changed.map(|()| "OK".to_string())
+        }
+        _ => {
+            let mut resp = text_response("Use GET to list, POST to add or DELETE to remove");
+            *resp.status_mut() = hyper::StatusCode::METHOD_NOT_ALLOWED;
+            resp.headers_mut().insert(
+                hyper::header::ALLOW,
+                hyper::header::HeaderValue::from_static("GET, POST, DELETE"),
+            );
+            return Ok(resp);
+        }
+    };
+
+    match result {
+        Ok(body) => Ok(json_response(body)),
+        Err(err) => {
+            let taken = err.contains("UNIQUE constraint failed");
+            let mut resp = text_response(if taken {
+                "That barcode is already in use, as an item's barcode or an alias".to_string()
+            } else {
+                err.clone()
+            });
+            *resp.status_mut() = if taken {
+                hyper::StatusCode::CONFLICT
+            } else if err == "Item not found" || err == "Alias not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+/// how many moves `/move` and `/item/{barcode}/trail` return, from `?limit=` (default 5)
+fn trail_limit<B>(req: &Request<B>) -> Result<u64, String> {
+    match query_param(req.uri().query(), "limit").map(|limit| limit.parse::<u64>()) {
+        None => Ok(5),
+        Some(Ok(limit)) if limit > 0 => Ok(limit),
+        Some(_) => Err("limit must be a whole number above 0".to_string()),
+    }
+}
+
+/// an item's moves as JSON, sanitized like the item itself
+fn trail_json(trail: Vec<Move>) -> serde_json::Value {
+    trail
+        .into_iter()
+        .map(|step| {
+            serde_json::json!({
+                "moved_at": step.moved_at,
+                "from": sanitize(&step.from),
+                "to": sanitize(&step.to),
+            })
+        })
+        .collect()
+}
+
+// endpoint to move an item, returning it with its recent location trail (hyper)
+// expected format (`cascade` moves everything packed inside it too):
+/*
+```
+{
+    "barcode": 42,
+    "location": "Rig",
+    "cascade": true
+}
+```
+*/
+async fn move_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct MoveRequest {
+        #[serde(deserialize_with = "barcode_from_number_or_string")]
+        barcode: u64,
+        location: String,
+        #[serde(default)]
+        cascade: bool,
+    }
+
+    let limit = match trail_limit(&req) {
+        Ok(limit) => limit,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+    let as_strings = barcodes_as_strings(&req);
+
+    let whole_body = match read_body(req).await {
+        Ok(whole_body) => whole_body,
+        Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+        Err(BodyError::Hyper(err)) => return Err(err),
+    };
+    let request: MoveRequest = match serde_json::from_slice(&whole_body) {
+        Ok(request) => request,
+        Err(err) => return Ok(invalid_json(&err)),
+    };
+
+    let location = sanitize(&request.location);
+    if location.trim().is_empty() {
+        let mut resp = text_response("location can't be empty");
+        *resp.status_mut() = hyper::StatusCode::UNPROCESSABLE_ENTITY;
+        return Ok(resp);
+    }
+
+    let requested = request.barcode;
+    let (barcode, alias) = match db
+        .read_blocking(move |conn| resolve_alias(conn, requested))
+        .await
+    {
+        Ok(Some(canonical)) => (canonical, Some(requested)),
+        Ok(None) => (requested, None),
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+
+    let cascade = request.cascade;
+    let moved = match db
+        .write_blocking(move |conn| move_item(conn, barcode, &location, cascade))
+        .await
+    {
+        Ok(moved) => {
+            db.read_blocking(move |conn| {
+                let mut item = load_item(conn, barcode)?;
+                item.sanitize();
+                Ok((moved, item, load_trail(conn, barcode, limit)?))
+            })
+            .await
+        }
+        Err(err) => Err(err),
+    };
+
+    match moved {
+        Ok((moved, item, trail)) => {
+            let body = serde_json::json!({
+                "item": item,
+                "moved": moved,
+                "trail": trail_json(trail),
+            });
+            Ok(with_matched_alias(
+                json_response(to_json(&body, as_strings).unwrap()), // a Value always serializes
+                alias,
+            ))
+        }
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Item not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for an item's recent moves, newest first, `?limit=` of them (default 5) (hyper)
+async fn trail(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+    let limit = match trail_limit(&req) {
+        Ok(limit) => limit,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    match db.read(|conn| load_trail(conn, barcode, limit)) {
+        Ok(trail) => Ok(json_response(trail_json(trail).to_string())),
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Item not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+/// `?limit=` (default 50, at most `MAX_PAGE_LIMIT`), and `?before=` with an optional `?before_id=`,
+/// for `/item/{barcode}/history`
+fn history_params<B>(req: &Request<B>) -> Result<(u64, Option<HistoryCursor>), String> {
+    let limit = match query_param(req.uri().query(), "limit").map(|limit| limit.parse::<u64>()) {
+        None => 50,
+        Some(Ok(limit)) if limit > 0 => limit.min(MAX_PAGE_LIMIT),
+        Some(_) => return Err("limit must be a whole number above 0".to_string()),
+    };
+    let before = match query_param(req.uri().query(), "before").map(|before| before.parse::<u64>())
+    {
+        None => None,
+        Some(Ok(before)) => Some(before),
+        Some(Err(_)) => return Err("before must be a unix timestamp in seconds".to_string()),
+    };
+    let before_id = match query_param(req.uri().query(), "before_id").map(|id| id.parse::<i64>()) {
+        None => None,
+        Some(Ok(id)) if before.is_some() => Some(id),
+        Some(Ok(_)) => return Err("before_id needs a before to go with it".to_string()),
+        Some(Err(_)) => return Err("before_id must be a whole number".to_string()),
+    };
+    Ok((limit, before.map(|before| (before, before_id))))
+}
+
+// endpoint for everywhere an item has been seen, newest first (hyper):
+// `?before=` and `?before_id=`, the oldest `seen_at` and `id` of one page, get the next
+async fn history(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+    let (limit, before) = match history_params(&req) {
+        Ok(params) => params,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    match db.read(|conn| load_history(conn, barcode, limit, before)) {
+        Ok(history) => {
+            let history: serde_json::Value = history
+                .into_iter()
+                .map(|sighting| {
+                    serde_json::json!({
+                        "id": sighting.id,
+                        "seen_at": sighting.seen_at,
+                        "location": sanitize(&sighting.location),
+                    })
+                })
+                .collect();
+            Ok(json_response(history.to_string()))
+        }
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Item not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for every scan of an item, oldest first (hyper); `?limit=` and `?offset=` to page
+async fn scans(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+    let (limit, offset) = match page(&req, None) {
+        Ok(page) => page,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    match db
+        .read_blocking(move |conn| load_scans(conn, barcode, limit, offset))
+        .await
+    {
+        Ok(scans) => {
+            let scans: serde_json::Value = scans
+                .into_iter()
+                .map(|scan| {
+                    serde_json::json!({
+                        "scanned_at": scan.scanned_at,
+                        "location": sanitize(&scan.location),
+                    })
+                })
+                .collect();
+            Ok(json_response(scans.to_string()))
+        }
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Item not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for an item's reservations (hyper):
+// GET lists the ones not yet finished, POST books it
+/*
+```
+{
+    "starts_at": 1767225600,
+    "ends_at": 1767830400,
+    "reserved_by": "Spring show",
+    "note": "needs a fresh fluid bottle"
+}
+```
+*/
+async fn reservations(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+    let as_strings = barcodes_as_strings(&req);
+
+    let result = match *req.method() {
+        hyper::Method::GET => {
+            db.read(|conn| item_reservations(conn, barcode))
+                .map(|mut reservations| {
+                    reservations.iter_mut().for_each(Reservation::sanitize);
+                    to_json(&reservations, as_strings).unwrap() // plain data, always serializes
+                })
+        }
+        hyper::Method::POST => {
+            let whole_body = match read_body(req).await {
+                Ok(whole_body) => whole_body,
+                Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+                Err(BodyError::Hyper(err)) => return Err(err),
+            };
+            let new: NewReservation = match serde_json::from_slice(&whole_body) {
+                Ok(new) => new,
+                Err(err) => return Ok(invalid_json(&err)),
+            };
+            if let Err(err) = new.validate() {
+                let mut resp = text_response(err);
+                *resp.status_mut() = hyper::StatusCode::UNPROCESSABLE_ENTITY;
+                return Ok(resp);
+            }
+
+            match db
+                .write_blocking(move |conn| add_reservation(conn, barcode, &new))
+                .await
+            {
+                Ok(Ok(mut reservation)) => {
+                    reservation.sanitize();
+                    Ok(to_json(&reservation, as_strings).unwrap()) // plain data, always serializes
+                }
+                Ok(Err(mut clash)) => {
+                    clash.sanitize();
+                    let body = serde_json::json!({
+                        "error": "The item is already reserved then",
+                        "conflict": clash,
+                    });
+                    let mut resp = json_response(to_json(&body, as_strings).unwrap()); // a Value always serializes
+                    *resp.status_mut() = hyper::StatusCode::CONFLICT;
+                    return Ok(resp);
+                }
+                Err(err) => Err(err),
+            }
+        }
+        _ => {
+            let mut resp = text_response("Use GET to list or POST to reserve");
+            *resp.status_mut() = hyper::StatusCode::METHOD_NOT_ALLOWED;
+            resp.headers_mut().insert(
+                hyper::header::ALLOW,
+                hyper::header::HeaderValue::from_static("GET, POST"),
+            );
+            return Ok(resp);
+        }
+    };
+
+    match result {
+        Ok(body) => Ok(json_response(body)),
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Item not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint to cancel one of an item's reservations (hyper)
+async fn cancel_reservation_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let mut segments = req.uri().path().split('/').skip(2);
+    let barcode = path_barcode(segments.next().unwrap_or_default());
+    let id = segments.nth(1).map(str::parse::<i64>);
+    let (barcode, id) = match (barcode, id) {
+        (Ok(barcode), Some(Ok(id))) => (barcode, id),
+        (Err(err), _) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+        _ => {
+            let mut resp = text_response("Invalid reservation id");
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    if *req.method() != hyper::Method::DELETE {
+        let mut resp = text_response("Use DELETE to cancel a reservation");
+        *resp.status_mut() = hyper::StatusCode::METHOD_NOT_ALLOWED;
+        resp.headers_mut().insert(
+            hyper::header::ALLOW,
+            hyper::header::HeaderValue::from_static("DELETE"),
+        );
+        return Ok(resp);
+    }
+
+    match db
+        .write_blocking(move |conn| cancel_reservation(conn, barcode, id))
+        .await
+    {
+        Ok(()) => Ok(ok()),
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Reservation not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for a calendar of reservations (hyper):
+// `?from=&to=` (unix seconds, default the next 30 days) lists every reservation overlapping that range
+async fn calendar(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let time = |key: &str| query_param(req.uri().query(), key).map(|time| time.parse::<u64>());
+    let from = match time("from") {
+        None => Ok(Utc::now().timestamp() as u64),
+        Some(from) => from,
+    };
+    let range = from.and_then(|from| match time("to") {
+        None => Ok((from, from.saturating_add(30 * 24 * 60 * 60))),
+        Some(to) => to.map(|to| (from, to)),
+    });
+    let (from, to) = match range {
+        Ok((from, to)) if from < to => (from, to),
+        _ => {
+            let mut resp =
+                text_response("from and to must be unix timestamps, with from before to");
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    match db.read(|conn| load_reservations(conn, from, to)) {
+        Ok(mut reservations) => {
+            reservations.iter_mut().for_each(Reservation::sanitize);
+            Ok(json_response(
+                to_json(&reservations, barcodes_as_strings(&req)).unwrap(), // plain data, always serializes
+            ))
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint listing double-booked items, as pairs of overlapping reservations (hyper);
+// only possible with BARCODE_ALLOW_DOUBLE_BOOKING=true, or bookings made before it was turned off
+async fn reservation_conflicts_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    match db.read(|conn| reservation_conflicts(conn)) {
+        Ok(conflicts) => {
+            let conflicts: Vec<[Reservation; 2]> = conflicts
+                .into_iter()
+                .map(|(mut first, mut second)| {
+                    first.sanitize();
+                    second.sanitize();
+                    [first, second]
+                })
+                .collect();
+            Ok(json_response(
+                to_json(&conflicts, barcodes_as_strings(&req)).unwrap(), // plain data, always serializes
+            ))
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for an item's maintenance log (hyper):
+// GET lists entries newest first, POST appends one
+/*
+```
+{
+    "type": "pat",
+    "description": "passed, 0.05 ohm earth",
+    "recorded_by": "Sam"
+}
+```
+*/
+async fn maintenance_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    let result = match *req.method() {
+        hyper::Method::GET => db
+            .read(|conn| load_maintenance(conn, barcode))
+            .map(|mut entries| {
+                entries.iter_mut().for_each(MaintenanceEntry::sanitize);
+                serde_json::to_string(&entries).unwrap() // plain data, always serializes
+            }),
+        hyper::Method::POST => {
+            let whole_body = match read_body(req).await {
+                Ok(whole_body) => whole_body,
+                Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+                Err(BodyError::Hyper(err)) => return Err(err),
+            };
+
+            let entry: NewMaintenance = match serde_json::from_slice(&whole_body) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    let mut resp = text_response(format!("Invalid JSON: {}", err));
+                    *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+                    return Ok(resp);
+                }
+            };
+
+            if let Err(err) = entry.validate() {
+                let mut resp = text_response(err);
+                *resp.status_mut() = hyper::StatusCode::UNPROCESSABLE_ENTITY;
+                return Ok(resp);
+            }
+
+            db.write_blocking(move |conn| add_maintenance(conn, barcode, &entry))
+                .await
+                .map(|mut entry| {
+                    entry.sanitize();
+                    serde_json::to_string(&entry).unwrap() // plain data, always serializes
+                })
+        }
+        _ => {
+            let mut resp = text_response("Use GET to list or POST to add");
+            *resp.status_mut() = hyper::StatusCode::METHOD_NOT_ALLOWED;
+            resp.headers_mut().insert(
+                hyper::header::ALLOW,
+                hyper::header::HeaderValue::from_static("GET, POST"),
+            );
+            return Ok(resp);
+        }
+    };
+
+    match result {
+        Ok(body) => Ok(json_response(body)),
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Item not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for items due some kind of maintenance (hyper):
+// `?type=pat&older_than_days=365` lists items whose last PAT test is over a year old, or who never had one
+async fn maintenance_due_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let kind = query_param(req.uri().query(), "type").unwrap_or_default();
+    if !maintenance_types().contains(&kind.to_lowercase()) {
+        let mut resp = text_response(format!(
+            "type must be one of {}",
+            maintenance_types().join(", ")
+        ));
+        *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+        return Ok(resp);
+    }
+
+    let older_than_days =
+        match query_param(req.uri().query(), "older_than_days").map(|days| days.parse::<u64>()) {
+            Some(Ok(days)) => days,
+            None => 365,
+            Some(Err(_)) => {
+                let mut resp = text_response("older_than_days must be a whole number of days");
+                *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+                return Ok(resp);
+            }
+        };
+    let before = (Utc::now().timestamp() as u64)
+        .saturating_sub(older_than_days.saturating_mul(24 * 60 * 60));
+
+    match db.read(|conn| maintenance_due(conn, &kind, before)) {
+        Ok(due) => {
+            let due: Vec<serde_json::Value> = due
+                .into_iter()
+                .map(|(mut item, last_done)| {
+                    item.sanitize();
+                    serde_json::json!({ "item": item, "last_done": last_done })
+                })
+                .collect();
+            let body = to_json(&due, barcodes_as_strings(&req)).unwrap(); // a Value always serializes
+            Ok(json_response(body))
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for item (hyper)
+async fn item(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = req.uri().path().split('/').next_back();
+
+    let barcode = match barcode {
+        Some(barcode) => match path_barcode(barcode) {
+            Ok(barcode) => barcode,
+            Err(err) => {
+                let mut resp = text_response(err);
+                *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+                return Ok(resp);
+            }
+        },
+        None => {
+            let mut resp = text_response("No barcode");
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    // a scanned alias means the item it belongs to
+    let (barcode, alias) = match db
+        .read_blocking(move |conn| resolve_alias(conn, barcode))
+        .await
+    {
+        Ok(Some(canonical)) => (canonical, Some(barcode)),
+        Ok(None) => (barcode, None),
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+
+    // ?touch=true counts viewing the item as seeing it, like /log followed by a lookup
+    let touch = query_param(req.uri().query(), "touch").is_some_and(|touch| touch == "true");
+
+    // ?fields=barcode,name returns only those fields, with an ETag only if version is one of them
+    let fields = match requested_fields(req.uri().query()) {
+        Ok(fields) => fields,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+    if let Some(fields) = fields {
+        return Ok(with_matched_alias(
+            item_fields(db, &req, barcode, fields, touch).await,
+            alias,
+        ));
+    }
+
+    let item = if touch {
+        db.write_blocking(move |conn| {
+            touch_item(conn, &barcode.to_string(), false).and_then(|_| load_item(conn, barcode))
+        })
+        .await
+    } else {
+        db.read_blocking(move |conn| load_item(conn, barcode)).await
+    };
+
+    if touch && item.is_ok() {
+        info!("{} seen via lookup", barcode);
+    }
+
+    if let Err(err) = item {
+        let mut resp = if err == "Item not found" {
+            text_response("Item not found")
+        } else {
+            text_response(err.clone())
+        };
+        *resp.status_mut() = if err == "Item not found" {
+            hyper::StatusCode::NOT_FOUND
+        } else {
+            hyper::StatusCode::INTERNAL_SERVER_ERROR
+        };
+        return Ok(resp);
+    }
+
+    let mut item = item.unwrap(); // unwrap is safe because we checked it above
+    item.sanitize();
+
+    // include the reservation it's out on, if any
+    let reservation = match db
+        .read_blocking(move |conn| active_reservation(conn, barcode))
+        .await
+    {
+        Ok(reservation) => reservation.map(|mut reservation| {
+            reservation.sanitize();
+            reservation
+        }),
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+
+    let notes = match db
+        .read_blocking(move |conn| load_notes(conn, barcode))
+        .await
+    {
+        Ok(mut notes) => {
+            notes.iter_mut().for_each(Note::sanitize);
+            notes
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+
+    let mut value = serde_json::to_value(&item).unwrap(); // plain data, always serializes
+    value["reservation"] = serde_json::to_value(reservation).unwrap();
+    value["notes"] = serde_json::to_value(notes).unwrap();
+    if let Some(alias) = alias {
+        value["matched_alias"] = alias.into();
+    }
+    let item_json = to_json(&value, barcodes_as_strings(&req));
+
+    if item_json.is_err() {
+        let mut resp = text_response(item_json.unwrap_err().to_string());
+        *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+        return Ok(resp);
+    }
+
+    let mut resp = json_response(item_json.unwrap()); // unwrap is safe because we checked it above
+    resp.headers_mut().insert(
+        hyper::header::ETAG,
+        hyper::header::HeaderValue::from_str(&format!("\"{}\"", item.version)).unwrap(), // always a plain number
+    );
+
+    Ok(with_matched_alias(resp, alias))
+}
+
+/// the response for `/item/{barcode}?fields=...`
+async fn item_fields<B>(
+    db: &Db,
+    req: &Request<B>,
+    barcode: u64,
+    fields: Vec<&'static str>,
+    touch: bool,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let item = if touch {
+        db.write_blocking(move |conn| {
+            touch_item(conn, &barcode.to_string(), false)
+                .and_then(|_| load_item_fields(conn, &fields, "barcode = ?1", &[&barcode], None))
+        })
+        .await
+    } else {
+        db.read_blocking(move |conn| {
+            load_item_fields(conn, &fields, "barcode = ?1", &[&barcode], None)
+        })
+        .await
+    };
+
+    let item = match item.map(|items| items.into_iter().next()) {
+        Ok(Some(item)) => item,
+        Ok(None) => {
+            let mut resp = text_response("Item not found");
+            *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
+            return resp;
+        }
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Item not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            return resp;
+        }
+    };
+    if touch {
+        info!("{} seen via lookup", barcode);
+    }
+
+    let version = item
+        .get("version")
+        .map(|version| format!("\"{}\"", version));
+    let mut resp = json_response(
+        to_json(&item, barcodes_as_strings(req)).unwrap(), // plain JSON, always serializes
+    );
+    if let Some(version) = version {
+        resp.headers_mut().insert(
+            hyper::header::ETAG,
+            hyper::header::HeaderValue::from_str(&version).unwrap(), // always a plain number
+        );
+    }
+    resp
+}
+
+// endpoint to modify item (hyper)
+// expected format:
+/*
+```
+{
+    "name": "item name",
+    "barcode": "42",
+    "location": "location",
+}
+```
+*/
+// with `If-Match: "<version>"` (the item's ETag) the update is refused with 409 if someone else got there first
+async fn modify_item_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let expected_version = match if_match_version(&req) {
+        Ok(expected_version) => expected_version,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    let whole_body = match read_body(req).await {
+        Ok(whole_body) => whole_body,
+        Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+        Err(BodyError::Hyper(err)) => return Err(err),
+    };
+
+    let item: Result<Item, serde_json::Error> = serde_json::from_slice(&whole_body);
+
+    if let Err(err) = item.as_ref() {
+        return Ok(invalid_json(err));
+    }
+    let deprecated = deprecated_fields(&whole_body);
+
+    let mut item = item.unwrap(); // unwrap is safe because we checked it above
+    if let Some(resp) = invalid_item(&item) {
+        return Ok(resp);
+    }
+    item.sanitize();
+    item.last_seen = Some(Utc::now().timestamp() as u64);
+
+    let res = db
+        .write_blocking(move |conn| modify_item(conn, item, expected_version))
+        .await;
+
+    if let Err(err) = res {
+        if err.starts_with("Parent ") {
+            return Ok(parent_error(err));
+        }
+
+        let mut resp = if err == "Item not found" {
+            text_response("Item not found")
+        } else if err == "Version mismatch" {
+            text_response("Version mismatch, refetch the item and try again")
+        } else {
+            text_response(err.clone())
+        };
+        *resp.status_mut() = if err == "Item not found" {
+            hyper::StatusCode::NOT_FOUND
+        } else if err == "Version mismatch" {
+            hyper::StatusCode::CONFLICT
+        } else {
+            hyper::StatusCode::INTERNAL_SERVER_ERROR
+        };
+        return Ok(resp);
+    }
+
+    Ok(with_deprecated_fields(ok(), &deprecated))
+}
+
+// endpoint to delete item (hyper)
+async fn delete_item_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    // GET as well, which the webclient and termclient send
+    if !matches!(*req.method(), hyper::Method::DELETE | hyper::Method::GET) {
+        let mut resp = text_response("Use DELETE");
+        *resp.status_mut() = hyper::StatusCode::METHOD_NOT_ALLOWED;
+        return Ok(resp);
+    }
+
+    let barcode = req.uri().path().split('/').next_back();
+
+    if barcode.is_none() {
+        let mut resp = text_response("No barcode");
+        *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+        return Ok(resp);
+    }
+
+    // unwrap is safe because we checked it above
+    let scanned = barcode.unwrap().to_string();
+    let (barcode, alias) = match db
+        .read_blocking(move |conn| canonical_barcode(conn, &scanned))
+        .await
+    {
+        Ok(resolved) => resolved,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+
+    let res = db
+        .write_blocking(move |conn| delete_item(conn, &barcode))
+        .await;
+
+    if let Err(err) = res {
+        let mut resp = if err == "Item not found" {
+            text_response("Item not found")
+        } else {
+            text_response(err.clone())
+        };
+        *resp.status_mut() = if err == "Item not found" {
+            hyper::StatusCode::NOT_FOUND
+        } else {
+            hyper::StatusCode::INTERNAL_SERVER_ERROR
+        };
+        return Ok(resp);
+    }
+
+    Ok(with_matched_alias(ok(), alias))
+}
+
+// endpoint to append a note to an item (hyper)
+async fn note_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    // a scanned alias means the item it belongs to
+    let (barcode, alias) = match db.read(|conn| resolve_alias(conn, barcode)) {
+        Ok(Some(canonical)) => (canonical, Some(barcode)),
+        Ok(None) => (barcode, None),
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+
+    let whole_body = match read_body(req).await {
+        Ok(whole_body) => whole_body,
+        Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+        Err(BodyError::Hyper(err)) => return Err(err),
+    };
+
+    let note: NewNote = match serde_json::from_slice(&whole_body) {
+        Ok(note) => note,
+        Err(err) => return Ok(invalid_json(&err)),
+    };
+
+    if let Some(problem) = invalid_note(&note.text) {
+        let mut resp = text_response(problem);
+        *resp.status_mut() = hyper::StatusCode::UNPROCESSABLE_ENTITY;
+        return Ok(resp);
+    }
+
+    match db
+        .write_blocking(move |conn| add_note(conn, barcode, &note.text))
+        .await
+    {
+        Ok(mut note) => {
+            note.sanitize();
+            Ok(with_matched_alias(
+                json_response(serde_json::to_string(&note).unwrap()), // plain data, always serializes
+                alias,
+            ))
+        }
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Item not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint to move an item into the archive (hyper)
+async fn archive_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    // ?history=true keeps its maintenance log and location trail with it
+    let history =
+        query_param(req.uri().query(), "history").is_some_and(|history| history == "true");
+
+    match db
+        .write_blocking(move |conn| archive_item(conn, barcode, history))
+        .await
+    {
+        Ok(mut archived) => {
+            archived.item.sanitize();
+            Ok(json_response(
+                to_json(&archived, barcodes_as_strings(&req)).unwrap(), // plain data, always serializes
+            ))
+        }
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = match err.as_str() {
+                "Item not found" => hyper::StatusCode::NOT_FOUND,
+                "Already archived" => hyper::StatusCode::CONFLICT,
+                _ => hyper::StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint to bring an item back from the archive (hyper)
+async fn unarchive_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = match path_barcode(req.uri().path().split('/').nth(2).unwrap_or_default()) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    match db
+        .write_blocking(move |conn| unarchive_item(conn, barcode))
+        .await
+    {
+        Ok(mut item) => {
+            item.sanitize();
+            Ok(json_response(
+                to_json(&item, barcodes_as_strings(&req)).unwrap(), // plain data, always serializes
+            ))
+        }
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = match err.as_str() {
+                "Archived item not found" => hyper::StatusCode::NOT_FOUND,
+                "Barcode reused" => hyper::StatusCode::CONFLICT,
+                _ => hyper::StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Ok(resp)
+        }
+    }
+}
+
+/// the most items one page holds, however many `?limit=` asks for
+const MAX_PAGE_LIMIT: u64 = 1000;
+
+/// run database work on tokio's blocking threads, so a slow disk or a locked database holds up
+/// only the request waiting for it, not every connection sharing its worker; a panic in `work`
+/// comes back as an error
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .unwrap_or_else(|err| Err(err.to_string()))
+}
+
+/// `?limit=` and `?offset=` for listings served a page at a time, `default` applying without
+/// a limit (everything when it's `None`); `?limit=all` is as many as a page holds, so clients
+/// wanting everything page through it by X-Total-Count
+fn page<B>(req: &Request<B>, default: Option<u64>) -> Result<(Option<u64>, u64), String> {
+    let limit = match query_param(req.uri().query(), "limit").as_deref() {
+        None => default,
+        Some("all") => Some(MAX_PAGE_LIMIT),
+        Some(limit) => match limit.parse::<u64>() {
+            Ok(limit) if limit > 0 => Some(limit.min(MAX_PAGE_LIMIT)),
+            _ => return Err("limit must be a whole number above 0, or all".to_string()),
+        },
+    };
+    let offset = match query_param(req.uri().query(), "offset").map(|offset| offset.parse::<u64>())
+    {
+        None => 0,
+        Some(Ok(offset)) => offset,
+        Some(Err(_)) => return Err("offset must be a whole number".to_string()),
+    };
+    Ok((limit, offset))
+}
+
+// endpoint for the archive, a page at a time with the total in X-Total-Count (hyper)
+async fn archived(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let (limit, offset) = match page(&req, Some(50)) {
+        Ok(page) => page,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    match db.read(|conn| load_archived(conn, limit, offset)) {
+        Ok((mut archived, total)) => {
+            archived
+                .iter_mut()
+                .for_each(|archived| archived.item.sanitize());
+            let mut resp = json_response(
+                to_json(&archived, barcodes_as_strings(&req)).unwrap(), // plain data, always serializes
+            );
+            resp.headers_mut()
+                .insert("x-total-count", hyper::header::HeaderValue::from(total));
+            Ok(resp)
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint to log an item (hyper)
+async fn log_item(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    // the termclient logs with GET
+    if !matches!(*req.method(), hyper::Method::POST | hyper::Method::GET) {
+        let mut resp = text_response("Use POST");
+        *resp.status_mut() = hyper::StatusCode::METHOD_NOT_ALLOWED;
+        return Ok(resp);
+    }
+
+    let barcode = req.uri().path().split('/').next_back();
+
+    if barcode.is_none() {
+        let mut resp = text_response("No barcode");
+        *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+        return Ok(resp);
+    }
+
+    // ?cascade=true logs everything packed inside it too
+    let cascade =
+        query_param(req.uri().query(), "cascade").is_some_and(|cascade| cascade == "true");
+
+    // unwrap is safe because we checked it above
+    let scanned = barcode.unwrap().to_string();
+    let (barcode, alias) = match db
+        .read_blocking(move |conn| canonical_barcode(conn, &scanned))
+        .await
+    {
+        Ok(resolved) => resolved,
+        Err(_) => {
+            let mut resp = text_response("Failed to log item");
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+
+    match db
+        .write_blocking(move |conn| touch_item(conn, &barcode, cascade))
+        .await
+    {
+        Ok(()) => {}
+        Err(err) if err == "Item not found" => {
+            let mut resp = text_response("Item not found");
+            *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
+            return Ok(resp);
+        }
+        Err(_) => {
+            let mut resp = text_response("Failed to log item");
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    }
+    Ok(with_matched_alias(ok(), alias))
+}
+
+// endpoint to change an item's quantity by some units without sending the whole item (hyper):
+// POST `{"delta": -2}` takes two away, stopping at zero
+async fn adjust_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    // an alias adjusts the item it stands for, as with /log
+    let scanned = req
+        .uri()
+        .path()
+        .split('/')
+        .nth(2)
+        .unwrap_or_default()
+        .to_string();
+    let (barcode, alias) = match db
+        .read_blocking(move |conn| canonical_barcode(conn, &scanned))
+        .await
+    {
+        Ok(resolved) => resolved,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+    let barcode = match path_barcode(&barcode) {
+        Ok(barcode) => barcode,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    #[derive(Deserialize)]
+    struct Adjustment {
+        delta: i64,
+    }
+
+    let whole_body = match read_body(req).await {
+        Ok(whole_body) => whole_body,
+        Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+        Err(BodyError::Hyper(err)) => return Err(err),
+    };
+    let delta = match serde_json::from_slice::<Adjustment>(&whole_body) {
+        Ok(adjustment) => adjustment.delta,
+        Err(err) => return Ok(invalid_json(&err)),
+    };
+
+    match db
+        .write_blocking(move |conn| adjust_quantity(conn, barcode, delta))
+        .await
+    {
+        Ok(quantity) => Ok(with_matched_alias(
+            json_response(serde_json::json!({ "quantity": quantity }).to_string()),
+            alias,
+        )),
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = match err.as_str() {
+                "Item not found" => hyper::StatusCode::NOT_FOUND,
+                _ => hyper::StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint to check a barcode is free before filling in a new item (hyper)
+// answers {"available":true}, or {"available":false,"reason":"..."} (a bad barcode is unavailable
+// too, rather than a 400, so a form can show the reason whatever it is)
+async fn check_barcode(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let barcode = req.uri().path().split('/').next_back().unwrap_or_default();
+
+    let reason = match path_barcode(barcode) {
+        Ok(barcode) => match db.read(|conn| barcode_unavailable(conn, barcode)) {
+            Ok(reason) => reason,
+            Err(_) => {
+                let mut resp = text_response("Failed to check barcode");
+                *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+                return Ok(resp);
+            }
+        },
+        Err(err) => Some(err),
+    };
+
+    let body = match reason {
+        Some(reason) => serde_json::json!({ "available": false, "reason": reason }),
+        None => serde_json::json!({ "available": true }),
+    };
+    Ok(json_response(body.to_string()))
+}
+
+/// what the body of `/reset` must contain, so the whole inventory can't be wiped by accident
+const RESET_CONFIRMATION: &str = "DELETE ALL";
+
+#[derive(Debug, Deserialize)]
+struct ResetRequest {
+    confirm: String,
+}
+
+// endpoint to delete every item (hyper)
+// expected format:
+/*
+```
+{
+    "confirm": "DELETE ALL"
+}
+```
+*/
+async fn reset(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    if req.method() != hyper::Method::POST {
+        let mut resp = text_response("Use POST");
+        *resp.status_mut() = hyper::StatusCode::METHOD_NOT_ALLOWED;
+        return Ok(resp);
+    }
+
+    let whole_body = match read_body(req).await {
+        Ok(whole_body) => whole_body,
+        Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+        Err(BodyError::Hyper(err)) => return Err(err),
+    };
+
+    let confirmed = serde_json::from_slice::<ResetRequest>(&whole_body)
+        .is_ok_and(|reset| reset.confirm == RESET_CONFIRMATION);
+
+    if !confirmed {
+        let mut resp = text_response(format!(
+            "Send {{\"confirm\": \"{}\"}} to delete every item",
+            RESET_CONFIRMATION
+        ));
+        *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+        return Ok(resp);
+    }
+
+    match db.write_blocking(reset_items).await {
+        Ok(deleted) => {
+            warn!("Inventory reset, {} items deleted", deleted);
+            Ok(json_response(
+                serde_json::json!({ "deleted": deleted }).to_string(),
+            ))
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            Ok(resp)
+        }
+    }
+}
+
+// endpoint for server health (hyper)
+async fn health(
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    // clients checking they can still reach the server only need the status
+    if req.method() == hyper::Method::HEAD {
+        return Ok(Response::new(full("")));
+    }
+
+    let last_optimize = match LAST_OPTIMIZE.load(Ordering::Relaxed) {
+        0 => None,
+        timestamp => Some(timestamp),
+    };
+
+    let health = serde_json::json!({
+        "status": "ok",
+        "last_optimize": last_optimize,
+        "slow_requests": SLOW_REQUESTS.load(Ordering::Relaxed),
+        "db_retries": DB_RETRIES.load(Ordering::Relaxed),
+    });
+
+    Ok(json_response(health.to_string()))
+}
+
+/// requests answered since the server started, counted in `dispatch_under`
+static REQUESTS_SERVED: AtomicU64 = AtomicU64::new(0);
+
+/// requests being handled right now
+static REQUESTS_IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+
+/// connections the accept loop in `serve` has open right now
+static OPEN_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// when the server started, for `/status`'s uptime
+fn started_at() -> std::time::Instant {
+    static STARTED_AT: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+
+    *STARTED_AT.get_or_init(std::time::Instant::now)
+}
+
+/// one of the counters above, decremented again when dropped, even if the handler panics
+struct Gauge(&'static AtomicU64);
+
+impl Gauge {
+    fn enter(counter: &'static AtomicU64) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Gauge(counter)
+    }
+}
+
+impl Drop for Gauge {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// endpoint for what the server is doing: uptime, requests, connections and the database's size (hyper)
+async fn status(
+    db: &Db,
+    _req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let items = match db.read(|conn| count_items(conn, "1", &[])) {
+        Ok(items) => items,
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+    let db_bytes = fs::metadata(&*db.path).map(|meta| meta.len()).ok();
+
+    let status = serde_json::json!({
+        "uptime_secs": started_at().elapsed().as_secs(),
+        "requests_served": REQUESTS_SERVED.load(Ordering::Relaxed),
+        // this one included
+        "requests_in_flight": REQUESTS_IN_FLIGHT.load(Ordering::Relaxed),
+        "open_connections": OPEN_CONNECTIONS.load(Ordering::Relaxed),
+        "db_bytes": db_bytes,
+        "items": items,
+    });
+
+    Ok(json_response(status.to_string()))
+}
+
+// endpoint for the server version (hyper)
+async fn version(
+    _req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let version = serde_json::json!({ "version": env!("CARGO_PKG_VERSION") });
+    Ok(json_response(version.to_string()))
+}
+
+/// largest image `/decode` will attempt to decode, checked from the header before decoding
+const MAX_DECODE_PIXELS: u64 = 40_000_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodedBarcode {
+    value: String,
+    symbology: String,
+}
+
+/// decode every barcode found in a JPEG or PNG image
+///
+/// finding nothing is not an error, the result is just empty
+fn decode_image(bytes: &[u8]) -> Result<Vec<DecodedBarcode>, String> {
+    let reader = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+
+    match reader.format() {
+        Some(image::ImageFormat::Jpeg) | Some(image::ImageFormat::Png) => {}
+        _ => return Err("Unsupported image format, send a JPEG or PNG".to_string()),
+    }
+
+    // only the header is read here, so absurd dimensions are rejected before allocating anything
+    let (width, height) = reader.into_dimensions().map_err(|e| e.to_string())?;
+    if width as u64 * height as u64 > MAX_DECODE_PIXELS {
+        return Err(format!("Image too large ({}x{})", width, height));
+    }
+
+    let luma = image::load_from_memory(bytes)
+        .map_err(|e| e.to_string())?
+        .to_luma8();
+    let (width, height) = luma.dimensions();
+
+    // rxing reports "nothing found" as an error, which is an empty result for us
+    let results =
+        rxing::helpers::detect_multiple_in_luma(luma.into_raw(), width, height).unwrap_or_default();
+
+    Ok(results
+        .iter()
+        .map(|result| DecodedBarcode {
+            value: result.getText().to_string(),
+            symbology: format!("{:?}", result.getBarcodeFormat()),
+        })
+        .collect())
+}
+
+// endpoint to decode barcodes from an uploaded photo (hyper)
+// `?log=true` also logs the first decoded barcode and returns its item
+async fn decode_endpoint(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let log = query_param(req.uri().query(), "log").is_some_and(|log| log == "true");
+    let as_strings = barcodes_as_strings(&req);
+
+    let whole_body = match read_body(req).await {
+        Ok(whole_body) => whole_body,
+        Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+        Err(BodyError::Hyper(err)) => return Err(err),
+    };
+
+    let decoded = tokio::task::spawn_blocking(move || decode_image(&whole_body)).await;
+
+    let barcodes = match decoded {
+        Ok(Ok(barcodes)) => barcodes,
+        Ok(Err(err)) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err.starts_with("Image too large") {
+                hyper::StatusCode::PAYLOAD_TOO_LARGE
+            } else {
+                hyper::StatusCode::BAD_REQUEST
+            };
+            return Ok(resp);
+        }
+        Err(err) => {
+            let mut resp = text_response(err.to_string());
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+
+    if barcodes.is_empty() {
+        let mut resp = text_response("No barcode found");
+        *resp.status_mut() = hyper::StatusCode::UNPROCESSABLE_ENTITY;
+        return Ok(resp);
+    }
+
+    if !log {
+        return Ok(json_response(
+            serde_json::json!({ "barcodes": barcodes }).to_string(),
+        ));
+    }
+
+    let barcode = barcodes[0].value.clone();
+    let item = db
+        .write_blocking(move |conn| {
+            touch_item(conn, &barcode, false)?;
+            load_item(
+                conn,
+                barcode.parse().map_err(|_| "Item not found".to_string())?,
+            )
+        })
+        .await;
+
+    match item {
+        Ok(mut item) => {
+            item.sanitize();
+            let body = serde_json::json!({ "barcodes": barcodes, "item": item });
+            Ok(json_response(to_json(&body, as_strings).unwrap())) // a Value always serializes
+        }
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err == "Item not found" {
+                hyper::StatusCode::NOT_FOUND
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+/// build a spreadsheet of the given items, with a filterable header row
+///
+/// barcodes are written as text so spreadsheet software doesn't mangle long numbers
+fn build_xlsx(items: &[Item], archived: &[ArchivedItem]) -> Result<Vec<u8>, XlsxError> {
+    let mut workbook = Workbook::new();
+    let header = Format::new().set_bold();
+    let date = Format::new().set_num_format("yyyy-mm-dd hh:mm:ss");
+    let day = Format::new().set_num_format("yyyy-mm-dd");
+    let money = Format::new().set_num_format("#,##0.00");
+
+    let worksheet = workbook.add_worksheet();
+    worksheet.set_name("Inventory")?;
+
+    // archived items follow the rest, with when they were archived in a column of their own
+    let rows: Vec<(&Item, Option<u64>)> = items
+        .iter()
+        .map(|item| (item, None))
+        .chain(
+            archived
+                .iter()
+                .map(|archived| (&archived.item, Some(archived.archived_at))),
+        )
+        .collect();
+
+    // purchase columns only appear once something has purchase information
+    let with_purchase = rows
+        .iter()
+        .any(|(item, _)| item.purchase_date.is_some() || item.value_pence.is_some());
+    let mut titles = vec!["Name", "Barcode", "Location", "Last Seen"];
+    if with_purchase {
+        titles.extend(["Purchase Date", "Value"]);
+    }
+    if !archived.is_empty() {
+        titles.push("Archived");
+    }
+    let archived_col = titles.len() as u16 - 1;
+
+    for (col, title) in titles.iter().enumerate() {
+        worksheet.write_string_with_format(0, col as u16, *title, &header)?;
+    }
+
+    for (i, (item, archived_at)) in rows.iter().enumerate() {
+        let row = i as u32 + 1;
+        worksheet.write_string(row, 0, &item.name)?;
+        worksheet.write_string(row, 1, item.barcode.to_string())?;
+        worksheet.write_string(row, 2, &item.location)?;
+        if let Some(last_seen) = item.last_seen {
+            let last_seen = ExcelDateTime::from_timestamp(last_seen as i64)?;
+            worksheet.write_datetime_with_format(row, 3, &last_seen, &date)?;
+        }
+        if let Some(purchase_date) = &item.purchase_date {
+            let purchase_date = ExcelDateTime::parse_from_str(purchase_date)?;
+            worksheet.write_datetime_with_format(row, 4, &purchase_date, &day)?;
+        }
+        if let Some(value) = item.value_pence {
+            worksheet.write_number_with_format(row, 5, value as f64 / 100.0, &money)?;
+        }
+        if let Some(archived_at) = archived_at {
+            let archived_at = ExcelDateTime::from_timestamp(*archived_at as i64)?;
+            worksheet.write_datetime_with_format(row, archived_col, &archived_at, &date)?;
+        }
+    }
+
+    worksheet.autofilter(0, 0, rows.len() as u32, titles.len() as u16 - 1)?;
+    worksheet.set_column_width(0, 30)?;
+    worksheet.set_column_width(1, 16)?;
+    worksheet.set_column_width(2, 30)?;
+    worksheet.set_column_width(3, 20)?;
+    if with_purchase {
+        worksheet.set_column_width(4, 14)?;
+        worksheet.set_column_width(5, 12)?;
+    }
+    if !archived.is_empty() {
+        worksheet.set_column_width(archived_col, 20)?;
+    }
+
+    workbook.save_to_buffer()
+}
+
+// endpoint to export all items as an .xlsx spreadsheet (hyper)
+async fn export_xlsx(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    // ?include_archived=true adds the archive after the inventory
+    let include_archived =
+        query_param(req.uri().query(), "include_archived").is_some_and(|include| include == "true");
+
+    // loading and building the workbook are both blocking, so keep them off the executor
+    let db = db.clone();
+    let xlsx = tokio::task::spawn_blocking(move || {
+        let (mut items, mut archived) = db.read(|conn| {
+            let items = load_items(conn)?;
+            let archived = if include_archived {
+                load_archived(conn, None, 0)?.0
+            } else {
+                Vec::new()
+            };
+            Ok((items, archived))
+        })?;
+        items.iter_mut().for_each(Item::sanitize);
+        archived
+            .iter_mut()
+            .for_each(|archived| archived.item.sanitize());
+        build_xlsx(&items, &archived).map_err(|e| e.to_string())
+    })
+    .await;
+
+    let xlsx = match xlsx {
+        Ok(Ok(xlsx)) => xlsx,
+        Ok(Err(err)) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+        Err(err) => {
+            let mut resp = text_response(err.to_string());
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+
+    let filename = format!(
+        "attachment; filename=\"inventory-{}.xlsx\"",
+        chrono::Local::now().format("%Y-%m-%d")
+    );
+
+    let mut resp = Response::new(full(xlsx));
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static(
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        ),
+    );
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_DISPOSITION,
+        hyper::header::HeaderValue::from_str(&filename).unwrap(), // always ASCII
+    );
+
+    Ok(resp)
+}
+
+/// most items `/report.pdf` will lay out; beyond that the spreadsheet is the better tool
+const MAX_REPORT_ITEMS: usize = 5000;
+
+/// an A4 page, in points
+const PDF_PAGE_SIZE: (u32, u32) = (595, 842);
+
+/// text for a PDF string: latin-1 bytes (as WinAnsiEncoding reads them), `?` for anything else,
+/// with the delimiters escaped
+fn pdf_string(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => bytes.extend([b'\\', c as u8]),
+            c if (' '..='\u{ff}').contains(&c) && !('\u{7f}'..'\u{a0}').contains(&c) => {
+                bytes.push(c as u8)
+            }
+            _ => bytes.push(b'?'),
+        }
+    }
+    bytes
+}
+
+/// `text` cut to `chars` characters, ending in `...` if anything was cut
+fn truncate_text(text: &str, chars: usize) -> String {
+    if text.chars().count() <= chars {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(chars.saturating_sub(3)).collect();
+    cut.push_str("...");
+    cut
+}
+
+/// a PDF of A4 pages, each given as its content stream, with Helvetica as `/F1` and
+/// Helvetica-Bold as `/F2`
+fn pdf_document(pages: &[Vec<u8>]) -> Vec<u8> {
+    // 1 catalog, 2 page tree, 3 and 4 fonts, then each page and its content
+    let kids: Vec<String> = (0..pages.len())
+        .map(|i| format!("{} 0 R", 5 + 2 * i))
+        .collect();
+    let mut objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .into_bytes(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+    ];
+    for (i, content) in pages.iter().enumerate() {
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PDF_PAGE_SIZE.0,
+                PDF_PAGE_SIZE.1,
+                6 + 2 * i
+            )
+            .into_bytes(),
+        );
+        let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+        stream.extend_from_slice(content);
+        stream.extend_from_slice(b"\nendstream");
+        objects.push(stream);
+    }
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+
+    // every cross-reference entry is exactly 20 bytes
+    let xref = pdf.len();
+    pdf.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+/// what dates fall back to when BARCODE_DATE_FORMAT isn't a pattern chrono can format with: ISO-8601
+const ISO_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+/// whether a strftime pattern is one chrono can format with
+fn valid_date_format(format: &str) -> bool {
+    !format.is_empty()
+        && chrono::format::StrftimeItems::new(format)
+            .all(|item| item != chrono::format::Item::Error)
+}
+
+/// how reports show dates and times, from BARCODE_DATE_FORMAT (a strftime pattern, default
+/// `%Y-%m-%d %H:%M`); checked at startup, an invalid pattern falls back to ISO-8601
+fn date_format() -> &'static str {
+    static DATE_FORMAT: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+    DATE_FORMAT.get_or_init(|| match env::var("BARCODE_DATE_FORMAT") {
+        Ok(format) if valid_date_format(&format) => format,
+        Ok(format) => {
+            warn!(
+                "Invalid BARCODE_DATE_FORMAT: {}, using ISO-8601 ({})",
+                format, ISO_DATE_FORMAT
+            );
+            ISO_DATE_FORMAT.to_string()
+        }
+        Err(_) => "%Y-%m-%d %H:%M".to_string(),
+    })
+}
+
+/// a printable stock report: a title, when it was made and totals, then a table of the items
+/// (name, barcode, location, last seen, value) over as many pages as it takes, each numbered
+fn build_report_pdf(items: &[Item], title: &str, generated: &str) -> Vec<u8> {
+    const MARGIN: u32 = 40;
+    const BOTTOM: u32 = 60;
+    const LINE: u32 = 13;
+    // x of each column, and how many characters fit in it at 9pt
+    const COLUMNS: [(&str, u32, usize); 5] = [
+        ("Name", MARGIN, 40),
+        ("Barcode", 250, 20),
+        ("Location", 340, 24),
+        ("Last seen", 455, 16),
+        ("Value", 530, 10),
+    ];
+
+    fn text(page: &mut Vec<u8>, font: &str, size: u32, x: u32, y: u32, text: &str) {
+        page.extend_from_slice(format!("BT /{} {} Tf {} {} Td (", font, size, x, y).as_bytes());
+        page.extend_from_slice(&pdf_string(text));
+        page.extend_from_slice(b") Tj ET\n");
+    }
+    fn header(page: &mut Vec<u8>, y: u32) {
+        for (title, x, _) in COLUMNS {
+            text(page, "F2", 9, x, y, title);
+        }
+        // a rule under the header
+        page.extend_from_slice(
+            format!(
+                "0.5 w {} {} m {} {} l S\n",
+                MARGIN,
+                y - 4,
+                PDF_PAGE_SIZE.0 - MARGIN,
+                y - 4
+            )
+            .as_bytes(),
+        );
+    }
+    let pounds = |pence: i64| format!("{}.{:02}", pence / 100, pence % 100);
+
+    let valued: Vec<i64> = items.iter().filter_map(|item| item.value_pence).collect();
+    let mut totals = match items.len() {
+        1 => "1 item".to_string(),
+        count => format!("{} items", count),
+    };
+    if !valued.is_empty() {
+        totals.push_str(&format!(", total value {}", pounds(valued.iter().sum())));
+        if valued.len() < items.len() {
+            totals.push_str(&format!(
+                " ({} without a value)",
+                items.len() - valued.len()
+            ));
+        }
+    }
+
+    let top = PDF_PAGE_SIZE.1 - 50;
+    let mut page = Vec::new();
+    text(&mut page, "F2", 16, MARGIN, top, title);
+    text(
+        &mut page,
+        "F1",
+        9,
+        MARGIN,
+        top - 20,
+        &format!("Generated {}", generated),
+    );
+    text(&mut page, "F1", 9, MARGIN, top - 34, &totals);
+    let mut y = top - 62;
+    header(&mut page, y);
+
+    let mut pages = Vec::new();
+    for item in items {
+        y -= LINE;
+        if y < BOTTOM {
+            pages.push(std::mem::take(&mut page));
+            y = top;
+            header(&mut page, y);
+            y -= LINE;
+        }
+
+        let last_seen = item
+            .last_seen
+            .and_then(|at| chrono::DateTime::from_timestamp(at as i64, 0))
+            .map(|at| {
+                at.with_timezone(&chrono::Local)
+                    .format(date_format())
+                    .to_string()
+            })
+            .unwrap_or_default();
+        let cells = [
+            item.name.clone(),
+            item.barcode.to_string(),
+            item.location.clone(),
+            last_seen,
+            item.value_pence.map(pounds).unwrap_or_default(),
+        ];
+        for ((_, x, chars), cell) in COLUMNS.iter().zip(cells) {
+            text(&mut page, "F1", 9, *x, y, &truncate_text(&cell, *chars));
+        }
+    }
+    pages.push(page);
+
+    let count = pages.len();
+    for (i, page) in pages.iter_mut().enumerate() {
+        text(
+            page,
+            "F1",
+            8,
+            MARGIN,
+            30,
+            &format!("Page {} of {}", i + 1, count),
+        );
+    }
+    pdf_document(&pages)
+}
+
+// endpoint for a printable stock report as a PDF, everything not retired or `?location=Rig` (hyper)
+async fn report_pdf(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let location = query_param(req.uri().query(), "location").filter(|l| !l.trim().is_empty());
+
+    let items = tokio::task::spawn_blocking({
+        let db = db.clone();
+        let location = location.clone();
+        move || match location {
+            Some(location) => db.read(|conn| load_items_at(conn, &location)),
+            None => db.read(|conn| load_items(conn)),
+        }
+    })
+    .await;
+    let mut items = match items {
+        Ok(Ok(items)) => items,
+        Ok(Err(err)) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+        Err(err) => {
+            let mut resp = text_response(err.to_string());
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    };
+    items.retain(|item| item.status.as_deref() != Some("retired"));
+    if items.len() > MAX_REPORT_ITEMS {
+        let mut resp = text_response(format!(
+            "{} items is more than a report can hold ({} at most), pick a ?location= or use /export.xlsx",
+            items.len(),
+            MAX_REPORT_ITEMS
+        ));
+        *resp.status_mut() = hyper::StatusCode::UNPROCESSABLE_ENTITY;
+        return Ok(resp);
+    }
+    items.iter_mut().for_each(Item::sanitize);
+    items.sort_by(|a, b| {
+        (a.location.to_lowercase(), a.name.to_lowercase())
+            .cmp(&(b.location.to_lowercase(), b.name.to_lowercase()))
+    });
+
+    let title = match &location {
+        Some(location) => format!("Stock report: {}", sanitize(location)),
+        None => "Stock report".to_string(),
+    };
+    let now = chrono::Local::now();
+    let pdf = build_report_pdf(&items, &title, &now.format(date_format()).to_string());
+
+    let filename = format!(
+        "attachment; filename=\"stock-report-{}.pdf\"",
+        now.format("%Y-%m-%d")
+    );
+    let mut resp = Response::new(full(pdf));
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/pdf"),
+    );
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_DISPOSITION,
+        hyper::header::HeaderValue::from_str(&filename).unwrap(), // always ASCII
+    );
+
+    Ok(resp)
+}
+
+/// a response body fed from a channel, so large responses are sent as they're produced instead of buffered
+struct ChannelBody {
+    rx: tokio::sync::mpsc::Receiver<Bytes>,
+}
+
+impl Body for ChannelBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Bytes>, hyper::Error>>> {
+        self.rx
+            .poll_recv(cx)
+            .map(|chunk| chunk.map(|chunk| Ok(hyper::body::Frame::data(chunk))))
+    }
+}
+
+/// tables that only make sense to this server, left out of dumps
+const DUMP_SKIP_TABLES: &[&str] = &["idempotency_keys"];
+
+/// write the database as SQL (`CREATE TABLE` then `INSERT`s per table, then indexes) to `send`,
+/// a chunk at a time; stops early if `send` returns false (the client went away)
+///
+/// headers are long gone by the time rows are read, so a row that can't be written is logged and
+/// replaced by a `-- skipped` comment, with a count at the end, rather than failing the whole dump
+fn dump_sql(conn: &Connection, mut send: impl FnMut(String) -> bool) -> Result<(), String> {
+    const CHUNK: usize = 16 * 1024;
+
+    let mut schema = conn
+        .prepare(
+            "SELECT type, name, sql FROM sqlite_master
+             WHERE sql IS NOT NULL AND name NOT LIKE 'sqlite_%'
+             ORDER BY type = 'table' DESC, rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let schema = schema
+        .query_map(params![], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut buf = String::from("PRAGMA foreign_keys=OFF;\nBEGIN TRANSACTION;\n");
+    let mut skipped = 0;
+
+    for (kind, name, sql) in schema {
+        if DUMP_SKIP_TABLES.contains(&name.as_str()) {
+            continue;
+        }
+
+        buf.push_str(&sql);
+        buf.push_str(";\n");
+
+        if kind != "table" {
+            continue;
+        }
+
+        let mut stmt = conn
+            .prepare(&format!("SELECT * FROM \"{}\"", name.replace('"', "\"\"")))
+            .map_err(|e| e.to_string())?;
+        let columns = stmt.column_count();
+        let mut rows = stmt.query(params![]).map_err(|e| e.to_string())?;
+
+        let mut row_number = 0;
+        while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+            row_number += 1;
+
+            let values = (0..columns)
+                .map(|i| {
+                    row.get_ref(i)
+                        .map_err(|e| e.to_string())
+                        .and_then(sql_literal)
+                        .map_err(|e| format!("column {}: {}", i, e))
+                })
+                .collect::<Result<Vec<_>, _>>();
+
+            match values {
+                Ok(values) => buf.push_str(&format!(
+                    "INSERT INTO \"{}\" VALUES({});\n",
+                    name.replace('"', "\"\""),
+                    values.join(",")
+                )),
+                Err(err) => {
+                    error!("SQL dump: skipping row {} of {}: {}", row_number, name, err);
+                    buf.push_str(&format!(
+                        "-- skipped row {} of {}: {}\n",
+                        row_number,
+                        name,
+                        err.replace('\n', " ")
+                    ));
+                    skipped += 1;
+                }
+            }
+
+            if buf.len() >= CHUNK && !send(std::mem::take(&mut buf)) {
+                return Ok(());
+            }
+        }
+    }
+
+    if skipped > 0 {
+        buf.push_str(&format!(
+            "-- {} rows skipped, see the comments above\n",
+            skipped
+        ));
+    }
+    buf.push_str("COMMIT;\n");
+    send(buf);
+    Ok(())
+}
+
+/// a SQLite value as an SQL literal, failing for text that isn't UTF-8 (the dump itself is UTF-8)
+fn sql_literal(value: rusqlite::types::ValueRef) -> Result<String, String> {
+    use rusqlite::types::ValueRef;
+
+    Ok(match value {
+        ValueRef::Null => "NULL".to_string(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) if f.is_infinite() => format!("{}9e999", if f < 0.0 { "-" } else { "" }),
+        ValueRef::Real(f) => format!("{:?}", f), // always has a decimal point or exponent, so it stays REAL
+        ValueRef::Text(text) => format!(
+            "'{}'",
+            std::str::from_utf8(text)
+                .map_err(|e| format!("text isn't valid UTF-8 ({})", e))?
+                .replace('\'', "''")
+        ),
+        ValueRef::Blob(blob) => format!(
+            "X'{}'",
+            blob.iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<String>()
+        ),
+    })
+}
+
+// endpoint to export the schema and data as an SQL dump, streamed as it is read (hyper)
+async fn dump_sql_endpoint(
+    db: &Db,
+    _req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let (tx, rx) = tokio::sync::mpsc::channel::<Bytes>(4);
+
+    let db = db.clone();
+    tokio::task::spawn_blocking(move || {
+        let send = |chunk: String| tx.blocking_send(Bytes::from(chunk)).is_ok();
+        let result = db.read(|conn| dump_sql(conn, send));
+
+        if let Err(err) = result {
+            // the status has already been sent, so all that's left is to say so in the dump itself
+            error!("SQL dump failed: {}", err);
+            let _ = tx.blocking_send(Bytes::from(format!("-- dump failed: {}\n", err)));
+        }
+    });
+
+    let filename = format!(
+        "attachment; filename=\"inventory-{}.sql\"",
+        chrono::Local::now().format("%Y-%m-%d")
+    );
+
+    let mut resp = Response::new(ChannelBody { rx }.boxed());
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/sql; charset=utf-8"),
+    );
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_DISPOSITION,
+        hyper::header::HeaderValue::from_str(&filename).unwrap(), // always ASCII
+    );
+
+    Ok(resp)
+}
+
+/// the SHA-256 of some bytes as lowercase hex
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// endpoint to download the raw SQLite database (hyper)
+// `X-Content-SHA256` is the hash of the database itself, so a client can check what it saved,
+// and the download is gzipped for clients that accept it
+async fn get_database(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    // with WAL, recent writes may only be in the -wal file until they're checkpointed into the database
+    let checkpoint = db.write(|conn| {
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", params![], |_| Ok(()))
+            .map_err(|e| e.to_string())
+    });
+    if let Err(err) = checkpoint {
+        warn!("Failed to checkpoint before /get_database: {}", err);
+    }
+
+    let database = match fs::read(&*db.path) {
+        Ok(database) => database,
+        Err(_) => {
+            let mut resp = text_response("Failed to read file");
+            *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
+            return Ok(resp);
+        }
+    };
+    let hash = sha256_hex(&database);
+
+    let gzipped = if accepts_gzip(&req) {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        match encoder.write_all(&database).and_then(|_| encoder.finish()) {
+            Ok(gzipped) => Some(gzipped),
+            Err(err) => {
+                // still worth sending, just bigger
+                warn!("Failed to gzip the database download: {}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut resp = match gzipped {
+        Some(gzipped) => {
+            let mut resp = Response::new(full(gzipped));
+            resp.headers_mut().insert(
+                hyper::header::CONTENT_ENCODING,
+                hyper::header::HeaderValue::from_static("gzip"),
+            );
+            resp
+        }
+        None => Response::new(full(database)),
+    };
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/octet-stream"),
+    );
+    resp.headers_mut().insert(
+        "x-content-sha256",
+        hyper::header::HeaderValue::from_str(&hash).unwrap(), // always hex
+    );
+    resp.headers_mut().insert(
+        hyper::header::VARY,
+        hyper::header::HeaderValue::from_static("accept-encoding"),
+    );
+
+    Ok(resp)
+}
+
+// endpoint to import items from CSV (hyper)
+// expected format: a header row then one item per row, e.g.
+/*
+```
+name,barcode,location,last_seen
+"Cable, XLR",42,Rig,1234567890
+```
+*/
+// `?dry_run=true` reports what would be created/updated/skipped without changing anything
+async fn import_csv(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let dry_run =
+        query_param(req.uri().query(), "dry_run").is_some_and(|dry_run| dry_run == "true");
+
+    let whole_body = match read_body(req).await {
+        Ok(whole_body) => whole_body,
+        Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+        Err(BodyError::Hyper(err)) => return Err(err),
+    };
+
+    let csv = match String::from_utf8(whole_body.to_vec()) {
+        Ok(csv) => csv,
+        Err(_) => {
+            let mut resp = text_response("CSV must be UTF-8");
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    match db
+        .write_blocking(move |conn| import_items(conn, &csv, dry_run))
+        .await
+    {
+        Ok(report) => Ok(json_response(
+            serde_json::to_string(&report).unwrap(), // plain data, always serializes
+        )),
+        Err(err) => {
+            let mut resp = text_response(err.clone());
+            *resp.status_mut() = if err.starts_with("Invalid CSV") {
+                hyper::StatusCode::BAD_REQUEST
+            } else {
+                hyper::StatusCode::INTERNAL_SERVER_ERROR
+            };
+            Ok(resp)
+        }
+    }
+}
+
+/// get a (percent-decoded) parameter from a query string like `a=1&b=two%20words`
+fn query_param(query: Option<&str>, key: &str) -> Option<String> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(k, _)| percent_decode(k) == key)
+        .map(|(_, v)| percent_decode(v))
+}
+
+/// decode `%XX` escapes and `+` (as space) in a query string component
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [iter.next(), iter.next()];
+                match hex {
+                    [Some(hi), Some(lo)] => {
+                        match u8::from_str_radix(&format!("{}{}", hi as char, lo as char), 16) {
+                            Ok(decoded) => bytes.push(decoded),
+                            Err(_) => bytes.extend([b'%', hi, lo]),
+                        }
+                    }
+                    [Some(hi), None] => bytes.extend([b'%', hi]),
+                    _ => bytes.push(b'%'),
+                }
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// whether the client asked for barcodes as strings (`?barcode_as=string`), so that JavaScript
+/// clients keep barcodes above 2^53 exact
+fn barcodes_as_strings<B>(req: &Request<B>) -> bool {
+    query_param(req.uri().query(), "barcode_as").is_some_and(|format| format == "string")
+}
+
+/// serialize a response body, turning every `barcode` field into a string if asked to
+fn to_json<T: Serialize>(
+    value: &T,
+    barcodes_as_strings: bool,
+) -> Result<String, serde_json::Error> {
+    let mut value = serde_json::to_value(value)?;
+    if barcodes_as_strings {
+        stringify_barcodes(&mut value);
+    }
+    serde_json::to_string(&value)
+}
+
+fn stringify_barcodes(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    serde_json::Value::Number(number)
+                        if key == "barcode"
+                            || key.ends_with("_barcode")
+                            || key == "matched_alias" =>
+                    {
+                        *value = serde_json::Value::String(number.to_string());
+                    }
+                    value => stringify_barcodes(value),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(stringify_barcodes),
+        _ => {}
+    }
+}
+
+fn cap_at_n(n: usize, s: &str) -> String {
+    if s.len() > n {
+        format!("{}...", &s[..n])
+    } else {
+        s.to_string()
+    }
+}
+
+/// a webclient file held in memory, with what conditional requests are checked against
+#[derive(Clone)]
+struct CachedFile {
+    body: Bytes,
+    modified: chrono::DateTime<Utc>,
+    etag: String,
+}
+
+/// read a file, or reuse the copy read last time if its modification time and size haven't changed,
+/// so repeat requests cost a stat rather than a read
+fn cached_file(path: &str) -> std::io::Result<CachedFile> {
+    static CACHE: std::sync::OnceLock<
+        std::sync::Mutex<std::collections::HashMap<String, CachedFile>>,
+    > = std::sync::OnceLock::new();
+
+    let metadata = fs::metadata(path)?;
+    let modified: chrono::DateTime<Utc> = metadata.modified()?.into();
+    // second precision, as Last-Modified can't say more
+    let etag = format!("\"{:x}-{:x}\"", modified.timestamp(), metadata.len());
+
+    let cache = CACHE.get_or_init(Default::default);
+    let cached = cache.lock().unwrap().get(path).cloned();
+    if let Some(cached) = cached.filter(|cached| cached.etag == etag) {
+        return Ok(cached);
+    }
+
+    let file = CachedFile {
+        body: Bytes::from(fs::read(path)?),
+        modified,
+        etag,
+    };
+    cache.lock().unwrap().insert(path.to_string(), file.clone());
+    Ok(file)
+}
+
+/// whether `If-None-Match` lists `etag` (weakly compared, or `*`)
+fn etag_matches(headers: &hyper::HeaderMap, etag: &str) -> bool {
+    headers
+        .get(hyper::header::IF_NONE_MATCH)
+        .and_then(|tags| tags.to_str().ok())
+        .is_some_and(|tags| {
+            tags.split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag)
+        })
+}
+
+/// whether the client's copy is still current, by `If-None-Match` or failing that `If-Modified-Since`
+fn not_modified(headers: &hyper::HeaderMap, file: &CachedFile) -> bool {
+    if headers.contains_key(hyper::header::IF_NONE_MATCH) {
+        return etag_matches(headers, &file.etag);
+    }
+
+    headers
+        .get(hyper::header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| {
+            chrono::NaiveDateTime::parse_from_str(since, "%a, %d %b %Y %H:%M:%S GMT").ok()
+        })
+        .is_some_and(|since| file.modified.timestamp() <= since.and_utc().timestamp())
+}
+
+/// serve a cached file, or 304 if the client already has it
+fn file_response(
+    file: CachedFile,
+    mime: &'static str,
+    headers: &hyper::HeaderMap,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let unchanged = not_modified(headers, &file);
+    let mut resp = if unchanged {
+        let mut resp = Response::new(full(Bytes::new()));
+        *resp.status_mut() = hyper::StatusCode::NOT_MODIFIED;
+        resp
+    } else {
+        Response::new(full(file.body))
+    };
+
+    let headers = resp.headers_mut();
+    headers.insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static(mime),
+    );
+    headers.insert(
+        hyper::header::ETAG,
+        hyper::header::HeaderValue::from_str(&file.etag).unwrap(), // hex digits and quotes
+    );
+    headers.insert(
+        hyper::header::LAST_MODIFIED,
+        hyper::header::HeaderValue::from_str(
+            &file
+                .modified
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        )
+        .unwrap(), // ASCII date
+    );
+    // keep it, but check back each time so edits to the webclient show up straight away
+    headers.insert(
+        hyper::header::CACHE_CONTROL,
+        hyper::header::HeaderValue::from_static("no-cache"),
+    );
+    resp
+}
+
+/// serve a text file from the webclient directory with a matching content type, see `file_response`
+///
+/// if the client accepts gzip and a precompressed `.gz` sibling exists, that is served instead
+fn webclient_file(
+    path: &str,
+    gzip: bool,
+    headers: &hyper::HeaderMap,
+    base: &str,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mime = match path {
+        "/index.html" => "text/html",
+        "/style.css" => "text/css",
+        "/script.js" => "application/javascript",
+        _ => "text/plain",
+    };
+
+    let precompressed = if gzip {
+        cached_file(&format!("../webclient{}.gz", path)).ok()
+    } else {
+        None
+    };
+
+    if let Some(precompressed) = precompressed {
+        let mut resp = file_response(precompressed, mime, headers);
+        resp.headers_mut().insert(
+            hyper::header::CONTENT_ENCODING,
+            hyper::header::HeaderValue::from_static("gzip"),
+        );
+        resp.headers_mut().insert(
+            hyper::header::VARY,
+            hyper::header::HeaderValue::from_static("Accept-Encoding"),
+        );
+        return resp;
+    }
+
+    match cached_file(&format!("../webclient{}", path)) {
+        Ok(file) => {
+            let mut resp = file_response(file, mime, headers);
+            resp.headers_mut().insert(
+                hyper::header::VARY,
+                hyper::header::HeaderValue::from_static("Accept-Encoding"),
+            );
+            resp
+        }
+        // an API-only install, see `no_webclient_page`
+        Err(_) if !webclient_installed() => match path {
+            "/index.html" => no_webclient_page(base),
+            _ => {
+                let mut resp = text_response("The web client isn't installed");
+                *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
+                resp
+            }
+        },
+        Err(_) => {
+            let mut resp = text_response("Failed to read file");
+            *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
+            resp
+        }
+    }
+}
+
+/// whether the web client is there to serve, checked once (the server warns at startup if it isn't)
+fn webclient_installed() -> bool {
+    static INSTALLED: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+    *INSTALLED.get_or_init(|| std::path::Path::new("../webclient/index.html").is_file())
+}
+
+/// the page browsers get instead of the web client when it isn't installed: what's running, and
+/// links to the API
+fn no_webclient_page(base: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let routes: String = ROUTES
+        .iter()
+        .filter(|route| route.api)
+        .map(|route| {
+            // only routes without parameters can be followed as they are
+            let path = format!("{}{}", base, route.pattern);
+            let path = match route.pattern.contains('{') {
+                true => format!("<code>{}</code>", path),
+                false => format!("<a href=\"{0}\"><code>{0}</code></a>", path),
+            };
+            format!(
+                "<li>{} {} - {}</li>\n",
+                route.methods, path, route.description
+            )
+        })
+        .collect();
+
+    let body = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body>\n\
+         <h1>{0} {1}</h1>\n\
+         <p>The API is running, but the web client isn't installed (no <code>../webclient/index.html</code>).</p>\n\
+         <ul>\n{2}</ul>\n</body></html>\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        routes
+    );
+
+    let mut resp = Response::new(full(body));
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    resp
+}
+
+/// whether the client accepts gzip-encoded responses (`Accept-Encoding: gzip`, not `gzip;q=0`)
+fn accepts_gzip<B>(req: &Request<B>) -> bool {
+    let accept_encoding = match req.headers().get(hyper::header::ACCEPT_ENCODING) {
+        Some(accept_encoding) => accept_encoding.to_str().unwrap_or(""),
+        None => "",
+    };
+
+    accept_encoding.split(',').any(|encoding| {
+        let mut parts = encoding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("");
+        let disabled = parts.any(|param| {
+            param
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !disabled
+    })
+}
+
+/// whether the request is a browser navigation (wants a page) rather than an API call
+fn wants_html<B>(req: &Request<B>) -> bool {
+    if req.method() != hyper::Method::GET {
+        return false;
+    }
+
+    let accept = match req.headers().get(hyper::header::ACCEPT) {
+        Some(accept) => accept.to_str().unwrap_or(""),
+        None => "",
+    };
+
+    accept.contains("text/html") && !accept.contains("application/json")
+}
+
+/// an entry in the route table, used both to dispatch requests and to describe the API at `/`
+struct Route {
+    /// path as shown in the index, `{barcode}` marks a trailing path parameter
+    pattern: &'static str,
+    methods: &'static str,
+    description: &'static str,
+    /// API routes are listed in the index and are not client routes of the webclient
+    api: bool,
+}
+
+impl Route {
+    /// the literal part of the pattern, up to any path parameter
+    fn prefix(&self) -> &'static str {
+        match self.pattern.find('{') {
+            Some(idx) => &self.pattern[..idx],
+            None => self.pattern,
+        }
+    }
+
+    /// whether a path fits the pattern, a `{param}` standing for any one segment
+    fn matches(&self, path: &str) -> bool {
+        let mut pattern = self.pattern.split('/');
+        let mut path = path.split('/');
+
+        loop {
+            match (pattern.next(), path.next()) {
+                (None, None) => return true,
+                (Some(expected), Some(segment))
+                    if expected == segment || expected.starts_with('{') => {}
+                _ => return false,
+            }
+        }
+    }
+}
+
+/// every route the server answers, in match order
+const ROUTES: &[Route] = &[
+    Route {
+        pattern: "/",
+        methods: "GET",
+        description: "webclient for browsers, this index otherwise",
+        api: false,
+    },
+    Route {
+        pattern: "/index.html",
+        methods: "GET",
+        description: "webclient page",
+        api: false,
+    },
+    Route {
+        pattern: "/style.css",
+        methods: "GET",
+        description: "webclient stylesheet",
+        api: false,
+    },
+    Route {
+        pattern: "/script.js",
+        methods: "GET",
+        description: "webclient script",
+        api: false,
+    },
+    Route {
+        pattern: "/config.js",
+        methods: "GET",
+        description: "webclient settings, such as the field policy",
+        api: false,
+    },
+    Route {
+        pattern: "/favicon.ico",
+        methods: "GET",
+        description: "webclient icon",
+        api: false,
+    },
+    Route {
+        pattern: "/new",
+        methods: "POST",
+        description: "create an item from a JSON body",
+        api: true,
+    },
+    Route {
+        pattern: "/check/{barcode}",
+        methods: "GET",
+        description: "whether a barcode is free for a new item, without creating anything",
+        api: true,
+    },
+    Route {
+        pattern: "/all",
+        methods: "GET",
+        description: "list items, a page of the most recently seen by default; ?limit= (or all) and ?offset= to page",
+        api: true,
+    },
+    Route {
+        pattern: "/changes",
+        methods: "GET",
+        description: "items changed and barcodes deleted since ?since=<counter>, the number in /all's ETag",
+        api: true,
+    },
+    Route {
+        pattern: "/sync",
+        methods: "GET",
+        description: "barcodes created, updated and deleted since ?since=<cursor>, and the new cursor",
+        api: true,
+    },
+    Route {
+        pattern: "/attention",
+        methods: "GET",
+        description: "stale items and items whose status isn't ok, ?stale_days=30",
+        api: true,
+    },
+    Route {
+        pattern: "/valuation",
+        methods: "GET",
+        description: "total item value overall and per location, for insurance",
+        api: true,
+    },
+    Route {
+        pattern: "/item/{barcode}/parent",
+        methods: "POST, DELETE",
+        description: "pack an item inside another ({\"parent_barcode\": 42}) or unpack it",
+        api: true,
+    },
+    Route {
+        pattern: "/item/{barcode}/children",
+        methods: "GET",
+        description: "the items packed directly inside an item",
+        api: true,
+    },
+    Route {
+        pattern: "/item/{barcode}/aliases",
+        methods: "GET, POST, DELETE",
+        description: "an item's alternate barcodes, {\"alias\": 5012345678900} to add or remove one",
+        api: true,
+    },
+    Route {
+        pattern: "/item/{barcode}/trail",
+        methods: "GET",
+        description: "an item's recent moves, newest first, ?limit=5",
+        api: true,
+    },
+    Route {
+        pattern: "/item/{barcode}/history",
+        methods: "GET",
+        description: "everywhere an item has been seen, newest first, ?limit=50 and ?before=",
+        api: true,
+    },
+    Route {
+        pattern: "/item/{barcode}/reservations",
+        methods: "GET, POST",
+        description: "an item's upcoming reservations, or book it",
+        api: true,
+    },
+    Route {
+        pattern: "/item/{barcode}/reservations/{id}",
+        methods: "DELETE",
+        description: "cancel a reservation",
+        api: true,
+    },
+    Route {
+        pattern: "/reservations",
+        methods: "GET",
+        description: "reservations in a date range, ?from=&to= as unix timestamps",
+        api: true,
+    },
+    Route {
+        pattern: "/reservations/conflicts",
+        methods: "GET",
+        description: "double-booked items",
+        api: true,
+    },
+    Route {
+        pattern: "/item/{barcode}/maintenance",
+        methods: "GET, POST",
+        description: "list an item's maintenance log, or append to it",
+        api: true,
+    },
+    Route {
+        pattern: "/maintenance/due",
+        methods: "GET",
+        description: "items due maintenance, ?type=pat&older_than_days=365",
+        api: true,
+    },
+    Route {
+        pattern: "/item/{barcode}",
+        methods: "GET",
+        description: "get one item, with its version as ETag",
+        api: true,
+    },
+    Route {
+        pattern: "/location/{location}",
+        methods: "GET",
+        description: "list the items at a location, ignoring case",
+        api: true,
+    },
+    Route {
+        pattern: "/search",
+        methods: "GET",
+        description: "items whose name and/or location contain ?name= and ?location=, or either ?q=, ignoring case",
+        api: true,
+    },
+    Route {
+        pattern: "/locations",
+        methods: "GET",
+        description: "list every location with its item count",
+        api: true,
+    },
+    Route {
+        pattern: "/move",
+        methods: "POST",
+        description: "move an item (and with cascade, its contents), returning its recent trail",
+        api: true,
+    },
+    Route {
+        pattern: "/modify",
+        methods: "POST",
+        description: "replace an item, honouring If-Match",
+        api: true,
+    },
+    Route {
+        pattern: "/delete/{barcode}",
+        methods: "DELETE, GET",
+        description: "delete an item",
+        api: true,
+    },
+    Route {
+        pattern: "/note/{barcode}",
+        methods: "POST",
+        description: "add a note to an item, keeping the ones before it",
+        api: true,
+    },
+    Route {
+        pattern: "/archive/{barcode}",
+        methods: "POST",
+        description: "move an item into the archive, with ?history=true its logs too",
+        api: true,
+    },
+    Route {
+        pattern: "/unarchive/{barcode}",
+        methods: "POST",
+        description: "bring an archived item back, unless its barcode has been reused",
+        api: true,
+    },
+    Route {
+        pattern: "/archived",
+        methods: "GET",
+        description: "list archived items, ?limit= and ?offset= to page",
+        api: true,
+    },
+    Route {
+        pattern: "/log/{barcode}",
+        methods: "POST, GET",
+        description: "mark an item as seen now",
+        api: true,
+    },
+    Route {
+        pattern: "/adjust/{barcode}",
+        methods: "POST",
+        description: "add to or take from an item's quantity, {\"delta\": -2}, stopping at zero",
+        api: true,
+    },
+    Route {
+        pattern: "/history/{barcode}",
+        methods: "GET",
+        description: "every scan of an item and where it was, oldest first, ?limit= and ?offset= to page",
+        api: true,
+    },
+    Route {
+        pattern: "/export.xlsx",
+        methods: "GET",
+        description: "download all items as a spreadsheet, ?include_archived=true adds the archive",
+        api: true,
+    },
+    Route {
+        pattern: "/report.pdf",
+        methods: "GET",
+        description: "a printable stock report, optionally for one location",
+        api: true,
+    },
+    Route {
+        pattern: "/dump.sql",
+        methods: "GET",
+        description: "download the schema and data as SQL statements",
+        api: true,
+    },
+    Route {
+        pattern: "/import.csv",
+        methods: "POST",
+        description: "create and update items from CSV, ?dry_run=true to preview",
+        api: true,
+    },
+    Route {
+        pattern: "/decode",
+        methods: "POST",
+        description: "decode barcodes in an uploaded image",
+        api: true,
+    },
+    Route {
+        pattern: "/health",
+        methods: "GET, HEAD",
+        description: "server status and last optimize time",
+        api: true,
+    },
+    Route {
+        pattern: "/activity",
+        methods: "GET",
+        description: "items created, modified, deleted and scanned per day",
+        api: true,
+    },
+    Route {
+        pattern: "/status",
+        methods: "GET",
+        description: "uptime, requests served and in flight, open connections, database size and item count",
+        api: true,
+    },
+    Route {
+        pattern: "/version",
+        methods: "GET",
+        description: "server name and version",
+        api: true,
+    },
+    Route {
+        pattern: "/reset",
+        methods: "POST",
+        description: "delete all items, needs a confirmation token",
+        api: true,
+    },
+    Route {
+        pattern: "/admin/reload",
+        methods: "POST",
+        description: "re-read the config file's log level, envelope and slow_ms, as SIGHUP does",
+        api: true,
+    },
+    Route {
+        pattern: "/get_database",
+        methods: "GET",
+        description: "download the raw SQLite database",
+        api: true,
+    },
+];
+
+/// the route table entry for a path, if any
+fn find_route(path: &str) -> Option<&'static Route> {
+    ROUTES.iter().find(|route| route.matches(path))
+}
+
+/// whether a path looks like a client-side route of the webclient rather than a file or API call
+///
+/// `/item/42` is both an API route and a client route, so browsers navigating there get the webclient,
+/// as do unknown paths without a file extension
+fn is_client_route(path: &str) -> bool {
+    let has_extension = path.rsplit('/').next().unwrap_or("").contains('.');
+
+    match find_route(path) {
+        Some(route) => route.pattern == "/item/{barcode}",
+        None => !has_extension,
+    }
+}
+
+/// endpoint for the API index at `/` for non-browser clients (hyper)
+fn api_index(base: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let routes: Vec<serde_json::Value> = ROUTES
+        .iter()
+        .filter(|route| route.api)
+        .map(|route| {
+            serde_json::json!({
+                "path": format!("{}{}", base, route.pattern),
+                "methods": route.methods.split(", ").collect::<Vec<_>>(),
+                "description": route.description,
+            })
+        })
+        .collect();
+
+    let body = serde_json::json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION"),
+        "routes": routes,
+    });
+
+    let mut resp = json_response(body.to_string());
+    *resp.status_mut() = hyper::StatusCode::OK;
+    resp
+}
+
+/// levenshtein distance between two strings, used for "did you mean" suggestions
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+
+    prev[b.len()]
+}
+
+/// the closest known route to an unknown path, keeping any trailing parameter (`/items/42` -> `/item/42`)
+fn suggest_route(path: &str) -> Option<String> {
+    ROUTES
+        .iter()
+        .filter_map(|route| {
+            let (head, rest) = if route.pattern.contains('{') {
+                // compare the first segment only, and carry the rest over to the suggestion
+                let trimmed = path.strip_prefix('/').unwrap_or(path);
+                match trimmed.find('/') {
+                    Some(idx) => (&path[..idx + 2], &path[idx + 2..]),
+                    None => (path, ""),
+                }
+            } else {
+                (path, "")
+            };
+
+            let distance = edit_distance(head, route.prefix());
+            (distance <= 2).then(|| (distance, format!("{}{}", route.prefix(), rest)))
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, suggestion)| suggestion)
+}
+
+/// send a browser on to `location` (hyper)
+fn redirect(location: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut resp = Response::new(full(""));
+    *resp.status_mut() = hyper::StatusCode::FOUND;
+    if let Ok(location) = hyper::header::HeaderValue::from_str(location) {
+        resp.headers_mut().insert(hyper::header::LOCATION, location);
+    }
+    resp
+}
+
+/// endpoint for unknown routes, with a "did you mean" suggestion when one is close (hyper)
+fn not_found(path: &str, base: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let body = serde_json::json!({
+        "error": "Not found",
+        "path": format!("{}{}", base, path),
+        "suggestion": suggest_route(path).map(|suggestion| format!("{}{}", base, suggestion)),
+    });
+
+    let mut resp = json_response(body.to_string());
+    *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
+    resp
+}
+
+/// pick the handler for a request
+async fn route(
+    db: &Db,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    // SPA fallback: browsers refreshing on a deep link like /item/42 get the webclient, API clients get JSON;
+    // the page's links are relative, so it's only served from the base path's root and deep links go there
+    let spa_fallback = wants_html(&req) && is_client_route(req.uri().path());
+    let browser = wants_html(&req);
+    let gzip = accepts_gzip(&req);
+    let base = mounted_at(&req);
+
+    if spa_fallback {
+        return Ok(redirect(&format!("{}/", base)));
+    }
+
+    let path = req.uri().path().to_string();
+
+    match find_route(&path).map(|route| route.pattern) {
+        Some("/") if browser => Ok(webclient_file("/index.html", gzip, req.headers(), base)),
+        Some("/") => Ok(api_index(base)),
+        Some("/index.html" | "/style.css" | "/script.js") => {
+            Ok(webclient_file(&path, gzip, req.headers(), base))
+        }
+        Some("/config.js") => config_js(req).await,
+        Some("/new") => new_item(db, req).await,
+        Some("/check/{barcode}") => check_barcode(db, req).await,
+        Some("/all") => all_items(db, req).await,
+        Some("/changes") => changes(db, req).await,
+        Some("/sync") => sync(db, req).await,
+        Some("/activity") => activity(db, req).await,
+        Some("/attention") => attention(db, req).await,
+        Some("/valuation") => valuation(db, req).await,
+        Some("/item/{barcode}/parent") => parent_endpoint(db, req).await,
+        Some("/item/{barcode}/children") => children(db, req).await,
+        Some("/item/{barcode}/aliases") => aliases(db, req).await,
+        Some("/item/{barcode}/trail") => trail(db, req).await,
+        Some("/item/{barcode}/history") => history(db, req).await,
+        Some("/item/{barcode}/reservations") => reservations(db, req).await,
+        Some("/item/{barcode}/reservations/{id}") => cancel_reservation_endpoint(db, req).await,
+        Some("/reservations") => calendar(db, req).await,
+        Some("/reservations/conflicts") => reservation_conflicts_endpoint(db, req).await,
+        Some("/item/{barcode}/maintenance") => maintenance_endpoint(db, req).await,
+        Some("/maintenance/due") => maintenance_due_endpoint(db, req).await,
+        Some("/item/{barcode}") => item(db, req).await,
+        Some("/location/{location}") => location_items(db, req).await,
+        Some("/search") => search(db, req).await,
+        Some("/locations") => locations(db, req).await,
+        Some("/move") => move_endpoint(db, req).await,
+        Some("/modify") => modify_item_endpoint(db, req).await,
+        Some("/delete/{barcode}") => delete_item_endpoint(db, req).await,
+        Some("/note/{barcode}") => note_endpoint(db, req).await,
+        Some("/archive/{barcode}") => archive_endpoint(db, req).await,
+        Some("/unarchive/{barcode}") => unarchive_endpoint(db, req).await,
+        Some("/archived") => archived(db, req).await,
+        Some("/log/{barcode}") => log_item(db, req).await,
+        Some("/adjust/{barcode}") => adjust_endpoint(db, req).await,
+        Some("/history/{barcode}") => scans(db, req).await,
+        Some("/export.xlsx") => export_xlsx(db, req).await,
+        Some("/report.pdf") => report_pdf(db, req).await,
+        Some("/dump.sql") => dump_sql_endpoint(db, req).await,
+        Some("/import.csv") => import_csv(db, req).await,
+        Some("/decode") => decode_endpoint(db, req).await,
+        Some("/health") => health(req).await,
+        Some("/status") => status(db, req).await,
+        Some("/version") => version(req).await,
+        Some("/reset") => reset(db, req).await,
+        Some("/admin/reload") => reload_endpoint(req).await,
+        // requested on every page load, so it's worth answering from memory with a 304 where possible
+        Some("/favicon.ico") => match cached_file("../webclient/favicon.ico") {
+            Ok(file) => Ok(file_response(file, "image/x-icon", req.headers())),
+            Err(_) => {
+                let mut resp = text_response("Failed to read file");
+                *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
+                Ok(resp)
+            }
+        },
+        Some("/get_database") => get_database(db, req).await,
+
+        _ => Ok(not_found(&path, base)),
+    }
+}
+
+/// whether a route changes data, so repeats with the same Idempotency-Key must not run it again
+fn is_mutation(path: &str, query: Option<&str>) -> bool {
+    [
+        "/new",
+        "/modify",
+        "/move",
+        "/decode",
+        "/reset",
+        "/import.csv",
+    ]
+    .contains(&path)
+        || (path.starts_with("/item/")
+            && (path.ends_with("/maintenance")
+                || path.ends_with("/parent")
+                || path.ends_with("/aliases")
+                || path.contains("/reservations")
+                || query_param(query, "touch").is_some_and(|touch| touch == "true")))
+        || path.starts_with("/delete/")
+        || path.starts_with("/log/")
+        || path.starts_with("/adjust/")
+        || path.starts_with("/note/")
+        || path.starts_with("/archive/")
+        || path.starts_with("/unarchive/")
+}
+
+/// run a mutating request at most once per Idempotency-Key, replaying the stored response for repeats
+/// of the same request; a key reused for a different method, path or body gets a 422
+async fn idempotent(
+    db: &Db,
+    key: String,
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let release = |key: String| db.write_blocking(move |conn| release_idempotency_key(conn, &key));
+
+    // the body is read up front to be hashed, then handed on to the route as it came
+    let limit = body_limits().limit(req.uri().path());
+    let (parts, body) = req.into_parts();
+    let body = match read_limited(body, limit).await {
+        Ok(body) => body,
+        Err(BodyError::TooLarge(limit)) => return Ok(too_large(limit)),
+        Err(BodyError::Hyper(err)) => return Err(err),
+    };
+    let request = IdempotentRequest {
+        method: parts.method.to_string(),
+        path: parts
+            .uri
+            .path_and_query()
+            .map_or_else(|| parts.uri.path().to_string(), ToString::to_string),
+        body_hash: sha256_hex(&body),
+    };
+    let req = Request::from_parts(parts, full(body));
+
+    match db
+        .write_blocking({
+            let key = key.clone();
+            move |conn| claim_idempotency_key(conn, &key, &request)
+        })
+        .await
+    {
+        Ok(IdempotencyClaim::New) => {}
+        Ok(IdempotencyClaim::Done(status, content_type, body)) => {
+            let mut resp = Response::new(full(body));
+            *resp.status_mut() =
+                hyper::StatusCode::from_u16(status).unwrap_or(hyper::StatusCode::OK);
+            if let Some(content_type) = content_type
+                .and_then(|content_type| hyper::header::HeaderValue::from_str(&content_type).ok())
+            {
+                resp.headers_mut()
+                    .insert(hyper::header::CONTENT_TYPE, content_type);
+            }
+            resp.headers_mut().insert(
+                "idempotent-replayed",
+                hyper::header::HeaderValue::from_static("true"),
+            );
+            return Ok(resp);
+        }
+        Ok(IdempotencyClaim::InProgress) => {
+            let mut resp =
+                text_response("A request with this Idempotency-Key is still in progress");
+            *resp.status_mut() = hyper::StatusCode::CONFLICT;
+            return Ok(resp);
+        }
+        Ok(IdempotencyClaim::Mismatch) => {
+            let mut resp = text_response("This Idempotency-Key was used for a different request");
+            *resp.status_mut() = hyper::StatusCode::UNPROCESSABLE_ENTITY;
+            return Ok(resp);
+        }
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+            return Ok(resp);
+        }
+    }
+
+    let resp = match route(db, req).await {
+        Ok(resp) => resp,
+        Err(err) => {
+            let _ = release(key.clone()).await;
+            return Err(err);
+        }
+    };
+
+    let (parts, body) = resp.into_parts();
+    let body = match body.collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(err) => {
+            let _ = release(key.clone()).await;
+            return Err(err);
+        }
+    };
+
+    // server errors are usually transient, so let a retry actually run again
+    let stored = if parts.status.is_server_error() {
+        release(key.clone()).await
+    } else {
+        let (key, status, body) = (key.clone(), parts.status.as_u16(), body.clone());
+        let content_type = parts
+            .headers
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map(str::to_string);
+        db.write_blocking(move |conn| {
+            store_idempotent_response(conn, &key, status, content_type.as_deref(), &body)
+        })
+        .await
+    };
+    if let Err(err) = stored {
+        warn!(
+            "Failed to store response for Idempotency-Key {}: {}",
+            key, err
+        );
+    }
+
+    Ok(Response::from_parts(parts, full(body)))
+}
+
+/// whether a request wants its response enveloped, `?envelope=true` or `false` overriding the default
+fn wants_envelope(query: Option<&str>, default: bool) -> bool {
+    match query_param(query, "envelope").as_deref() {
+        Some("true" | "1") => true,
+        Some("false" | "0") => false,
+        _ => default,
+    }
+}
+
+/// wrap a JSON or plain text response as `{"ok": true, "data": ..}` or `{"ok": false, "error": ..}`,
+/// keeping the status; files, spreadsheets and empty 304s are passed through untouched
+async fn envelope(
+    resp: Response<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    let wrappable = match resp.headers().get(hyper::header::CONTENT_TYPE) {
+        Some(content_type) => content_type.to_str().is_ok_and(|content_type| {
+            content_type.starts_with("application/json") || content_type.starts_with("text/plain")
+        }),
+        None => false,
+    };
+    if !wrappable || resp.status() == hyper::StatusCode::NOT_MODIFIED {
+        return Ok(resp);
+    }
+
+    let (mut parts, body) = resp.into_parts();
+    let body = body.collect().await?.to_bytes();
+
+    // JSON bodies are nested as they are, anything else (e.g. "OK", an error message) as a string
+    let payload = serde_json::from_slice::<serde_json::Value>(&body)
+        .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(&body).into_owned()));
+
+    let wrapped = if parts.status.is_success() {
+        serde_json::json!({"ok": true, "data": payload})
+    } else {
+        serde_json::json!({"ok": false, "error": payload})
+    };
+
+    parts.headers.insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    Ok(Response::from_parts(parts, full(wrapped.to_string())))
+}
+
+/// how many requests took longer than the slow-request threshold, reported by `/health`
+static SLOW_REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// run a handler, timing it along with the slowest database operation it ran
+async fn timed<F: Future>(handler: F) -> (F::Output, Duration, Option<(&'static str, Duration)>) {
+    SLOWEST_QUERY
+        .scope(std::cell::Cell::new(None), async {
+            let start = std::time::Instant::now();
+            let output = handler.await;
+            (
+                output,
+                start.elapsed(),
+                SLOWEST_QUERY.with(|slowest| slowest.get()),
+            )
+        })
+        .await
+}
+
+/// warn about (and count) a request that took longer than `threshold`, returning whether it did
+fn note_slow_request(
+    method: &hyper::Method,
+    path: &str,
+    elapsed: Duration,
+    slowest_query: Option<(&'static str, Duration)>,
+    threshold: Option<Duration>,
+) -> bool {
+    let threshold = match threshold {
+        Some(threshold) if elapsed > threshold => threshold,
+        _ => return false,
+    };
+
+    SLOW_REQUESTS.fetch_add(1, Ordering::Relaxed);
+
+    let culprit = match slowest_query {
+        Some((operation, took)) => format!(
+            "slowest database operation {} took {:.3}ms",
+            operation,
+            took.as_secs_f64() * 1000.0
+        ),
+        None => "no database operations".to_string(),
+    };
+
+    warn!(
+        "slow request: {} {} took {:.3}ms (threshold {}ms), {}",
+        method,
+        path,
+        elapsed.as_secs_f64() * 1000.0,
+        threshold.as_millis(),
+        culprit
+    );
+
+    true
+}
+
+/// the sub-path the server is mounted under behind a reverse proxy, from BARCODE_BASE_PATH
+/// (e.g. `/inventory`; default empty, the root)
+fn base_path() -> &'static str {
+    static BASE_PATH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+    BASE_PATH
+        .get_or_init(|| normalize_base_path(&env::var("BARCODE_BASE_PATH").unwrap_or_default()))
+}
+
+/// a base path with one leading slash and no trailing one, or empty for the root
+fn normalize_base_path(base: &str) -> String {
+    match base.trim().trim_matches('/') {
+        "" => String::new(),
+        base => format!("/{}", base),
+    }
+}
+
+/// a request path with `base` taken off the front, `None` if it's outside `base`
+fn strip_base_path<'a>(path: &'a str, base: &str) -> Option<&'a str> {
+    match path.strip_prefix(base)? {
+        "" => Some("/"),
+        rest if rest.starts_with('/') => Some(rest),
+        _ => None, // "/inventoryx" isn't under "/inventory"
+    }
+}
+
+/// the base path a request came in under, see `unmount`
+#[derive(Debug, Clone, Copy)]
+struct BasePath(&'static str);
+
+/// the base path a request came in under, for putting back on URLs sent to the client
+fn mounted_at<B>(req: &Request<B>) -> &'static str {
+    req.extensions().get::<BasePath>().map_or("", |base| base.0)
+}
+
+/// a request with `base` taken off its path so routes match as if mounted at the root,
+/// remembering `base` for `mounted_at`; `None` if the path is outside `base`
+fn unmount<B>(req: Request<B>, base: &'static str) -> Option<Request<B>> {
+    if base.is_empty() {
+        return Some(req);
+    }
+
+    let path = strip_base_path(req.uri().path(), base)?;
+    let uri = match req.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+    let uri = uri.parse::<hyper::Uri>().ok()?;
+
+    let (mut parts, body) = req.into_parts();
+    parts.uri = uri;
+    parts.extensions.insert(BasePath(base));
+    Some(Request::from_parts(parts, body))
+}
+
+async fn dispatch(
+    db: Db,
+    config: Arc<LiveConfig>,
+    req: Request<Incoming>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    dispatch_under(&db, &config, base_path(), req).await
+}
+
+/// `dispatch` for a server mounted under `base`
+async fn dispatch_under(
+    db: &Db,
+    config: &Arc<LiveConfig>,
+    base: &'static str,
+    mut req: Request<Incoming>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    // read once, so a reload part way through a request doesn't mix old and new settings
+    let settings = config.current();
+    req.extensions_mut().insert(config.clone());
+
+    let user_agent = match req.headers().get(USER_AGENT) {
+        Some(user_agent) => user_agent.to_str().unwrap_or("unknown"),
+        None => "unknown",
+    };
+
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let user_agent = cap_at_n(25, user_agent);
+
+    let idempotency_key = req
+        .headers()
+        .get("idempotency-key")
+        .and_then(|key| key.to_str().ok())
+        .map(str::to_string);
+
+    let enveloped = wants_envelope(req.uri().query(), settings.envelope);
+    let in_flight = Gauge::enter(&REQUESTS_IN_FLIGHT);
+
+    // time the handler itself (database work included), not writing the body to the socket
+    let (res, elapsed, slowest_query) = timed(async {
+        // "/inventory" would resolve the page's relative links against the host root, so add the slash
+        if !base.is_empty() && path == base && wants_html(&req) {
+            return Ok(redirect(&format!("{}/", base)));
+        }
+        let Some(req) = unmount(req, base) else {
+            return Ok(not_found(&path, ""));
+        };
+        let req = req.map(|body| body.boxed());
+        let res = match idempotency_key {
+            Some(key) if is_mutation(req.uri().path(), req.uri().query()) => {
+                idempotent(db, key, req).await
+            }
+            _ => route(db, req).await,
+        };
+        match res {
+            Ok(resp) if enveloped => envelope(resp).await,
+            res => res,
+        }
+    })
+    .await;
+
+    drop(in_flight);
+    REQUESTS_SERVED.fetch_add(1, Ordering::Relaxed);
+
+    let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+    note_slow_request(&method, &path, elapsed, slowest_query, settings.slow);
+
+    if let Ok(response) = res.as_ref() {
+        info!(
+            "{} {} from {} -> {} in {:.3}ms",
+            method,
+            path,
+            user_agent,
+            response.status(),
+            elapsed_ms
+        );
+    } else {
+        error!(
+            "{} {} from {} -> couldn't process request (unknown error) in {:.3}ms",
+            method, path, user_agent, elapsed_ms
+        );
+    }
+
+    res.map(|mut resp| {
+        // add CORS headers
+        resp.headers_mut().insert(
+            hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            hyper::header::HeaderValue::from_static("*"),
+        );
+        resp.headers_mut().insert(
+            "x-response-time-ms",
+            hyper::header::HeaderValue::from_str(&format!("{:.3}", elapsed_ms)).unwrap(), // always a plain number
+        );
+        resp
+    })
+}
+
+fn setup_if_not_exists(db: &Db) {
+    let conn = db.conn().unwrap();
+    let result = conn.execute(
+        "CREATE TABLE IF NOT EXISTS items (
+            id INTEGER PRIMARY KEY,
+            name VARCHAR NOT NULL,
+            barcode INTEGER NOT NULL UNIQUE,
+            location VARCHAR NOT NULL,
+            last_seen TIMESTAMP NOT NULL,
+            version INTEGER NOT NULL DEFAULT 1,
+            status TEXT NOT NULL DEFAULT 'ok'
+        )",
+        params![],
+    );
+
+    if let Err(e) = result {
+        panic!("Failed to create table: {}", e);
+    }
+
+    if let Err(e) = upgrade_schema(&conn) {
+        panic!("Failed to upgrade schema: {}", e);
+    }
+
+    // readers don't wait on writers (nor writers on readers), whether they share the pool or
+    // use a separate read connection; the setting is kept in the file
+    if let Err(e) = conn.pragma_update(None, "journal_mode", "WAL") {
+        panic!("Failed to enable WAL: {}", e);
+    }
+}
+
+/// whether a table has a column
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool, String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .map_err(|e| e.to_string())?;
+    let exists = stmt
+        .query_map(params![], |row| row.get::<_, String>(1))
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .any(|name| name == column);
+    Ok(exists)
+}
+
+/// add a column to a table unless it is already there, returning whether it was added
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<bool, String> {
+    if has_column(conn, table, column)? {
+        return Ok(false);
+    }
+
+    conn.execute(
+        &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+        params![],
+    )
+    .map_err(|e| e.to_string())?;
+    info!("Added column {}.{}", table, column);
+
+    Ok(true)
+}
+
+/// give items an explicit `id INTEGER PRIMARY KEY`, so references to an item survive a VACUUM
+/// (which may renumber an implicit rowid); existing rows keep their rowid as their id
+///
+/// SQLite can't add a primary key to a table, so the table is rebuilt in one transaction
+fn add_item_ids(conn: &Connection) -> Result<(), String> {
+    if has_column(conn, "items", "id")? {
+        return Ok(());
+    }
+
+    // dropping the old table must not cascade into tables referencing it
+    conn.pragma_update(None, "foreign_keys", "OFF")
+        .map_err(|e| e.to_string())?;
+
+    let result = rebuild_items_with_ids(conn);
+
+    conn.pragma_update(None, "foreign_keys", "ON")
+        .map_err(|e| e.to_string())?;
+    result.map_err(|e| e.to_string())?;
+    info!("Added column items.id");
+
+    Ok(())
+}
+
+/// the table rebuild behind `add_item_ids`, rolled back if any step fails
+fn rebuild_items_with_ids(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch("BEGIN IMMEDIATE")?;
+
+    // another connection may have migrated while we waited for the lock
+    if has_column(conn, "items", "id").unwrap_or(false) {
+        return conn.execute_batch("ROLLBACK");
+    }
+
+    let migrated = conn.execute_batch(
+        "CREATE TABLE items_new (
+            id INTEGER PRIMARY KEY,
+            name VARCHAR NOT NULL,
+            barcode INTEGER NOT NULL UNIQUE,
+            location VARCHAR NOT NULL,
+            last_seen TIMESTAMP NOT NULL,
+            version INTEGER NOT NULL DEFAULT 1
+        );
+        INSERT INTO items_new (id, name, barcode, location, last_seen, version)
+            SELECT rowid, name, barcode, location, last_seen, version FROM items;
+        DROP TABLE items;
+        ALTER TABLE items_new RENAME TO items;
+        COMMIT;",
+    );
+
+    if migrated.is_err() {
+        let _ = conn.execute_batch("ROLLBACK");
+    }
+    migrated
+}
+
+/// merge stored locations that differ only in case or whitespace ("rig", "Rig", "RIG ") into one
+/// spelling, the most common one unless BARCODE_LOCATION_CASE says otherwise; cheap enough to run on every start
+fn normalize_locations(conn: &Connection) -> Result<(), String> {
+    let mut stmt = conn
+        .prepare("SELECT location FROM items GROUP BY location ORDER BY COUNT(*) DESC, MIN(id)")
+        .map_err(|e| e.to_string())?;
+    let spellings = stmt
+        .query_map(params![], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    // group spellings by their case-folded form, most common first
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    for spelling in spellings {
+        let key = normalize_location(&spelling, LocationCase::Lower);
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, variants)) => variants.push(spelling),
+            None => groups.push((key, vec![spelling])),
+        }
+    }
+
+    for (_, variants) in groups {
+        let canonical = normalize_location(&variants[0], location_case());
+        let merged: Vec<&String> = variants.iter().filter(|v| **v != canonical).collect();
+        if merged.is_empty() {
+            continue;
+        }
+
+        for variant in &merged {
+            conn.execute(
+                "UPDATE items SET location = ?1 WHERE location = ?2",
+                params![canonical, variant],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        info!("Merged locations {:?} into {:?}", merged, canonical);
+    }
+
+    Ok(())
+}
+
+/// every column of items once `upgrade_schema` has run, with its declared type
+const ITEM_COLUMNS: [(&str, &str); 11] = [
+    ("id", "INTEGER"),
+    ("name", "VARCHAR"),
+    ("barcode", "INTEGER"),
+    ("location", "VARCHAR"),
+    ("last_seen", "TIMESTAMP"),
+    ("version", "INTEGER"),
+    ("status", "TEXT"),
+    ("purchase_date", "TEXT"),
+    ("value_pence", "INTEGER"),
+    ("parent_id", "INTEGER"),
+    ("quantity", "INTEGER"),
+];
+
+/// a column as `PRAGMA table_info` gives it: name, declared type, NOT NULL and default
+type ColumnInfo = (String, String, bool, Option<String>);
+
+/// a table's columns as `PRAGMA table_info` gives them
+fn table_columns(conn: &Connection, table: &str) -> Result<Vec<ColumnInfo>, String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .map_err(|e| e.to_string())?;
+    stmt.query_map(params![], |row| {
+        Ok((row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+    })
+    .map_err(|e| e.to_string())?
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|e| e.to_string())
+}
+
+/// check that missing columns are all the items table is short of: a column whose type has changed,
+/// or one this version doesn't know that must be given a value, can't be repaired by adding columns
+fn check_item_columns(conn: &Connection) -> Result<(), String> {
+    for (name, declared, not_null, default) in table_columns(conn, "items")? {
+        match ITEM_COLUMNS.iter().find(|(column, _)| *column == name) {
+            Some((_, expected)) if !declared.eq_ignore_ascii_case(expected) => {
+                return Err(format!(
+                    "items.{} is {} where {} is expected, which can't be repaired automatically; migrate the database by hand",
+                    name, declared, expected
+                ));
+            }
+            None if not_null && default.is_none() => {
+                return Err(format!(
+                    "items.{} is NOT NULL with no default and isn't known to this version, so items couldn't be added; migrate the database by hand",
+                    name
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// a change to the schema, applied once to each database
+type Migration = (&'static str, fn(&Connection) -> Result<(), String>);
+
+/// every migration in the order they're applied; a database's `PRAGMA user_version` is how many
+/// it has had. add new ones at the end and never change one that has shipped, as databases that
+/// already have it won't run it again. a step that fails part way is run again from the start
+/// next time, so each must be safe to repeat
+const MIGRATIONS: &[Migration] = &[
+    (
+        "columns added to items before migrations were tracked",
+        |conn| {
+            add_column_if_missing(conn, "items", "version", "INTEGER NOT NULL DEFAULT 1")?;
+            add_item_ids(conn)?;
+            add_column_if_missing(conn, "items", "status", "TEXT NOT NULL DEFAULT 'ok'")?;
+            add_column_if_missing(conn, "items", "purchase_date", "TEXT")?;
+            add_column_if_missing(conn, "items", "value_pence", "INTEGER")?;
+            // unpacked, not deleted, when their case is
+            add_column_if_missing(
+                conn,
+                "items",
+                "parent_id",
+                "INTEGER REFERENCES items(id) ON DELETE SET NULL",
+            )?;
+            add_column_if_missing(conn, "items", "quantity", "INTEGER NOT NULL DEFAULT 1")?;
+            Ok(())
+        },
+    ),
+    (
+        "item_history, where each item was every time it was added, seen or moved",
+        // whichever endpoint did it, written in the same transaction as the change; like the trail
+        // it goes when the item does
+        |conn| {
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS item_history (
+                    id INTEGER PRIMARY KEY,
+                    item_id INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+                    seen_at TIMESTAMP NOT NULL,
+                    location TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS item_history_by_item ON item_history (item_id, seen_at);
+                CREATE TRIGGER IF NOT EXISTS item_history_insert AFTER INSERT ON items
+                BEGIN
+                    INSERT INTO item_history (item_id, seen_at, location)
+                    VALUES (NEW.id, NEW.last_seen, NEW.location);
+                END;
+                CREATE TRIGGER IF NOT EXISTS item_history_update AFTER UPDATE OF last_seen, location ON items
+                WHEN NEW.last_seen IS NOT OLD.last_seen OR NEW.location != OLD.location COLLATE NOCASE
+                BEGIN
+                    INSERT INTO item_history (item_id, seen_at, location)
+                    VALUES (NEW.id, NEW.last_seen, NEW.location);
+                END;",
+            )
+            .map_err(|e| e.to_string())
+        },
+    ),
+    (
+        "scan_log, every scan of each item and where it was",
+        // every `/log`, or `/item?touch=true`, written by `touch_item` with the scan itself; it goes
+        // when the item does
+        |conn| {
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS scan_log (
+                    id INTEGER PRIMARY KEY,
+                    item_id INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+                    scanned_at TIMESTAMP NOT NULL,
+                    location TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS scan_log_by_item ON scan_log (item_id, scanned_at);",
+            )
+            .map_err(|e| e.to_string())
+        },
+    ),
+    (
+        "idempotency_keys, with the request each key was used for",
+        // stored responses are only kept a day anyway, so older keys (which can't be checked
+        // against a request) are dropped rather than carried over
+        |conn| {
+            conn.execute_batch(
+                "DROP TABLE IF EXISTS idempotency_keys;
+                CREATE TABLE idempotency_keys (
+                    key TEXT PRIMARY KEY,
+                    method TEXT NOT NULL,
+                    path TEXT NOT NULL,
+                    body_hash TEXT NOT NULL,
+                    status INTEGER,
+                    body BLOB,
+                    created_at TIMESTAMP NOT NULL
+                );",
+            )
+            .map_err(|e| e.to_string())
+        },
+    ),
+    (
+        "content_type on idempotency_keys, so a replayed response is labelled like the first",
+        |conn| {
+            add_column_if_missing(conn, "idempotency_keys", "content_type", "TEXT")?;
+            Ok(())
+        },
+    ),
+];
+
+/// apply the migrations a database hasn't had yet, returning the numbers of those that ran;
+/// a database from a newer version is refused rather than written to with an older schema
+fn migrate(conn: &Connection) -> Result<Vec<usize>, String> {
+    let applied: usize = conn
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if applied > MIGRATIONS.len() {
+        return Err(format!(
+            "the database has had {} migrations but this version only knows {}; use a newer server",
+            applied,
+            MIGRATIONS.len()
+        ));
+    }
+
+    let mut ran = Vec::new();
+    for (number, (description, step)) in MIGRATIONS.iter().enumerate().skip(applied) {
+        let number = number + 1;
+        step(conn).map_err(|e| format!("migration {} ({}) failed: {}", number, description, e))?;
+        conn.pragma_update(None, "user_version", number)
+            .map_err(|e| e.to_string())?;
+        info!("Applied migration {}: {}", number, description);
+        ran.push(number);
+    }
+    Ok(ran)
+}
+
+/// bring a database created by an older version up to date: versioned changes go through
+/// `migrate`, while the tables, indexes and triggers below are made sure of on every start
+fn upgrade_schema(conn: &Connection) -> Result<(), String> {
+    check_item_columns(conn)?;
+    migrate(conn)?;
+    normalize_locations(conn)?;
+
+    // entries are a record, so they can be added but never edited; they go when their item does
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS maintenance (
+            id INTEGER PRIMARY KEY,
+            item_id INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+            recorded_at TIMESTAMP NOT NULL,
+            type TEXT NOT NULL,
+            description TEXT NOT NULL,
+            recorded_by TEXT
+        );
+        CREATE INDEX IF NOT EXISTS maintenance_by_item ON maintenance (item_id, type, recorded_at);
+        CREATE TRIGGER IF NOT EXISTS maintenance_immutable BEFORE UPDATE ON maintenance
+        BEGIN
+            SELECT RAISE(ABORT, 'maintenance entries can not be changed');
+        END;",
+    )
+    .map_err(|e| e.to_string())?;
+
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS reservations (
+            id INTEGER PRIMARY KEY,
+            item_id INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+            starts_at TIMESTAMP NOT NULL,
+            ends_at TIMESTAMP NOT NULL,
+            reserved_by TEXT NOT NULL,
+            note TEXT
+        );
+        CREATE INDEX IF NOT EXISTS reservations_by_item ON reservations (item_id, starts_at);
+        CREATE INDEX IF NOT EXISTS reservations_by_time ON reservations (starts_at, ends_at);",
+    )
+    .map_err(|e| e.to_string())?;
+
+    // every change of location is recorded, whichever endpoint made it;
+    // a change of case alone (see `normalize_locations`) isn't a move
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS location_log (
+            id INTEGER PRIMARY KEY,
+            item_id INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+            moved_at TIMESTAMP NOT NULL,
+            from_location TEXT NOT NULL,
+            to_location TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS location_log_by_item ON location_log (item_id, moved_at);
+        CREATE TRIGGER IF NOT EXISTS log_location AFTER UPDATE OF location ON items
+        WHEN NEW.location != OLD.location COLLATE NOCASE
+        BEGIN
+            INSERT INTO location_log (item_id, moved_at, from_location, to_location)
+            VALUES (NEW.id, CAST(strftime('%s', 'now') AS INTEGER), OLD.location, NEW.location);
+        END;",
+    )
+    .map_err(|e| e.to_string())?;
+
+    // a scanned barcode must mean one item, so aliases and barcodes share one namespace;
+    // the triggers word their errors like SQLite's own so clashes are reported as conflicts
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS item_aliases (
+            alias INTEGER NOT NULL UNIQUE,
+            item_id INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS item_aliases_by_item ON item_aliases (item_id);
+        CREATE TRIGGER IF NOT EXISTS alias_not_a_barcode BEFORE INSERT ON item_aliases
+        WHEN EXISTS (SELECT 1 FROM items WHERE barcode = NEW.alias)
+        BEGIN
+            SELECT RAISE(ABORT, 'UNIQUE constraint failed: alias is already an item barcode');
+        END;
+        CREATE TRIGGER IF NOT EXISTS barcode_not_an_alias BEFORE INSERT ON items
+        WHEN EXISTS (SELECT 1 FROM item_aliases WHERE alias = NEW.barcode)
+        BEGIN
+            SELECT RAISE(ABORT, 'UNIQUE constraint failed: barcode is already an alias');
+        END;
+        CREATE TRIGGER IF NOT EXISTS changed_barcode_not_an_alias BEFORE UPDATE OF barcode ON items
+        WHEN EXISTS (SELECT 1 FROM item_aliases WHERE alias = NEW.barcode)
+        BEGIN
+            SELECT RAISE(ABORT, 'UNIQUE constraint failed: barcode is already an alias');
+        END;",
+    )
+    .map_err(|e| e.to_string())?;
+
+    // notes are appended, never edited, and go when their item does
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS item_notes (
+            id INTEGER PRIMARY KEY,
+            item_id INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+            noted_at TIMESTAMP NOT NULL,
+            text TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS item_notes_by_item ON item_notes (item_id, noted_at);",
+    )
+    .map_err(|e| e.to_string())?;
+
+    // items moved out of the inventory by `/archive`, with every field as a plain column so
+    // nothing in them depends on items that may be gone; history archived with them is kept
+    // until they're brought back
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS archived_items (
+            id INTEGER PRIMARY KEY,
+            name VARCHAR NOT NULL,
+            barcode INTEGER NOT NULL UNIQUE,
+            location VARCHAR NOT NULL,
+            last_seen TIMESTAMP NOT NULL,
+            version INTEGER NOT NULL DEFAULT 1,
+            status TEXT NOT NULL DEFAULT 'ok',
+            purchase_date TEXT,
+            value_pence INTEGER,
+            parent_barcode INTEGER,
+            archived_at TIMESTAMP NOT NULL,
+            quantity INTEGER NOT NULL DEFAULT 1
+        );
+        CREATE INDEX IF NOT EXISTS archived_items_by_time ON archived_items (archived_at);
+        CREATE TABLE IF NOT EXISTS archived_maintenance (
+            archived_id INTEGER NOT NULL REFERENCES archived_items(id) ON DELETE CASCADE,
+            recorded_at TIMESTAMP NOT NULL,
+            type TEXT NOT NULL,
+            description TEXT NOT NULL,
+            recorded_by TEXT
+        );
+        CREATE INDEX IF NOT EXISTS archived_maintenance_by_item ON archived_maintenance (archived_id);
+        CREATE TABLE IF NOT EXISTS archived_location_log (
+            archived_id INTEGER NOT NULL REFERENCES archived_items(id) ON DELETE CASCADE,
+            moved_at TIMESTAMP NOT NULL,
+            from_location TEXT NOT NULL,
+            to_location TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS archived_location_log_by_item ON archived_location_log (archived_id);
+        CREATE TABLE IF NOT EXISTS archived_item_history (
+            archived_id INTEGER NOT NULL REFERENCES archived_items(id) ON DELETE CASCADE,
+            seen_at TIMESTAMP NOT NULL,
+            location TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS archived_item_history_by_item ON archived_item_history (archived_id);
+        CREATE TABLE IF NOT EXISTS archived_scan_log (
+            archived_id INTEGER NOT NULL REFERENCES archived_items(id) ON DELETE CASCADE,
+            scanned_at TIMESTAMP NOT NULL,
+            location TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS archived_scan_log_by_item ON archived_scan_log (archived_id);
+        CREATE TABLE IF NOT EXISTS archived_notes (
+            archived_id INTEGER NOT NULL REFERENCES archived_items(id) ON DELETE CASCADE,
+            noted_at TIMESTAMP NOT NULL,
+            text TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS archived_notes_by_item ON archived_notes (archived_id);",
+    )
+    .map_err(|e| e.to_string())?;
+    add_column_if_missing(
+        conn,
+        "archived_items",
+        "quantity",
+        "INTEGER NOT NULL DEFAULT 1",
+    )?;
+
+    // every insert, update or delete of an item, whichever endpoint made it, bumps one counter
+    // and records it against the item's barcode (only the latest change per barcode is kept,
+    // with when the barcode last came into use, and deletes kept as tombstones), giving `/all`
+    // a cheap ETag and `/changes` and `/sync` a cursor
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS meta (
+            key TEXT PRIMARY KEY,
+            value INTEGER NOT NULL
+        );
+        INSERT OR IGNORE INTO meta (key, value) VALUES ('change_counter', 0);
+        CREATE TABLE IF NOT EXISTS item_changes (
+            barcode INTEGER PRIMARY KEY,
+            counter INTEGER NOT NULL,
+            deleted INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS item_changes_by_counter ON item_changes (counter);",
+    )
+    .map_err(|e| e.to_string())?;
+    // 0 for items that were there before changes were counted
+    add_column_if_missing(
+        conn,
+        "item_changes",
+        "created",
+        "INTEGER NOT NULL DEFAULT 0",
+    )?;
+
+    // recreated every start, so databases with older versions of them get these; upserts rather
+    // than INSERT OR REPLACE, which fails when a delete's ON DELETE SET NULL on children fires them
+    conn.execute_batch(
+        "DROP TRIGGER IF EXISTS count_item_insert;
+        DROP TRIGGER IF EXISTS count_item_update;
+        DROP TRIGGER IF EXISTS count_item_delete;
+        CREATE TRIGGER count_item_insert AFTER INSERT ON items
+        BEGIN
+            UPDATE meta SET value = value + 1 WHERE key = 'change_counter';
+            INSERT INTO item_changes (barcode, counter, deleted, created)
+            SELECT NEW.barcode, value, 0, value FROM meta WHERE key = 'change_counter'
+            ON CONFLICT (barcode) DO UPDATE SET
+                counter = excluded.counter, deleted = excluded.deleted, created = excluded.created;
+        END;
+        CREATE TRIGGER count_item_update AFTER UPDATE ON items
+        BEGIN
+            UPDATE meta SET value = value + 1 WHERE key = 'change_counter';
+            INSERT INTO item_changes (barcode, counter, deleted, created)
+            SELECT OLD.barcode, value, 1,
+                COALESCE((SELECT created FROM item_changes WHERE barcode = OLD.barcode), 0)
+            FROM meta
+            WHERE key = 'change_counter' AND OLD.barcode != NEW.barcode
+            ON CONFLICT (barcode) DO UPDATE SET
+                counter = excluded.counter, deleted = excluded.deleted, created = excluded.created;
+            INSERT INTO item_changes (barcode, counter, deleted, created)
+            SELECT NEW.barcode, value, 0,
+                CASE WHEN OLD.barcode = NEW.barcode
+                THEN COALESCE((SELECT created FROM item_changes WHERE barcode = NEW.barcode), 0)
+                ELSE value END
+            FROM meta WHERE key = 'change_counter'
+            ON CONFLICT (barcode) DO UPDATE SET
+                counter = excluded.counter, deleted = excluded.deleted, created = excluded.created;
+        END;
+        CREATE TRIGGER count_item_delete AFTER DELETE ON items
+        BEGIN
+            UPDATE meta SET value = value + 1 WHERE key = 'change_counter';
+            INSERT INTO item_changes (barcode, counter, deleted, created)
+            SELECT OLD.barcode, value, 1,
+                COALESCE((SELECT created FROM item_changes WHERE barcode = OLD.barcode), 0)
+            FROM meta WHERE key = 'change_counter'
+            ON CONFLICT (barcode) DO UPDATE SET
+                counter = excluded.counter, deleted = excluded.deleted, created = excluded.created;
+        END;",
+    )
+    .map_err(|e| e.to_string())?;
+
+    // one row per create, modify, delete or scan (an update that only moves last_seen), for
+    // `/activity`; also recreated every start
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS activity (
+            at INTEGER NOT NULL,
+            operation TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS activity_by_time ON activity (at);
+        DROP TRIGGER IF EXISTS activity_insert;
+        DROP TRIGGER IF EXISTS activity_update;
+        DROP TRIGGER IF EXISTS activity_delete;
+        CREATE TRIGGER activity_insert AFTER INSERT ON items
+        BEGIN
+            INSERT INTO activity (at, operation) VALUES (CAST(strftime('%s', 'now') AS INTEGER), 'created');
+        END;
+        CREATE TRIGGER activity_update AFTER UPDATE ON items
+        BEGIN
+            INSERT INTO activity (at, operation) VALUES (
+                CAST(strftime('%s', 'now') AS INTEGER),
+                CASE WHEN OLD.last_seen IS NOT NEW.last_seen
+                    AND OLD.barcode = NEW.barcode
+                    AND OLD.name IS NEW.name
+                    AND OLD.location IS NEW.location
+                    AND OLD.version IS NEW.version
+                    AND OLD.status IS NEW.status
+                    AND OLD.purchase_date IS NEW.purchase_date
+                    AND OLD.value_pence IS NEW.value_pence
+                    AND OLD.parent_id IS NEW.parent_id
+                    AND OLD.quantity IS NEW.quantity
+                THEN 'scanned' ELSE 'modified' END
+            );
+        END;
+        CREATE TRIGGER activity_delete AFTER DELETE ON items
+        BEGIN
+            INSERT INTO activity (at, operation) VALUES (CAST(strftime('%s', 'now') AS INTEGER), 'deleted');
+        END;",
+    )
+    .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+fn get_addr(config_path: &str, config: Option<&ConfigFile>) -> SocketAddr {
+    // if the environment variable BARCODE_SERVER_ADDR is set, use that
+    // else use barcode.cfg
+    // else fall back on 0.0.0.0:3000
+
+    if env::var("BARCODE_SERVER_ADDR").is_ok() {
+        let addr = env::var("BARCODE_SERVER_ADDR").unwrap_or_default();
+        let addr = addr.parse::<SocketAddr>();
+
+        if addr.is_ok() {
+            info!(
+                "Using address from BARCODE_SERVER_ADDR: {}",
+                addr.clone().unwrap()
+            );
+            return addr.unwrap();
+        } else {
+            warn!(
+                "Invalid address: {}, checking other options",
+                addr.unwrap_err()
+            );
+        }
+    }
+
+    match config.and_then(|config| config.addr.as_deref()) {
+        None => {
+            info!(
+                "Using 0.0.0.0:3000 by default, try setting BARCODE_SERVER_ADDR or BARCODE_CFG (config file location)"
+            );
+            SocketAddr::from(([0, 0, 0, 0], 3000))
+        }
+        Some(addr) => match addr.parse::<SocketAddr>() {
+            Ok(addr) => {
+                info!("Using address from config file ({}): {}", config_path, addr);
+                addr
+            }
+            Err(_) => {
+                warn!(
+                    "Using 0.0.0.0:3000 by default as address in {} is invalid",
+                    config_path
+                );
+                SocketAddr::from(([0, 0, 0, 0], 3000))
+            }
+        },
+    }
+}
+
+fn get_optimize_interval() -> Option<Duration> {
+    // BARCODE_OPTIMIZE_INTERVAL is in seconds, 0 disables the periodic run
+    // else fall back on hourly
+
+    let secs = match env::var("BARCODE_OPTIMIZE_INTERVAL") {
+        Ok(secs) => match secs.parse::<u64>() {
+            Ok(secs) => secs,
+            Err(_) => {
+                warn!(
+                    "Invalid BARCODE_OPTIMIZE_INTERVAL: {}, running PRAGMA optimize hourly",
+                    secs
+                );
+                3600
+            }
+        },
+        Err(_) => 3600,
+    };
+
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
+/// run `PRAGMA optimize` every `period` in the background
+fn spawn_optimize_task(db: Db, period: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.tick().await; // the first tick is immediate, and startup is no time to optimize
+
+        loop {
+            interval.tick().await;
+            let db = db.clone();
+            match tokio::task::spawn_blocking(move || db.write(|conn| optimize_db(conn))).await {
+                Ok(Ok(())) => info!("Ran PRAGMA optimize"),
+                Ok(Err(err)) => warn!("PRAGMA optimize failed: {}", err),
+                Err(err) => warn!("PRAGMA optimize failed: {}", err),
+            }
+        }
+    })
+}
+
+/// where the config file is, from BARCODE_CFG (default barcode.cfg)
+fn config_path() -> String {
+    env::var("BARCODE_CFG").unwrap_or_else(|_| "barcode.cfg".to_string())
+}
+
+/// the settings a config file can hold, as written
+#[derive(Debug, Default, PartialEq)]
+struct ConfigFile {
+    /// the address to listen on, which only takes effect on a restart
+    addr: Option<String>,
+    /// the log filter, as for BARCODE_LOG
+    log: Option<String>,
+    /// whether responses are enveloped by default, as for BARCODE_ENVELOPE
+    envelope: Option<String>,
+    /// the slow-request threshold in milliseconds, as for BARCODE_SLOW_MS
+    slow_ms: Option<String>,
+}
+
+impl ConfigFile {
+    /// either just an address, as config files have always been, or `key = value` lines, skipping
+    /// blank lines and `#` comments
+    fn parse(text: &str) -> Result<Self, String> {
+        let lines: Vec<&str> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+
+        let mut config = Self::default();
+        if let [addr] = lines[..]
+            && !addr.contains('=')
+        {
+            config.addr = Some(addr.to_string());
+            return Ok(config);
+        }
+        for line in lines {
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("Expected key = value, got {}", line));
+            };
+            let value = Some(value.trim().to_string());
+            match key.trim() {
+                "addr" => config.addr = value,
+                "log" => config.log = value,
+                "envelope" => config.envelope = value,
+                "slow_ms" => config.slow_ms = value,
+                key => return Err(format!("Unknown setting {}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// the config file at `path`, `None` if there isn't one, or the error reading or parsing it
+fn read_config_file(path: &str) -> Result<Option<ConfigFile>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => ConfigFile::parse(&text).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// `true`/`1`/`yes` or `false`/`0`/`no` (or nothing)
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" => Some(true),
+        "false" | "0" | "no" | "" => Some(false),
+        _ => None,
+    }
+}
+
+/// the settings that can change while the server runs, looked at on every request
+#[derive(Debug, Clone, PartialEq)]
+struct RuntimeConfig {
+    /// the log filter directive
+    log: String,
+    /// whether responses are wrapped in `{"ok": .., "data"/"error": ..}` unless a request says otherwise
+    envelope: bool,
+    /// requests slower than this are logged as warnings, `None` when that's turned off
+    slow: Option<Duration>,
+}
+
+impl RuntimeConfig {
+    /// the settings from the environment: BARCODE_LOG (or RUST_LOG, default `info`), BARCODE_ENVELOPE
+    /// (default off) and BARCODE_SLOW_MS (default 500, 0 disables), an invalid one warned about
+    /// and left at its default
+    fn from_env() -> Self {
+        let log = env::var("BARCODE_LOG")
+            .or_else(|_| env::var("RUST_LOG"))
+            .ok()
+            .filter(|directive| EnvFilter::try_new(directive).is_ok())
+            .unwrap_or_else(|| "info".to_string());
+
+        let envelope = match env::var("BARCODE_ENVELOPE") {
+            Ok(value) => parse_flag(&value).unwrap_or_else(|| {
+                warn!(
+                    "Invalid BARCODE_ENVELOPE: {}, leaving responses bare",
+                    value
+                );
+                false
+            }),
+            Err(_) => false,
+        };
+
+        let slow_ms = match env::var("BARCODE_SLOW_MS") {
+            Ok(ms) => ms.parse::<u64>().unwrap_or_else(|_| {
+                warn!("Invalid BARCODE_SLOW_MS: {}, using 500", ms);
+                500
+            }),
+            Err(_) => 500,
+        };
+
+        Self {
+            log,
+            envelope,
+            slow: (slow_ms != 0).then(|| Duration::from_millis(slow_ms)),
+        }
+    }
+
+    /// the environment's settings with those in `file` taking their place; unlike the environment,
+    /// an invalid value in the file is an error, so a bad edit can't half apply
+    fn load(file: &ConfigFile) -> Result<Self, String> {
+        let mut config = Self::from_env();
+        if let Some(log) = &file.log {
+            EnvFilter::try_new(log).map_err(|e| format!("invalid log {}: {}", log, e))?;
+            config.log = log.clone();
+        }
+        if let Some(envelope) = &file.envelope {
+            config.envelope =
+                parse_flag(envelope).ok_or_else(|| format!("invalid envelope {}", envelope))?;
+        }
+        if let Some(ms) = &file.slow_ms {
+            let ms = ms
+                .parse::<u64>()
+                .map_err(|_| format!("invalid slow_ms {}", ms))?;
+            config.slow = (ms != 0).then(|| Duration::from_millis(ms));
+        }
+        Ok(config)
+    }
+
+    /// each setting that differs in `new`, as `name: old -> new`
+    fn diff(&self, new: &Self) -> Vec<String> {
+        let slow_ms = |slow: Option<Duration>| slow.map_or(0, |slow| slow.as_millis());
+        let mut changed = Vec::new();
+        if self.log != new.log {
+            changed.push(format!("log: {} -> {}", self.log, new.log));
+        }
+        if self.envelope != new.envelope {
+            changed.push(format!("envelope: {} -> {}", self.envelope, new.envelope));
+        }
+        if self.slow != new.slow {
+            changed.push(format!(
+                "slow_ms: {} -> {}",
+                slow_ms(self.slow),
+                slow_ms(new.slow)
+            ));
+        }
+        changed
+    }
+}
+
+/// what a reload changed, and what it found changed that only a restart will pick up
+#[derive(Debug, Serialize)]
+struct Reloaded {
+    changed: Vec<String>,
+    requires_restart: Vec<String>,
+}
+
+/// the config file and the runtime settings last loaded from it, shared by every request and
+/// replaced whole by `reload`
+struct LiveConfig {
+    path: String,
+    /// the address being listened on, which can't change without a restart
+    addr: SocketAddr,
+    current: std::sync::RwLock<Arc<RuntimeConfig>>,
+}
+
+impl LiveConfig {
+    /// the settings from `file` (already read from `path`), or the environment's if they're invalid
+    fn new(path: String, addr: SocketAddr, file: Option<&ConfigFile>) -> Arc<Self> {
+        let config = match file.map(RuntimeConfig::load) {
+            Some(Ok(config)) => config,
+            Some(Err(err)) => {
+                warn!("Ignoring the settings in {}: {}", path, err);
+                RuntimeConfig::from_env()
+            }
+            None => RuntimeConfig::from_env(),
+        };
+        if let Err(err) = set_log_filter(&config.log) {
+            warn!("Failed to set the log level: {}", err);
+        }
+
+        Arc::new(Self {
+            path,
+            addr,
+            current: std::sync::RwLock::new(Arc::new(config)),
+        })
+    }
+
+    /// the settings in force right now
+    fn current(&self) -> Arc<RuntimeConfig> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// re-read and check the config file, then swap its settings in all at once, logging each
+    /// change; anything wrong with the file keeps the settings as they were
+    fn reload(&self) -> Result<Reloaded, String> {
+        let file = read_config_file(&self.path)
+            .and_then(|file| file.ok_or_else(|| "no such file".to_string()))
+            .map_err(|e| format!("{}: {}", self.path, e))?;
+        let new = RuntimeConfig::load(&file).map_err(|e| format!("{}: {}", self.path, e))?;
+
+        let mut requires_restart = Vec::new();
+        if let Some(addr) = &file.addr {
+            let addr = addr
+                .parse::<SocketAddr>()
+                .map_err(|_| format!("{}: invalid addr {}", self.path, addr))?;
+            if addr != self.addr {
+                requires_restart.push(format!("addr: {} -> {}", self.addr, addr));
+            }
+        }
+
+        let mut current = self.current.write().unwrap();
+        if current.log != new.log {
+            set_log_filter(&new.log)?;
+        }
+        let changed = current.diff(&new);
+        *current = Arc::new(new);
+        drop(current);
+
+        for change in &changed {
+            info!("Reloaded {}, {}", self.path, change);
+        }
+        for change in &requires_restart {
+            warn!(
+                "Reloaded {}, {} requires a restart to take effect",
+                self.path, change
+            );
+        }
+        Ok(Reloaded {
+            changed,
+            requires_restart,
+        })
+    }
+}
+
+/// the running log filter, so a reload can swap it
+static LOG_FILTER: std::sync::OnceLock<reload::Handle<EnvFilter, Registry>> =
+    std::sync::OnceLock::new();
+
+/// set up the global logger
+///
+/// verbosity starts out from BARCODE_LOG, then RUST_LOG, and defaults to `info`, until the config
+/// file's `log` replaces it. both accept a plain level (`error`, `warn`, `info`, `debug`) or a full
+/// filter directive
+fn init_logging() {
+    let (filter, handle) = reload::Layer::new(EnvFilter::new(RuntimeConfig::from_env().log));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    let _ = LOG_FILTER.set(handle);
+}
+
+/// switch the global logger to `directive`, if there is one (tests run without)
+fn set_log_filter(directive: &str) -> Result<(), String> {
+    let filter = EnvFilter::try_new(directive).map_err(|e| e.to_string())?;
+    match LOG_FILTER.get() {
+        Some(handle) => handle.reload(filter).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+}
+
+/// reload the config file on every SIGHUP
+#[cfg(unix)]
+fn spawn_reload_task(config: Arc<LiveConfig>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(err) => {
+            warn!("Can't listen for SIGHUP: {}", err);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("Reloading {} on SIGHUP", config.path);
+            if let Err(err) = config.reload() {
+                warn!("Kept the old config: {}", err);
+            }
+        }
+    });
+}
+
+// endpoint to re-read the config file without a restart (hyper), as SIGHUP does:
+// answers {"changed": [...], "requires_restart": [...]}, or 400 keeping the old settings
+async fn reload_endpoint(
+    req: Request<BoxBody<Bytes, hyper::Error>>,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
+    if req.method() != hyper::Method::POST {
+        let mut resp = text_response("Use POST");
+        *resp.status_mut() = hyper::StatusCode::METHOD_NOT_ALLOWED;
+        return Ok(resp);
+    }
+    let Some(config) = req.extensions().get::<Arc<LiveConfig>>().cloned() else {
+        let mut resp = text_response("No config to reload");
+        *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
+        return Ok(resp);
+    };
+
+    match blocking(move || config.reload()).await {
+        Ok(reloaded) => Ok(json_response(serde_json::to_string(&reloaded).unwrap())),
+        Err(err) => {
+            let mut resp = text_response(err);
+            *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
+            Ok(resp)
+        }
+    }
+}
+
+/// where `--daemon` writes its pid and `--stop` reads it, from BARCODE_PID_FILE (default barcode.pid)
+fn pid_file() -> String {
+    env::var("BARCODE_PID_FILE").unwrap_or_else(|_| "barcode.pid".to_string())
+}
+
+/// where a daemon's output goes, from BARCODE_LOG_FILE (default barcode.log)
+#[cfg(unix)]
+fn log_file() -> String {
+    env::var("BARCODE_LOG_FILE").unwrap_or_else(|_| "barcode.log".to_string())
+}
+
+/// the pid in a pid file, if there is one and it's a number
+#[cfg(unix)]
+fn read_pid(path: &str) -> Option<i32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// whether a process is running (signal 0 checks without sending anything); one we may not
+/// signal is still running
+#[cfg(unix)]
+fn process_alive(pid: i32) -> bool {
+    // SAFETY: kill with signal 0 only checks the pid
+    (unsafe { libc::kill(pid, 0) } == 0)
+        || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// the pid of a server already running from the pid file; a file left behind by one that
+/// crashed is removed
+#[cfg(unix)]
+fn running_pid(path: &str) -> Option<i32> {
+    let pid = read_pid(path);
+    match pid {
+        Some(pid) if pid > 0 && process_alive(pid) => Some(pid),
+        _ => {
+            if fs::remove_file(path).is_ok() {
+                warn!("Removed stale pid file {}", path);
+            }
+            None
+        }
+    }
+}
+
+/// `--stop`: SIGTERM the server in the pid file, which shuts down as it does on ctrl-c
+#[cfg(unix)]
+fn stop_daemon() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let path = pid_file();
+    let Some(pid) = running_pid(&path) else {
+        return Err(format!("No server running from {}", path).into());
+    };
+
+    // SAFETY: plain syscall on a pid we just checked
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    info!("Sent SIGTERM to {}", pid);
+    Ok(())
+}
+
+/// `--daemon`: detach from the terminal into the background, with output going to the log file
+///
+/// the parent exits once the child is forked, so this only returns in the child, which writes
+/// the pid file; called before the runtime starts, since forking a running runtime isn't safe
+#[cfg(unix)]
+fn daemonize() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use std::os::fd::AsRawFd;
+
+    let path = pid_file();
+    if let Some(pid) = running_pid(&path) {
+        return Err(format!("Already running as {} (from {})", pid, path).into());
+    }
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file())?;
+    let null = fs::File::open("/dev/null")?;
+
+    // SAFETY: no other threads exist yet, so the child gets a consistent copy of the process
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error().into()),
+        0 => {}
+        child => {
+            println!("Started as {}, logging to {}", child, log_file());
+            std::process::exit(0);
+        }
+    }
+
+    // SAFETY: plain syscalls on descriptors we own
+    unsafe {
+        libc::setsid();
+        libc::dup2(null.as_raw_fd(), 0);
+        libc::dup2(log.as_raw_fd(), 1);
+        libc::dup2(log.as_raw_fd(), 2);
+    }
+    fs::write(&path, std::process::id().to_string())?;
+    Ok(())
+}
+
+/// resolves on ctrl-c, or on unix SIGTERM too (what `--stop` sends)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+                Ok(terminate) => terminate,
+                Err(err) => {
+                    warn!("Can't listen for SIGTERM: {}", err);
+                    let _ = tokio::signal::ctrl_c().await;
+                    return;
+                }
+            };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let daemon = args.iter().any(|arg| arg == "--daemon");
+
+    init_logging();
+    if args.iter().any(|arg| arg == "--stop") {
+        #[cfg(unix)]
+        return stop_daemon();
+        #[cfg(not(unix))]
+        return Err("--stop is only supported on unix".into());
+    }
+    BODY_LIMITS
+        .set(BodyLimits::from_env()?)
+        .expect("body limits are only set once");
+    let db = Db::open(
+        DB_NAME,
+        env::var("BARCODE_READ_DB").ok().as_deref(),
+        db_pool_size(),
+    );
+    setup_if_not_exists(&db);
+    date_format(); // warns about an invalid pattern now rather than on the first report
+    let config_path = config_path();
+    let config_file = read_config_file(&config_path).unwrap_or_else(|err| {
+        warn!("Ignoring {}: {}", config_path, err);
+        None
+    });
+    let addr = get_addr(&config_path, config_file.as_ref());
+    let config = LiveConfig::new(config_path, addr, config_file.as_ref());
+
+    // bound before detaching, so a port already in use is reported to whoever started it
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    if daemon {
+        #[cfg(unix)]
+        {
+            // so no connection opened by the schema setup is carried across the fork
+            db.close_idle();
+            daemonize()?;
+        }
+        #[cfg(not(unix))]
+        return Err("--daemon is only supported on unix".into());
+    }
+
+    let result = tokio::runtime::Runtime::new()?.block_on(serve(db, config, listener, addr));
+    if daemon {
+        let _ = fs::remove_file(pid_file());
+    }
+    result
+}
+
+async fn serve(
+    db: Db,
+    config: Arc<LiveConfig>,
+    listener: std::net::TcpListener,
+    addr: SocketAddr,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(period) = get_optimize_interval() {
+        spawn_optimize_task(db.clone(), period);
+    }
+    #[cfg(unix)]
+    spawn_reload_task(config.clone());
+
+    let listener = TcpListener::from_std(listener)?;
+    started_at();
+    info!("Listening on http://{}{}/", addr, base_path());
+    if !webclient_installed() {
+        warn!(
+            "No web client at ../webclient, browsers will get a page pointing at the API instead"
+        );
+    }
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = &mut shutdown => break,
+        };
+        let io = TokioIo::new(stream);
+        let connection = Gauge::enter(&OPEN_CONNECTIONS);
+        let db = db.clone();
+        let config = config.clone();
+
+        tokio::task::spawn(async move {
+            let _connection = connection;
+            let result = http1::Builder::new()
+                .serve_connection(
+                    io,
+                    service_fn(move |req| dispatch(db.clone(), config.clone(), req)),
+                )
+                .await;
+
+            if let Err(err) = result {
+                error!("HTTP/1 Error: {}", err);
+            }
+        });
+    }
+
+    // SQLite recommends running optimize just before closing the database
+    info!("Shutting down");
+    match db.write(|conn| optimize_db(conn)) {
+        Ok(()) => info!("Ran PRAGMA optimize"),
+        Err(err) => warn!("PRAGMA optimize failed: {}", err),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn setup_test_db(db: &Db) {
+    let conn = db.conn().unwrap();
+    let result = conn.execute(
+        "CREATE TABLE items (
+            name VARCHAR NOT NULL,
+            barcode INTEGER NOT NULL UNIQUE,
+            location VARCHAR NOT NULL,
+            last_seen TIMESTAMP NOT NULL
+        )",
+        params![],
+    );
+
+    if let Err(e) = result {
+        if e.to_string().contains("table items already exists") {
+            // Table already exists, no need to panic
+        } else {
+            // Other errors should still panic
+            panic!("Failed to create table: {}", e);
+        }
+    }
+
+    upgrade_schema(&conn).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// a database of a test's own, set up as an old database would be and then upgraded, and
+    /// gone once the test is done
+    struct TestDb {
+        db: Db,
+        /// an in-memory database only lasts as long as some connection to it, so one is held here
+        _anchor: Connection,
+        /// the file of a database on disk, removed (with its -wal and -shm) on drop
+        file: Option<std::path::PathBuf>,
+    }
+
+    impl TestDb {
+        fn at(path: &str, file: Option<std::path::PathBuf>) -> Self {
+            let db = Db::open(path, None, 4);
+            let anchor = Connection::open(path).unwrap();
+            setup_test_db(&db);
+            TestDb {
+                db,
+                _anchor: anchor,
+                file,
+            }
+        }
+    }
+
+    impl std::ops::Deref for TestDb {
+        type Target = Db;
+
+        fn deref(&self) -> &Db {
+            &self.db
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            if let Some(file) = &self.file {
+                for suffix in ["", "-wal", "-shm"] {
+                    let mut path = file.clone().into_os_string();
+                    path.push(suffix);
+                    let _ = fs::remove_file(path);
+                }
+            }
+        }
+    }
+
+    /// a unique name for each test's database
+    fn test_db_name() -> String {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        format!(
+            "barcode-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    /// an in-memory database for one test, which connections share like a file (unlike
+    /// `:memory:`, which is private to its connection)
+    fn test_db() -> TestDb {
+        TestDb::at(&format!("file:/{}?vfs=memdb", test_db_name()), None)
+    }
+
+    /// a database in a temporary file, for tests of what's read from the file itself
+    fn test_file_db() -> TestDb {
+        let file = env::temp_dir().join(format!("{}.db", test_db_name()));
+        TestDb::at(file.to_str().unwrap(), Some(file.clone()))
+    }
+
+    /// start a server on a random local port, for tests that go through `dispatch`
+    async fn spawn_test_server(db: &Db) -> SocketAddr {
+        spawn_test_server_under(db, "").await
+    }
+
+    /// `spawn_test_server` for a server mounted under `base`, as if behind a reverse proxy
+    async fn spawn_test_server_under(db: &Db, base: &'static str) -> SocketAddr {
+        spawn_test_server_with(db, base, "no-such-barcode.cfg").await
+    }
+
+    /// `spawn_test_server_under` with its settings read from the config file at `config_path`
+    async fn spawn_test_server_with(db: &Db, base: &'static str, config_path: &str) -> SocketAddr {
+        let db = db.clone();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config_file = read_config_file(config_path).unwrap();
+        let config = LiveConfig::new(config_path.to_string(), addr, config_file.as_ref());
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let io = TokioIo::new(stream);
+                let db = db.clone();
+                let config = config.clone();
+
+                tokio::task::spawn(async move {
+                    let _ = http1::Builder::new()
+                        .serve_connection(
+                            io,
+                            service_fn(move |req| {
+                                let (db, config) = (db.clone(), config.clone());
+                                async move { dispatch_under(&db, &config, base, req).await }
+                            }),
+                        )
+                        .await;
+                });
+            }
+        });
+
+        addr
+    }
+
+    struct TestResponse {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl TestResponse {
+        fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        }
+
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.body).into_owned()
+        }
+    }
+
+    /// send a single HTTP/1.1 request and read the whole response
+    async fn send_request(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> TestResponse {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+
+        let mut head = format!(
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            addr,
+            body.len()
+        );
+        for (name, value) in headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+
+        stream.write_all(head.as_bytes()).await.unwrap();
+        // the server may answer (e.g. 413) and hang up without reading the whole body
+        let _ = stream.write_all(body).await;
+
+        let mut raw = Vec::new();
+        let mut buf = [0; 8192];
+        loop {
+            match stream.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => raw.extend_from_slice(&buf[..n]),
+            }
+        }
+
+        let split = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&raw[..split]).into_owned();
+        let mut body = raw[split + 4..].to_vec();
+
+        let mut lines = head.split("\r\n");
+        let status = lines
+            .next()
+            .unwrap()
+            .split(' ')
+            .nth(1)
+            .unwrap()
+            .parse()
+            .unwrap();
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+            .collect();
+
+        let chunked = headers.iter().any(|(k, v)| {
+            k.eq_ignore_ascii_case("transfer-encoding") && v.eq_ignore_ascii_case("chunked")
+        });
+        if chunked {
+            let mut decoded = Vec::new();
+            let mut rest = &body[..];
+            loop {
+                let line_end = rest.windows(2).position(|w| w == b"\r\n").unwrap();
+                let size =
+                    usize::from_str_radix(String::from_utf8_lossy(&rest[..line_end]).trim(), 16)
+                        .unwrap();
+                if size == 0 {
+                    break;
+                }
+                decoded.extend_from_slice(&rest[line_end + 2..line_end + 2 + size]);
+                rest = &rest[line_end + 2 + size + 2..];
+            }
+            body = decoded;
+        }
+
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    #[test]
+    fn test_item() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+
+        let item = Item::new("item".to_string(), 42, "location".to_string());
+        item.save(&mut conn).unwrap();
+        let loaded_item = load_item(&conn, 42).unwrap();
+        assert_eq!(item.name, loaded_item.name);
+        assert_eq!(item.barcode, loaded_item.barcode);
+        assert_eq!(item.location, loaded_item.location);
+        assert_eq!(item.last_seen, loaded_item.last_seen);
+    }
+
+    #[test]
+    fn test_new_and_load() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+
+        let item = Item::new("item".to_string(), 43, "location".to_string());
+        item.save(&mut conn).unwrap();
+        let checked_item = load_item(&conn, 43).unwrap();
+        assert_eq!(item.name, checked_item.name);
+        assert_eq!(item.barcode, checked_item.barcode);
+        assert_eq!(item.location, checked_item.location);
+        assert_eq!(item.last_seen, checked_item.last_seen);
+    }
+
+    #[test]
+    fn test_delete() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+
+        let item = Item::new("item".to_string(), 44, "location".to_string());
+        item.save(&mut conn).unwrap();
+        let items_initial_len = load_items(&conn).unwrap().len();
+        conn.execute("DELETE FROM items WHERE barcode = ?1", params!["44"])
+            .unwrap();
+        let items = load_items(&conn).unwrap();
+        assert_eq!(items.len(), items_initial_len - 1);
+    }
+
+    #[test]
+    fn test_spa_fallback() {
+        let browser = Request::builder()
+            .uri("/item/42")
+            .header(
+                hyper::header::ACCEPT,
+                "text/html,application/xhtml+xml,*/*;q=0.8",
+            )
+            .body(())
+            .unwrap();
+        let api = Request::builder()
+            .uri("/item/42")
+            .header(hyper::header::ACCEPT, "application/json")
+            .body(())
+            .unwrap();
+        assert!(wants_html(&browser));
+        assert!(!wants_html(&api));
+
+        assert!(is_client_route("/item/42"));
+        assert!(is_client_route("/some/page"));
+        assert!(!is_client_route("/all"));
+        assert!(!is_client_route("/log/42"));
+        assert!(!is_client_route("/script.js"));
+    }
+
+    #[test]
+    fn test_build_xlsx() {
+        let items = vec![
+            Item {
+                name: "XLR cable".to_string(),
+                barcode: 9780201379624,
+                location: "Rig".to_string(),
+                last_seen: Some(1_700_000_000),
+                version: 1,
+                status: Some("ok".to_string()),
+                purchase_date: Some("2023-09-01".to_string()),
+                value_pence: Some(1299),
+                parent_barcode: None,
+                notes: None,
+                quantity: Some(1),
+            },
+            Item {
+                name: "Hazer".to_string(),
+                barcode: 7,
+                location: "Drama Studio Tech Box".to_string(),
+                last_seen: None,
+                version: 1,
+                status: Some("needs_repair".to_string()),
+                purchase_date: None,
+                value_pence: None,
+                parent_barcode: None,
+                notes: None,
+                quantity: Some(1),
+            },
+        ];
+
+        let xlsx = build_xlsx(&items, &[]).unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(xlsx)).unwrap();
+
+        let mut strings = String::new();
+        archive
+            .by_name("xl/sharedStrings.xml")
+            .unwrap()
+            .read_to_string(&mut strings)
+            .unwrap();
+        for expected in [
+            "Name",
+            "Barcode",
+            "XLR cable",
+            "9780201379624",
+            "Hazer",
+            "Rig",
+            "Purchase Date",
+            "Value",
+        ] {
+            assert!(strings.contains(expected), "missing {}", expected);
+        }
+
+        let mut sheet = String::new();
+        archive
+            .by_name("xl/worksheets/sheet1.xml")
+            .unwrap()
+            .read_to_string(&mut sheet)
+            .unwrap();
+        assert!(sheet.contains("<autoFilter ref=\"A1:F3\""));
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        let with = |encoding: &str| {
+            Request::builder()
+                .header(hyper::header::ACCEPT_ENCODING, encoding)
+                .body(())
+                .unwrap()
+        };
+
+        assert!(accepts_gzip(&with("gzip, deflate, br")));
+        assert!(accepts_gzip(&with("br;q=1.0, gzip;q=0.8")));
+        assert!(!accepts_gzip(&with("gzip;q=0")));
+        assert!(!accepts_gzip(&with("deflate, br")));
+        assert!(!accepts_gzip(&Request::builder().body(()).unwrap()));
+    }
+
+    /// render a barcode to a PNG, with a white border as a quiet zone
+    fn barcode_png(contents: &str, format: rxing::BarcodeFormat) -> Vec<u8> {
+        use rxing::Writer;
+
+        let matrix = rxing::MultiFormatWriter::default()
+            .encode(contents, &format, 600, 200)
+            .unwrap();
+        let image =
+            image::GrayImage::from_fn(matrix.getWidth() + 40, matrix.getHeight() + 40, |x, y| {
+                let inside =
+                    x >= 20 && y >= 20 && x < matrix.getWidth() + 20 && y < matrix.getHeight() + 20;
+                if inside && matrix.get(x - 20, y - 20) {
+                    image::Luma([0])
+                } else {
+                    image::Luma([255])
+                }
+            });
+
+        let mut png = Vec::new();
+        image
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    #[test]
+    fn test_decode_image() {
+        let ean =
+            decode_image(&barcode_png("5901234123457", rxing::BarcodeFormat::EAN_13)).unwrap();
+        assert_eq!(ean.len(), 1);
+        assert_eq!(ean[0].value, "5901234123457");
+        assert_eq!(ean[0].symbology, "EAN_13");
+
+        let code128 =
+            decode_image(&barcode_png("LX-1042", rxing::BarcodeFormat::CODE_128)).unwrap();
+        assert_eq!(code128.len(), 1);
+        assert_eq!(code128[0].value, "LX-1042");
+        assert_eq!(code128[0].symbology, "CODE_128");
+
+        // a photo of nothing in particular
+        let blank =
+            image::GrayImage::from_fn(640, 480, |x, y| image::Luma([((x ^ y) % 64) as u8 + 96]));
+        let mut png = Vec::new();
+        blank
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        assert!(decode_image(&png).unwrap().is_empty());
+
+        assert!(decode_image(b"definitely not an image").is_err());
+    }
+
+    #[test]
+    fn test_query_param() {
+        let query = Some("log=true&name=xlr%20cable&location=rig+one&empty");
+        assert_eq!(query_param(query, "log").as_deref(), Some("true"));
+        assert_eq!(query_param(query, "name").as_deref(), Some("xlr cable"));
+        assert_eq!(query_param(query, "location").as_deref(), Some("rig one"));
+        assert_eq!(query_param(query, "empty").as_deref(), Some(""));
+        assert_eq!(query_param(query, "missing"), None);
+        assert_eq!(query_param(None, "log"), None);
+    }
+
+    #[tokio::test]
+    async fn test_optimize_task() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+
+        let task = spawn_optimize_task(db.clone(), Duration::from_millis(50));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        // the database stays usable while the task is running
+        let item = Item::new("item".to_string(), 45, "location".to_string());
+        item.save(&mut conn).unwrap();
+        assert_eq!(load_item(&conn, 45).unwrap().name, "item");
+
+        assert!(LAST_OPTIMIZE.load(Ordering::Relaxed) > 0);
+        task.abort();
+    }
+
+    #[tokio::test]
+    async fn test_response_time_header() {
+        let db = test_db();
+        let addr = spawn_test_server(&db).await;
+
+        for (path, status) in [
+            ("/all", 200),
+            ("/no/such/route", 404),
+            ("/item/999999", 404),
+        ] {
+            let resp = send_request(addr, "GET", path, &[], b"").await;
+            assert_eq!(resp.status, status, "{}", resp.text());
+
+            let elapsed: f64 = resp
+                .header("x-response-time-ms")
+                .expect("missing X-Response-Time-Ms")
+                .parse()
+                .unwrap();
+            assert!(elapsed >= 0.0);
+        }
+    }
+
+    #[test]
+    fn test_modify_version() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+
+        let item = Item::new("item".to_string(), 46, "location".to_string());
+        item.save(&mut conn).unwrap();
+        assert_eq!(load_item(&conn, 46).unwrap().version, 1);
+
+        let edited = Item::new("edited".to_string(), 46, "location".to_string());
+        modify_item(&mut conn, edited.clone(), Some(1)).unwrap();
+        assert_eq!(load_item(&conn, 46).unwrap().version, 2);
+
+        // someone else's edit (made against version 1) must not overwrite ours
+        assert_eq!(
+            modify_item(&mut conn, edited.clone(), Some(1)).unwrap_err(),
+            "Version mismatch"
+        );
+        assert_eq!(load_item(&conn, 46).unwrap().version, 2);
+
+        // without If-Match the update always goes through
+        modify_item(&mut conn, edited, None).unwrap();
+        assert_eq!(load_item(&conn, 46).unwrap().version, 3);
+    }
+
+    #[test]
+    fn test_if_match_version() {
+        let with = |if_match: &str| {
+            Request::builder()
+                .header(hyper::header::IF_MATCH, if_match)
+                .body(())
+                .unwrap()
+        };
+
+        assert_eq!(if_match_version(&with("\"3\"")), Ok(Some(3)));
+        assert_eq!(if_match_version(&with("W/\"3\"")), Ok(Some(3)));
+        assert_eq!(if_match_version(&with("3")), Ok(Some(3)));
+        assert_eq!(if_match_version(&with("*")), Ok(None));
+        assert_eq!(
+            if_match_version(&Request::builder().body(()).unwrap()),
+            Ok(None)
+        );
+        assert!(if_match_version(&with("\"abc\"")).is_err());
+    }
+
+    #[test]
+    fn test_describe_json_error() {
+        let describe = |payload: &str| {
+            describe_json_error(&serde_json::from_str::<Item>(payload).unwrap_err())
+        };
+
+        let string_barcode = describe(r#"{"name": "a", "barcode": "4x2", "location": "b"}"#);
+        assert!(
+            string_barcode["detail"]
+                .as_str()
+                .unwrap()
+                .contains("expected u64")
+        );
+        assert!(
+            string_barcode["hint"]
+                .as_str()
+                .unwrap()
+                .contains("digits only")
+        );
+        assert_eq!(string_barcode["line"], 1);
+
+        let too_big =
+            describe(r#"{"name": "a", "barcode": 18446744073709551616, "location": "b"}"#);
+        assert_eq!(
+            too_big["hint"],
+            "barcode must be a whole number between 0 and 9223372036854775807"
+        );
+        let negative = describe(r#"{"name": "a", "barcode": -1, "location": "b"}"#);
+        assert_eq!(negative["hint"], too_big["hint"]);
+
+        let typo = describe(r#"{"name": "a", "barcode": 42, "locaton": "b"}"#);
+        assert!(
+            typo["detail"]
+                .as_str()
+                .unwrap()
+                .contains("unknown field `locaton`")
+        );
+        assert!(typo["hint"].as_str().unwrap().contains("\"locaton\""));
+
+        let missing = describe(r#"{"barcode": 42, "location": "b"}"#);
+        assert!(
+            missing["detail"]
+                .as_str()
+                .unwrap()
+                .contains("missing field `name`")
+        );
+
+        let truncated = describe(r#"{"name": "a", "barc"#);
+        assert!(truncated["hint"].as_str().unwrap().contains("ended early"));
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_replay() {
+        let db = test_db();
+        let conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        let body = br#"{"name": "item", "barcode": 47, "location": "location"}"#;
+        let key = [("Idempotency-Key", "test-replay-47")];
+
+        let first = send_request(addr, "POST", "/new", &key, body).await;
+        assert_eq!(first.status, 200, "{}", first.text());
+        assert_eq!(first.header("idempotent-replayed"), None);
+
+        // a retry of a create that succeeded gets the original answer, not a 409
+        let retry = send_request(addr, "POST", "/new", &key, body).await;
+        assert_eq!(retry.status, 200, "{}", retry.text());
+        assert_eq!(retry.header("idempotent-replayed"), Some("true"));
+
+        let rows = load_items(&conn)
+            .unwrap()
+            .iter()
+            .filter(|item| item.barcode == 47)
+            .count();
+        assert_eq!(rows, 1);
+
+        // a different key really runs again
+        let other = [("Idempotency-Key", "test-replay-47-again")];
+        let conflict = send_request(addr, "POST", "/new", &other, body).await;
+        assert_eq!(conflict.status, 409);
+
+        // the same key on another route, or with another body, isn't a retry
+        let log = send_request(addr, "POST", "/log/47", &key, b"").await;
+        assert_eq!(log.status, 422, "{}", log.text());
+        let changed = br#"{"name": "other", "barcode": 47, "location": "location"}"#;
+        let new = send_request(addr, "POST", "/new", &key, changed).await;
+        assert_eq!(new.status, 422, "{}", new.text());
+
+        // viewing with ?touch=true writes, so it's run once per key too
+        let touch = [("Idempotency-Key", "test-touch-47")];
+        let first = send_request(addr, "GET", "/item/47?touch=true", &touch, b"").await;
+        assert_eq!(first.status, 200, "{}", first.text());
+        let retry = send_request(addr, "GET", "/item/47?touch=true", &touch, b"").await;
+        assert_eq!(retry.header("idempotent-replayed"), Some("true"));
+    }
+
+    #[test]
+    fn test_config_file() {
+        // a file holding just the address still works
+        assert_eq!(
+            ConfigFile::parse("127.0.0.1:3001\n").unwrap(),
+            ConfigFile {
+                addr: Some("127.0.0.1:3001".to_string()),
+                ..ConfigFile::default()
+            }
+        );
+        assert_eq!(
+            ConfigFile::parse(
+                "# on the stage network\naddr = [::1]:3002\n\nlog = debug\nenvelope = yes\n"
+            )
+            .unwrap(),
+            ConfigFile {
+                addr: Some("[::1]:3002".to_string()),
+                log: Some("debug".to_string()),
+                envelope: Some("yes".to_string()),
+                slow_ms: None,
+            }
+        );
+        assert_eq!(ConfigFile::parse("").unwrap(), ConfigFile::default());
+        assert!(ConfigFile::parse("addr = 127.0.0.1:3001\nport = 3002").is_err());
+        assert!(ConfigFile::parse("127.0.0.1:3001\nlog = debug").is_err());
+
+        let file = ConfigFile::parse("envelope = sometimes").unwrap();
+        assert!(RuntimeConfig::load(&file).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reload_config() {
+        let db = test_db();
+        let path = env::temp_dir().join(format!("{}.cfg", test_db_name()));
+        let path = path.to_str().unwrap().to_string();
+        fs::write(&path, "envelope = false\n").unwrap();
+        let addr = spawn_test_server_with(&db, "", &path).await;
+        let reload = || send_request(addr, "POST", "/admin/reload", &[], b"");
+
+        let bare = send_request(addr, "GET", "/version", &[], b"").await;
+        assert!(!bare.text().contains(r#""ok":true"#), "{}", bare.text());
+
+        fs::write(&path, "envelope = true\nslow_ms = 0\naddr = 127.0.0.1:1\n").unwrap();
+        let reloaded = reload().await;
+        assert_eq!(reloaded.status, 200, "{}", reloaded.text());
+        let reloaded: serde_json::Value = serde_json::from_str(&reloaded.text()).unwrap();
+        assert_eq!(
+            reloaded["changed"],
+            serde_json::json!(["envelope: false -> true", "slow_ms: 500 -> 0"])
+        );
+        assert_eq!(
+            reloaded["requires_restart"],
+            serde_json::json!([format!("addr: {} -> 127.0.0.1:1", addr)])
+        );
+
+        // later requests see the new settings
+        let enveloped = send_request(addr, "GET", "/version", &[], b"").await;
+        assert!(
+            enveloped.text().contains(r#""ok":true"#),
+            "{}",
+            enveloped.text()
+        );
+
+        // a bad edit is refused as a whole, keeping what was running
+        fs::write(&path, "envelope = false\nslow_ms = soon\n").unwrap();
+        assert_eq!(reload().await.status, 400);
+        let still = send_request(addr, "GET", "/version", &[], b"").await;
+        assert!(still.text().contains(r#""ok":true"#), "{}", still.text());
+
+        assert_eq!(
+            send_request(addr, "GET", "/admin/reload", &[], b"")
+                .await
+                .status,
+            405
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reset_requires_confirmation() {
+        let db = test_db();
+        let addr = spawn_test_server(&db).await;
+
+        for body in [
+            &br#"{"confirm": "delete all"}"#[..],
+            &br#"{"confirm": "yes"}"#[..],
+            &b""[..],
+        ] {
+            let resp = send_request(addr, "POST", "/reset", &[], body).await;
+            assert_eq!(resp.status, 400);
+            assert!(resp.text().contains("DELETE ALL"));
+        }
+
+        let resp = send_request(addr, "GET", "/reset", &[], b"").await;
+        assert_eq!(resp.status, 405);
+    }
+
+    #[test]
+    fn test_body_limits() {
+        let defaults = BodyLimits::default();
+        assert_eq!(defaults.limit("/new"), 64 * 1024);
+        assert_ne!(defaults.limit("/decode"), defaults.limit("/new"));
+
+        let limits = BodyLimits::parse(Some("32KiB"), Some("/new=1KiB, /decode=5MiB")).unwrap();
+        assert_eq!(limits.limit("/modify"), 32 * 1024);
+        assert_eq!(limits.limit("/new"), 1024);
+        assert_eq!(limits.limit("/decode"), 5 * 1024 * 1024);
+
+        assert!(BodyLimits::parse(Some("0"), None).is_err());
+        assert!(BodyLimits::parse(Some("1024MiB"), None).is_err());
+        assert!(BodyLimits::parse(None, Some("/new=0")).is_err());
+        assert!(BodyLimits::parse(None, Some("new=1KiB")).is_err());
+        assert!(BodyLimits::parse(None, Some("/new")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_body_limit_enforced() {
+        let db = test_db();
+        let addr = spawn_test_server(&db).await;
+        let limit = body_limits().limit("/new") as usize;
+
+        // exactly at the limit is read (and then rejected as JSON, not for its size)
+        let at_limit = send_request(addr, "POST", "/new", &[], &vec![b' '; limit]).await;
+        assert_eq!(at_limit.status, 400);
+
+        let over_limit = send_request(addr, "POST", "/new", &[], &vec![b' '; limit + 1]).await;
+        assert_eq!(over_limit.status, 413);
+        assert!(over_limit.text().contains(&limit.to_string()));
+    }
+
+    #[test]
+    fn test_read_connection() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+
+        let item = Item::new("item".to_string(), 48, "location".to_string());
+        item.save(&mut conn).unwrap();
+
+        let read = Db::open(&db.path, Some(&db.path), 4).read_conn().unwrap();
+        let name: String = read
+            .query_row(
+                "SELECT name FROM items WHERE barcode = 48",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(name, "item");
+
+        let write = read.execute("DELETE FROM items WHERE barcode = 48", params![]);
+        assert!(write.unwrap_err().to_string().contains("readonly"));
+    }
+
+    #[tokio::test]
+    async fn test_api_index_and_not_found() {
+        let db = test_db();
+        let addr = spawn_test_server(&db).await;
+        let browser = [("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")];
+        let api = [("Accept", "application/json")];
+
+        // browsers keep getting the webclient, at the root and on unknown deep links
+        let page = send_request(addr, "GET", "/", &browser, b"").await;
+        assert_eq!(page.status, 200);
+        assert_eq!(
+            page.text(),
+            fs::read_to_string("../webclient/index.html").unwrap()
+        );
+        let deep_link = send_request(addr, "GET", "/items/42", &browser, b"").await;
+        assert_eq!(deep_link.status, 302);
+        assert_eq!(deep_link.header("location"), Some("/"));
+
+        let index = send_request(addr, "GET", "/", &api, b"").await;
+        assert_eq!(index.status, 200);
+        let index: serde_json::Value = serde_json::from_str(&index.text()).unwrap();
+        let routes = index["routes"].as_array().unwrap();
+        assert_eq!(
+            routes.len(),
+            ROUTES.iter().filter(|route| route.api).count()
+        );
+        assert!(
+            routes
+                .iter()
+                .any(|route| route["path"] == "/item/{barcode}" && route["methods"][0] == "GET")
+        );
+
+        let missing = send_request(addr, "GET", "/items/42", &api, b"").await;
+        assert_eq!(missing.status, 404);
+        let missing: serde_json::Value = serde_json::from_str(&missing.text()).unwrap();
+        assert_eq!(missing["error"], "Not found");
+        assert_eq!(missing["suggestion"], "/item/42");
+
+        assert_eq!(suggest_route("/helth").as_deref(), Some("/health"));
+        assert_eq!(suggest_route("/completely/unrelated"), None);
+    }
+
+    #[tokio::test]
+    async fn test_barcode_as_string() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        let barcode = (1u64 << 53) + 1; // the first integer a JavaScript number can't hold
+
+        // both forms are accepted on input
+        let from_number: Item = serde_json::from_str(&format!(
+            r#"{{"name": "a", "barcode": {}, "location": "b"}}"#,
+            barcode
+        ))
+        .unwrap();
+        let from_string: Item = serde_json::from_str(&format!(
+            r#"{{"name": "a", "barcode": "{}", "location": "b"}}"#,
+            barcode
+        ))
+        .unwrap();
+        assert_eq!(from_number.barcode, barcode);
+        assert_eq!(from_string.barcode, barcode);
+
+        let body = format!(
+            r#"{{"name": "Big barcode", "barcode": "{}", "location": "Rig"}}"#,
+            barcode
+        );
+        let created = send_request(addr, "POST", "/new", &[], body.as_bytes()).await;
+        assert_eq!(created.status, 200);
+
+        let path = format!("/item/{}?barcode_as=string", barcode);
+        let item: serde_json::Value =
+            serde_json::from_str(&send_request(addr, "GET", &path, &[], b"").await.text()).unwrap();
+        assert_eq!(item["barcode"], barcode.to_string());
+
+        let all: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/all?barcode_as=string", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        assert!(
+            all.as_array()
+                .unwrap()
+                .iter()
+                .any(|item| item["barcode"] == barcode.to_string())
+        );
+
+        // numbers stay the default
+        let path = format!("/item/{}", barcode);
+        let item: serde_json::Value =
+            serde_json::from_str(&send_request(addr, "GET", &path, &[], b"").await.text()).unwrap();
+        assert_eq!(item["barcode"].as_u64(), Some(barcode));
+
+        delete_item(&mut conn, &barcode.to_string()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_slow_request_warning() {
+        let threshold = Duration::from_millis(20);
+        let before = SLOW_REQUESTS.load(Ordering::Relaxed);
+
+        // a handler held up in the database layer
+        let ((), elapsed, slowest_query) = timed(async {
+            let _timer = QueryTimer::start("delayed_query");
+            tokio::time::sleep(threshold * 2).await;
+        })
+        .await;
+        assert_eq!(
+            slowest_query.map(|(operation, _)| operation),
+            Some("delayed_query")
+        );
+        assert!(note_slow_request(
+            &hyper::Method::GET,
+            "/delayed",
+            elapsed,
+            slowest_query,
+            Some(threshold)
+        ));
+        assert!(SLOW_REQUESTS.load(Ordering::Relaxed) > before);
+
+        let ((), elapsed, slowest_query) = timed(async {}).await;
+        assert!(slowest_query.is_none());
+        assert!(!note_slow_request(
+            &hyper::Method::GET,
+            "/fast",
+            elapsed,
+            slowest_query,
+            Some(threshold)
+        ));
+
+        // disabled
+        assert!(!note_slow_request(
+            &hyper::Method::GET,
+            "/delayed",
+            Duration::from_secs(60),
+            None,
+            None
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_item_touch() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        let mut item = Item::new("Gaffer tape".to_string(), 49, "Rig".to_string());
+        item.last_seen = Some(1);
+        item.save(&mut conn).unwrap();
+
+        let plain: serde_json::Value =
+            serde_json::from_str(&send_request(addr, "GET", "/item/49", &[], b"").await.text())
+                .unwrap();
+        assert_eq!(plain["last_seen"], 1);
+        assert_eq!(load_item(&conn, 49).unwrap().last_seen, Some(1));
+
+        let touched: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/item/49?touch=true", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        assert!(touched["last_seen"].as_u64().unwrap() > 1);
+        assert_eq!(
+            load_item(&conn, 49).unwrap().last_seen,
+            touched["last_seen"].as_u64()
+        );
+
+        let missing = send_request(addr, "GET", "/item/999999?touch=true", &[], b"").await;
+        assert_eq!(missing.status, 404);
+
+        delete_item(&mut conn, "49").unwrap();
+    }
+
+    #[test]
+    fn test_write_retries_while_locked() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let before = DB_RETRIES.load(Ordering::Relaxed);
+
+        // another connection holds an exclusive lock for longer than one attempt waits
+        let (locked, wait_for_lock) = std::sync::mpsc::channel();
+        let holder = std::thread::spawn(move || {
+            let conn = db.conn().unwrap();
+            conn.execute_batch("BEGIN EXCLUSIVE").unwrap();
+            locked.send(()).unwrap();
+            std::thread::sleep(DB_BUSY_TIMEOUT * 2);
+            conn.execute_batch("COMMIT").unwrap();
+        });
+        wait_for_lock.recv().unwrap();
+
+        Item::new("Spare lamp".to_string(), 50, "Rig".to_string())
+            .save(&mut conn)
+            .unwrap();
+        holder.join().unwrap();
+
+        assert!(DB_RETRIES.load(Ordering::Relaxed) > before);
+        assert_eq!(load_item(&conn, 50).unwrap().name, "Spare lamp");
+
+        // constraint violations fail straight away
+        let duplicate = Item::new("Spare lamp".to_string(), 50, "Rig".to_string()).save(&mut conn);
+        assert!(duplicate.unwrap_err().contains("UNIQUE"));
+        let failure = |code| rusqlite::Error::SqliteFailure(rusqlite::ffi::Error::new(code), None);
+        assert!(is_transient(&failure(rusqlite::ffi::SQLITE_BUSY)));
+        assert!(is_transient(&failure(rusqlite::ffi::SQLITE_LOCKED)));
+        assert!(!is_transient(&failure(rusqlite::ffi::SQLITE_CONSTRAINT)));
+
+        delete_item(&mut conn, "50").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dump_sql() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        Item::new(
+            "Smoke machine, \"hazer\"".to_string(),
+            51,
+            "Drama's store".to_string(),
+        )
+        .save(&mut conn)
+        .unwrap();
+
+        let dump = send_request(addr, "GET", "/dump.sql", &[], b"").await;
+        assert_eq!(dump.status, 200);
+        assert!(
+            dump.header("content-type")
+                .unwrap()
+                .starts_with("application/sql")
+        );
+
+        let dump = dump.text();
+        // SQLite quotes the name once the table has been rebuilt by `add_item_ids`
+        assert!(dump.contains("CREATE TABLE items") || dump.contains("CREATE TABLE \"items\""));
+        assert!(dump.contains("'Drama''s store'"));
+        assert!(!dump.contains("idempotency_keys"));
+        assert!(dump.ends_with("COMMIT;\n"));
+
+        // the dump loads back into an empty database
+        let restored = Connection::open_in_memory().unwrap();
+        restored.execute_batch(&dump).unwrap();
+        let (name, location): (String, String) = restored
+            .query_row(
+                "SELECT name, location FROM items WHERE barcode = 51",
+                params![],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(name, "Smoke machine, \"hazer\"");
+        assert_eq!(location, "Drama's store");
+
+        delete_item(&mut conn, "51").unwrap();
+    }
+
+    #[test]
+    fn test_migrations() {
+        // the first version's table, before any columns were added or migrations tracked
+        let legacy = Connection::open_in_memory().unwrap();
+        legacy
+            .execute_batch(
+                "CREATE TABLE items (
+                    name VARCHAR NOT NULL,
+                    barcode INTEGER NOT NULL UNIQUE,
+                    location VARCHAR NOT NULL,
+                    last_seen TIMESTAMP NOT NULL
+                );
+                INSERT INTO items VALUES ('Hazer', 7, 'Rig', 1700000000);",
+            )
+            .unwrap();
+        let user_version = |conn: &Connection| -> usize {
+            conn.pragma_query_value(None, "user_version", |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(user_version(&legacy), 0);
+
+        upgrade_schema(&legacy).unwrap();
+        assert_eq!(user_version(&legacy), MIGRATIONS.len());
+        let columns: Vec<String> = table_columns(&legacy, "items")
+            .unwrap()
+            .into_iter()
+            .map(|(name, ..)| name)
+            .collect();
+        for (column, _) in ITEM_COLUMNS {
+            assert!(columns.iter().any(|name| name == column), "{}", column);
+        }
+        let item = legacy
+            .query_row(
+                &format!("SELECT {} FROM items WHERE barcode = 7", item_columns()),
+                params![],
+                Item::from_row,
+            )
+            .unwrap();
+        assert_eq!(item.name, "Hazer");
+        assert_eq!(item.version, 1);
+        assert_eq!(item.status.as_deref(), Some("ok"));
+        assert_eq!(item.quantity, Some(1));
+
+        // nothing runs twice
+        assert_eq!(migrate(&legacy).unwrap(), Vec::<usize>::new());
+        upgrade_schema(&legacy).unwrap();
+        assert_eq!(user_version(&legacy), MIGRATIONS.len());
+
+        // a database from a newer server is left alone
+        legacy
+            .pragma_update(None, "user_version", MIGRATIONS.len() + 1)
+            .unwrap();
+        assert!(migrate(&legacy).unwrap_err().contains("use a newer server"));
+    }
+
+    #[test]
+    fn test_item_ids() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        // a database from before items had ids migrates in place, keeping rowids as ids
+        let legacy = Connection::open_in_memory().unwrap();
+        legacy
+            .execute_batch(
+                "CREATE TABLE items (
+                    name VARCHAR NOT NULL,
+                    barcode INTEGER NOT NULL UNIQUE,
+                    location VARCHAR NOT NULL,
+                    last_seen TIMESTAMP NOT NULL
+                );
+                INSERT INTO items VALUES ('a', 1, 'Rig', 0), ('b', 2, 'Rig', 0);
+                DELETE FROM items WHERE barcode = 1;",
+            )
+            .unwrap();
+        upgrade_schema(&legacy).unwrap();
+        upgrade_schema(&legacy).unwrap(); // and only once
+
+        let id: i64 = legacy
+            .query_row("SELECT id FROM items WHERE barcode = 2", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(id, 2);
+        assert!(
+            legacy
+                .execute("INSERT INTO items (name, barcode, location, last_seen) VALUES ('c', 2, 'Rig', 0)", params![])
+                .is_err()
+        );
+
+        // rows referencing an item by id go when it does
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS test_item_refs (
+                item_id INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE
+            )",
+            params![],
+        )
+        .unwrap();
+        Item::new("Hazer fluid".to_string(), 52, "Rig".to_string())
+            .save(&mut conn)
+            .unwrap();
+        conn.execute(
+            "INSERT INTO test_item_refs SELECT id FROM items WHERE barcode = 52",
+            params![],
+        )
+        .unwrap();
+
+        delete_item(&mut conn, "52").unwrap();
+
+        let refs: i64 = conn
+            .query_row("SELECT COUNT(*) FROM test_item_refs", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(refs, 0);
+        conn.execute("DROP TABLE test_item_refs", params![])
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_import_csv() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        assert_eq!(
+            parse_csv("a,\"b, \"\"c\"\"\"\r\n\"multi\nline\",d"),
+            vec![
+                vec!["a".to_string(), "b, \"c\"".to_string()],
+                vec!["multi\nline".to_string(), "d".to_string()],
+            ]
+        );
+
+        let csv = "barcode,name,location\n53,\"Cable, XLR\",Rig\n54,Mic stand,Drama Studio\n5x5,Bad row,Rig\n";
+
+        // a dry run reports without writing
+        let preview = send_request(
+            addr,
+            "POST",
+            "/import.csv?dry_run=true",
+            &[],
+            csv.as_bytes(),
+        )
+        .await;
+        assert_eq!(preview.status, 200);
+        let preview: serde_json::Value = serde_json::from_str(&preview.text()).unwrap();
+        assert_eq!(preview["created"], 2);
+        assert_eq!(preview["skipped"], 1);
+        assert_eq!(preview["errors"][0]["row"], 4);
+        assert_eq!(preview["dry_run"], true);
+        assert!(load_item(&conn, 53).is_err());
+
+        let report = import_items(&mut conn, csv, false).unwrap();
+        assert_eq!((report.created, report.updated, report.skipped), (2, 0, 1));
+        // sanitized as /new would, so importing the same file again changes nothing
+        assert_eq!(load_item(&conn, 53).unwrap().name, "Cable XLR");
+        let report = import_items(&mut conn, csv, false).unwrap();
+        assert_eq!((report.created, report.updated, report.skipped), (0, 0, 3));
+
+        // re-importing only touches what changed
+        let changed = "name,barcode,location\n\"Cable, XLR\",53,Rig\nMic stand,54,Rig\n";
+        let report = import_items(&mut conn, changed, false).unwrap();
+        assert_eq!((report.created, report.updated, report.skipped), (0, 1, 1));
+        assert_eq!(load_item(&conn, 54).unwrap().location, "Rig");
+        assert_eq!(load_item(&conn, 54).unwrap().version, 2);
+
+        assert!(
+            import_items(&mut conn, "name,location\nx,y\n", false)
+                .unwrap_err()
+                .starts_with("Invalid CSV")
+        );
+
+        // a barcode that can't be stored, or that's another item's alias, fails its row alone
+        add_alias(&mut conn, 53, 5012345678900).unwrap();
+        let clashing = format!(
+            "name,barcode,location\nHuge,{},Rig\nLabel,5012345678900,Rig\nGobo,57,Rig\n",
+            MAX_BARCODE + 1
+        );
+        let report = import_items(&mut conn, &clashing, false).unwrap();
+        assert_eq!((report.created, report.updated, report.skipped), (1, 0, 2));
+        assert_eq!(report.errors[0].row, 2);
+        assert_eq!(report.errors[0].errors[0].code, "too_large");
+        assert_eq!(report.errors[1].row, 3);
+        assert!(report.errors[1].error.contains("already an alias"));
+        delete_item(&mut conn, "57").unwrap();
+
+        delete_item(&mut conn, "53").unwrap();
+        delete_item(&mut conn, "54").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_location_normalization() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        assert_eq!(
+            normalize_location("  levi  fox hall ", LocationCase::Existing),
+            "levi fox hall"
+        );
+        assert_eq!(
+            normalize_location("levi  FOX hall", LocationCase::Title),
+            "Levi Fox Hall"
+        );
+        assert_eq!(normalize_location("RIG ", LocationCase::Lower), "rig");
+
+        Item::new(
+            "Flight case".to_string(),
+            55,
+            "props  cupboard ".to_string(),
+        )
+        .save(&mut conn)
+        .unwrap();
+        Item::new("Fog fluid".to_string(), 56, "PROPS CUPBOARD".to_string())
+            .save(&mut conn)
+            .unwrap();
+        assert_eq!(load_item(&conn, 56).unwrap().location, "props cupboard");
+
+        let found: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/location/Props%20Cupboard", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        assert_eq!(found.as_array().unwrap().len(), 2);
+
+        let locations: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/locations", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        let cupboards: Vec<&serde_json::Value> = locations
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|l| {
+                l["location"]
+                    .as_str()
+                    .unwrap()
+                    .eq_ignore_ascii_case("props cupboard")
+            })
+            .collect();
+        assert_eq!(cupboards.len(), 1);
+        assert_eq!(cupboards[0]["items"], 2);
+
+        // variants already stored are merged by the migration
+        let legacy = Connection::open_in_memory().unwrap();
+        legacy
+            .execute_batch(
+                "CREATE TABLE items (
+                    name VARCHAR NOT NULL,
+                    barcode INTEGER NOT NULL UNIQUE,
+                    location VARCHAR NOT NULL,
+                    last_seen TIMESTAMP NOT NULL
+                );
+                INSERT INTO items VALUES ('a', 1, 'rig ', 0), ('b', 2, 'Rig', 0), ('c', 3, 'Rig', 0);",
+            )
+            .unwrap();
+        upgrade_schema(&legacy).unwrap();
+        let distinct: i64 = legacy
+            .query_row(
+                "SELECT COUNT(DISTINCT location) FROM items",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(distinct, 1);
+
+        delete_item(&mut conn, "55").unwrap();
+        delete_item(&mut conn, "56").unwrap();
+    }
+
+    #[test]
+    fn test_dump_sql_skips_bad_rows() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE items (name TEXT, barcode INTEGER);
+            INSERT INTO items VALUES ('good', 1);
+            INSERT INTO items VALUES (CAST(X'FF' AS TEXT), 2);
+            INSERT INTO items VALUES ('also good', 3);",
+        )
+        .unwrap();
+
+        let dump = |conn: &Connection| {
+            let mut dump = String::new();
+            dump_sql(conn, |chunk| {
+                dump.push_str(&chunk);
+                true
+            })
+            .unwrap();
+            dump
+        };
+
+        let first = dump(&conn);
+        assert!(first.contains("VALUES('good',1)"));
+        assert!(first.contains("VALUES('also good',3)"));
+        assert!(first.contains("-- skipped row 2 of items: column 0: text isn't valid UTF-8"));
+        assert!(first.contains("-- 1 rows skipped"));
+        assert!(first.ends_with("COMMIT;\n"));
+        assert_eq!(first, dump(&conn)); // the same every time
+
+        // and what's left still loads
+        let restored = Connection::open_in_memory().unwrap();
+        restored.execute_batch(&first).unwrap();
+        let count: i64 = restored
+            .query_row("SELECT COUNT(*) FROM items", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_item_status() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        let created = send_request(
+            addr,
+            "POST",
+            "/new",
+            &[],
+            br#"{"name": "Old dimmer", "barcode": 57, "location": "Rig", "status": "needs_repair"}"#,
+        )
+        .await;
+        assert_eq!(created.status, 200);
+        assert_eq!(
+            load_item(&conn, 57).unwrap().status.as_deref(),
+            Some("needs_repair")
+        );
+
+        let unknown = send_request(
+            addr,
+            "POST",
+            "/new",
+            &[],
+            br#"{"name": "Mystery box", "barcode": 58, "location": "Rig", "status": "haunted"}"#,
+        )
+        .await;
+        assert_eq!(unknown.status, 422);
+        assert!(load_item(&conn, 58).is_err());
+
+        let listed = |path: &'static str| async move {
+            let all: serde_json::Value =
+                serde_json::from_str(&send_request(addr, "GET", path, &[], b"").await.text())
+                    .unwrap();
+            all.as_array()
+                .unwrap()
+                .iter()
+                .any(|item| item["barcode"] == 57)
+        };
+        assert!(listed("/all").await);
+        assert!(listed("/all?status=needs_repair").await);
+        assert!(!listed("/all?status=missing").await);
+
+        // modify without a status leaves it alone
+        let mut item = load_item(&conn, 57).unwrap();
+        item.status = None;
+        modify_item(&mut conn, item, None).unwrap();
+        assert_eq!(
+            load_item(&conn, 57).unwrap().status.as_deref(),
+            Some("needs_repair")
+        );
+
+        // retired items drop out of /all but can still be looked up
+        let mut item = load_item(&conn, 57).unwrap();
+        item.status = Some("retired".to_string());
+        modify_item(&mut conn, item, None).unwrap();
+        assert!(!listed("/all").await);
+        assert!(listed("/all?include_retired=true").await);
+        assert!(listed("/all?status=retired").await);
+        let one: serde_json::Value =
+            serde_json::from_str(&send_request(addr, "GET", "/item/57", &[], b"").await.text())
+                .unwrap();
+        assert_eq!(one["status"], "retired");
+
+        delete_item(&mut conn, "57").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_attention() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        let mut forgotten = Item::new("Forgotten gel".to_string(), 59, "Rig".to_string());
+        forgotten.last_seen = Some(Utc::now().timestamp() as u64 - 40 * 24 * 60 * 60);
+        forgotten.save(&mut conn).unwrap();
+        let mut broken = Item::new("Broken clamp".to_string(), 60, "Rig".to_string());
+        broken.status = Some("needs_repair".to_string());
+        broken.save(&mut conn).unwrap();
+
+        let barcodes = |list: &serde_json::Value| -> Vec<u64> {
+            list.as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["barcode"].as_u64().unwrap())
+                .collect()
+        };
+
+        let report: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/attention", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        assert_eq!(report["stale_days"], 30);
+        assert!(barcodes(&report["stale"]).contains(&59));
+        assert!(!barcodes(&report["stale"]).contains(&60));
+        assert!(barcodes(&report["status"]["needs_repair"]).contains(&60));
+
+        let report: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/attention?stale_days=60", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        assert!(!barcodes(&report["stale"]).contains(&59));
+
+        let invalid = send_request(addr, "GET", "/attention?stale_days=soon", &[], b"").await;
+        assert_eq!(invalid.status, 400);
+
+        delete_item(&mut conn, "59").unwrap();
+        delete_item(&mut conn, "60").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_log() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        for (barcode, name) in [
+            (61, "Never tested"),
+            (62, "Tested long ago"),
+            (63, "Just tested"),
+        ] {
+            Item::new(name.to_string(), barcode, "Rig".to_string())
+                .save(&mut conn)
+                .unwrap();
+        }
+
+        let post = |path: &'static str, body: &'static str| async move {
+            send_request(addr, "POST", path, &[], body.as_bytes()).await
+        };
+
+        let added = post(
+            "/item/63/maintenance",
+            r#"{"type": "PAT", "description": "passed", "recorded_by": "Sam"}"#,
+        )
+        .await;
+        assert_eq!(added.status, 200);
+        let added: serde_json::Value = serde_json::from_str(&added.text()).unwrap();
+        assert_eq!(added["type"], "pat");
+
+        assert_eq!(
+            post(
+                "/item/63/maintenance",
+                r#"{"type": "polish", "description": "shiny"}"#
+            )
+            .await
+            .status,
+            422
+        );
+        assert_eq!(
+            post(
+                "/item/999999/maintenance",
+                r#"{"type": "pat", "description": "passed"}"#
+            )
+            .await
+            .status,
+            404
+        );
+
+        // a PAT test from two years ago, and a repair since
+        let two_years_ago = Utc::now().timestamp() as u64 - 2 * 365 * 24 * 60 * 60;
+        conn.execute(
+            "INSERT INTO maintenance (item_id, recorded_at, type, description)
+             SELECT id, ?1, 'pat', 'passed' FROM items WHERE barcode = 62",
+            params![two_years_ago],
+        )
+        .unwrap();
+        add_maintenance(
+            &mut conn,
+            62,
+            &NewMaintenance {
+                kind: "repair".to_string(),
+                description: "new plug".to_string(),
+                recorded_by: None,
+            },
+        )
+        .unwrap();
+
+        let log: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/item/62/maintenance", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        assert_eq!(log[0]["type"], "repair"); // newest first
+        assert_eq!(log[1]["recorded_at"], two_years_ago);
+
+        // entries can't be edited
+        assert!(
+            conn.execute("UPDATE maintenance SET description = 'failed'", params![])
+                .is_err()
+        );
+
+        // due: the item never tested and the one tested too long ago, not the one just tested
+        let due: serde_json::Value = serde_json::from_str(
+            &send_request(
+                addr,
+                "GET",
+                "/maintenance/due?type=pat&older_than_days=365",
+                &[],
+                b"",
+            )
+            .await
+            .text(),
+        )
+        .unwrap();
+        let due: Vec<(u64, serde_json::Value)> = due
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                (
+                    entry["item"]["barcode"].as_u64().unwrap(),
+                    entry["last_done"].clone(),
+                )
+            })
+            .collect();
+        assert!(due.contains(&(61, serde_json::Value::Null)));
+        assert!(due.contains(&(62, serde_json::json!(two_years_ago))));
+        assert!(!due.iter().any(|(barcode, _)| *barcode == 63));
+
+        // deleting an item takes its log with it
+        delete_item(&mut conn, "62").unwrap();
+        let orphans: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM maintenance WHERE item_id NOT IN (SELECT id FROM items)",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(orphans, 0);
+
+        delete_item(&mut conn, "61").unwrap();
+        delete_item(&mut conn, "63").unwrap();
+    }
+
+    #[test]
+    fn test_value_by_location() {
+        let valuation = value_by_location([
+            ("Rig".to_string(), Some(1000)),
+            ("rig".to_string(), None),
+            ("Store".to_string(), Some(250)),
+        ])
+        .unwrap();
+        assert_eq!(valuation.value_pence, 1250);
+        assert_eq!(valuation.items, 3);
+        assert_eq!(valuation.missing_value, 1);
+        assert_eq!(
+            valuation.locations[0],
+            LocationValue {
+                location: "Rig".to_string(),
+                value_pence: 1000,
+                items: 2,
+                missing_value: 1,
+            }
+        );
+
+        // too much to add up is an error, not a wrapped-around total
+        assert!(
+            value_by_location([
+                ("Rig".to_string(), Some(i64::MAX)),
+                ("Store".to_string(), Some(1)),
+            ])
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_item_value() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        let new = |body: &'static str| async move {
+            send_request(addr, "POST", "/new", &[], body.as_bytes()).await
+        };
+
+        for bad in [
+            r#"{"name": "Smoke machine", "barcode": 64, "location": "Valuables", "value_pence": -1}"#,
+            r#"{"name": "Smoke machine", "barcode": 64, "location": "Valuables", "purchase_date": "2024-02-30"}"#,
+            r#"{"name": "Smoke machine", "barcode": 64, "location": "Valuables", "purchase_date": "31/03/2024"}"#,
+        ] {
+            assert_eq!(new(bad).await.status, 422, "{}", bad);
+        }
+
+        assert_eq!(
+            new(r#"{"name": "Smoke machine", "barcode": 64, "location": "Valuables", "value_pence": 34999, "purchase_date": "2024-03-31"}"#)
+                .await
+                .status,
+            200
+        );
+        assert_eq!(
+            new(r#"{"name": "Gaffer tape", "barcode": 65, "location": "Valuables"}"#)
+                .await
+                .status,
+            200
+        );
+
+        let item: serde_json::Value =
+            serde_json::from_str(&send_request(addr, "GET", "/item/64", &[], b"").await.text())
+                .unwrap();
+        assert_eq!(item["value_pence"], 34999);
+        assert_eq!(item["purchase_date"], "2024-03-31");
+
+        // modify without them leaves them alone
+        let mut item = load_item(&conn, 64).unwrap();
+        item.value_pence = None;
+        item.purchase_date = None;
+        modify_item(&mut conn, item, None).unwrap();
+        assert_eq!(load_item(&conn, 64).unwrap().value_pence, Some(34999));
+
+        let valuation: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/valuation", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        let valuables = valuation["locations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|location| location["location"] == "Valuables")
+            .unwrap();
+        assert_eq!(valuables["value_pence"], 34999);
+        assert_eq!(valuables["items"], 2);
+        assert_eq!(valuables["missing_value"], 1);
+
+        delete_item(&mut conn, "64").unwrap();
+        delete_item(&mut conn, "65").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_item_fields() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        Item::new("Fresnel".to_string(), 66, "Rig".to_string())
+            .save(&mut conn)
+            .unwrap();
+
+        let item: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/item/66?fields=barcode,name", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        assert_eq!(item, serde_json::json!({"barcode": 66, "name": "Fresnel"}));
+
+        let with_version = send_request(addr, "GET", "/item/66?fields=version", &[], b"").await;
+        assert_eq!(with_version.header("etag"), Some("\"1\""));
+
+        let all: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/all?fields=name&barcode_as=string", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        assert!(
+            all.as_array()
+                .unwrap()
+                .iter()
+                .all(|item| item.as_object().unwrap().len() == 1 && item["name"].is_string())
+        );
+
+        assert_eq!(
+            send_request(addr, "GET", "/all?fields=name,colour", &[], b"")
+                .await
+                .status,
+            400
+        );
+        assert_eq!(
+            send_request(addr, "GET", "/item/999999?fields=name", &[], b"")
+                .await
+                .status,
+            404
+        );
+
+        delete_item(&mut conn, "66").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_item_parents() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        for (barcode, name) in [(67, "Flight case"), (68, "Cable tray"), (69, "DMX cable")] {
+            Item::new(name.to_string(), barcode, "Store".to_string())
+                .save(&mut conn)
+                .unwrap();
+        }
+
+        let set_parent = |child: &'static str, body: &'static str| async move {
+            send_request(
+                addr,
+                "POST",
+                &format!("/item/{}/parent", child),
+                &[],
+                body.as_bytes(),
+            )
+            .await
+            .status
+        };
+
+        // cable in tray in case
+        assert_eq!(set_parent("68", r#"{"parent_barcode": 67}"#).await, 200);
+        assert_eq!(set_parent("69", r#"{"parent_barcode": "68"}"#).await, 200);
+        assert_eq!(load_item(&conn, 69).unwrap().parent_barcode, Some(68));
+
+        // the case can't go inside anything already inside it, or itself
+        assert_eq!(set_parent("67", r#"{"parent_barcode": 69}"#).await, 409);
+        assert_eq!(set_parent("67", r#"{"parent_barcode": 67}"#).await, 409);
+        let mut case = load_item(&conn, 67).unwrap();
+        case.parent_barcode = Some(68);
+        assert_eq!(
+            modify_item(&mut conn, case, None).unwrap_err(),
+            "Parent cycle"
+        );
+        assert_eq!(set_parent("67", r#"{"parent_barcode": 999999}"#).await, 422);
+
+        let children: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/item/67/children", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        let children = children.as_array().unwrap();
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0]["barcode"], 68);
+
+        // logging the case with cascade logs both levels inside it
+        conn.execute(
+            "UPDATE items SET last_seen = 1 WHERE barcode IN (67, 68, 69)",
+            params![],
+        )
+        .unwrap();
+        assert_eq!(
+            send_request(addr, "POST", "/log/67?cascade=true", &[], b"")
+                .await
+                .status,
+            200
+        );
+        for barcode in [67, 68, 69] {
+            assert!(
+                load_item(&conn, barcode).unwrap().last_seen.unwrap() > 1,
+                "{}",
+                barcode
+            );
+        }
+
+        // deleting the tray unpacks the cable rather than deleting it
+        delete_item(&mut conn, "68").unwrap();
+        assert_eq!(load_item(&conn, 69).unwrap().parent_barcode, None);
+
+        delete_item(&mut conn, "67").unwrap();
+        delete_item(&mut conn, "69").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_delete_and_log_methods() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        Item::new("Gel frame".to_string(), 113, "Store".to_string())
+            .save(&mut conn)
+            .unwrap();
+
+        // what the route table advertises is what's accepted
+        for (method, path, status) in [
+            ("PUT", "/log/113", 405),
+            ("GET", "/log/113", 200),
+            ("POST", "/log/113", 200),
+            ("POST", "/delete/113", 405),
+            ("PUT", "/delete/113", 405),
+            ("GET", "/delete/113", 200),
+        ] {
+            let res = send_request(addr, method, path, &[], b"").await;
+            assert_eq!(res.status, status, "{} {}", method, path);
+            let methods = find_route(path).unwrap().methods;
+            assert_eq!(
+                methods.contains(method),
+                status != 405,
+                "{} {}",
+                method,
+                path
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_delete_parent_endpoint() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        for (barcode, name) in [(110, "Flight case"), (111, "Gobo"), (112, "Gobo holder")] {
+            Item::new(name.to_string(), barcode, "Store".to_string())
+                .save(&mut conn)
+                .unwrap();
+        }
+        set_parent(&mut conn, 111, Some(110)).unwrap();
+        set_parent(&mut conn, 112, Some(110)).unwrap();
+        let before = change_counter(&conn).unwrap();
+
+        // unpacking the children (ON DELETE SET NULL) is counted as a change to each of them
+        let res = send_request(addr, "DELETE", "/delete/110", &[], b"").await;
+        assert_eq!(res.status, 200, "{}", res.text());
+        assert!(load_item(&conn, 110).is_err());
+        for barcode in [111, 112] {
+            assert_eq!(load_item(&conn, barcode).unwrap().parent_barcode, None);
+        }
+        let (_, changed, deleted) = load_changes(&mut conn, before).unwrap();
+        let mut changed: Vec<u64> = changed.iter().map(|item| item.barcode).collect();
+        changed.sort();
+        assert_eq!(changed, [111, 112]);
+        assert_eq!(deleted, [110]);
+
+        delete_item(&mut conn, "111").unwrap();
+        delete_item(&mut conn, "112").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_item_aliases() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        for (barcode, name) in [(70, "Hazer"), (72, "Smoke fluid")] {
+            Item::new(name.to_string(), barcode, "Store".to_string())
+                .save(&mut conn)
+                .unwrap();
+        }
+
+        let alias = |method: &'static str, barcode: u64, body: &'static str| async move {
+            send_request(
+                addr,
+                method,
+                &format!("/item/{}/aliases", barcode),
+                &[],
+                body.as_bytes(),
+            )
+            .await
+            .status
+        };
+
+        assert_eq!(alias("POST", 70, r#"{"alias": 71}"#).await, 200);
+        assert_eq!(
+            send_request(addr, "GET", "/item/70/aliases", &[], b"")
+                .await
+                .text(),
+            "[71]"
+        );
+
+        // scanning the alias finds, and logs, the item
+        let found = send_request(addr, "GET", "/item/71", &[], b"").await;
+        assert_eq!(found.header("x-matched-alias"), Some("71"));
+        let found: serde_json::Value = serde_json::from_str(&found.text()).unwrap();
+        assert_eq!(found["barcode"], 70);
+        assert_eq!(found["matched_alias"], 71);
+
+        conn.execute(
+            "UPDATE items SET last_seen = 1 WHERE barcode = 70",
+            params![],
+        )
+        .unwrap();
+        let logged = send_request(addr, "POST", "/log/71", &[], b"").await;
+        assert_eq!(logged.status, 200);
+        assert_eq!(logged.header("x-matched-alias"), Some("71"));
+        assert!(load_item(&conn, 70).unwrap().last_seen.unwrap() > 1);
+
+        // an alias can't be a barcode, and a barcode can't be an alias
+        assert_eq!(
+            send_request(
+                addr,
+                "POST",
+                "/new",
+                &[],
+                br#"{"name": "Clash", "barcode": 71, "location": "Store"}"#,
+            )
+            .await
+            .status,
+            409
+        );
+        assert_eq!(alias("POST", 70, r#"{"alias": 72}"#).await, 409);
+        assert_eq!(alias("POST", 72, r#"{"alias": 71}"#).await, 409);
+
+        assert_eq!(alias("DELETE", 70, r#"{"alias": 71}"#).await, 200);
+        assert_eq!(alias("DELETE", 70, r#"{"alias": 71}"#).await, 404);
+
+        // deleting by alias deletes the item, and its aliases with it
+        assert_eq!(alias("POST", 70, r#"{"alias": 71}"#).await, 200);
+        assert_eq!(
+            send_request(addr, "DELETE", "/delete/71", &[], b"")
+                .await
+                .status,
+            200
+        );
+        assert!(load_item(&conn, 70).is_err());
+        assert_eq!(resolve_alias(&conn, 71).unwrap(), None);
+
+        delete_item(&mut conn, "72").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_move_trail() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        Item::new("Prop trunk".to_string(), 73, "Store".to_string())
+            .save(&mut conn)
+            .unwrap();
+        Item::new("Prop sword".to_string(), 74, "Store".to_string())
+            .save(&mut conn)
+            .unwrap();
+        set_parent(&mut conn, 74, Some(73)).unwrap();
+
+        let move_to = |body: &'static str| async move {
+            send_request(addr, "POST", "/move", &[], body.as_bytes()).await
         };
-        return Ok(resp);
+
+        assert_eq!(
+            move_to(r#"{"barcode": 73, "location": "Rig"}"#)
+                .await
+                .status,
+            200
+        );
+        assert_eq!(load_item(&conn, 74).unwrap().location, "Store"); // no cascade
+
+        let moved =
+            move_to(r#"{"barcode": 73, "location": "Drama Studio", "cascade": true}"#).await;
+        assert_eq!(moved.status, 200);
+        let moved: serde_json::Value = serde_json::from_str(&moved.text()).unwrap();
+        assert_eq!(moved["item"]["location"], "Drama Studio");
+        assert_eq!(moved["moved"], 2);
+        assert_eq!(moved["trail"][0]["from"], "Rig");
+        assert_eq!(moved["trail"][0]["to"], "Drama Studio");
+        assert_eq!(moved["trail"][1]["from"], "Store");
+        assert_eq!(load_item(&conn, 74).unwrap().location, "Drama Studio");
+
+        // /modify moves are on the trail too, case-only changes aren't
+        let mut trunk = load_item(&conn, 73).unwrap();
+        trunk.location = "drama studio".to_string();
+        modify_item(&mut conn, trunk, None).unwrap();
+        let mut trunk = load_item(&conn, 73).unwrap();
+        trunk.location = "Store".to_string();
+        modify_item(&mut conn, trunk, None).unwrap();
+        let trail: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/item/73/trail?limit=2", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        assert_eq!(trail.as_array().unwrap().len(), 2);
+        assert_eq!(trail[0]["to"], "Store");
+        assert_eq!(trail[1]["to"], "Drama Studio");
+
+        assert_eq!(
+            move_to(r#"{"barcode": 999999, "location": "Rig"}"#)
+                .await
+                .status,
+            404
+        );
+        assert_eq!(
+            move_to(r#"{"barcode": 73, "location": "  "}"#).await.status,
+            422
+        );
+
+        delete_item(&mut conn, "73").unwrap();
+        delete_item(&mut conn, "74").unwrap();
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let booked = (1000, 2000);
+        // touching at either end
+        assert!(!overlaps(booked, (2000, 3000)));
+        assert!(!overlaps(booked, (0, 1000)));
+        // identical, contained and containing
+        assert!(overlaps(booked, booked));
+        assert!(overlaps(booked, (1200, 1500)));
+        assert!(overlaps(booked, (500, 2500)));
+        // sharing a start or an end
+        assert!(overlaps(booked, (1000, 1001)));
+        assert!(overlaps(booked, (1999, 2000)));
+        // partly before, partly after, and well clear
+        assert!(overlaps(booked, (500, 1001)));
+        assert!(overlaps(booked, (1999, 2500)));
+        assert!(!overlaps(booked, (3000, 4000)));
+        // symmetric
+        assert!(overlaps((1200, 1500), booked));
+        assert!(!overlaps((2000, 3000), booked));
+    }
+
+    #[tokio::test]
+    async fn test_reservations() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        Item::new("Hazer".to_string(), 75, "Store".to_string())
+            .save(&mut conn)
+            .unwrap();
+
+        let reserve = |body: String| async move {
+            send_request(addr, "POST", "/item/75/reservations", &[], body.as_bytes()).await
+        };
+        let range = |start: u64, end: u64| {
+            format!(
+                r#"{{"starts_at": {}, "ends_at": {}, "reserved_by": "Panto"}}"#,
+                start, end
+            )
+        };
+
+        let booked = reserve(range(1000, 2000)).await;
+        assert_eq!(booked.status, 200);
+        let booked: serde_json::Value = serde_json::from_str(&booked.text()).unwrap();
+
+        // touching ranges are fine, any overlap isn't, and the clash is named
+        assert_eq!(reserve(range(2000, 3000)).await.status, 200);
+        assert_eq!(reserve(range(0, 1000)).await.status, 200);
+        for (start, end) in [(1000, 2000), (1200, 1500), (500, 2500), (1999, 2001)] {
+            let clash = reserve(range(start, end)).await;
+            assert_eq!(clash.status, 409, "{}-{}", start, end);
+            let clash: serde_json::Value = serde_json::from_str(&clash.text()).unwrap();
+            assert!(clash["conflict"]["id"].is_i64());
+        }
+        let clash: serde_json::Value =
+            serde_json::from_str(&reserve(range(1500, 1600)).await.text()).unwrap();
+        assert_eq!(clash["conflict"]["id"], booked["id"]);
+
+        // validation
+        assert_eq!(reserve(range(2000, 2000)).await.status, 422);
+        assert_eq!(reserve(range(5000, 4000)).await.status, 422);
+        assert_eq!(reserve(range(0, 365 * 24 * 60 * 60)).await.status, 422);
+        assert_eq!(
+            reserve(r#"{"starts_at": 8000, "ends_at": 9000, "reserved_by": " "}"#.to_string())
+                .await
+                .status,
+            422
+        );
+
+        // the calendar shows what overlaps the range asked for
+        let calendar: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/reservations?from=1500&to=2500", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        let ours: Vec<(u64, u64)> = calendar
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|reservation| reservation["barcode"] == 75)
+            .map(|reservation| {
+                (
+                    reservation["starts_at"].as_u64().unwrap(),
+                    reservation["ends_at"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(ours, [(1000, 2000), (2000, 3000)]);
+
+        // cancelling frees the slot
+        let cancel = format!("/item/75/reservations/{}", booked["id"]);
+        assert_eq!(
+            send_request(addr, "DELETE", &cancel, &[], b"").await.status,
+            200
+        );
+        assert_eq!(
+            send_request(addr, "DELETE", &cancel, &[], b"").await.status,
+            404
+        );
+        assert_eq!(reserve(range(1200, 1500)).await.status, 200);
+
+        // a reservation running now shows on the item
+        let now = Utc::now().timestamp() as u64;
+        assert_eq!(reserve(range(now - 60, now + 3600)).await.status, 200);
+        let item: serde_json::Value =
+            serde_json::from_str(&send_request(addr, "GET", "/item/75", &[], b"").await.text())
+                .unwrap();
+        assert_eq!(item["reservation"]["reserved_by"], "Panto");
+
+        // double bookings that got in anyway are reported
+        conn.execute(
+            "INSERT INTO reservations (item_id, starts_at, ends_at, reserved_by)
+             SELECT id, 2500, 2600, 'Sneaky' FROM items WHERE barcode = 75",
+            params![],
+        )
+        .unwrap();
+        let conflicts: serde_json::Value = serde_json::from_str(
+            &send_request(addr, "GET", "/reservations/conflicts", &[], b"")
+                .await
+                .text(),
+        )
+        .unwrap();
+        assert!(conflicts.as_array().unwrap().iter().any(|pair| {
+            pair[0]["barcode"] == 75 && pair[0]["starts_at"] == 2000 && pair[1]["starts_at"] == 2500
+        }));
+
+        delete_item(&mut conn, "75").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_static_not_modified() {
+        let db = test_db();
+        let addr = spawn_test_server(&db).await;
+
+        let first = send_request(addr, "GET", "/favicon.ico", &[], b"").await;
+        assert_eq!(first.status, 200);
+        let etag = first.header("etag").unwrap().to_string();
+        let last_modified = first.header("last-modified").unwrap().to_string();
+
+        let again = send_request(
+            addr,
+            "GET",
+            "/favicon.ico",
+            &[("If-None-Match", etag.as_str())],
+            b"",
+        )
+        .await;
+        assert_eq!(again.status, 304);
+        assert!(again.body.is_empty());
+        assert_eq!(again.header("etag"), Some(etag.as_str()));
+
+        let since = send_request(
+            addr,
+            "GET",
+            "/favicon.ico",
+            &[("If-Modified-Since", last_modified.as_str())],
+            b"",
+        )
+        .await;
+        assert_eq!(since.status, 304);
+
+        let stale = send_request(
+            addr,
+            "GET",
+            "/favicon.ico",
+            &[("If-None-Match", "\"0-0\"")],
+            b"",
+        )
+        .await;
+        assert_eq!(stale.status, 200);
+
+        let script = send_request(addr, "GET", "/script.js", &[], b"").await;
+        let etag = script.header("etag").unwrap().to_string();
+        assert_eq!(
+            send_request(
+                addr,
+                "GET",
+                "/script.js",
+                &[("If-None-Match", etag.as_str())],
+                b""
+            )
+            .await
+            .status,
+            304
+        );
+    }
+
+    #[tokio::test]
+    async fn test_archive() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        Item::new("Old dimmer".to_string(), 76, "Store".to_string())
+            .save(&mut conn)
+            .unwrap();
+        move_item(&mut conn, 76, "Rig", false).unwrap();
+        add_maintenance(
+            &mut conn,
+            76,
+            &NewMaintenance {
+                kind: "pat".to_string(),
+                description: "failed".to_string(),
+                recorded_by: None,
+            },
+        )
+        .unwrap();
+
+        let post =
+            |path: &'static str| async move { send_request(addr, "POST", path, &[], b"").await };
+
+        let archived = post("/archive/76?history=true").await;
+        assert_eq!(archived.status, 200);
+        let archived: serde_json::Value = serde_json::from_str(&archived.text()).unwrap();
+        assert_eq!(archived["name"], "Old dimmer");
+        assert!(archived["archived_at"].is_u64());
+        assert_eq!(load_item(&conn, 76).unwrap_err(), "Item not found");
+        assert_eq!(post("/archive/76").await.status, 404);
+
+        let listed = send_request(addr, "GET", "/archived?limit=10", &[], b"").await;
+        assert_eq!(listed.status, 200);
+        assert!(
+            listed
+                .header("x-total-count")
+                .unwrap()
+                .parse::<u64>()
+                .unwrap()
+                >= 1
+        );
+        let listed: serde_json::Value = serde_json::from_str(&listed.text()).unwrap();
+        assert!(
+            listed
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|item| item["barcode"] == 76)
+        );
+        assert_eq!(
+            send_request(addr, "GET", "/archived?limit=0", &[], b"")
+                .await
+                .status,
+            400
+        );
+
+        // the barcode is free again by default, and coming back waits until it is again
+        Item::new("New dimmer".to_string(), 76, "Rig".to_string())
+            .save(&mut conn)
+            .unwrap();
+        assert_eq!(post("/unarchive/76").await.status, 409);
+        assert_eq!(post("/archive/76").await.status, 409);
+        delete_item(&mut conn, "76").unwrap();
+
+        let restored = post("/unarchive/76").await;
+        assert_eq!(restored.status, 200);
+        assert_eq!(load_item(&conn, 76).unwrap().name, "Old dimmer");
+        assert_eq!(load_trail(&conn, 76, 5).unwrap()[0].to, "Rig");
+        assert_eq!(
+            load_maintenance(&conn, 76).unwrap()[0].description,
+            "failed"
+        );
+        assert_eq!(post("/unarchive/76").await.status, 404);
+
+        // without its history, the history goes
+        archive_item(&mut conn, 76, false).unwrap();
+        unarchive_item(&mut conn, 76).unwrap();
+        assert!(load_maintenance(&conn, 76).unwrap().is_empty());
+
+        delete_item(&mut conn, "76").unwrap();
+    }
+
+    #[test]
+    fn test_archived_barcode_reuse() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        Item::new("Old cable".to_string(), 77, "Store".to_string())
+            .save(&mut conn)
+            .unwrap();
+        archive_item(&mut conn, 77, false).unwrap();
+
+        assert_eq!(check_archived_barcode(&conn, 77, true).unwrap(), Ok(()));
+        assert_eq!(
+            check_archived_barcode(&conn, 77, false).unwrap(),
+            Err("Barcode is archived".to_string())
+        );
+        // barcodes nobody has archived are always free
+        assert_eq!(
+            check_archived_barcode(&conn, 999999, false).unwrap(),
+            Ok(())
+        );
+
+        unarchive_item(&mut conn, 77).unwrap();
+        assert_eq!(check_archived_barcode(&conn, 77, false).unwrap(), Ok(()));
+        delete_item(&mut conn, "77").unwrap();
+    }
+
+    #[test]
+    fn test_field_policy() {
+        assert_eq!(
+            parse_field_policy("").unwrap(),
+            vec![
+                ("location", FieldPolicy::Required),
+                ("notes", FieldPolicy::Optional)
+            ]
+        );
+        assert_eq!(
+            parse_field_policy(" location = disabled ").unwrap(),
+            vec![
+                ("location", FieldPolicy::Disabled),
+                ("notes", FieldPolicy::Optional)
+            ]
+        );
+        assert!(parse_field_policy("location").is_err());
+        assert!(parse_field_policy("location=sometimes").is_err());
+        assert!(parse_field_policy("colour=optional").is_err());
+
+        let placed = Item::new("Gobo".to_string(), 1, "Rig".to_string());
+        let unplaced = Item::new("Gobo".to_string(), 1, " ".to_string());
+
+        let messages = |violations: Vec<Violation>| {
+            violations
+                .into_iter()
+                .map(|violation| violation.message)
+                .collect::<Vec<_>>()
+        };
+
+        let required = parse_field_policy("location=required").unwrap();
+        assert!(policy_violations(&placed, &required).is_empty());
+        assert_eq!(
+            messages(policy_violations(&unplaced, &required)),
+            ["location is required"]
+        );
+
+        let optional = parse_field_policy("location=optional").unwrap();
+        assert!(policy_violations(&placed, &optional).is_empty());
+        assert!(policy_violations(&unplaced, &optional).is_empty());
+
+        let disabled = parse_field_policy("location=disabled").unwrap();
+        assert_eq!(
+            messages(policy_violations(&placed, &disabled)),
+            ["location is disabled"]
+        );
+        assert!(policy_violations(&unplaced, &disabled).is_empty());
+
+        let noted = Item {
+            notes: Some("dented".to_string()),
+            ..placed.clone()
+        };
+        let notes_required = parse_field_policy("notes=required").unwrap();
+        assert!(policy_violations(&noted, &notes_required).is_empty());
+        assert_eq!(
+            messages(policy_violations(&placed, &notes_required)),
+            ["notes is required"]
+        );
+        // both at once
+        let both = parse_field_policy("location=required,notes=required").unwrap();
+        assert_eq!(
+            messages(policy_violations(&unplaced, &both)),
+            ["location is required", "notes is required"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_js() {
+        let db = test_db();
+        let addr = spawn_test_server(&db).await;
+        let config = send_request(addr, "GET", "/config.js", &[], b"").await;
+        assert_eq!(config.status, 200);
+        assert_eq!(
+            config.header("content-type"),
+            Some("application/javascript")
+        );
+        assert_eq!(
+            config.text(),
+            "const FIELD_POLICY = {\"location\":\"required\",\"notes\":\"optional\"};\nconst BASE_PATH = \"\";\n"
+        );
+
+        // the default policy requires a location
+        let new = send_request(
+            addr,
+            "POST",
+            "/new",
+            &[],
+            br#"{"name": "Gobo", "barcode": 999998, "last_seen": null}"#,
+        )
+        .await;
+        assert_eq!(new.status, 422);
+        let body: serde_json::Value = serde_json::from_str(&new.text()).unwrap();
+        assert_eq!(
+            body["errors"],
+            serde_json::json!([{"field": "location", "code": "required", "message": "location is required"}])
+        );
+    }
+
+    #[test]
+    fn test_strip_base_path() {
+        assert_eq!(normalize_base_path(""), "");
+        assert_eq!(normalize_base_path("/"), "");
+        assert_eq!(normalize_base_path("inventory/"), "/inventory");
+        assert_eq!(normalize_base_path(" /inventory "), "/inventory");
+
+        assert_eq!(strip_base_path("/all", ""), Some("/all"));
+        assert_eq!(
+            strip_base_path("/inventory/all", "/inventory"),
+            Some("/all")
+        );
+        assert_eq!(strip_base_path("/inventory", "/inventory"), Some("/"));
+        assert_eq!(strip_base_path("/inventory/", "/inventory"), Some("/"));
+        assert_eq!(strip_base_path("/inventoryx/all", "/inventory"), None);
+        assert_eq!(strip_base_path("/all", "/inventory"), None);
+    }
+
+    #[tokio::test]
+    async fn test_base_path() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server_under(&db, "/inventory").await;
+        let browser = [("Accept", "text/html,application/xhtml+xml,*/*;q=0.8")];
+        let api = [("Accept", "application/json")];
+
+        let new = send_request(
+            addr,
+            "POST",
+            "/inventory/new",
+            &[],
+            br#"{"name": "Follow spot", "barcode": 78, "location": "Rig"}"#,
+        )
+        .await;
+        assert_eq!(new.status, 200);
+        let item = send_request(
+            addr,
+            "GET",
+            "/inventory/item/78?barcode_as=string",
+            &[],
+            b"",
+        )
+        .await;
+        assert_eq!(item.status, 200);
+        let item: serde_json::Value = serde_json::from_str(&item.text()).unwrap();
+        assert_eq!(item["barcode"], "78");
+
+        // nothing is served outside the base path
+        assert_eq!(
+            send_request(addr, "GET", "/item/78", &[], b"").await.status,
+            404
+        );
+
+        let index = send_request(addr, "GET", "/inventory/", &api, b"").await;
+        let index: serde_json::Value = serde_json::from_str(&index.text()).unwrap();
+        assert!(
+            index["routes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .all(|route| route["path"].as_str().unwrap().starts_with("/inventory/"))
+        );
+        let missing = send_request(addr, "GET", "/inventory/items/78", &api, b"").await;
+        let missing: serde_json::Value = serde_json::from_str(&missing.text()).unwrap();
+        assert_eq!(missing["suggestion"], "/inventory/item/78");
+
+        // the page is served from the base path's root, where its relative links resolve under it,
+        // and the webclient is told where the API is
+        let page = send_request(addr, "GET", "/inventory", &browser, b"").await;
+        assert_eq!(page.status, 302);
+        assert_eq!(page.header("location"), Some("/inventory/"));
+        let deep_link = send_request(addr, "GET", "/inventory/item/78", &browser, b"").await;
+        assert_eq!(deep_link.header("location"), Some("/inventory/"));
+        let page = send_request(addr, "GET", "/inventory/", &browser, b"").await;
+        assert_eq!(page.status, 200);
+        assert!(page.text().contains(r#"src="script.js""#));
+        let config = send_request(addr, "GET", "/inventory/config.js", &[], b"").await;
+        assert!(config.text().contains(r#"const BASE_PATH = "/inventory";"#));
+
+        delete_item(&mut conn, "78").unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_item_notes() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        let post = |path: &'static str, body: &'static str| async move {
+            send_request(addr, "POST", path, &[], body.as_bytes()).await
+        };
+
+        assert_eq!(
+            post(
+                "/new",
+                r#"{"name": "Fresnel", "barcode": 79, "location": "Rig", "notes": "arrived boxed"}"#
+            )
+            .await
+            .status,
+            200
+        );
+        let noted = post("/note/79", r#"{"text": "connector slightly bent"}"#).await;
+        assert_eq!(noted.status, 200);
+        let noted: serde_json::Value = serde_json::from_str(&noted.text()).unwrap();
+        assert_eq!(noted["text"], "connector slightly bent");
+
+        // modifying adds its note rather than replacing the others, and no note adds nothing
+        assert_eq!(
+            post(
+                "/modify",
+                r#"{"name": "Fresnel", "barcode": 79, "location": "Store", "notes": "lamp replaced"}"#
+            )
+            .await
+            .status,
+            200
+        );
+        assert_eq!(
+            post(
+                "/modify",
+                r#"{"name": "Fresnel", "barcode": 79, "location": "Store"}"#
+            )
+            .await
+            .status,
+            200
+        );
+
+        let item: serde_json::Value =
+            serde_json::from_str(&send_request(addr, "GET", "/item/79", &[], b"").await.text())
+                .unwrap();
+        let notes: Vec<&str> = item["notes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|note| note["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            notes,
+            ["lamp replaced", "connector slightly bent", "arrived boxed"]
+        );
+
+        assert_eq!(post("/note/79", r#"{"text": "  "}"#).await.status, 422);
+        assert_eq!(post("/note/79", r#"{"txt": "typo"}"#).await.status, 400);
+        assert_eq!(
+            post("/note/999999", r#"{"text": "lost"}"#).await.status,
+            404
+        );
+
+        delete_item(&mut conn, "79").unwrap();
+    }
+
+    #[test]
+    fn test_page() {
+        let page_of =
+            |uri: &str, default| page(&Request::builder().uri(uri).body(()).unwrap(), default);
+
+        assert_eq!(page_of("/all", Some(100)), Ok((Some(100), 0)));
+        assert_eq!(page_of("/all", None), Ok((None, 0)));
+        assert_eq!(page_of("/all?limit=5&offset=10", None), Ok((Some(5), 10)));
+        assert_eq!(
+            page_of("/all?limit=5000", Some(100)),
+            Ok((Some(MAX_PAGE_LIMIT), 0))
+        );
+        assert_eq!(
+            page_of("/all?limit=all", None),
+            Ok((Some(MAX_PAGE_LIMIT), 0))
+        );
+        assert!(page_of("/all?limit=0", Some(100)).is_err());
+        assert!(page_of("/all?offset=-1", Some(100)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_all_paging() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        // seen in the future, so they're the most recently seen whatever else is in the database
+        let later = Utc::now().timestamp() as u64 + 1_000_000;
+        for (barcode, seen) in [(80, later + 3), (81, later + 2), (82, later + 1)] {
+            Item {
+                last_seen: Some(seen),
+                ..Item::new("Cue light".to_string(), barcode, "Store".to_string())
+            }
+            .save(&mut conn)
+            .unwrap();
+        }
+
+        let barcodes = |resp: &TestResponse| -> Vec<u64> {
+            serde_json::from_str::<serde_json::Value>(&resp.text())
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| item["barcode"].as_u64().unwrap())
+                .collect()
+        };
+
+        let first = send_request(addr, "GET", "/all?limit=2", &[], b"").await;
+        assert_eq!(barcodes(&first), [80, 81]);
+        let total: usize = first.header("x-total-count").unwrap().parse().unwrap();
+        assert!(total >= 3);
+
+        let second = send_request(addr, "GET", "/all?limit=2&offset=1", &[], b"").await;
+        assert_eq!(barcodes(&second), [81, 82]);
+        let fields = send_request(addr, "GET", "/all?fields=barcode&limit=1", &[], b"").await;
+        assert_eq!(barcodes(&fields), [80]);
+
+        let everything = send_request(addr, "GET", "/all?limit=all", &[], b"").await;
+        assert!(barcodes(&everything).len() >= 3);
+        assert_eq!(
+            send_request(addr, "GET", "/all?limit=0", &[], b"")
+                .await
+                .status,
+            400
+        );
+
+        for barcode in ["80", "81", "82"] {
+            delete_item(&mut conn, barcode).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_changes() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        let all = send_request(addr, "GET", "/all?fields=barcode", &[], b"").await;
+        let etag = all.header("etag").unwrap().to_string();
+        let since = etag.trim_matches('"').parse::<u64>().unwrap();
+
+        Item::new("Smoke machine".to_string(), 83, "Store".to_string())
+            .save(&mut conn)
+            .unwrap();
+
+        let changes = |resp: &TestResponse, key: &str| -> Vec<u64> {
+            serde_json::from_str::<serde_json::Value>(&resp.text()).unwrap()[key]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|change| change.get("barcode").unwrap_or(change).as_u64().unwrap())
+                .collect()
+        };
+        let path = format!("/changes?since={}", since);
+        let saved = send_request(addr, "GET", &path, &[], b"").await;
+        assert_eq!(saved.status, 200);
+        assert!(changes(&saved, "changed").contains(&83));
+        assert!(!changes(&saved, "deleted").contains(&83));
+
+        // the list has changed, so the old ETag is stale
+        let stale = send_request(
+            addr,
+            "GET",
+            "/all?fields=barcode",
+            &[("If-None-Match", etag.as_str())],
+            b"",
+        )
+        .await;
+        assert_eq!(stale.status, 200);
+        assert_ne!(stale.header("etag"), Some(etag.as_str()));
+        let any = send_request(addr, "GET", "/all", &[("If-None-Match", "*")], b"").await;
+        assert_eq!(any.status, 304);
+
+        delete_item(&mut conn, "83").unwrap();
+        let deleted = send_request(addr, "GET", &path, &[], b"").await;
+        assert!(!changes(&deleted, "changed").contains(&83));
+        assert!(changes(&deleted, "deleted").contains(&83));
+
+        let counter =
+            serde_json::from_str::<serde_json::Value>(&deleted.text()).unwrap()["counter"]
+                .as_u64()
+                .unwrap();
+        let ahead = format!("/changes?since={}", counter + 1_000_000);
+        assert_eq!(
+            send_request(addr, "GET", &ahead, &[], b"").await.status,
+            410
+        );
+        assert_eq!(
+            send_request(addr, "GET", "/changes", &[], b"").await.status,
+            400
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sync() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        let sync = |since: u64| async move {
+            let resp = send_request(addr, "GET", &format!("/sync?since={}", since), &[], b"").await;
+            assert_eq!(resp.status, 200);
+            serde_json::from_str::<serde_json::Value>(&resp.text()).unwrap()
+        };
+        let has = |delta: &serde_json::Value, list: &str| {
+            delta[list]
+                .as_array()
+                .unwrap()
+                .contains(&serde_json::Value::from(84))
+        };
+
+        let before = sync(0).await["cursor"].as_u64().unwrap();
+        Item::new("Haze machine".to_string(), 84, "Store".to_string())
+            .save(&mut conn)
+            .unwrap();
+        let created = sync(before).await;
+        assert!(has(&created, "created"));
+        assert!(!has(&created, "updated") && !has(&created, "deleted"));
+
+        let saved = created["cursor"].as_u64().unwrap();
+        touch_item(&mut conn, "84", false).unwrap();
+        let updated = sync(saved).await;
+        assert!(has(&updated, "updated"));
+        assert!(!has(&updated, "created") && !has(&updated, "deleted"));
+
+        delete_item(&mut conn, "84").unwrap();
+        let deleted = sync(saved).await;
+        assert!(has(&deleted, "deleted"));
+        assert!(!has(&deleted, "created") && !has(&deleted, "updated"));
+        // it came and went, so a client that never saw it has nothing to do
+        let gone = sync(before).await;
+        assert!(!has(&gone, "created") && !has(&gone, "updated") && !has(&gone, "deleted"));
+
+        let ahead = format!("/sync?since={}", u64::MAX);
+        assert_eq!(
+            send_request(addr, "GET", &ahead, &[], b"").await.status,
+            410
+        );
+    }
+
+    #[test]
+    fn test_path_barcode() {
+        assert_eq!(path_barcode("42"), Ok(42));
+        assert_eq!(path_barcode(&MAX_BARCODE.to_string()), Ok(MAX_BARCODE));
+        assert_eq!(
+            path_barcode("forty-two"),
+            Err("Invalid barcode: not a number".to_string())
+        );
+        assert_eq!(
+            path_barcode(""),
+            Err("Invalid barcode: not a number".to_string())
+        );
+        for too_large in [
+            (MAX_BARCODE + 1).to_string(),
+            u64::MAX.to_string(),
+            format!("{}0", u64::MAX),
+        ] {
+            assert!(path_barcode(&too_large).unwrap_err().contains("too large"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_large_path_barcodes() {
+        let db = test_db();
+        let addr = spawn_test_server(&db).await;
+
+        let largest = format!("/item/{}", MAX_BARCODE);
+        assert_eq!(
+            send_request(addr, "GET", &largest, &[], b"").await.status,
+            404
+        );
+
+        for path in [
+            format!("/item/{}", u64::MAX),
+            format!("/item/{}0", u64::MAX),
+            format!("/item/{}/maintenance", u64::MAX),
+        ] {
+            let resp = send_request(addr, "GET", &path, &[], b"").await;
+            assert_eq!(resp.status, 400);
+            assert!(resp.text().contains("too large"), "{}", resp.text());
+        }
+        let resp = send_request(addr, "GET", "/item/12ab", &[], b"").await;
+        assert_eq!(resp.status, 400);
+        assert_eq!(resp.text(), "Invalid barcode: not a number");
+    }
+
+    #[tokio::test]
+    async fn test_large_json_barcodes() {
+        let db = test_db();
+        let addr = spawn_test_server(&db).await;
+        let new = |barcode: String| async move {
+            let body = format!(
+                r#"{{"name": "Fader", "barcode": {}, "location": "Store"}}"#,
+                barcode
+            );
+            send_request(addr, "POST", "/new", &[], body.as_bytes()).await
+        };
+
+        let largest = new(MAX_BARCODE.to_string()).await;
+        assert_eq!(largest.status, 200, "{}", largest.text());
+
+        // one more can't be stored, so it's a 400 whether sent as a number or a string
+        for barcode in [
+            (MAX_BARCODE + 1).to_string(),
+            format!("\"{}\"", MAX_BARCODE + 1),
+        ] {
+            let resp = new(barcode).await;
+            assert_eq!(resp.status, 400, "{}", resp.text());
+            assert!(
+                resp.text().contains(&MAX_BARCODE.to_string()),
+                "{}",
+                resp.text()
+            );
+        }
     }
 
-    Ok(Response::new(ok()))
-}
+    #[cfg(unix)]
+    #[test]
+    fn test_running_pid() {
+        let path = std::env::temp_dir().join(format!("barcode-test-{}.pid", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        fs::write(path, std::process::id().to_string()).unwrap();
+        assert_eq!(running_pid(path), Some(std::process::id() as i32));
+
+        // a pid that can't be running, as if the server had crashed and its pid file was left
+        fs::write(path, i32::MAX.to_string()).unwrap();
+        assert_eq!(running_pid(path), None);
+        assert!(!std::path::Path::new(path).exists());
+
+        fs::write(path, "not a pid").unwrap();
+        assert_eq!(running_pid(path), None);
+        assert_eq!(running_pid(path), None);
+    }
+
+    #[test]
+    fn test_wants_envelope() {
+        assert!(!wants_envelope(None, false));
+        assert!(wants_envelope(None, true));
+        assert!(wants_envelope(Some("limit=5&envelope=true"), false));
+        assert!(!wants_envelope(Some("envelope=false"), true));
+        assert!(!wants_envelope(Some("envelope=maybe"), false));
+    }
+
+    #[tokio::test]
+    async fn test_envelope() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        Item::new("Gobo rotator".to_string(), 85, "Store".to_string())
+            .save(&mut conn)
+            .unwrap();
+
+        let resp = send_request(addr, "GET", "/item/85?envelope=true", &[], b"").await;
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.header("content-type"), Some("application/json"));
+        let body: serde_json::Value = serde_json::from_str(&resp.text()).unwrap();
+        assert_eq!(body["ok"], true);
+        assert_eq!(body["data"]["barcode"], 85);
+
+        // plain text replies become strings
+        let resp = send_request(addr, "POST", "/log/85?envelope=true", &[], b"").await;
+        let body: serde_json::Value = serde_json::from_str(&resp.text()).unwrap();
+        assert_eq!(body["ok"], true);
+        assert_eq!(body["data"], "OK");
+
+        // errors keep their status
+        let resp = send_request(addr, "GET", "/item/abc?envelope=true", &[], b"").await;
+        assert_eq!(resp.status, 400);
+        let body: serde_json::Value = serde_json::from_str(&resp.text()).unwrap();
+        assert_eq!(body["ok"], false);
+        assert!(body["error"].is_string());
+
+        // bare by default
+        let resp = send_request(addr, "GET", "/item/85", &[], b"").await;
+        let body: serde_json::Value = serde_json::from_str(&resp.text()).unwrap();
+        assert_eq!(body["barcode"], 85);
+        assert!(body.get("ok").is_none());
+
+        delete_item(&mut conn, "85").unwrap();
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_database_hash() {
+        let db = test_file_db();
+        let addr = spawn_test_server(&db).await;
+
+        let resp = send_request(addr, "GET", "/get_database", &[], b"").await;
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.header("content-encoding"), None);
+        assert_eq!(
+            resp.header("x-content-sha256"),
+            Some(sha256_hex(&resp.body).as_str())
+        );
+
+        let resp = send_request(
+            addr,
+            "GET",
+            "/get_database",
+            &[("Accept-Encoding", "gzip")],
+            b"",
+        )
+        .await;
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.header("content-encoding"), Some("gzip"));
+        let mut database = Vec::new();
+        flate2::read::GzDecoder::new(resp.body.as_slice())
+            .read_to_end(&mut database)
+            .unwrap();
+        assert!(database.starts_with(b"SQLite format 3"));
+        assert_eq!(
+            resp.header("x-content-sha256"),
+            Some(sha256_hex(&database).as_str())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_every_violation_reported() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        let new = send_request(
+            addr,
+            "POST",
+            "/new",
+            &[],
+            br#"{"name": " ", "barcode": 86, "location": "Store", "value_pence": -1, "purchase_date": "2024-02-30"}"#,
+        )
+        .await;
+        assert_eq!(new.status, 422);
+        assert_eq!(new.header("content-type"), Some("application/json"));
+        let body: serde_json::Value = serde_json::from_str(&new.text()).unwrap();
+        let reported: Vec<(&str, &str)> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| {
+                (
+                    error["field"].as_str().unwrap(),
+                    error["code"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                ("name", "empty"),
+                ("value_pence", "negative"),
+                ("purchase_date", "invalid")
+            ]
+        );
+        assert!(load_item(&conn, 86).is_err());
+
+        // import rows list theirs too
+        let report = import_items(
+            &mut conn,
+            "name,barcode,location
+,8x6,Store
+",
+            true,
+        )
+        .unwrap();
+        assert_eq!(report.skipped, 1);
+        let codes: Vec<(&str, &str)> = report.errors[0]
+            .errors
+            .iter()
+            .map(|violation| (violation.field, violation.code))
+            .collect();
+        assert_eq!(codes, [("barcode", "invalid"), ("name", "empty")]);
+        assert_eq!(
+            report.errors[0].error,
+            "invalid barcode \"8x6\", name can't be empty"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status() {
+        let db = test_file_db();
+        let addr = spawn_test_server(&db).await;
+
+        let status = || async move {
+            let resp = send_request(addr, "GET", "/status", &[], b"").await;
+            assert_eq!(resp.status, 200);
+            serde_json::from_str::<serde_json::Value>(&resp.text()).unwrap()
+        };
+
+        let first = status().await;
+        // the request asking is itself in flight
+        assert!(first["requests_in_flight"].as_u64().unwrap() >= 1);
+        assert!(first["items"].is_u64());
+        assert!(first["db_bytes"].as_u64().unwrap() > 0);
+        assert!(first["uptime_secs"].is_u64());
+
+        let second = status().await;
+        assert!(
+            second["requests_served"].as_u64().unwrap()
+                > first["requests_served"].as_u64().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_gauge() {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let outer = Gauge::enter(&COUNTER);
+        {
+            let _inner = Gauge::enter(&COUNTER);
+            assert_eq!(COUNTER.load(Ordering::Relaxed), 2);
+        }
+        assert_eq!(COUNTER.load(Ordering::Relaxed), 1);
+        drop(outer);
+        assert_eq!(COUNTER.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_last_seen_spellings() {
+        for body in [
+            r#"{"name": "Gel", "barcode": 1, "location": "Rig", "last_seen": 1234567890}"#,
+            r#"{"name": "Gel", "barcode": 1, "location": "Rig", "last-seen": 1234567890}"#,
+        ] {
+            let item: Item = serde_json::from_str(body).unwrap();
+            assert_eq!(item.last_seen, Some(1234567890), "{}", body);
+
+            let out = serde_json::to_value(&item).unwrap();
+            assert_eq!(out["last_seen"], 1234567890);
+            assert!(out.get("last-seen").is_none());
+        }
+
+        assert_eq!(
+            deprecated_fields(br#"{"name": "Gel", "last-seen": 1}"#),
+            [("last-seen", "last_seen")]
+        );
+        assert!(deprecated_fields(br#"{"name": "Gel", "last_seen": 1}"#).is_empty());
+        assert!(deprecated_fields(b"not json").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_deprecated_field_warning() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        let new = send_request(
+            addr,
+            "POST",
+            "/new",
+            &[],
+            br#"{"name": "Iris", "barcode": 87, "location": "Store", "last-seen": 1234567890}"#,
+        )
+        .await;
+        assert_eq!(new.status, 200);
+        assert_eq!(
+            new.header("warning"),
+            Some("299 - \"last-seen is deprecated, use last_seen\"")
+        );
+
+        let modify = send_request(
+            addr,
+            "POST",
+            "/modify",
+            &[],
+            br#"{"name": "Iris", "barcode": 87, "location": "Rig", "last_seen": 1234567890}"#,
+        )
+        .await;
+        assert_eq!(modify.status, 200);
+        assert_eq!(modify.header("warning"), None);
+
+        let item = send_request(addr, "GET", "/item/87", &[], b"").await;
+        let item: serde_json::Value = serde_json::from_str(&item.text()).unwrap();
+        assert!(item["last_seen"].is_u64());
+        assert!(item.get("last-seen").is_none());
 
-// endpoint to log an item (hyper)
-async fn log_item(
-    req: Request<Incoming>,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    let barcode = req.uri().path().split('/').last();
+        delete_item(&mut conn, "87").unwrap();
+    }
 
-    if barcode.is_none() {
-        let mut resp = Response::new(full("No barcode"));
-        *resp.status_mut() = hyper::StatusCode::BAD_REQUEST;
-        return Ok(resp);
+    #[test]
+    fn test_parse_utc_offset() {
+        assert_eq!(parse_utc_offset("UTC"), Ok(0));
+        assert_eq!(parse_utc_offset("+01:00"), Ok(3600));
+        assert_eq!(parse_utc_offset("-0530"), Ok(-(5 * 3600 + 30 * 60)));
+        assert_eq!(parse_utc_offset("+2"), Ok(7200));
+        assert!(parse_utc_offset("01:00").is_err());
+        assert!(parse_utc_offset("+25:00").is_err());
+        assert!(parse_utc_offset("Europe/London").is_err());
     }
 
-    let conn = Connection::open(DB_NAME);
+    #[test]
+    fn test_activity_triggers() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE items (
+                name VARCHAR NOT NULL,
+                barcode INTEGER NOT NULL UNIQUE,
+                location VARCHAR NOT NULL,
+                last_seen TIMESTAMP NOT NULL
+            );",
+        )
+        .unwrap();
+        upgrade_schema(&conn).unwrap();
 
-    if conn.is_err() {
-        let mut resp = Response::new(full(conn.unwrap_err().to_string()));
-        *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
-        return Ok(resp);
+        conn.execute_batch(
+            "INSERT INTO items (name, barcode, location, last_seen) VALUES ('Gel', 1, 'Rig', 0);
+            UPDATE items SET last_seen = 10 WHERE barcode = 1;
+            UPDATE items SET location = 'Store', last_seen = 20 WHERE barcode = 1;
+            UPDATE items SET version = version + 1 WHERE barcode = 1;
+            DELETE FROM items WHERE barcode = 1;",
+        )
+        .unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT operation FROM activity ORDER BY rowid")
+            .unwrap();
+        let operations: Vec<String> = stmt
+            .query_map(params![], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            operations,
+            ["created", "scanned", "modified", "modified", "deleted"]
+        );
     }
 
-    let rows_affected = conn
-        .unwrap() // unwrap is safe because we checked it above
-        .execute(
-            "UPDATE items SET last_seen = ?1 WHERE barcode = ?2",
-            params![Utc::now().timestamp() as u64, barcode],
+    #[tokio::test]
+    async fn test_activity() {
+        let db = test_db();
+        let conn = db.conn().unwrap();
+
+        // long before any other test's activity: 23:30 and 00:30 either side of midnight UTC
+        let (late, early) = (978391800, 978395400); // 2001-01-01 23:30, 2001-01-02 00:30
+        let noon = 978436800; // 2001-01-02 12:00
+        conn.execute(
+            "INSERT INTO activity (at, operation) VALUES (?1, 'created'), (?2, 'scanned'), (?2, 'scanned')",
+            params![late, early],
+        )
+        .unwrap();
+
+        let utc = load_activity(&conn, 3, 0, noon).unwrap();
+        let dates: Vec<&str> = utc.iter().map(|day| day.date.as_str()).collect();
+        assert_eq!(dates, ["2000-12-31", "2001-01-01", "2001-01-02"]);
+        assert_eq!(
+            utc[0],
+            DayActivity {
+                date: "2000-12-31".to_string(),
+                ..Default::default()
+            }
         );
+        assert_eq!((utc[1].created, utc[1].scanned), (1, 0));
+        assert_eq!((utc[2].created, utc[2].scanned), (0, 2));
 
-    match rows_affected {
-        Ok(0) => {
-            let mut resp = Response::new(full("Item not found"));
-            *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
-            return Ok(resp);
+        // an hour east, 23:30 UTC is already the next day
+        let east = load_activity(&conn, 2, 3600, noon).unwrap();
+        assert_eq!(east[0].date, "2001-01-01");
+        assert_eq!((east[0].created, east[0].scanned), (0, 0));
+        assert_eq!((east[1].created, east[1].scanned), (1, 2));
+
+        conn.execute(
+            "DELETE FROM activity WHERE at IN (?1, ?2)",
+            params![late, early],
+        )
+        .unwrap();
+
+        let addr = spawn_test_server(&db).await;
+        let resp = send_request(addr, "GET", "/activity?days=7&tz=%2B01%3A00", &[], b"").await;
+        assert_eq!(resp.status, 200);
+        let days: serde_json::Value = serde_json::from_str(&resp.text()).unwrap();
+        let days = days.as_array().unwrap();
+        assert_eq!(days.len(), 7);
+        let today = (Utc::now() + chrono::Duration::hours(1))
+            .format("%Y-%m-%d")
+            .to_string();
+        assert_eq!(days[6]["date"], today.as_str());
+        for key in ["created", "modified", "deleted", "scanned"] {
+            assert!(days[0][key].is_u64());
         }
-        Ok(_) => {}
-        Err(_) => {
-            let mut resp = Response::new(full("Failed to log item"));
-            *resp.status_mut() = hyper::StatusCode::INTERNAL_SERVER_ERROR;
-            return Ok(resp);
+
+        for bad in [
+            "/activity?days=0",
+            "/activity?days=400",
+            "/activity?tz=Mars",
+        ] {
+            assert_eq!(
+                send_request(addr, "GET", bad, &[], b"").await.status,
+                400,
+                "{}",
+                bad
+            );
         }
     }
-    Ok(Response::new(ok()))
-}
 
-fn cap_at_n(n: usize, s: &str) -> String {
-    if s.len() > n {
-        format!("{}...", &s[..n])
-    } else {
-        s.to_string()
+    #[test]
+    fn test_pdf_string() {
+        assert_eq!(pdf_string("Cable (XLR)"), b"Cable \\(XLR\\)");
+        assert_eq!(pdf_string("a\\b"), b"a\\\\b");
+        assert_eq!(pdf_string("£5 €5"), b"\xa35 ?5");
+        assert_eq!(truncate_text("Smoke machine", 20), "Smoke machine");
+        assert_eq!(truncate_text("Smoke machine", 8), "Smoke...");
     }
-}
-
-async fn dispatch(
-    req: Request<Incoming>,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    let user_agent = match req.headers().get(USER_AGENT) {
-        Some(user_agent) => user_agent.to_str().unwrap_or("unknown"),
-        None => "unknown",
-    };
 
-    print!(
-        "[{}] {} {} from {}",
-        chrono::Local::now().format("%Y-%m-%dT%H:%M:%SZ"),
-        req.method(),
-        req.uri().path(),
-        cap_at_n(25, user_agent)
-    );
-    let res = match req.uri().path() {
-        "/new" => new_item(req).await,
-        "/all" => all_items(req).await,
-        path if path.starts_with("/item/") => item(req).await,
-        "/modify" => modify_item_endpoint(req).await,
-        path if path.starts_with("/delete/") => delete_item_endpoint(req).await,
-        path if path.starts_with("/log/") => log_item(req).await,
-        path if path == "/"
-            || path.starts_with("/index.html")
-            || path.starts_with("/style.css")
-            || path.starts_with("/script.js")=>
-        {
-            let path = if path == "/" { "/index.html" } else { path };
-            let resp = fs::read_to_string(format!("../webclient{}", path));
-            let res: Response<BoxBody<Bytes, hyper::Error>>;
-            if resp.is_err() {
-                let mut resp = Response::new(full("Failed to read file"));
-                *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
-                res = resp;
-            } else {
-                let resp = resp.unwrap();
-                let mut resp = Response::new(full(resp));
-                *resp.status_mut() = hyper::StatusCode::OK;
-                let mime = match path {
-                    "/index.html" => "text/html",
-                    "/style.css" => "text/css",
-                    "/script.js" => "application/javascript",
-                    _ => "text/plain",
-                };
-                resp.headers_mut().insert(
-                    hyper::header::CONTENT_TYPE,
-                    hyper::header::HeaderValue::from_static(mime),
-                );
+    #[test]
+    fn test_build_report_pdf() {
+        let items: Vec<Item> = (0..120)
+            .map(|i| Item {
+                value_pence: (i % 2 == 0).then_some(1050),
+                ..Item::new(format!("Lamp {}", i), 1000 + i, "Rig".to_string())
+            })
+            .collect();
+        let pdf = build_report_pdf(&items, "Stock report", "2024-03-31 09:00");
 
-                res = resp;
-            }
+        assert!(pdf.starts_with(b"%PDF-1.4\n"));
+        assert!(pdf.ends_with(b"%%EOF\n"));
+        let text = String::from_utf8_lossy(&pdf);
+        // 51 rows fit under the title, 56 on later pages
+        assert!(text.contains("/Count 3"));
+        assert!(text.contains("(Page 3 of 3)"));
+        assert!(text.contains("(120 items, total value 630.00 \\(60 without a value\\))"));
+        assert!(text.contains("(Lamp 119)"));
 
-            Ok(res)
+        // the cross-reference table points at each object
+        let startxref = text.rsplit("startxref\n").next().unwrap();
+        let xref: usize = startxref.lines().next().unwrap().parse().unwrap();
+        assert!(pdf[xref..].starts_with(b"xref\n"));
+        let entries = text[xref..].lines().skip(3);
+        for (i, entry) in entries.take_while(|line| line.ends_with(" n ")).enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(format!("{} 0 obj\n", i + 1).as_bytes()));
         }
-        path if path.starts_with("/favicon.ico") => {
-            let resp = fs::File::open("../webclient/favicon.ico");
+    }
 
-            let resp: Result<Vec<u8>, std::io::Error> = resp.and_then(|file| {
-                let mut file = file;
-                let mut buf = Vec::new();
-                file.read_to_end(&mut buf).map(|_| buf)
-            });
+    #[tokio::test]
+    async fn test_report_pdf() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
 
-            let res: Response<BoxBody<Bytes, hyper::Error>>;
-            if resp.is_err() {
-                let mut resp = Response::new(full("Failed to read file"));
-                *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
-                res = resp;
-            } else {
-                let resp = resp.unwrap();
-                let mut resp = Response::new(full(resp));
-                *resp.status_mut() = hyper::StatusCode::OK;
-                resp.headers_mut().insert(
-                    hyper::header::CONTENT_TYPE,
-                    hyper::header::HeaderValue::from_static("image/x-icon"),
-                );
+        Item::new("Fog fluid".to_string(), 88, "Report shelf".to_string())
+            .save(&mut conn)
+            .unwrap();
 
-                res = resp;
-            }
+        let resp = send_request(addr, "GET", "/report.pdf?location=report%20shelf", &[], b"").await;
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.header("content-type"), Some("application/pdf"));
+        let text = resp.text();
+        assert!(text.starts_with("%PDF-"));
+        assert!(text.contains("(Stock report: report shelf)"));
+        assert!(text.contains("(Fog fluid)"));
+        assert!(text.contains("(1 item)"));
 
-            Ok(res)
-        }
-        path if path.starts_with("/get_database") => {
-            let resp = fs::File::open(DB_NAME);
-            let resp: Result<Vec<u8>, std::io::Error> = resp.and_then(|file| {
-                let mut file = file;
-                let mut buf = Vec::new();
-                file.read_to_end(&mut buf).map(|_| buf)
-            });
+        delete_item(&mut conn, "88").unwrap();
+    }
 
-            let res: Response<BoxBody<Bytes, hyper::Error>>;
-            if resp.is_err() {
-                let mut resp = Response::new(full("Failed to read file"));
-                *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
-                res = resp;
-            } else {
-                let resp = resp.unwrap();
-                let mut resp = Response::new(full(resp));
-                *resp.status_mut() = hyper::StatusCode::OK;
-                resp.headers_mut().insert(
-                    hyper::header::CONTENT_TYPE,
-                    hyper::header::HeaderValue::from_static("application/octet-stream"),
-                );
+    #[test]
+    fn test_date_format() {
+        assert!(valid_date_format("%Y-%m-%d %H:%M"));
+        assert!(valid_date_format("%d/%m/%Y %I:%M %p"));
+        assert!(valid_date_format(ISO_DATE_FORMAT));
+        assert!(!valid_date_format("%Y-%m-%Q"));
+        assert!(!valid_date_format("%"));
+        assert!(!valid_date_format(""));
+    }
 
-                res = resp;
-            }
+    #[test]
+    fn test_schema_repair() {
+        let current = Connection::open_in_memory().unwrap();
+        current
+            .execute_batch(
+                "CREATE TABLE items (
+                    id INTEGER PRIMARY KEY,
+                    name VARCHAR NOT NULL,
+                    barcode INTEGER NOT NULL UNIQUE,
+                    location VARCHAR NOT NULL,
+                    last_seen TIMESTAMP NOT NULL,
+                    version INTEGER NOT NULL DEFAULT 1,
+                    status TEXT NOT NULL DEFAULT 'ok'
+                );",
+            )
+            .unwrap();
+        upgrade_schema(&current).unwrap();
+        let expected = table_columns(&current, "items").unwrap();
+        let names: Vec<&str> = expected.iter().map(|(name, ..)| name.as_str()).collect();
+        assert_eq!(names, ITEM_COLUMNS.map(|(name, _)| name));
 
-            Ok(res)
+        // the first release, then with versions, then with ids and statuses but nothing after
+        for layout in [
+            "name VARCHAR NOT NULL, barcode INTEGER NOT NULL UNIQUE, location VARCHAR NOT NULL,
+            last_seen TIMESTAMP NOT NULL",
+            "name VARCHAR NOT NULL, barcode INTEGER NOT NULL UNIQUE, location VARCHAR NOT NULL,
+            last_seen TIMESTAMP NOT NULL, version INTEGER NOT NULL DEFAULT 1",
+            "id INTEGER PRIMARY KEY, name VARCHAR NOT NULL, barcode INTEGER NOT NULL UNIQUE,
+            location VARCHAR NOT NULL, last_seen TIMESTAMP NOT NULL, version INTEGER NOT NULL DEFAULT 1,
+            status TEXT NOT NULL DEFAULT 'ok'",
+        ] {
+            let legacy = Connection::open_in_memory().unwrap();
+            legacy
+                .execute_batch(&format!(
+                    "CREATE TABLE items ({});
+                    INSERT INTO items (name, barcode, location, last_seen) VALUES ('Gel', 7, 'Rig', 42);",
+                    layout
+                ))
+                .unwrap();
+            upgrade_schema(&legacy).unwrap();
+
+            assert_eq!(table_columns(&legacy, "items").unwrap(), expected, "{}", layout);
+            let (name, location, last_seen, status): (String, String, i64, String) = legacy
+                .query_row(
+                    "SELECT name, location, last_seen, status FROM items WHERE barcode = 7",
+                    params![],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                )
+                .unwrap();
+            assert_eq!((name.as_str(), location.as_str(), last_seen, status.as_str()), ("Gel", "Rig", 42, "ok"));
         }
 
-        _ => {
-            let mut resp = Response::new(full("Not found"));
-            *resp.status_mut() = hyper::StatusCode::NOT_FOUND;
-            Ok(resp)
+        // changes adding columns can't fix are refused, leaving the data alone
+        for (layout, column) in [
+            (
+                "name VARCHAR NOT NULL, barcode TEXT NOT NULL UNIQUE, location VARCHAR NOT NULL, last_seen TIMESTAMP NOT NULL",
+                "items.barcode",
+            ),
+            (
+                "name VARCHAR NOT NULL, barcode INTEGER NOT NULL UNIQUE, location VARCHAR NOT NULL, last_seen TIMESTAMP NOT NULL, owner TEXT NOT NULL",
+                "items.owner",
+            ),
+        ] {
+            let drifted = Connection::open_in_memory().unwrap();
+            drifted
+                .execute_batch(&format!("CREATE TABLE items ({});", layout))
+                .unwrap();
+            let error = upgrade_schema(&drifted).unwrap_err();
+            assert!(error.contains(column), "{}", error);
+            assert!(!has_column(&drifted, "items", "version").unwrap());
         }
-    };
 
-    if let Ok(response) = res.as_ref() {
-        println!(" -> {}", response.status());
-    } else {
-        eprintln!(" -> Couldn't process request (unknown error)");
+        // an extra column that's optional is left be
+        let extended = Connection::open_in_memory().unwrap();
+        extended
+            .execute_batch(
+                "CREATE TABLE items (id INTEGER PRIMARY KEY, name VARCHAR NOT NULL, barcode INTEGER NOT NULL UNIQUE,
+                location VARCHAR NOT NULL, last_seen TIMESTAMP NOT NULL, colour TEXT);",
+            )
+            .unwrap();
+        upgrade_schema(&extended).unwrap();
+        assert!(has_column(&extended, "items", "status").unwrap());
+        assert!(has_column(&extended, "items", "colour").unwrap());
     }
 
-    res.map(|mut resp| {
-        // add CORS headers
-        resp.headers_mut().insert(
-            hyper::header::ACCESS_CONTROL_ALLOW_ORIGIN,
-            hyper::header::HeaderValue::from_static("*"),
+    #[tokio::test]
+    async fn test_check_barcode() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        let check = |barcode: &'static str| async move {
+            let res = send_request(addr, "GET", &format!("/check/{}", barcode), &[], b"").await;
+            assert_eq!(res.status, 200);
+            serde_json::from_str::<serde_json::Value>(&res.text()).unwrap()
+        };
+
+        assert_eq!(check("89").await, serde_json::json!({ "available": true }));
+
+        let res = send_request(
+            addr,
+            "POST",
+            "/new",
+            &[],
+            br#"{"name":"Check","barcode":89,"location":"Rig"}"#,
+        )
+        .await;
+        assert_eq!(res.status, 200);
+        assert_eq!(
+            check("89").await,
+            serde_json::json!({ "available": false, "reason": "Item already exists" })
         );
-        resp
-    })
-}
 
-fn setup_if_not_exists() {
-    let conn = Connection::open(DB_NAME).unwrap();
-    let result = conn.execute(
-        "CREATE TABLE IF NOT EXISTS items (
-            name VARCHAR NOT NULL,
-            barcode INTEGER NOT NULL UNIQUE,
-            location VARCHAR NOT NULL,
-            last_seen TIMESTAMP NOT NULL
-        )",
-        params![],
-    );
+        // checking creates nothing
+        check("90").await;
+        assert_eq!(
+            send_request(addr, "GET", "/item/90", &[], b"").await.status,
+            404
+        );
 
-    if let Err(e) = result {
-        panic!("Failed to create table: {}", e);
+        assert_eq!(check("abc").await["available"], false);
+        assert_eq!(
+            check("abc").await["reason"],
+            "Invalid barcode: not a number"
+        );
+        assert_eq!(check("99999999999999999999").await["available"], false);
+
+        delete_item(&mut conn, "89").unwrap();
     }
-}
 
-fn get_addr() -> SocketAddr {
-    // if the environment variable BARCODE_SERVER_ADDR is set, use that
-    // else use barcode.cfg
-    // else fall back on 0.0.0.0:3000
+    #[tokio::test]
+    async fn test_head_health() {
+        let db = test_db();
+        let addr = spawn_test_server(&db).await;
 
-    if env::var("BARCODE_SERVER_ADDR").is_ok() {
-        let addr = env::var("BARCODE_SERVER_ADDR").unwrap_or_default();
-        let addr = addr.parse::<SocketAddr>();
+        let res = send_request(addr, "HEAD", "/health", &[], b"").await;
+        assert_eq!(res.status, 200);
+        assert!(res.body.is_empty());
 
-        if addr.is_ok() {
-            println!(
-                "Using address from BARCODE_SERVER_ADDR: {}",
-                addr.clone().unwrap()
-            );
-            return addr.unwrap();
-        } else {
-            eprintln!(
-                "Invalid address: {}, checking other options",
-                addr.unwrap_err()
-            );
-        }
+        let res = send_request(addr, "GET", "/health", &[], b"").await;
+        assert!(res.text().contains("\"status\":\"ok\""));
     }
 
-    let config_path = env::var("BARCODE_CFG").unwrap_or_else(|_| "barcode.cfg".to_string());
-    let config = std::fs::read_to_string(config_path.clone());
-
-    if config.is_err() {
-        println!(
-            "Using 0.0.0.0:3000 by default, try setting BARCODE_SERVER_ADDR or BARCODE_CFG (config file location)"
+    #[tokio::test]
+    async fn test_no_webclient_page() {
+        let resp = no_webclient_page("/inventory");
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers()[hyper::header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
         );
-        SocketAddr::from(([0, 0, 0, 0], 3000))
-    } else {
-        let addr = config.unwrap().parse::<SocketAddr>();
 
-        if addr.is_ok() {
-            println!(
-                "Using address from config file ({}): {}",
-                config_path,
-                addr.clone().unwrap()
-            );
-            addr.unwrap()
-        } else {
-            println!(
-                "Using 0.0.0.0:3000 by default as address in {} is invalid",
-                config_path
-            );
-            SocketAddr::from(([0, 0, 0, 0], 3000))
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("web client isn't installed"));
+        assert!(body.contains("<a href=\"/inventory/all\"><code>/inventory/all</code></a>"));
+        assert!(body.contains("<code>/inventory/item/{barcode}</code>"));
+        assert!(!body.contains("href=\"/inventory/item/{barcode}\""));
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+
+        for body in [
+            r#"{"name":"XLR Cable 10m","barcode":91,"location":"Search Rig"}"#,
+            r#"{"name":"xlr cable 5m","barcode":92,"location":"Search Store"}"#,
+        ] {
+            let res = send_request(addr, "POST", "/new", &[], body.as_bytes()).await;
+            assert_eq!(res.status, 200, "{}", res.text());
+        }
+        // saved directly, since /new sanitizes % and _ out of names
+        for (barcode, name) in [(93, "50% off_cable"), (94, "50x offXcable")] {
+            Item::new(name.to_string(), barcode, "Search Store".to_string())
+                .save(&mut conn)
+                .unwrap();
+        }
+        let search = |query: &'static str| async move {
+            let res = send_request(addr, "GET", &format!("/search?{}", query), &[], b"").await;
+            assert_eq!(res.status, 200, "{}", query);
+            serde_json::from_str::<Vec<serde_json::Value>>(&res.text())
+                .unwrap()
+                .iter()
+                .map(|item| item["barcode"].as_u64().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        // part of a name, either case
+        assert_eq!(search("name=xlr+CABLE").await, vec![91, 92]);
+        assert_eq!(search("location=search+store").await, vec![93, 94, 92]);
+        assert_eq!(search("name=cable&location=rig").await, vec![91]);
+        // % and _ are only themselves
+        assert_eq!(search("name=50%25+off_").await, vec![93]);
+        assert_eq!(search("name=%25&location=search").await, vec![93]);
+        assert!(search("name=no+such+thing").await.is_empty());
+        // either field
+        assert_eq!(search("q=SEARCH+rig").await, vec![91]);
+        assert_eq!(search("q=off").await, vec![93, 94]);
+        assert_eq!(search("q=cable&location=store").await, vec![93, 94, 92]);
+
+        for query in ["", "?name=", "?colour=red"] {
+            let res = send_request(addr, "GET", &format!("/search{}", query), &[], b"").await;
+            assert_eq!(res.status, 400, "{}", query);
+        }
+
+        for barcode in ["91", "92", "93", "94"] {
+            delete_item(&mut conn, barcode).unwrap();
         }
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    setup_if_not_exists();
-    let addr = get_addr();
+    #[test]
+    fn test_pooled_connection() {
+        let pool = Pool::new(2, Connection::open_in_memory);
 
-    let listener = TcpListener::bind(addr).await?;
-    println!("Listening on http://{}", addr);
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let io = TokioIo::new(stream);
+        // dropped, a connection is handed out again rather than a new one opened
+        let conn = pool.take().unwrap();
+        conn.execute_batch("CREATE TABLE marker (x)").unwrap();
+        drop(conn);
+        let conn = pool.take().unwrap();
+        assert!(has_column(&conn, "marker", "x").unwrap());
 
-        tokio::task::spawn(async move {
-            let result = http1::Builder::new()
-                .serve_connection(io, service_fn(dispatch))
-                .await;
+        // unless it was left in a transaction
+        conn.execute_batch("BEGIN").unwrap();
+        drop(conn);
+        assert!(pool.idle.lock().unwrap().is_empty());
+        let conn = pool.take().unwrap();
+        assert!(!has_column(&conn, "marker", "x").unwrap());
+        drop(conn);
 
-            if let Err(err) = result {
-                eprintln!("HTTP/1 Error: {}", err);
-            }
+        // and no more are kept idle than the pool's size
+        let many: Vec<_> = (0..4).map(|_| pool.take().unwrap()).collect();
+        drop(many);
+        assert_eq!(pool.idle.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_locked_database_blocks_only_its_request() {
+        // on disk, since an in-memory database won't let anyone read while a writer holds it
+        let db = test_file_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        for barcode in [95, 96] {
+            Item::new("Slow".to_string(), barcode, "Rig".to_string())
+                .save(&mut conn)
+                .unwrap();
+        }
+
+        // another writer holds the lock for a while, so logging waits (and retries) for it
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let holder_db = db.clone();
+        let holder = std::thread::spawn(move || {
+            let conn = holder_db.conn().unwrap();
+            conn.execute_batch("BEGIN IMMEDIATE").unwrap();
+            locked_tx.send(()).unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(800));
+            conn.execute_batch("COMMIT").unwrap();
+        });
+        locked_rx.recv().unwrap();
+
+        // this test runs on a single-threaded runtime, so a handler blocking it would hold up the lookup too
+        let started = std::time::Instant::now();
+        let log = tokio::spawn(async move {
+            let res = send_request(addr, "POST", "/log/95", &[], b"").await;
+            (res.status, started.elapsed())
         });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let lookup = send_request(addr, "GET", "/item/96", &[], b"").await;
+        let looked_up = started.elapsed();
+
+        assert_eq!(lookup.status, 200);
+        let (log_status, logged) = log.await.unwrap();
+        assert_eq!(log_status, 200);
+        assert!(
+            looked_up < logged,
+            "lookup took {:?}, log {:?}",
+            looked_up,
+            logged
+        );
+
+        holder.join().unwrap();
+        delete_item(&mut conn, "95").unwrap();
+        delete_item(&mut conn, "96").unwrap();
     }
-}
 
-#[cfg(test)]
-fn setup_test_db() {
-    let conn = Connection::open("test.db").unwrap();
-    let result = conn.execute(
-        "CREATE TABLE items (
-            name VARCHAR NOT NULL,
-            barcode INTEGER NOT NULL UNIQUE,
-            location VARCHAR NOT NULL,
-            last_seen TIMESTAMP NOT NULL
-        )",
-        params![],
-    );
+    #[tokio::test]
+    async fn test_content_type() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        Item::new("Typed".to_string(), 97, "Rig".to_string())
+            .save(&mut conn)
+            .unwrap();
 
-    if let Err(e) = result {
-        if e.to_string().contains("table items already exists") {
-            // Table already exists, no need to panic
-            return;
-        } else {
-            // Other errors should still panic
-            panic!("Failed to create table: {}", e);
+        for path in ["/all", "/item/97", "/locations", "/version"] {
+            let res = send_request(addr, "GET", path, &[], b"").await;
+            assert_eq!(res.status, 200, "{}", path);
+            assert_eq!(
+                res.header("content-type"),
+                Some("application/json"),
+                "{}",
+                path
+            );
         }
+
+        // error messages are plain text, unless enveloped
+        let res = send_request(addr, "GET", "/item/98", &[], b"").await;
+        assert_eq!(res.status, 404);
+        assert_eq!(
+            res.header("content-type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(res.text(), "Item not found");
+        let res = send_request(addr, "GET", "/item/abc", &[], b"").await;
+        assert_eq!(res.status, 400);
+        assert_eq!(
+            res.header("content-type"),
+            Some("text/plain; charset=utf-8")
+        );
+        let res = send_request(addr, "GET", "/item/98?envelope=true", &[], b"").await;
+        assert_eq!(res.header("content-type"), Some("application/json"));
+
+        // files keep their own types, and empty bodies get none
+        let res = send_request(addr, "GET", "/report.pdf", &[], b"").await;
+        assert_eq!(res.header("content-type"), Some("application/pdf"));
+        let res = send_request(addr, "HEAD", "/health", &[], b"").await;
+        assert_eq!(res.header("content-type"), None);
+
+        // a replayed response keeps the type it was first sent with
+        let key = [("Idempotency-Key", "test-content-type-97")];
+        let first = send_request(addr, "POST", "/adjust/97", &key, br#"{"delta": 1}"#).await;
+        let again = send_request(addr, "POST", "/adjust/97", &key, br#"{"delta": 1}"#).await;
+        assert_eq!(first.status, 200);
+        assert_eq!(again.header("idempotent-replayed"), Some("true"));
+        assert_eq!(first.header("content-type"), Some("application/json"));
+        assert_eq!(again.header("content-type"), Some("application/json"));
+
+        delete_item(&mut conn, "97").unwrap();
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn test_item_quantity() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
 
-    #[test]
-    fn test_item() {
-        setup_test_db();
+        let res = send_request(
+            addr,
+            "POST",
+            "/new",
+            &[],
+            br#"{"name": "AA batteries", "barcode": 98, "location": "Store", "quantity": 48}"#,
+        )
+        .await;
+        assert_eq!(res.status, 200);
+        let res = send_request(
+            addr,
+            "POST",
+            "/new",
+            &[],
+            br#"{"name": "Gel pack", "barcode": 99, "location": "Store"}"#,
+        )
+        .await;
+        assert_eq!(res.status, 200);
 
-        let item = Item::new("item".to_string(), 42, "location".to_string());
-        item.save().unwrap();
-        let loaded_item = load_item(42).unwrap();
-        assert_eq!(item.name, loaded_item.name);
-        assert_eq!(item.barcode, loaded_item.barcode);
-        assert_eq!(item.location, loaded_item.location);
-        assert_eq!(item.last_seen, loaded_item.last_seen);
+        let item: serde_json::Value =
+            serde_json::from_str(&send_request(addr, "GET", "/item/98", &[], b"").await.text())
+                .unwrap();
+        assert_eq!(item["quantity"], 48);
+        assert_eq!(load_item(&conn, 99).unwrap().quantity, Some(1));
+
+        // left out of a modify it's unchanged, and a stock can run out
+        let res = send_request(
+            addr,
+            "POST",
+            "/modify",
+            &[],
+            br#"{"name": "AA batteries", "barcode": 98, "location": "Rig"}"#,
+        )
+        .await;
+        assert_eq!(res.status, 200);
+        assert_eq!(load_item(&conn, 98).unwrap().quantity, Some(48));
+        let res = send_request(
+            addr,
+            "POST",
+            "/modify",
+            &[],
+            br#"{"name": "AA batteries", "barcode": 98, "location": "Rig", "quantity": 0}"#,
+        )
+        .await;
+        assert_eq!(res.status, 200);
+        assert_eq!(load_item(&conn, 98).unwrap().quantity, Some(0));
+
+        let res = send_request(
+            addr,
+            "POST",
+            "/modify",
+            &[],
+            br#"{"name": "AA batteries", "barcode": 98, "location": "Rig", "quantity": -1}"#,
+        )
+        .await;
+        assert_eq!(res.status, 400);
+        assert_eq!(load_item(&conn, 98).unwrap().quantity, Some(0));
+
+        delete_item(&mut conn, "98").unwrap();
+        delete_item(&mut conn, "99").unwrap();
     }
 
-    #[test]
-    fn test_new_and_load() {
-        setup_test_db();
+    #[tokio::test]
+    async fn test_adjust_quantity() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        Item {
+            quantity: Some(10),
+            ..Item::new("Gel sheets".to_string(), 100, "Store".to_string())
+        }
+        .save(&mut conn)
+        .unwrap();
 
-        let item = Item::new("item".to_string(), 43, "location".to_string());
-        item.save().unwrap();
-        let checked_item = load_item(43).unwrap();
-        assert_eq!(item.name, checked_item.name);
-        assert_eq!(item.barcode, checked_item.barcode);
-        assert_eq!(item.location, checked_item.location);
-        assert_eq!(item.last_seen, checked_item.last_seen);
+        let adjust = |body: &'static [u8]| async move {
+            send_request(addr, "POST", "/adjust/100", &[], body).await
+        };
+        let res = adjust(br#"{"delta": -2}"#).await;
+        assert_eq!(res.status, 200);
+        assert_eq!(res.text(), r#"{"quantity":8}"#);
+        assert_eq!(
+            adjust(br#"{"delta": 5}"#).await.text(),
+            r#"{"quantity":13}"#
+        );
+        // taking more than there are leaves none
+        assert_eq!(
+            adjust(br#"{"delta": -20}"#).await.text(),
+            r#"{"quantity":0}"#
+        );
+
+        let item = load_item(&conn, 100).unwrap();
+        assert_eq!(item.quantity, Some(0));
+        assert_eq!(item.version, 4);
+
+        assert_eq!(adjust(br#"{"delta": "two"}"#).await.status, 400);
+        assert_eq!(adjust(b"{}").await.status, 400);
+        let res = send_request(addr, "POST", "/adjust/101", &[], br#"{"delta": 1}"#).await;
+        assert_eq!(res.status, 404);
+
+        // an alias's label adjusts the item it stands for
+        add_alias(&mut conn, 100, 5012345678900).unwrap();
+        let res = send_request(
+            addr,
+            "POST",
+            "/adjust/5012345678900",
+            &[],
+            br#"{"delta": 3}"#,
+        )
+        .await;
+        assert_eq!(res.status, 200, "{}", res.text());
+        assert_eq!(res.text(), r#"{"quantity":3}"#);
+        assert_eq!(res.header("x-matched-alias"), Some("5012345678900"));
+
+        delete_item(&mut conn, "100").unwrap();
     }
 
-    #[test]
-    fn test_delete() {
-        setup_test_db();
+    #[tokio::test]
+    async fn test_item_history() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        Item {
+            last_seen: Some(1_700_000_000),
+            ..Item::new("Smoke machine".to_string(), 102, "Store".to_string())
+        }
+        .save(&mut conn)
+        .unwrap();
 
-        let item = Item::new("item".to_string(), 44, "location".to_string());
-        item.save().unwrap();
-        let items_initial_len = load_items().unwrap().len();
-        let conn = Connection::open("test.db").unwrap();
-        conn.execute("DELETE FROM items WHERE barcode = ?1", params!["44"])
+        assert_eq!(
+            send_request(addr, "POST", "/log/102", &[], b"")
+                .await
+                .status,
+            200
+        );
+        let res = send_request(
+            addr,
+            "POST",
+            "/modify",
+            &[],
+            br#"{"name": "Smoke machine", "barcode": 102, "location": "Rig"}"#,
+        )
+        .await;
+        assert_eq!(res.status, 200);
+        // a change of quantity isn't a sighting
+        send_request(addr, "POST", "/adjust/102", &[], br#"{"delta": 1}"#).await;
+
+        let history = |query: &str| {
+            let path = format!("/item/102/history{}", query);
+            async move {
+                let res = send_request(addr, "GET", &path, &[], b"").await;
+                assert_eq!(res.status, 200);
+                serde_json::from_str::<Vec<serde_json::Value>>(&res.text()).unwrap()
+            }
+        };
+        let all = history("").await;
+        let locations: Vec<&str> = all
+            .iter()
+            .map(|sighting| sighting["location"].as_str().unwrap())
+            .collect();
+        assert_eq!(locations, ["Rig", "Store", "Store"]);
+        assert_eq!(all[2]["seen_at"], 1_700_000_000);
+        assert!(all[0]["seen_at"].as_u64().unwrap() > 1_700_000_000);
+
+        assert_eq!(history("?limit=1").await.len(), 1);
+        let older = history("?before=1700000001").await;
+        assert_eq!(older.len(), 1);
+        assert_eq!(older[0]["seen_at"], 1_700_000_000);
+
+        // the log and the modify are seen in the same second, so paging one at a time needs the id
+        // to get past the first without skipping the second
+        let mut paged = history("?limit=1").await;
+        while paged.len() < all.len() {
+            let last = &paged[paged.len() - 1];
+            let query = format!(
+                "?limit=1&before={}&before_id={}",
+                last["seen_at"], last["id"]
+            );
+            let page = history(&query).await;
+            assert_eq!(page.len(), 1);
+            paged.extend(page);
+        }
+        assert_eq!(paged, all);
+        let oldest = format!("?before=1700000000&before_id={}", all[2]["id"]);
+        assert!(history(&oldest).await.is_empty());
+
+        for query in [
+            "?before=yesterday",
+            "?before_id=1",
+            "?before=1700000001&before_id=x",
+        ] {
+            let path = format!("/item/102/history{}", query);
+            let res = send_request(addr, "GET", &path, &[], b"").await;
+            assert_eq!(res.status, 400, "{}", query);
+        }
+
+        // deleting an item deletes its history with it
+        let id: i64 = conn
+            .query_row(
+                "SELECT id FROM items WHERE barcode = 102",
+                params![],
+                |row| row.get(0),
+            )
             .unwrap();
-        let items = load_items().unwrap();
-        assert_eq!(items.len(), items_initial_len - 1);
+        delete_item(&mut conn, "102").unwrap();
+        let left: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM item_history
+                 WHERE item_id = ?1 AND item_id NOT IN (SELECT id FROM items)",
+                params![id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(left, 0);
+        let res = send_request(addr, "GET", "/item/102/history", &[], b"").await;
+        assert_eq!(res.status, 404);
     }
 
-    #[test]
-    fn teardown() {
-        // hacky, but just sleep for a bit so the other tests can finish
-        std::thread::sleep(std::time::Duration::from_secs(1));
+    #[tokio::test]
+    async fn test_scan_log() {
+        let db = test_db();
+        let mut conn = db.conn().unwrap();
+        let addr = spawn_test_server(&db).await;
+        Item::new("Flight case".to_string(), 103, "Store".to_string())
+            .save(&mut conn)
+            .unwrap();
+        Item {
+            parent_barcode: Some(103),
+            ..Item::new("Radio mic".to_string(), 104, "Store".to_string())
+        }
+        .save(&mut conn)
+        .unwrap();
+
+        let scans = |barcode: u64, query: &'static str| async move {
+            let res = send_request(
+                addr,
+                "GET",
+                &format!("/history/{}{}", barcode, query),
+                &[],
+                b"",
+            )
+            .await;
+            assert_eq!(res.status, 200);
+            serde_json::from_str::<Vec<serde_json::Value>>(&res.text()).unwrap()
+        };
+        assert!(scans(103, "").await.is_empty());
+
+        let res = send_request(addr, "POST", "/log/103?cascade=true", &[], b"").await;
+        assert_eq!(res.status, 200);
+        // edits aren't scans, lookups that touch the item are
+        let res = send_request(
+            addr,
+            "POST",
+            "/modify",
+            &[],
+            br#"{"name": "Flight case", "barcode": 103, "location": "Rig"}"#,
+        )
+        .await;
+        assert_eq!(res.status, 200);
+        let res = send_request(addr, "GET", "/item/103?touch=true", &[], b"").await;
+        assert_eq!(res.status, 200);
+
+        let case = scans(103, "").await;
+        let locations: Vec<&str> = case
+            .iter()
+            .map(|scan| scan["location"].as_str().unwrap())
+            .collect();
+        assert_eq!(locations, ["Store", "Rig"]);
+        assert!(case[0]["scanned_at"].as_u64() <= case[1]["scanned_at"].as_u64());
+        assert_eq!(
+            load_item(&conn, 103).unwrap().last_seen,
+            case[1]["scanned_at"].as_u64()
+        );
+        assert_eq!(scans(104, "").await.len(), 1);
+        assert_eq!(scans(103, "?limit=1&offset=1").await[0]["location"], "Rig");
+
+        let res = send_request(addr, "GET", "/history/105", &[], b"").await;
+        assert_eq!(res.status, 404);
 
-        std::fs::remove_file("test.db").unwrap();
+        delete_item(&mut conn, "104").unwrap();
+        delete_item(&mut conn, "103").unwrap();
     }
 }
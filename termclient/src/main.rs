@@ -1,14 +1,158 @@
-use std::io::{Read, Write};
+use std::io::Write;
 
 use chrono::TimeZone;
-use lazy_static::lazy_static;
-use once_cell::sync::OnceCell;
 /// terminal interface to server in ../server
 use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
+use indicatif::{ProgressBar, ProgressStyle};
+use rustyline::error::ReadlineError;
+use std::io::IsTerminal;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
 
-lazy_static! {
-    static ref SERVER: Mutex<OnceCell<String>> = Mutex::new(OnceCell::new());
+/// the server's address as `normalize_server` gives it, `None` until it's loaded or entered
+static SERVER: RwLock<Option<String>> = RwLock::new(None);
+
+/// the server address in use
+fn server() -> String {
+    SERVER.read().unwrap().clone().expect("Server not set")
+}
+
+/// set by `--pretend`: new/modify/delete/log print the request they would send instead of sending it
+static PRETEND: AtomicBool = AtomicBool::new(false);
+
+/// set by `--offline`: see and all answer from the cache `pull` keeps instead of asking the server
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// set by `--verbose`: show how scanned barcodes were cleaned up (see `scanned`)
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+/// where the server came from when it wasn't the saved one: `--server` or BARCODE_SERVER
+static SERVER_OVERRIDE: std::sync::OnceLock<&'static str> = std::sync::OnceLock::new();
+
+/// set by `--profile`, shown in the prompt
+static PROFILE: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+/// set by `--timeout`: how long any request may take
+static TIMEOUT: std::sync::OnceLock<Duration> = std::sync::OnceLock::new();
+
+/// set by `--config` or `--profile`: the settings file and server file to use instead of
+/// `CONFIG_FILE` and `SERVER_FILE`
+static FILES: std::sync::OnceLock<(String, String)> = std::sync::OnceLock::new();
+
+/// the settings file in use
+fn config_file() -> &'static str {
+    FILES.get().map_or(CONFIG_FILE, |(config, _)| config.as_str())
+}
+
+/// the file the server address is saved in
+fn server_file() -> &'static str {
+    FILES.get().map_or(SERVER_FILE, |(_, server)| server.as_str())
+}
+
+/// an HTTP client, with `--timeout` if it was given
+fn http() -> reqwest::Client {
+    let mut client = reqwest::Client::builder();
+    if let Some(timeout) = TIMEOUT.get() {
+        client = client.timeout(*timeout);
+    }
+    client.build().expect("Failed to build the HTTP client")
+}
+
+/// connection settings for just this run, from the command line; never saved
+#[derive(Debug, Default, PartialEq)]
+struct Overrides {
+    server: Option<String>,
+    timeout: Option<Duration>,
+    /// the settings file and the server file beside it
+    files: Option<(String, String)>,
+    profile: Option<String>,
+}
+
+/// take `--server`, `--timeout`, `--config` and `--profile` (as `--flag value` or `--flag=value`,
+/// anywhere in the arguments) out of `args`, checking each
+fn take_overrides(args: &mut Vec<String>) -> Result<Overrides, String> {
+    let mut overrides = Overrides::default();
+    let (mut config, mut profile) = (None, None);
+
+    let mut i = 0;
+    while i < args.len() {
+        let (flag, inline) = match args[i].split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (args[i].clone(), None),
+        };
+        if !["--server", "--timeout", "--config", "--profile"].contains(&flag.as_str()) {
+            i += 1;
+            continue;
+        }
+        args.remove(i);
+        let value = match inline {
+            Some(value) => value,
+            None if i < args.len() => args.remove(i),
+            None => return Err(format!("{} needs a value", flag)),
+        };
+
+        match flag.as_str() {
+            "--server" => overrides.server = Some(normalize_server(&value).map_err(|e| format!("--server: {}", e))?),
+            "--timeout" => match value.parse::<f64>() {
+                Ok(secs) if secs > 0.0 && secs.is_finite() => overrides.timeout = Some(Duration::from_secs_f64(secs)),
+                _ => return Err(format!("--timeout takes a number of seconds, not {}", value)),
+            },
+            "--config" => config = Some(value),
+            _ => profile = Some(value),
+        }
+    }
+
+    overrides.files = match (config, profile) {
+        (Some(_), Some(_)) => return Err("Use --config or --profile, not both".to_string()),
+        (Some(config), None) => {
+            if !std::path::Path::new(&config).is_file() {
+                return Err(format!("--config: {} doesn't exist", config));
+            }
+            let server = std::path::Path::new(&config).with_extension("cfg");
+            if server == std::path::Path::new(&config) {
+                return Err(format!("--config: {} is where the server is kept, give the .toml beside it", config));
+            }
+            Some((config, server.display().to_string()))
+        }
+        (None, Some(profile)) => {
+            if profile.is_empty() || !profile.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(format!("--profile: {} isn't a name (letters, digits, - and _)", profile));
+            }
+            let files = (format!("barcode-{}.toml", profile), format!("barcode-{}.cfg", profile));
+            overrides.profile = Some(profile);
+            Some(files)
+        }
+        (None, None) => None,
+    };
+    Ok(overrides)
+}
+
+/// the server to use without looking at the saved one, and where it came from: `--server`, then
+/// BARCODE_SERVER; an error if BARCODE_SERVER is no good
+fn server_override(flag: Option<String>, env: Option<String>) -> Result<Option<(String, &'static str)>, String> {
+    if let Some(server) = flag {
+        return Ok(Some((server, "flag (--server)")));
+    }
+    match env.filter(|env| !env.trim().is_empty()) {
+        Some(env) => normalize_server(&env)
+            .map(|server| Some((server, "env (BARCODE_SERVER)")))
+            .map_err(|e| format!("BARCODE_SERVER: {}", e)),
+        None => Ok(None),
+    }
+}
+
+/// in pretend mode print what `action` would send and return true, so the caller can skip the request
+fn pretend(action: &str, method: &str, url: &str, body: Option<&str>) -> bool {
+    if !PRETEND.load(Ordering::Relaxed) {
+        return false;
+    }
+
+    match body {
+        Some(body) => println!("[PRETEND] would {}: {} {} {}", action, method, url, body),
+        None => println!("[PRETEND] would {}: {} {}", action, method, url),
+    }
+    true
 }
 
 const HELP: &str = "
@@ -17,13 +161,67 @@ new <barcode1> <barcode2> ... - create new item
 modify <barcode1> <barcode2> ... - modify item
 delete <barcode1> <barcode2> ... - delete item
 log <barcode1> <barcode2> ... - see item
-all - get all items
+all [page] - get all items, or 100 at a time, most recently seen first
 see <barcode1> <barcode2> ... - get item
+search <text> - list items whose name or location contains the text, ignoring case
+status <barcode> <status> - set an item's status (ok, needs_repair, missing, retired)
+status - whether the server is answering (✓ or ✗ in the prompt) and when it last did
+decode <image-file> - read barcodes from a photo, then see/log/create them
+import <file.csv> [--dry-run] - create/update items from a CSV (name,barcode,location columns), --dry-run to preview
+note <barcode> <text> - leave a note on an item, keeping earlier ones
+history <barcode1> <barcode2> ... [--limit N] [--json] - an item's scans, moves, notes and last sighting, newest first
+diff <file.csv> [--apply] [--csv] - compare a CSV (as import reads) with the server, --apply to push the file's values
+audit [location] [--out missing.csv] [--dry] - stocktake: scan everything there, then done to list what's missing
+rename-location <from> <to> - move everything at one location to another (quote names with spaces, hotkeys work)
+delete-location <location> [--archive] [--dry-run] - delete (or retire) everything at a location once you type its name back
+dedup - go through items with the same name, choosing which to keep and deleting or merging the rest
+report [--markdown] [--json] - one-screen overview: totals, items per location, items not seen lately
+pull - refresh the local cache with what's changed on the server since the last pull
+backup [file] - download the database (to barcode-backup-<date>.db by default), checking it arrived intact
+selftest - create, see, modify, log and delete a throwaway item
 server - change server ip
+config - show every setting, its value and where it came from
+config set <key> <value> / config unset <key> - change a setting in barcode.toml
 <barcode> - create new item
 quit - quit
 
-server will be written to and read from barcode.cfg";
+server will be written to and read from barcode.cfg, other settings from barcode.toml
+(an environment variable overrides the file, see config):
+currency (BARCODE_CURRENCY) - the symbol item values are shown with (default £)
+on_conflict (BARCODE_ON_CONFLICT) - when a new barcode already exists you are asked whether to
+update it, set this to update (or fail) to skip the question
+scan_prefix, scan_suffix (BARCODE_SCAN_PREFIX, BARCODE_SCAN_SUFFIX) - what your scanner adds around
+each barcode, taken off again (\\t, \\r and \\n for TAB, CR and LF)
+strip_check_digit (BARCODE_STRIP_CHECK_DIGIT) - true drops an EAN-13's check digit once it's verified
+date_format (BARCODE_DATE_FORMAT) - how dates are shown, a strftime pattern (default %Y-%m-%d %H:%M:%S;
+ISO-8601 if it isn't valid); one with spaces has to be quoted in barcode.toml
+history_file (BARCODE_HISTORY_FILE) - where commands are kept between runs for up/down and Ctrl-R
+search (default .barcode_history, empty to keep none)
+
+non-interactive use:
+termclient decode <image-file> - print decoded barcodes one per line
+termclient search <text> - list items whose name or location contains the text
+termclient selftest - run the selftest, exiting non-zero if any step fails
+termclient import <file.csv> [--dry-run] - import a CSV, exiting non-zero if it fails
+termclient history <barcode> ... [--limit N] [--json] - print item histories, --json as one JSON object per item
+termclient diff <file.csv> [--apply] [--csv] - compare a CSV with the server, exiting non-zero if they differ
+termclient audit [location] [--out missing.csv] - stocktake with barcodes from stdin, exiting non-zero if any are missing
+termclient rename-location <from> <to> - move everything at one location to another, exiting non-zero if any fail
+termclient delete-location <location> [--archive] [--dry-run] - delete or retire everything there, exiting non-zero if any fail
+termclient report [--markdown] [--json] - print the overview, e.g. for the weekly email
+termclient config [set <key> <value> | unset <key>] - show or change settings
+termclient pull - refresh the local cache, exiting non-zero if it fails
+termclient backup [file] - download the database, exiting non-zero if it fails or doesn't match the server's hash
+termclient --offline - see and all answer from the local cache, without the network
+termclient --verbose ... - show how scanned barcodes were cleaned up
+termclient --pretend ... - new, modify, delete and log print what they would send without changing anything
+
+for one run only (never saved), anywhere on the command line, interactive or not:
+--server <ip[:port]> - use this server; otherwise BARCODE_SERVER, then barcode.cfg, then you're asked
+--timeout <seconds> - give up on any request after this long (by default only changes time out, after 10s)
+--config <file.toml> - read and write settings there instead of barcode.toml, and the server in the .cfg
+beside it; the file must exist
+--profile <name> - the same with barcode-<name>.toml and barcode-<name>.cfg, e.g. one per inventory";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Item {
@@ -32,95 +230,504 @@ pub struct Item {
     location: String,
 }
 
+/// a key unique to one logical operation, so the server can tell a retry from a new request
+fn new_idempotency_key() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    format!(
+        "{:x}-{:x}-{:x}",
+        chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// send a mutating request, retrying on network errors
+///
+/// every attempt carries the same Idempotency-Key, so if an attempt that timed out
+/// actually reached the server, the retry gets its response instead of repeating it
+async fn send_idempotent(
+    build: impl Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+) -> Result<reqwest::Response, reqwest::Error> {
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT.get().copied().unwrap_or(Duration::from_secs(10)))
+        .build()?;
+    let key = new_idempotency_key();
+
+    let mut attempt = 1;
+    loop {
+        match build(&client).header("Idempotency-Key", &key).send().await {
+            Err(e) if attempt < 3 && (e.is_timeout() || e.is_connect()) => {
+                tokio::time::sleep(Duration::from_millis(500 * attempt)).await;
+                attempt += 1;
+            }
+            res => return track(res),
+        }
+    }
+}
+
+/// what the server said was wrong with a rejected (422) request, one problem per line:
+/// each of `{"errors": [{"field": .., "message": ..}]}`, or the body as it is
+fn explain_rejection(body: &str) -> String {
+    let errors = serde_json::from_str::<serde_json::Value>(body)
+        .ok()
+        .and_then(|body| body["errors"].as_array().cloned());
+
+    match errors {
+        Some(errors) if !errors.is_empty() => errors
+            .iter()
+            .map(|error| match (error["field"].as_str(), error["message"].as_str()) {
+                (Some(field), Some(message)) => format!("{}: {}", field, message),
+                (None, Some(message)) => message.to_string(),
+                _ => error.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => body.to_string(),
+    }
+}
+
 async fn new_item(item: Item) -> Result<u16, reqwest::Error> {
-    let client = reqwest::Client::new();
+    let url = format!(
+        "{}/new",
+        server()
+    );
+    let body = serde_json::to_string(&item).expect("Failed to serialize item");
 
-    let res = client
-        .post(format!(
-            "{}/new",
-            SERVER.lock().unwrap().get().expect("Server not set")
-        ))
-        .body(serde_json::to_string(&item).expect("Failed to serialize item"));
+    if pretend(&format!("create barcode {}", item.barcode), "POST", &url, Some(&body)) {
+        return Ok(200);
+    }
+
+    let res = send_idempotent(|client| client.post(&url).body(body.clone())).await?;
+
+    let code = res.status().as_u16();
+    if code == 422 {
+        eprintln!("{}", explain_rejection(&res.text().await?));
+    }
 
-    Ok(res.send().await?.status().as_u16())
+    Ok(code)
 }
 
 async fn modify_item(item: Item) -> Result<u16, reqwest::Error> {
-    let client = reqwest::Client::new();
+    let url = format!(
+        "{}/modify",
+        server()
+    );
+    let body = serde_json::to_string(&item).expect("Failed to serialize item");
 
-    let res = client
-        .post(format!(
-            "{}/modify",
-            SERVER.lock().unwrap().get().expect("Server not set")
-        ))
-        .body(serde_json::to_string(&item).expect("Failed to serialize item"));
+    if pretend(&format!("modify barcode {}", item.barcode), "POST", &url, Some(&body)) {
+        return Ok(200);
+    }
+
+    let res = send_idempotent(|client| client.post(&url).body(body.clone())).await?;
 
-    Ok(res.send().await?.status().as_u16())
+    let code = res.status().as_u16();
+    if code == 422 {
+        eprintln!("{}", explain_rejection(&res.text().await?));
+    }
+
+    Ok(code)
 }
 
-async fn delete_item(barcode: u64) -> Result<u16, reqwest::Error> {
-    let client = reqwest::Client::new();
+/// whether to update an item when `new` finds its barcode already exists
+///
+/// the on_conflict setting: update always updates, fail never does, otherwise ask
+fn update_on_conflict(barcode: u64) -> bool {
+    match setting("on_conflict").0.as_str() {
+        "update" => true,
+        "fail" => false,
+        _ => {
+            let mut answer = String::new();
+            flush_print!("new>{}> already exists, update it instead? [y/N] ", barcode);
+            std::io::stdin()
+                .read_line(&mut answer)
+                .expect("Failed to read input");
+            matches!(answer.trim(), "y" | "Y" | "yes")
+        }
+    }
+}
 
-    let res = client.get(format!(
+/// create an item, falling back to modifying it if it already exists (HTTP 409)
+///
+/// returns the final status and whether the item was updated rather than created
+async fn new_or_modify_item(item: Item) -> Result<(u16, bool), reqwest::Error> {
+    let status = new_item(item.clone()).await?;
+
+    if status == 409 && update_on_conflict(item.barcode) {
+        return Ok((modify_item(item).await?, true));
+    }
+
+    Ok((status, false))
+}
+
+/// create an item from user input, reporting whether it was created or updated
+///
+/// returns (created, updated) counts for the summary
+async fn create_from_input(barcode: u64) -> (usize, usize) {
+    match new_or_modify_item(process_new_item(barcode)).await {
+        Ok((200, false)) => {
+            println!("Created item {}", barcode);
+            (1, 0)
+        }
+        Ok((200, true)) => {
+            println!("Updated existing item {}", barcode);
+            (0, 1)
+        }
+        Ok((status, _)) => {
+            eprintln!("Failed to create item with barcode {}: HTTP {}", barcode, status);
+            (0, 0)
+        }
+        Err(e) => {
+            eprintln!("Error creating item with barcode {}: {}", barcode, e);
+            (0, 0)
+        }
+    }
+}
+
+async fn delete_item(barcode: u64) -> Result<u16, reqwest::Error> {
+    let url = format!(
         "{}/delete/{}",
-        SERVER.lock().unwrap().get().expect("Server not set"),
+        server(),
         barcode
-    ));
+    );
+
+    if pretend(&format!("delete barcode {}", barcode), "GET", &url, None) {
+        return Ok(200);
+    }
 
-    Ok(res.send().await?.status().as_u16())
+    let res = send_idempotent(|client| client.get(&url)).await?;
+
+    Ok(res.status().as_u16())
 }
 
-async fn get_all_items() -> Result<u16, reqwest::Error> {
-    let client = reqwest::Client::new();
+/// how many requests a bulk command keeps in flight at once
+const BULK_CONCURRENCY: usize = 8;
 
-    let res = client.get(format!(
-        "{}/all",
-        SERVER.lock().unwrap().get().expect("Server not set")
-    ));
+/// a progress bar for `total` steps, hidden when stdout isn't a terminal so piped output stays clean
+fn progress_bar(total: u64) -> ProgressBar {
+    if !std::io::stdout().is_terminal() {
+        return ProgressBar::hidden();
+    }
+
+    let bar = ProgressBar::new(total);
+    bar.set_style(
+        ProgressStyle::with_template("{bar:40} {pos}/{len} {msg}")
+            .expect("valid progress template"),
+    );
+    bar
+}
+
+/// run `action` for every barcode, a few at a time, showing progress and failures as they finish
+///
+/// returns how many succeeded
+async fn run_bulk<F, Fut>(barcodes: Vec<u64>, verb: &'static str, action: F) -> usize
+where
+    F: Fn(u64) -> Fut,
+    Fut: std::future::Future<Output = Result<u16, reqwest::Error>> + Send + 'static,
+{
+    let bar = progress_bar(barcodes.len() as u64);
+    let mut pending = barcodes.into_iter();
+    let mut running = tokio::task::JoinSet::new();
+    let (mut succeeded, mut failed) = (0, 0);
+
+    loop {
+        while running.len() < BULK_CONCURRENCY {
+            match pending.next() {
+                Some(barcode) => {
+                    let request = action(barcode);
+                    running.spawn(async move { (barcode, request.await) });
+                }
+                None => break,
+            }
+        }
+
+        let (barcode, res) = match running.join_next().await {
+            Some(Ok(done)) => done,
+            Some(Err(e)) => {
+                failed += 1;
+                bar.suspend(|| eprintln!("Error: {}", e));
+                bar.inc(1);
+                continue;
+            }
+            None => break,
+        };
+
+        match res {
+            Ok(200) => succeeded += 1,
+            Ok(status) => {
+                failed += 1;
+                bar.suspend(|| eprintln!("Failed to {} item with barcode {}: HTTP {}", verb, barcode, status));
+            }
+            Err(e) => {
+                failed += 1;
+                bar.suspend(|| eprintln!("Error trying to {} item with barcode {}: {}", verb, barcode, e));
+            }
+        }
+        if failed > 0 {
+            bar.set_message(format!("{} failed", failed));
+        }
+        bar.inc(1);
+    }
+
+    bar.finish_and_clear();
+    succeeded
+}
+
+/// how many items `all <page>` shows at a time
+const PAGE_SIZE: u64 = 100;
+
+/// the most items the server sends in one page of /all, however many are asked for
+const SERVER_PAGE_LIMIT: u64 = 1000;
+
+/// every item /all lists with `query` (e.g. `&include_retired=true`), asked for a page at a time
+/// until there are as many as X-Total-Count says
+async fn fetch_all_items(query: &str) -> Result<Vec<serde_json::Value>, String> {
+    let server = server();
+    let mut items = Vec::new();
+    loop {
+        let url = format!("{}/all?limit={}&offset={}{}", server, SERVER_PAGE_LIMIT, items.len(), query);
+        let res = track(http().get(&url).send().await).map_err(|e| e.to_string())?;
+        if res.status().as_u16() != 200 {
+            return Err(format!("HTTP {}", res.status().as_u16()));
+        }
+        let total = res
+            .headers()
+            .get("x-total-count")
+            .and_then(|total| total.to_str().ok())
+            .and_then(|total| total.parse::<usize>().ok());
+        let page = serde_json::from_str::<Vec<serde_json::Value>>(&res.text().await.map_err(|e| e.to_string())?)
+            .map_err(|e| e.to_string())?;
+        let last = page.len() < SERVER_PAGE_LIMIT as usize;
+        items.extend(page);
+        if last || total.is_none_or(|total| items.len() >= total) {
+            return Ok(items);
+        }
+    }
+}
+
+/// how many pages of `per_page` it takes to show `total` items (always at least one)
+fn page_count(total: u64, per_page: u64) -> u64 {
+    total.div_ceil(per_page).max(1)
+}
+
+/// print every item, or with `page` (from 1) one page of them, most recently seen first
+async fn get_all_items(page: Option<u64>) -> Result<u16, String> {
+    let offset = page.map(|page| page.saturating_sub(1) * PAGE_SIZE);
+
+    if OFFLINE.load(Ordering::Relaxed) {
+        let Some(cache) = load_cache() else {
+            eprintln!("Nothing cached yet, run pull first");
+            return Ok(404);
+        };
+        eprintln!("{}", cache_warning());
+        let mut items: Vec<serde_json::Value> = cache
+            .into_values()
+            .filter(|item| item["status"].as_str() != Some("retired"))
+            .collect();
+        let total = items.len() as u64;
+        if let (Some(page), Some(offset)) = (page, offset) {
+            items.sort_by_key(|item| std::cmp::Reverse(item["last_seen"].as_i64()));
+            let items: Vec<_> = items.into_iter().skip(offset as usize).take(PAGE_SIZE as usize).collect();
+            print_listing(&items);
+            println!("Page {} of {} ({} items)", page, page_count(total, PAGE_SIZE), total);
+        } else {
+            print_listing(&items);
+        }
+        return Ok(200);
+    }
+
+    let Some(offset) = offset else {
+        print_listing(&fetch_all_items("").await?);
+        return Ok(200);
+    };
+
+    let client = http();
 
-    let items = res.send().await?;
+    let res = client.get(format!("{}/all?limit={}&offset={}", server(), PAGE_SIZE, offset));
+
+    let items = track(res.send().await).map_err(|e| e.to_string())?;
 
     if items.status().as_u16() != 200 {
         return Ok(items.status().as_u16());
     }
 
-    let items = items.text().await?;
+    let total = items
+        .headers()
+        .get("x-total-count")
+        .and_then(|total| total.to_str().ok())
+        .and_then(|total| total.parse::<u64>().ok());
+    let items = items.text().await.map_err(|e| e.to_string())?;
 
     let actual_items = serde_json::from_str::<serde_json::Value>(&items)
         .expect("Failed to deserialize items")
         .clone();
 
-    for item in actual_items.as_array().expect("Failed to get items") {
-        #[allow(deprecated)]
-        let last_seen = chrono::NaiveDateTime::from_timestamp(
-            item["last_seen"]
-                .as_i64()
-                .expect("Failed to parse last_seen"),
-            0,
-        );
-        let local_last_seen = chrono::Local.from_utc_datetime(&last_seen);
-        let formatted_last_seen = local_last_seen.format("%Y-%m-%d %H:%M:%S").to_string();
+    print_listing(actual_items.as_array().expect("Failed to get items"));
+
+    if let (Some(page), Some(total)) = (page, total) {
+        let pages = page_count(total, PAGE_SIZE);
+        if page < pages {
+            println!("Page {} of {} ({} items), all {} for the next", page, pages, total, page + 1);
+        } else {
+            println!("Page {} of {} ({} items)", page, pages, total);
+        }
+    }
+
+    Ok(200)
+}
+
+/// print the items whose name or location contains `query` (ignoring case) as `all` does,
+/// from the cache when offline
+async fn search_items(query: &str) -> Result<u16, reqwest::Error> {
+    let items = if OFFLINE.load(Ordering::Relaxed) {
+        let Some(cache) = load_cache() else {
+            eprintln!("Nothing cached yet, run pull first");
+            return Ok(404);
+        };
+        eprintln!("{}", cache_warning());
+        let query = query.to_lowercase();
+        let mut items: Vec<serde_json::Value> = cache
+            .into_values()
+            .filter(|item| {
+                [&item["name"], &item["location"]]
+                    .iter()
+                    .any(|field| json_text(field).to_lowercase().contains(&query))
+            })
+            .collect();
+        items.sort_by_key(|item| json_text(&item["name"]).to_lowercase());
+        items
+    } else {
+        let mut url = reqwest::Url::parse(&format!("{}/search", server())).expect("Invalid server");
+        url.query_pairs_mut().append_pair("q", query);
+
+        let res = track(http().get(url).send().await)?;
+        if res.status().as_u16() != 200 {
+            return Ok(res.status().as_u16());
+        }
+        serde_json::from_str::<Vec<serde_json::Value>>(&res.text().await?)
+            .expect("Failed to deserialize items")
+    };
+
+    if items.is_empty() {
+        println!("Nothing matches {}", query);
+    }
+    print_listing(&items);
+    Ok(200)
+}
+
+/// one line per item, as `all` shows them
+fn print_listing(items: &[serde_json::Value]) {
+    for item in items {
+        let formatted_last_seen = local_time(item["last_seen"].as_i64().expect("Failed to parse last_seen"));
         println!(
-            "{}: {} @ {}, last seen {}",
-            item["barcode"], item["name"], item["location"], formatted_last_seen
+            "{}: {} @ {}, last seen {}{}",
+            item["barcode"], item["name"], item["location"], formatted_last_seen, status_marker(item)
         );
     }
 
-    println!("Retrieved {} items", actual_items.as_array().expect("Failed to get items").len());
+    println!("Retrieved {} items", items.len());
+}
 
-    Ok(200)
+/// a loud suffix for items whose status isn't ok, e.g. " [NEEDS REPAIR]", so they stand out in listings
+fn status_marker(item: &serde_json::Value) -> String {
+    match item["status"].as_str() {
+        None | Some("ok") => String::new(),
+        Some(status) => format!(" [{}]", status.replace('_', " ").to_uppercase()),
+    }
+}
+
+/// an amount in minor units (pence) as e.g. "£1,234.56", the symbol from the currency setting
+fn format_value(pence: i64) -> String {
+    let (symbol, _) = setting("currency");
+    let units = (pence / 100).to_string();
+
+    // group the whole units in threes from the right
+    let mut grouped = String::new();
+    for (i, digit) in units.chars().enumerate() {
+        if i > 0 && (units.len() - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+
+    format!("{}{}.{:02}", symbol, grouped, pence % 100)
+}
+
+/// ", worth £12.99, bought 2024-03-31" for whichever of value and purchase date an item has
+fn purchase_info(item: &serde_json::Value) -> String {
+    let mut info = String::new();
+    if let Some(pence) = item["value_pence"].as_i64() {
+        info.push_str(&format!(", worth {}", format_value(pence)));
+    }
+    if let Some(date) = item["purchase_date"].as_str() {
+        info.push_str(&format!(", bought {}", date));
+    }
+    info
+}
+
+/// set an item's status (ok, needs_repair, missing, retired...), leaving the rest of it alone
+async fn set_status(barcode: u64, status: &str) -> Result<u16, reqwest::Error> {
+    let server = server();
+
+    let res = track(http().get(format!("{}/item/{}", server, barcode)).send().await)?;
+    if res.status().as_u16() != 200 {
+        return Ok(res.status().as_u16());
+    }
+
+    let item = serde_json::from_str::<serde_json::Value>(&res.text().await?)
+        .expect("Failed to deserialize item");
+    let body = serde_json::json!({
+        "name": item["name"],
+        "barcode": item["barcode"],
+        "location": item["location"],
+        "status": status,
+    })
+    .to_string();
+    // only if nobody changed it since we looked
+    let version = format!("\"{}\"", item["version"]);
+
+    let url = format!("{}/modify", server);
+    let res = send_idempotent(|client| {
+        client
+            .post(&url)
+            .header("If-Match", &version)
+            .body(body.clone())
+    })
+    .await?;
+
+    let code = res.status().as_u16();
+    if code == 422 {
+        eprintln!("{}", explain_rejection(&res.text().await?));
+    }
+
+    Ok(code)
 }
 
 async fn see_item(barcode: u64) -> Result<u16, reqwest::Error> {
-    let client = reqwest::Client::new();
+    if OFFLINE.load(Ordering::Relaxed) {
+        let Some(cache) = load_cache() else {
+            eprintln!("Nothing cached yet, run pull first");
+            return Ok(404);
+        };
+        eprintln!("{}", cache_warning());
+        return Ok(match cache.get(&barcode.to_string()) {
+            Some(item) => {
+                print_item(item);
+                200
+            }
+            None => 404,
+        });
+    }
+
+    let client = http();
 
     let res = client.get(format!(
         "{}/item/{}",
-        SERVER.lock().unwrap().get().expect("Server not set"),
+        server(),
         barcode
     ));
 
-    let item = res.send().await?;
+    let item = track(res.send().await)?;
 
     if item.status().as_u16() != 200 {
         return Ok(item.status().as_u16());
@@ -132,33 +739,1824 @@ async fn see_item(barcode: u64) -> Result<u16, reqwest::Error> {
         .expect("Failed to deserialize item")
         .clone();
 
-    #[allow(deprecated)]
-    let last_seen = chrono::NaiveDateTime::from_timestamp(
-        actual_item["last_seen"]
-            .as_i64()
-            .expect("Failed to parse last_seen"),
-        0,
-    );
-    let local_last_seen = chrono::Local.from_utc_datetime(&last_seen);
-    let formatted_last_seen = local_last_seen.format("%Y-%m-%d %H:%M:%S").to_string();
+    print_item(&actual_item);
+
+    Ok(200)
+}
+
+/// an item with its notes, as `see` shows it
+fn print_item(actual_item: &serde_json::Value) {
+    let formatted_last_seen = local_time(actual_item["last_seen"].as_i64().expect("Failed to parse last_seen"));
     println!(
-        "{}: {} @ {}, last seen {}",
-        actual_item["barcode"], actual_item["name"], actual_item["location"], formatted_last_seen
+        "{}: {} @ {}, last seen {}{}{}",
+        actual_item["barcode"],
+        actual_item["name"],
+        actual_item["location"],
+        formatted_last_seen,
+        purchase_info(actual_item),
+        status_marker(actual_item)
     );
+    // newest first, as the server sends them
+    for note in actual_item["notes"].as_array().into_iter().flatten() {
+        if let (Some(at), Some(text)) = (note["noted_at"].as_i64(), note["text"].as_str()) {
+            println!("  note ({}): {}", local_time(at), text);
+        }
+    }
+}
 
-    Ok(200)
+/// where `pull` keeps a copy of every item, keyed by barcode, for `--offline`
+const CACHE_FILE: &str = "barcode-cache.json";
+
+/// the cached items by barcode, or `None` if nothing has been pulled
+fn load_cache() -> Option<serde_json::Map<String, serde_json::Value>> {
+    let cache = std::fs::read_to_string(CACHE_FILE).ok()?;
+    match serde_json::from_str(&cache) {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            eprintln!("Ignoring {}, which can't be read ({}); run pull to rebuild it", CACHE_FILE, e);
+            None
+        }
+    }
 }
 
-async fn log_item(barcode: u64) -> Result<u16, reqwest::Error> {
-    let client = reqwest::Client::new();
+/// a GET for JSON, `None` on the `gone` status so the caller can handle it
+async fn get_json(url: &str, gone: Option<u16>) -> Result<Option<serde_json::Value>, String> {
+    let res = track(http().get(url).send().await).map_err(|e| e.to_string())?;
+    match res.status().as_u16() {
+        200 => {}
+        status if Some(status) == gone => return Ok(None),
+        status => return Err(format!("HTTP {} from {}", status, url)),
+    }
+    let text = res.text().await.map_err(|e| e.to_string())?;
+    serde_json::from_str(&text).map(Some).map_err(|e| e.to_string())
+}
 
-    let res = client.get(format!(
-        "{}/log/{}",
-        SERVER.lock().unwrap().get().expect("Server not set"),
+/// bring the cache up to date with what changed on the server since the last pull (everything, the
+/// first time), returning how many items were refreshed and how many removed
+///
+/// the sync cursor is kept in barcode.toml, and only saved once the cache is
+async fn pull() -> Result<(usize, usize), String> {
+    let server = server();
+
+    let mut since = config_file_value("sync_cursor")
+        .and_then(|cursor| cursor.parse::<u64>().ok())
+        .unwrap_or(0);
+    let mut cache = load_cache().unwrap_or_default();
+    if cache.is_empty() {
+        since = 0;
+    }
+
+    let delta = match get_json(&format!("{}/sync?since={}", server, since), Some(410)).await? {
+        Some(delta) => delta,
+        // the server's database was reset or restored, so start again
+        None => {
+            since = 0;
+            get_json(&format!("{}/sync?since=0", server), Some(410))
+                .await?
+                .ok_or("The server refused to sync from the start")?
+        }
+    };
+    let cursor = delta["cursor"].as_u64().ok_or("The server sent no sync cursor")?;
+
+    let (mut refreshed, mut removed) = (0, 0);
+    if since == 0 {
+        // everything, retired items included, as it is now or later than the cursor
+        let items = fetch_all_items("&include_retired=true").await?;
+        let old = std::mem::take(&mut cache);
+        for item in &items {
+            cache.insert(item["barcode"].to_string(), item.clone());
+        }
+        refreshed = cache.len();
+        removed = old.keys().filter(|barcode| !cache.contains_key(*barcode)).count();
+    } else {
+        for barcode in delta["deleted"].as_array().into_iter().flatten() {
+            removed += usize::from(cache.remove(&barcode.to_string()).is_some());
+        }
+        let changed = ["created", "updated"]
+            .iter()
+            .flat_map(|list| delta[*list].as_array().into_iter().flatten());
+        for barcode in changed {
+            // a 404 means it's gone again since the cursor
+            match get_json(&format!("{}/item/{}", server, barcode), Some(404)).await? {
+                Some(item) => {
+                    cache.insert(barcode.to_string(), item);
+                    refreshed += 1;
+                }
+                None => removed += usize::from(cache.remove(&barcode.to_string()).is_some()),
+            }
+        }
+    }
+
+    std::fs::write(CACHE_FILE, serde_json::Value::Object(cache).to_string())
+        .map_err(|e| format!("Failed to write {}: {}", CACHE_FILE, e))?;
+    write_config_value("sync_cursor", Some(&cursor.to_string()))
+        .map_err(|e| format!("Failed to write {}: {}", config_file(), e))?;
+    Ok((refreshed, removed))
+}
+
+/// the SHA-256 of some bytes as lowercase hex, as the server sends it in `X-Content-SHA256`
+fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::Digest;
+
+    sha2::Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// download the server's database to a file (barcode-backup-<date>.db unless one is given),
+/// checking it against the hash the server sent so a cut-off or garbled download isn't kept
+async fn backup(args: &[&str]) -> bool {
+    let file = match args {
+        [] => format!("barcode-backup-{}.db", chrono::Local::now().format("%Y-%m-%d")),
+        [file] => file.to_string(),
+        _ => {
+            eprintln!("Usage: backup [file]");
+            return false;
+        }
+    };
+    let url = format!("{}/get_database", server());
+
+    // reqwest asks for (and undoes) gzip itself, so the hash is of the database as saved
+    let res = match track(http().get(&url).send().await) {
+        Ok(res) if res.status().is_success() => res,
+        Ok(res) => {
+            eprintln!("Failed to download the database: HTTP {}", res.status().as_u16());
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Failed to download the database: {}", e);
+            return false;
+        }
+    };
+    let expected = res
+        .headers()
+        .get("x-content-sha256")
+        .and_then(|hash| hash.to_str().ok())
+        .map(str::to_string);
+    let database = match res.bytes().await {
+        Ok(database) => database,
+        Err(e) => {
+            eprintln!("Failed to download the database: {}", e);
+            return false;
+        }
+    };
+
+    let actual = sha256_hex(&database);
+    match &expected {
+        Some(expected) if !expected.eq_ignore_ascii_case(&actual) => {
+            eprintln!(
+                "WARNING: the download is corrupt or incomplete (got {} bytes hashing to {}, the server sent {}), not saved; try again",
+                database.len(),
+                actual,
+                expected
+            );
+            return false;
+        }
+        Some(_) => {}
+        None => eprintln!("The server sent no hash (it may be older than this client), so the backup can't be checked"),
+    }
+
+    if let Err(e) = std::fs::write(&file, &database) {
+        eprintln!("Failed to write {}: {}", file, e);
+        return false;
+    }
+    match expected {
+        Some(_) => println!("Saved {} bytes to {} (SHA-256 {}, verified)", database.len(), file, actual),
+        None => println!("Saved {} bytes to {} (SHA-256 {})", database.len(), file, actual),
+    }
+    true
+}
+
+/// append a note to an item, leaving its earlier notes alone
+async fn add_note(barcode: u64, text: &str) -> Result<u16, reqwest::Error> {
+    let url = format!(
+        "{}/note/{}",
+        server(),
         barcode
-    ));
+    );
+    let body = serde_json::json!({ "text": text }).to_string();
+
+    if pretend(&format!("note barcode {}", barcode), "POST", &url, Some(&body)) {
+        return Ok(200);
+    }
+
+    let res = send_idempotent(|client| client.post(&url).body(body.clone())).await?;
+
+    let code = res.status().as_u16();
+    if code == 422 {
+        eprintln!("{}", explain_rejection(&res.text().await?));
+    }
+
+    Ok(code)
+}
+
+/// how many history entries `history` shows per item unless given `--limit`
+const HISTORY_LIMIT: usize = 20;
+
+/// one line of an item's history
+#[derive(Debug, Serialize)]
+struct HistoryEntry {
+    at: i64,
+    kind: &'static str,
+    text: String,
+}
+
+/// what dates fall back to when the date_format setting isn't a pattern chrono can format with
+const ISO_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%:z";
+
+/// whether a strftime pattern is one chrono can format with
+fn valid_date_format(format: &str) -> bool {
+    !format.is_empty()
+        && chrono::format::StrftimeItems::new(format).all(|item| item != chrono::format::Item::Error)
+}
+
+/// the date_format setting, or ISO-8601 if it isn't valid (which is warned about at startup)
+fn date_format() -> String {
+    let (format, _) = setting("date_format");
+    if valid_date_format(&format) { format } else { ISO_DATE_FORMAT.to_string() }
+}
+
+/// a timestamp in local time, as the date_format setting says, like listings show last seen
+fn local_time(at: i64) -> String {
+    chrono::Local
+        .timestamp_opt(at, 0)
+        .single()
+        .map_or_else(|| at.to_string(), |time| time.format(&date_format()).to_string())
+}
+
+/// how often an idle REPL checks it can still reach the server
+const LINK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// whether the server has been answering this session, for the prompt, `status` and cached listings
+#[derive(Debug, Default)]
+struct Link {
+    /// when the server last answered anything at all (an error status is still an answer)
+    last_ok: Option<i64>,
+    /// whether the most recent request went unanswered
+    down: bool,
+}
+
+static LINK: std::sync::Mutex<Link> = std::sync::Mutex::new(Link { last_ok: None, down: false });
+
+impl Link {
+    /// note whether a request got an answer, returning what to say if that changed whether the
+    /// server is reachable (so going down, or coming back, is said once)
+    fn record(&mut self, answered: bool, now: i64) -> Option<String> {
+        let was_down = self.down;
+        self.down = !answered;
+        if answered {
+            self.last_ok = Some(now);
+        }
+
+        match (was_down, self.down) {
+            (true, false) => Some("Reached the server again".to_string()),
+            (false, true) => Some(match self.last_ok {
+                Some(at) => format!("Lost the server, it last answered {}", time_ago(at, now)),
+                None => "Can't reach the server".to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// for the prompt: ✓, ✗ and how long since the server last answered, or nothing before the first request
+    fn marker(&self, now: i64) -> String {
+        match (self.down, self.last_ok) {
+            (true, Some(at)) => format!("✗ {}", short_age(now - at)),
+            (true, None) => "✗".to_string(),
+            (false, Some(_)) => "✓".to_string(),
+            (false, None) => String::new(),
+        }
+    }
+
+    /// for `status`
+    fn describe(&self, now: i64) -> String {
+        let last = match self.last_ok {
+            Some(at) => format!("last answered {} ({})", time_ago(at, now), local_time(at)),
+            None => "hasn't answered this session".to_string(),
+        };
+        match (self.down, self.last_ok) {
+            (false, None) => "not contacted yet".to_string(),
+            (false, Some(_)) => format!("up, {}", last),
+            (true, _) => format!("unreachable, {}", last),
+        }
+    }
+}
+
+/// a duration in seconds as the prompt shows it: 45s, 5m, 3h, 2d
+fn short_age(seconds: i64) -> String {
+    match seconds.max(0) {
+        s @ 0..60 => format!("{}s", s),
+        s @ 60..3_600 => format!("{}m", s / 60),
+        s @ 3_600..86_400 => format!("{}h", s / 3_600),
+        s => format!("{}d", s / 86_400),
+    }
+}
+
+/// note whether a request got an answer, saying so if the server has gone or come back
+fn track<T>(res: Result<T, reqwest::Error>) -> Result<T, reqwest::Error> {
+    let change = LINK.lock().unwrap().record(res.is_ok(), chrono::Utc::now().timestamp());
+    if let Some(change) = change {
+        eprintln!("[{}]", change);
+    }
+    res
+}
+
+/// the REPL prompt: the profile, then whether the server is answering (see `Link::marker`)
+fn prompt() -> String {
+    let marker = match OFFLINE.load(Ordering::Relaxed) {
+        true => "offline".to_string(),
+        false => LINK.lock().unwrap().marker(chrono::Utc::now().timestamp()),
+    };
+    let parts: Vec<&str> = [PROFILE.get().map(String::as_str).unwrap_or_default(), marker.as_str()]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect();
+    match parts.is_empty() {
+        true => "> ".to_string(),
+        false => format!("{}> ", parts.join(" ")),
+    }
+}
+
+/// printed before anything shown from the cache, which is only as fresh as the last pull
+fn cache_warning() -> String {
+    let pulled = std::fs::metadata(CACHE_FILE)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|since| time_ago(since.as_secs() as i64, chrono::Utc::now().timestamp()));
+    match pulled {
+        Some(pulled) => format!("[CACHED] pulled {}, may be out of date", pulled),
+        None => "[CACHED] may be out of date".to_string(),
+    }
+}
+
+/// roughly how long before `now` a timestamp was: "just now", "5 minutes ago", "3 days ago"
+fn time_ago(at: i64, now: i64) -> String {
+    let seconds = (now - at).max(0);
+    let (amount, unit) = match seconds {
+        0..60 => return "just now".to_string(),
+        60..3_600 => (seconds / 60, "minute"),
+        3_600..86_400 => (seconds / 3_600, "hour"),
+        86_400..2_592_000 => (seconds / 86_400, "day"),
+        2_592_000..31_536_000 => (seconds / 2_592_000, "month"),
+        _ => (seconds / 31_536_000, "year"),
+    };
+    format!("{} {}{} ago", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+/// an item's scans and moves (from its scan log and trail, if the server keeps them), notes and
+/// last sighting, newest first
+fn history_entries(
+    item: &serde_json::Value,
+    trail: Option<&serde_json::Value>,
+    scans: Option<&serde_json::Value>,
+    limit: usize,
+) -> Vec<HistoryEntry> {
+    let mut entries = Vec::new();
+
+    for scan in scans.and_then(serde_json::Value::as_array).into_iter().flatten() {
+        if let Some(at) = scan["scanned_at"].as_i64() {
+            entries.push(HistoryEntry {
+                at,
+                kind: "scanned",
+                text: format!("scanned at {}", scan["location"].as_str().unwrap_or("?")),
+            });
+        }
+    }
+    // unless the last sighting was a scan already listed
+    if let Some(at) = item["last_seen"].as_i64()
+        && !entries.iter().any(|entry| entry.at == at)
+    {
+        entries.push(HistoryEntry { at, kind: "seen", text: "last seen".to_string() });
+    }
+    for step in trail.and_then(serde_json::Value::as_array).into_iter().flatten() {
+        if let Some(at) = step["moved_at"].as_i64() {
+            entries.push(HistoryEntry {
+                at,
+                kind: "moved",
+                text: format!(
+                    "location: {} → {}",
+                    step["from"].as_str().unwrap_or("?"),
+                    step["to"].as_str().unwrap_or("?")
+                ),
+            });
+        }
+    }
+    for note in item["notes"].as_array().into_iter().flatten() {
+        if let (Some(at), Some(text)) = (note["noted_at"].as_i64(), note["text"].as_str()) {
+            entries.push(HistoryEntry { at, kind: "note", text: format!("note: {}", text) });
+        }
+    }
+
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.at));
+    entries.truncate(limit);
+    entries
+}
+
+/// print one item's history, or with `json` a single JSON object for scripts
+async fn show_history(barcode: u64, limit: usize, json: bool) -> Result<u16, reqwest::Error> {
+    let server = server();
+
+    let res = track(http().get(format!("{}/item/{}", server, barcode)).send().await)?;
+    if res.status().as_u16() != 200 {
+        return Ok(res.status().as_u16());
+    }
+    let item = serde_json::from_str::<serde_json::Value>(&res.text().await?)
+        .expect("Failed to deserialize item");
+
+    // servers from before the trail was added can only say when it was last seen
+    let res = track(http().get(format!("{}/item/{}/trail?limit={}", server, barcode, limit)).send().await)?;
+    let trail = if res.status().as_u16() == 200 {
+        Some(
+            serde_json::from_str::<serde_json::Value>(&res.text().await?)
+                .expect("Failed to deserialize trail"),
+        )
+    } else {
+        None
+    };
+
+    // every scan, oldest first, of which the newest are kept
+    let res = track(http().get(format!("{}/history/{}", server, barcode)).send().await)?;
+    let scans = if res.status().as_u16() == 200 {
+        Some(
+            serde_json::from_str::<serde_json::Value>(&res.text().await?)
+                .expect("Failed to deserialize scans"),
+        )
+    } else {
+        None
+    };
+
+    let entries = history_entries(&item, trail.as_ref(), scans.as_ref(), limit);
+
+    if json {
+        println!(
+            "{}",
+            serde_json::json!({
+                "barcode": item["barcode"],
+                "name": item["name"],
+                "complete": trail.is_some() && scans.is_some(),
+                "history": entries,
+            })
+        );
+        return Ok(200);
+    }
+
+    println!("{}: {}{}", item["barcode"], item["name"], status_marker(&item));
+    let now = chrono::Utc::now().timestamp();
+    for entry in &entries {
+        println!("  {:<16} {}  {}", time_ago(entry.at, now), local_time(entry.at), entry.text);
+    }
+    if trail.is_none() {
+        println!("  (the server keeps no history, so this is only when it was last seen)");
+    }
+
+    Ok(200)
+}
+
+/// run `history` with the words after it, returning whether every item's history was shown
+async fn history(args: &[&str]) -> bool {
+    let (mut barcodes, mut limit, mut json) = (Vec::new(), HISTORY_LIMIT, false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--json" => json = true,
+            "--limit" => match args.next().map(|limit| limit.parse::<usize>()) {
+                Some(Ok(n)) if n > 0 => limit = n,
+                _ => {
+                    eprintln!("--limit needs a number above 0");
+                    return false;
+                }
+            },
+            barcode => match barcode.parse::<u64>() {
+                Ok(barcode) => barcodes.push(barcode),
+                Err(_) => {
+                    eprintln!("Invalid barcode {}", barcode);
+                    return false;
+                }
+            },
+        }
+    }
+    if barcodes.is_empty() {
+        eprintln!("Usage: history <barcode1> <barcode2> ... [--limit N] [--json]");
+        return false;
+    }
+
+    let mut shown = 0;
+    for (i, barcode) in barcodes.iter().enumerate() {
+        if i > 0 && !json {
+            println!();
+        }
+        match show_history(*barcode, limit, json).await {
+            Ok(200) => shown += 1,
+            Ok(status) => eprintln!("Failed to get history for barcode {}: HTTP {}", barcode, status),
+            Err(e) => eprintln!("Error getting history for barcode {}: {}", barcode, e),
+        }
+    }
+    shown == barcodes.len()
+}
+
+/// the items a stocktake expects to find: everything not retired, or just what's at `location`
+async fn expected_items(location: Option<&str>) -> Result<Vec<serde_json::Value>, String> {
+    Ok(fetch_items(location)
+        .await?
+        .into_iter()
+        .filter(|item| item["status"].as_str() != Some("retired"))
+        .collect())
+}
+
+/// every item at `location` (matched as the server matches, ignoring case), or every item not retired
+async fn fetch_items(location: Option<&str>) -> Result<Vec<serde_json::Value>, String> {
+    let Some(location) = location else {
+        return fetch_all_items("").await;
+    };
+    let server = server();
+
+    let mut url = reqwest::Url::parse(&server).map_err(|e| e.to_string())?;
+    url.path_segments_mut()
+        .map_err(|_| format!("Invalid server {}", server))?
+        .pop_if_empty()
+        .push("location")
+        .push(location);
+
+    let res = track(http().get(url).send().await).map_err(|e| e.to_string())?;
+    if res.status().as_u16() != 200 {
+        return Err(format!("HTTP {}", res.status().as_u16()));
+    }
+    serde_json::from_str::<Vec<serde_json::Value>>(&res.text().await.map_err(|e| e.to_string())?)
+        .map_err(|e| e.to_string())
+}
+
+/// run a stocktake: read barcodes from stdin until `done`, keeping count of how many expected
+/// items have turned up and flagging any that weren't expected, then list what never did
+///
+/// the server has no audit sessions, so the comparison is always made here against the item
+/// list fetched at the start (`--dry` is accepted for scripts that ask for that explicitly);
+/// returns whether everything expected was found
+async fn audit(args: &[&str]) -> bool {
+    let mut words = Vec::new();
+    let mut out = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--dry" => {}
+            "--out" => match args.next() {
+                Some(path) => out = Some(*path),
+                None => {
+                    eprintln!("--out needs a file name");
+                    return false;
+                }
+            },
+            word => words.push(word),
+        }
+    }
+    let location = (!words.is_empty()).then(|| words.join(" "));
+
+    let expected = match expected_items(location.as_deref()).await {
+        Ok(expected) => expected,
+        Err(e) => {
+            eprintln!("Failed to get the items to expect: {}", e);
+            return false;
+        }
+    };
+    match &location {
+        Some(location) => println!("Auditing {}: expecting {} items", location, expected.len()),
+        None => println!("Auditing everything: expecting {} items", expected.len()),
+    }
+    println!("Scan barcodes, then type done");
+
+    let mut seen = std::collections::HashSet::new();
+    let mut input = String::new();
+    loop {
+        input.clear();
+        match std::io::stdin().read_line(&mut input) {
+            Ok(0) => break, // end of input counts as done
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Failed to read input: {}", e);
+                break;
+            }
+        }
+
+        if input.trim() == "done" {
+            break;
+        }
+        let code = scanned(&input);
+        if code.is_empty() {
+            continue;
+        }
+        let Ok(barcode) = code.parse::<u64>() else {
+            eprintln!("Not a barcode: {}", code);
+            continue;
+        };
+
+        if !expected.iter().any(|item| item["barcode"].as_u64() == Some(barcode)) {
+            println!("UNEXPECTED {} - not on the list for this audit", barcode);
+        } else if !seen.insert(barcode) {
+            println!("{} already scanned", barcode);
+        }
+        println!("seen {} of {} expected", seen.len(), expected.len());
+    }
+
+    let missing: Vec<&serde_json::Value> = expected
+        .iter()
+        .filter(|item| item["barcode"].as_u64().is_none_or(|barcode| !seen.contains(&barcode)))
+        .collect();
+
+    println!("Found {} of {} expected, {} missing", seen.len(), expected.len(), missing.len());
+    for item in &missing {
+        println!("MISSING {}: {} @ {}", item["barcode"], item["name"], item["location"]);
+    }
+
+    // the same columns `import` reads
+    if let Some(path) = out {
+        let mut csv = String::from("name,barcode,location\n");
+        for item in &missing {
+            csv.push_str(&format!(
+                "{},{},{}\n",
+                csv_field(&json_text(&item["name"])),
+                item["barcode"],
+                csv_field(&json_text(&item["location"]))
+            ));
+        }
+        match std::fs::write(path, csv) {
+            Ok(()) => println!("Saved the missing items to {}", path),
+            Err(e) => eprintln!("Failed to write {}: {}", path, e),
+        }
+    }
+
+    missing.is_empty()
+}
+
+/// how long ago, in days, `report` counts items as not seen
+const REPORT_STALE_DAYS: [i64; 3] = [30, 90, 365];
+
+/// the numbers in `report`, worked out from a list of items so they don't need a server
+#[derive(Debug, Serialize)]
+struct Report {
+    total: usize,
+    /// most items first
+    locations: Vec<LocationCount>,
+    /// one per `REPORT_STALE_DAYS`; items never seen count too
+    not_seen: Vec<StaleCount>,
+    /// the server tracks neither loans nor stock levels yet, so these are always null
+    checked_out: Option<usize>,
+    low_stock: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct LocationCount {
+    location: String,
+    items: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct StaleCount {
+    days: i64,
+    items: usize,
+}
+
+/// count up `items` (as `/all` sends them) as of `now`
+fn summarize(items: &[serde_json::Value], now: i64) -> Report {
+    let mut locations: Vec<LocationCount> = Vec::new();
+    for item in items {
+        let location = item["location"].as_str().unwrap_or_default();
+        match locations.iter_mut().find(|count| count.location == location) {
+            Some(count) => count.items += 1,
+            None => locations.push(LocationCount { location: location.to_string(), items: 1 }),
+        }
+    }
+    locations.sort_by(|a, b| b.items.cmp(&a.items).then_with(|| a.location.cmp(&b.location)));
+
+    let not_seen = REPORT_STALE_DAYS
+        .iter()
+        .map(|&days| StaleCount {
+            days,
+            items: items
+                .iter()
+                .filter(|item| item["last_seen"].as_i64().is_none_or(|seen| seen < now - days * 86_400))
+                .count(),
+        })
+        .collect();
+
+    Report { total: items.len(), locations, not_seen, checked_out: None, low_stock: None }
+}
+
+/// lay a report out as plain text, or with `markdown` as tables for pasting into an email
+fn format_report(report: &Report, markdown: bool) -> String {
+    let untracked = |count: Option<usize>| count.map_or("not tracked".to_string(), |count| count.to_string());
+    let mut rows = vec![("Total items".to_string(), report.total.to_string())];
+    rows.extend(
+        report
+            .not_seen
+            .iter()
+            .map(|stale| (format!("Not seen in {} days", stale.days), stale.items.to_string())),
+    );
+    rows.push(("Checked out".to_string(), untracked(report.checked_out)));
+    rows.push(("Low-stock consumables".to_string(), untracked(report.low_stock)));
+
+    let mut out = String::new();
+    if markdown {
+        out.push_str("| | |\n|---|---:|\n");
+        for (label, value) in &rows {
+            out.push_str(&format!("| {} | {} |\n", label, value));
+        }
+        out.push_str("\n| Location | Items |\n|---|---:|\n");
+        for count in &report.locations {
+            out.push_str(&format!("| {} | {} |\n", count.location.replace('|', "\\|"), count.items));
+        }
+    } else {
+        for (label, value) in &rows {
+            out.push_str(&format!("{:<24}{}\n", label, value));
+        }
+        out.push_str("\nItems per location:\n");
+        for count in &report.locations {
+            out.push_str(&format!("  {:<22}{}\n", count.location, count.items));
+        }
+    }
+    out
+}
+
+/// run `report` with the words after it, returning whether it could be made
+async fn report(args: &[&str]) -> bool {
+    let (mut markdown, mut json) = (false, false);
+    for arg in args {
+        match *arg {
+            "--markdown" => markdown = true,
+            "--json" => json = true,
+            other => {
+                eprintln!("Unknown option {}, expected --markdown or --json", other);
+                return false;
+            }
+        }
+    }
+
+    // retired items aren't in /all, and aren't part of the inventory being reported on
+    let items = match expected_items(None).await {
+        Ok(items) => items,
+        Err(e) => {
+            eprintln!("Failed to get items: {}", e);
+            return false;
+        }
+    };
+    let report = summarize(&items, chrono::Utc::now().timestamp());
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).expect("Failed to serialize report"));
+    } else {
+        print!("{}", format_report(&report, markdown));
+    }
+    true
+}
+
+/// a JSON value as plain text, without the quotes a string would get from `to_string`
+fn json_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// a field quoted for CSV, with any quotes in it doubled
+fn csv_field(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// split CSV text into rows of fields, the same way the server's import does
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.is_empty() => quoted = true,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    rows
+}
+
+/// a name or location as the server sends it back: only letters, digits and whitespace
+fn sanitize(s: &str) -> String {
+    s.replace(|c: char| !c.is_ascii_alphanumeric() && !c.is_ascii_whitespace(), "")
+}
+
+/// whether two locations are the one the server would store them as, which ignores case and spacing
+fn same_location(a: &str, b: &str) -> bool {
+    let words = |s: &str| sanitize(s).to_lowercase().split_whitespace().collect::<Vec<_>>().join(" ");
+    words(a) == words(b)
+}
+
+/// one barcode that differs between a CSV and the server
+struct Difference {
+    barcode: u64,
+    /// name and location (if the file has a location column) in the file
+    file: Option<(String, Option<String>)>,
+    /// name and location on the server
+    server: Option<(String, String)>,
+}
+
+/// the rows of a CSV with the columns `import` reads, by barcode; bad rows are reported and left out
+fn read_diff_csv(text: &str) -> Result<Vec<(u64, String, Option<String>)>, String> {
+    let rows = parse_csv(text);
+    let (header, rows) = rows.split_first().ok_or("no header row")?;
+    let column = |name: &str| header.iter().position(|field| field.trim().eq_ignore_ascii_case(name));
+    let (Some(name_col), Some(barcode_col)) = (column("name"), column("barcode")) else {
+        return Err("the header must name the name and barcode columns".to_string());
+    };
+    let location_col = column("location");
+
+    let mut items = Vec::new();
+    for (i, row) in rows.iter().enumerate() {
+        let field = |col: usize| row.get(col).map(|field| field.trim()).unwrap_or("");
+        if row.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+        match field(barcode_col).parse::<u64>() {
+            Ok(barcode) => items.push((
+                barcode,
+                field(name_col).to_string(),
+                location_col.map(|col| field(col).to_string()),
+            )),
+            // row 1 is the header
+            Err(_) => eprintln!("row {}: invalid barcode {:?}, skipping", i + 2, field(barcode_col)),
+        }
+    }
+    Ok(items)
+}
+
+/// every barcode that's only in the file, only on the server, or in both with a different name or location,
+/// in barcode order; names and locations are compared as the server would store them
+fn diff_items(file: &[(u64, String, Option<String>)], server: &[serde_json::Value]) -> Vec<Difference> {
+    let server: std::collections::BTreeMap<u64, (String, String)> = server
+        .iter()
+        .filter_map(|item| {
+            Some((item["barcode"].as_u64()?, (json_text(&item["name"]), json_text(&item["location"]))))
+        })
+        .collect();
+    let file: std::collections::BTreeMap<u64, (String, Option<String>)> = file
+        .iter()
+        .map(|(barcode, name, location)| (*barcode, (name.clone(), location.clone())))
+        .collect();
+
+    let mut barcodes: Vec<u64> = file.keys().chain(server.keys()).copied().collect();
+    barcodes.sort_unstable();
+    barcodes.dedup();
+
+    barcodes
+        .into_iter()
+        .filter_map(|barcode| {
+            let (file, server) = (file.get(&barcode).cloned(), server.get(&barcode).cloned());
+            if let (Some((file_name, file_location)), Some((server_name, server_location))) = (&file, &server) {
+                let same_name = sanitize(file_name).trim() == server_name.trim();
+                let same_place = file_location.as_ref().is_none_or(|location| same_location(location, server_location));
+                if same_name && same_place {
+                    return None;
+                }
+            }
+            Some(Difference { barcode, file, server })
+        })
+        .collect()
+}
+
+/// run `diff` with the words after it, returning whether the file and the server agree (or were made to)
+async fn diff(args: &[&str]) -> bool {
+    let (apply, csv) = (args.contains(&"--apply"), args.contains(&"--csv"));
+    let Some(path) = args.iter().find(|arg| !arg.starts_with("--")) else {
+        eprintln!("Usage: diff <file.csv> [--apply] [--csv]");
+        return false;
+    };
+
+    let file = match std::fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| read_diff_csv(&text)) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path, e);
+            return false;
+        }
+    };
+    // retired items too, so a retired barcode in the file isn't mistaken for a missing one
+    let server = match fetch_all_items("&include_retired=true").await {
+        Ok(items) => items,
+        Err(e) => {
+            eprintln!("Error getting the server's items: {}", e);
+            return false;
+        }
+    };
+
+    let differences = diff_items(&file, &server);
+    let only_in_file: Vec<&Difference> = differences.iter().filter(|d| d.server.is_none()).collect();
+    let only_on_server: Vec<&Difference> = differences.iter().filter(|d| d.file.is_none()).collect();
+    let changed: Vec<&Difference> = differences.iter().filter(|d| d.file.is_some() && d.server.is_some()).collect();
+
+    if csv {
+        println!("difference,barcode,file_name,file_location,server_name,server_location");
+        for (kind, list) in [("only_in_file", &only_in_file), ("only_on_server", &only_on_server), ("changed", &changed)] {
+            for d in list.iter() {
+                let (file_name, file_location) = d.file.clone().unwrap_or_default();
+                let (server_name, server_location) = d.server.clone().unwrap_or_default();
+                println!(
+                    "{},{},{},{},{},{}",
+                    kind,
+                    d.barcode,
+                    csv_field(&file_name),
+                    csv_field(&file_location.unwrap_or_default()),
+                    csv_field(&server_name),
+                    csv_field(&server_location)
+                );
+            }
+        }
+    } else {
+        println!("Only in {} ({}):", path, only_in_file.len());
+        for d in &only_in_file {
+            let (name, location) = d.file.clone().unwrap_or_default();
+            println!("  {}: {} @ {}", d.barcode, name, location.unwrap_or_default());
+        }
+        println!("Only on the server ({}):", only_on_server.len());
+        for d in &only_on_server {
+            let (name, location) = d.server.clone().unwrap_or_default();
+            println!("  {}: {} @ {}", d.barcode, name, location);
+        }
+        println!("Different ({}):", changed.len());
+        for d in &changed {
+            let (file_name, file_location) = d.file.clone().unwrap_or_default();
+            let (server_name, server_location) = d.server.clone().unwrap_or_default();
+            println!(
+                "  {}: file {} @ {}, server {} @ {}",
+                d.barcode,
+                file_name,
+                file_location.unwrap_or_else(|| server_location.clone()),
+                server_name,
+                server_location
+            );
+        }
+    }
+
+    if !apply || (only_in_file.is_empty() && changed.is_empty()) {
+        return differences.is_empty();
+    }
+
+    // items only on the server are left alone: a file missing rows is no reason to delete them
+    let mut answer = String::new();
+    flush_print!(
+        "diff> create {} and update {} items from {}? [y/N] ",
+        only_in_file.len(),
+        changed.len(),
+        path
+    );
+    std::io::stdin().read_line(&mut answer).expect("Failed to read input");
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        return false;
+    }
+
+    let mut failed = 0;
+    for d in only_in_file.iter().chain(changed.iter()) {
+        let (name, location) = d.file.clone().unwrap_or_default();
+        let location = location.unwrap_or_else(|| d.server.clone().unwrap_or_default().1);
+        let item = Item { name, barcode: d.barcode, location };
+        let res = if d.server.is_none() { new_item(item).await } else { modify_item(item).await };
+        match res {
+            Ok(200) => {}
+            Ok(status) => {
+                failed += 1;
+                eprintln!("Failed to apply barcode {}: HTTP {}", d.barcode, status);
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("Error applying barcode {}: {}", d.barcode, e);
+            }
+        }
+    }
+    println!(
+        "Applied {} of {} changes",
+        only_in_file.len() + changed.len() - failed,
+        only_in_file.len() + changed.len()
+    );
+    failed == 0 && only_on_server.is_empty()
+}
+
+async fn log_item(barcode: u64) -> Result<u16, reqwest::Error> {
+    let url = format!(
+        "{}/log/{}",
+        server(),
+        barcode
+    );
+
+    if pretend(&format!("log barcode {}", barcode), "GET", &url, None) {
+        return Ok(200);
+    }
+
+    let res = send_idempotent(|client| client.get(&url)).await?;
+
+    Ok(res.status().as_u16())
+}
+
+/// upload a CSV file to the server's /import.csv and print what was created, updated and skipped
+///
+/// with `dry_run` the server only reports what it would do
+async fn import_csv(csv: Vec<u8>, dry_run: bool) -> Result<u16, reqwest::Error> {
+    let url = format!(
+        "{}/import.csv{}",
+        server(),
+        if dry_run { "?dry_run=true" } else { "" }
+    );
+
+    let res = send_idempotent(|client| client.post(&url).body(csv.clone())).await?;
+    let status = res.status().as_u16();
+    let body = res.text().await?;
+
+    if status != 200 {
+        eprintln!("{}", body);
+        return Ok(status);
+    }
+
+    let report = match serde_json::from_str::<serde_json::Value>(&body) {
+        Ok(report) => report,
+        Err(_) => {
+            println!("{}", body);
+            return Ok(status);
+        }
+    };
+
+    for error in report["errors"].as_array().into_iter().flatten() {
+        match error["errors"].as_array() {
+            Some(violations) if !violations.is_empty() => {
+                eprintln!("row {}:", error["row"]);
+                for line in explain_rejection(&serde_json::json!({ "errors": violations }).to_string()).lines() {
+                    eprintln!("  {}", line);
+                }
+            }
+            _ => eprintln!("row {}: {}", error["row"], error["error"].as_str().unwrap_or("")),
+        }
+    }
+
+    println!(
+        "{} {} items, updated {}, skipped {}",
+        if dry_run { "Dry run: would create" } else { "Created" },
+        report["created"],
+        report["updated"],
+        report["skipped"]
+    );
+
+    Ok(status)
+}
+
+/// read the file for `import <file.csv> [--dry-run]` and import it, returning whether it worked
+async fn import_file(args: &[&str]) -> bool {
+    let dry_run = args.contains(&"--dry-run");
+    let path = match args.iter().find(|arg| **arg != "--dry-run") {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: import <file.csv> [--dry-run]");
+            return false;
+        }
+    };
+
+    let csv = match std::fs::read(path) {
+        Ok(csv) => csv,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path, e);
+            return false;
+        }
+    };
+
+    // the server replies once the whole file is in, so all there is to show until then is that it's busy
+    let spinner = if std::io::stdout().is_terminal() {
+        ProgressBar::new_spinner()
+    } else {
+        ProgressBar::hidden()
+    };
+    spinner.set_message(format!("Importing {}", path));
+    spinner.enable_steady_tick(Duration::from_millis(100));
+
+    let res = import_csv(csv, dry_run).await;
+    spinner.finish_and_clear();
+
+    match res {
+        Ok(200) => true,
+        Ok(status) => {
+            eprintln!("Failed to import {}: HTTP {}", path, status);
+            false
+        }
+        Err(e) => {
+            eprintln!("Error importing {}: {}", path, e);
+            false
+        }
+    }
+}
+
+/// why decoding an image failed, each with its own exit code in non-interactive mode
+#[derive(Debug)]
+enum DecodeError {
+    FileNotFound(String),
+    UnsupportedFormat(String),
+    NothingFound,
+    Other(String),
+}
+
+impl DecodeError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            DecodeError::Other(_) => 1,
+            DecodeError::FileNotFound(_) => 2,
+            DecodeError::UnsupportedFormat(_) => 3,
+            DecodeError::NothingFound => 4,
+        }
+    }
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::FileNotFound(path) => write!(f, "No such file: {}", path),
+            DecodeError::UnsupportedFormat(reason) => {
+                write!(f, "Unsupported image (use a JPEG or PNG): {}", reason)
+            }
+            DecodeError::NothingFound => write!(f, "No barcode found in image"),
+            DecodeError::Other(reason) => write!(f, "Failed to decode image: {}", reason),
+        }
+    }
+}
+
+/// read an image file and decode the barcodes in it
+async fn decode_file(path: &str) -> Result<Vec<String>, DecodeError> {
+    let image = std::fs::read(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => DecodeError::FileNotFound(path.to_string()),
+        _ => DecodeError::Other(e.to_string()),
+    })?;
+
+    decode_image(image).await
+}
+
+/// decode barcodes by uploading the image to the server
+#[cfg(not(feature = "local-decode"))]
+async fn decode_image(image: Vec<u8>) -> Result<Vec<String>, DecodeError> {
+    let client = http();
+    let url = format!(
+        "{}/decode",
+        server()
+    );
+
+    let res = client
+        .post(url)
+        .body(image)
+        .send()
+        .await
+        .map_err(|e| DecodeError::Other(e.to_string()))?;
+
+    let status = res.status().as_u16();
+    let body = res
+        .text()
+        .await
+        .map_err(|e| DecodeError::Other(e.to_string()))?;
+
+    match status {
+        200 => {}
+        422 => return Err(DecodeError::NothingFound),
+        400 => return Err(DecodeError::UnsupportedFormat(body)),
+        status => return Err(DecodeError::Other(format!("HTTP {}: {}", status, body))),
+    }
+
+    let decoded = serde_json::from_str::<serde_json::Value>(&body)
+        .map_err(|e| DecodeError::Other(e.to_string()))?;
+
+    Ok(decoded["barcodes"]
+        .as_array()
+        .map(|barcodes| {
+            barcodes
+                .iter()
+                .filter_map(|barcode| barcode["value"].as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+/// decode barcodes locally, so decoding works without a server
+#[cfg(feature = "local-decode")]
+async fn decode_image(image: Vec<u8>) -> Result<Vec<String>, DecodeError> {
+    tokio::task::spawn_blocking(move || {
+        let luma = image::load_from_memory(&image)
+            .map_err(|e| DecodeError::UnsupportedFormat(e.to_string()))?
+            .to_luma8();
+        let (width, height) = luma.dimensions();
+
+        let results = rxing::helpers::detect_multiple_in_luma(luma.into_raw(), width, height)
+            .unwrap_or_default();
+
+        if results.is_empty() {
+            return Err(DecodeError::NothingFound);
+        }
+
+        Ok(results
+            .iter()
+            .map(|result| result.getText().to_string())
+            .collect())
+    })
+    .await
+    .map_err(|e| DecodeError::Other(e.to_string()))?
+}
+
+/// offer to see, log or create each decoded barcode
+async fn decode_follow_up(barcodes: Vec<String>) {
+    for barcode in barcodes {
+        let numeric: u64 = match barcode.parse() {
+            Ok(numeric) => numeric,
+            Err(_) => {
+                println!("{} is not a numeric barcode, skipping", barcode);
+                continue;
+            }
+        };
+
+        let mut action = String::new();
+        flush_print!("decode>{}> (s)ee, (l)og, (n)ew or enter to skip> ", barcode);
+        std::io::stdin()
+            .read_line(&mut action)
+            .expect("Failed to read input");
+
+        let res = match action.trim() {
+            "s" | "see" => see_item(numeric).await,
+            "l" | "log" => log_item(numeric).await,
+            "n" | "new" => new_or_modify_item(process_new_item(numeric))
+                .await
+                .map(|(status, _)| status),
+            _ => continue,
+        };
+
+        match res {
+            Ok(status) if status == 200 => {}
+            Ok(status) => eprintln!("Failed to process barcode {}: HTTP {}", barcode, status),
+            Err(e) => eprintln!("Error processing barcode {}: {}", barcode, e),
+        }
+    }
+}
+
+/// print the outcome of a selftest step, returning whether it passed
+fn report_step(step: &str, res: Result<u16, reqwest::Error>) -> bool {
+    match res {
+        Ok(200) => {
+            println!("PASS {} (HTTP 200)", step);
+            true
+        }
+        Ok(status) => {
+            println!("FAIL {} (HTTP {})", step, status);
+            false
+        }
+        Err(e) => {
+            println!("FAIL {} ({})", step, e);
+            false
+        }
+    }
+}
+
+/// round-trip a throwaway item through every endpoint, returning whether all steps passed
+async fn selftest() -> bool {
+    // far away from real barcodes, and different on every run
+    let barcode = 990_000_000_000 + chrono::Utc::now().timestamp() as u64 % 1_000_000;
+    println!("Running selftest with barcode {}", barcode);
+
+    let item = Item {
+        name: "selftest".to_string(),
+        barcode,
+        location: "selftest".to_string(),
+    };
+    let modified = Item {
+        name: "selftest modified".to_string(),
+        ..item.clone()
+    };
+
+    let mut passed = true;
+    passed &= report_step("create", new_item(item).await);
+    passed &= report_step("see", see_item(barcode).await);
+    passed &= report_step("modify", modify_item(modified).await);
+    passed &= report_step("log", log_item(barcode).await);
+    passed &= report_step("delete", delete_item(barcode).await);
+
+    if passed {
+        println!("Selftest passed");
+    } else {
+        println!("Selftest failed");
+    }
+
+    passed
+}
+
+/// run a single command given on the command line and return the exit code
+async fn run_once(args: &[String]) -> i32 {
+    match args[0].as_str() {
+        "search" if args.len() > 1 => {
+            load_server_ip();
+
+            let query = args[1..].join(" ");
+            match search_items(&query).await {
+                Ok(200) => 0,
+                Ok(status) => {
+                    eprintln!("Failed to search for {}: HTTP {}", query, status);
+                    1
+                }
+                Err(e) => {
+                    eprintln!("Error searching for {}: {}", query, e);
+                    1
+                }
+            }
+        }
+        "decode" if args.len() == 2 => {
+            #[cfg(not(feature = "local-decode"))]
+            load_server_ip();
+
+            match decode_file(&args[1]).await {
+                Ok(barcodes) => {
+                    for barcode in barcodes {
+                        println!("{}", barcode);
+                    }
+                    0
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    e.exit_code()
+                }
+            }
+        }
+        "selftest" => {
+            load_server_ip();
+
+            if selftest().await { 0 } else { 1 }
+        }
+        "import" if args.len() > 1 => {
+            load_server_ip();
+
+            let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+            if import_file(&args).await { 0 } else { 1 }
+        }
+        "rename-location" => {
+            load_server_ip();
+
+            let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+            if rename_location(&args).await { 0 } else { 1 }
+        }
+        "delete-location" => {
+            load_server_ip();
+
+            let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+            if delete_location(&args).await { 0 } else { 1 }
+        }
+        "diff" if args.len() > 1 => {
+            load_server_ip();
+
+            let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+            if diff(&args).await { 0 } else { 1 }
+        }
+        "report" => {
+            load_server_ip();
+
+            let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+            if report(&args).await { 0 } else { 1 }
+        }
+        "pull" => {
+            load_server_ip();
+
+            match pull().await {
+                Ok((refreshed, removed)) => {
+                    println!("Refreshed {} items, removed {}", refreshed, removed);
+                    0
+                }
+                Err(e) => {
+                    eprintln!("Failed to pull: {}", e);
+                    1
+                }
+            }
+        }
+        "backup" => {
+            load_server_ip();
+
+            let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+            if backup(&args).await { 0 } else { 1 }
+        }
+        "config" => {
+            // showing or changing settings shouldn't ask for a server
+            if SERVER.read().unwrap().is_none()
+                && let Some(Ok(server)) = read_server(server_file())
+            {
+                *SERVER.write().unwrap() = Some(server);
+            }
+
+            let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+            if config(&args) { 0 } else { 1 }
+        }
+        "audit" => {
+            load_server_ip();
+
+            let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+            if audit(&args).await { 0 } else { 1 }
+        }
+        "history" if args.len() > 1 => {
+            load_server_ip();
+
+            let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+            if history(&args).await { 0 } else { 1 }
+        }
+        _ => {
+            eprintln!("{}", HELP);
+            1
+        }
+    }
+}
+
+/// ask the server for its version, `None` if it is too old to say
+async fn get_server_version() -> Result<Option<String>, reqwest::Error> {
+    let client = http();
+
+    let res = client.get(format!(
+        "{}/version",
+        server()
+    ));
+
+    let res = track(res.send().await)?;
+
+    if res.status().as_u16() != 200 {
+        return Ok(None);
+    }
+
+    let version = serde_json::from_str::<serde_json::Value>(&res.text().await?)
+        .ok()
+        .and_then(|v| v["version"].as_str().map(str::to_string));
+
+    Ok(version)
+}
+
+/// major component of a semver version string
+fn major_version(version: &str) -> Option<u64> {
+    version.split('.').next()?.parse().ok()
+}
+
+/// print the startup banner, warning if the server's major version differs from ours
+///
+/// if the server can't be reached, offers to enter another address rather than carry on with one that doesn't work
+async fn check_server_version() {
+    let client_version = env!("CARGO_PKG_VERSION");
+
+    loop {
+        let server = server();
+        match get_server_version().await {
+            Ok(Some(server_version)) => {
+                println!(
+                    "barcode termclient {} connected to {} (server {})",
+                    client_version, server, server_version
+                );
+                if major_version(&server_version) != major_version(client_version) {
+                    eprintln!(
+                        "Warning: server version {} is incompatible with client version {}, responses may not be understood",
+                        server_version, client_version
+                    );
+                }
+            }
+            Ok(None) => {
+                println!(
+                    "barcode termclient {} connected to {} (server version unknown)",
+                    client_version, server
+                );
+                eprintln!("Warning: server does not report its version, it may be older than this client");
+            }
+            Err(e) if e.is_connect() || e.is_builder() => {
+                eprintln!("Could not reach server at {}: {}", server, e);
+                println!("Enter another address, or press enter to keep {}", server);
+                prompt_server();
+                if self::server() != server {
+                    continue;
+                }
+                println!("barcode termclient {}", client_version);
+            }
+            Err(e) => {
+                println!("barcode termclient {}", client_version);
+                eprintln!("Warning: could not reach server at {}: {}", server, e);
+            }
+        }
+        break;
+    }
+}
+
+/// a location typed at a prompt, with the single-letter hotkeys expanded
+fn expand_location(location: &str) -> &str {
+    match location {
+        "l" => "Levi Fox Hall Tech Box",
+        "d" => "Drama Studio Tech Box",
+        "r" => "Rig",
+        "s" => "Storage outside Levi Fox Hall Tech Box",
+        _ => location,
+    }
+}
+
+/// split a command line into words, keeping "quoted words" (or 'quoted words') together
+fn split_quoted(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+
+    for c in line.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+/// run `rename-location` with the words after it: move everything at one location to another,
+/// after saying how many items that is and asking; returns whether every item moved
+async fn rename_location(args: &[&str]) -> bool {
+    let [from, to] = args else {
+        eprintln!("Usage: rename-location <from> <to> (quote names with spaces)");
+        return false;
+    };
+    let (from, to) = (expand_location(from), expand_location(to).to_string());
+
+    // the server has no rename of its own, so each item is modified in turn
+    let items = match fetch_items(Some(from)).await {
+        Ok(items) => items,
+        Err(e) => {
+            eprintln!("Failed to get the items at {}: {}", from, e);
+            return false;
+        }
+    };
+    if items.is_empty() {
+        println!("Nothing is at {}", from);
+        return true;
+    }
+
+    let mut answer = String::new();
+    flush_print!("rename-location> move {} items from {} to {}? [y/N] ", items.len(), from, to);
+    std::io::stdin()
+        .read_line(&mut answer)
+        .expect("Failed to read input");
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        return false;
+    }
+
+    let names: std::collections::HashMap<u64, String> = items
+        .iter()
+        .filter_map(|item| Some((item["barcode"].as_u64()?, json_text(&item["name"]))))
+        .collect();
+    let total = names.len();
+    let barcodes = names.keys().copied().collect();
+    let moved = run_bulk(barcodes, "move", move |barcode| {
+        modify_item(Item { name: names[&barcode].clone(), barcode, location: to.clone() })
+    })
+    .await;
+
+    println!("Moved {} of {} items", moved, total);
+    moved == total
+}
+
+/// whether what was typed back at the `delete-location` prompt is the location's name, ignoring case
+/// and surrounding spaces (hotkeys don't count, the point is to read the name)
+fn confirms_location(answer: &str, location: &str) -> bool {
+    !answer.trim().is_empty() && answer.trim().to_lowercase() == location.trim().to_lowercase()
+}
+
+/// run `delete-location` with the words after it: delete (or with `--archive`, retire) every item at
+/// a location, after listing them and having the location's name typed back; `--dry-run` only lists
+/// them. Returns whether every item went
+async fn delete_location(args: &[&str]) -> bool {
+    let (mut location, mut archive, mut dry_run) = (None, false, false);
+    for arg in args {
+        match *arg {
+            "--archive" => archive = true,
+            "--dry-run" => dry_run = true,
+            arg if location.is_none() => location = Some(arg),
+            _ => location = None,
+        }
+    }
+    let Some(location) = location.map(expand_location).filter(|location| !location.trim().is_empty()) else {
+        eprintln!("Usage: delete-location <location> [--archive] [--dry-run] (quote names with spaces)");
+        return false;
+    };
+
+    // the server has no bulk delete, so each item goes in turn
+    let items: Vec<serde_json::Value> = match fetch_items(Some(location)).await {
+        Ok(items) => items
+            .into_iter()
+            .filter(|item| !archive || item["status"].as_str() != Some("retired"))
+            .collect(),
+        Err(e) => {
+            eprintln!("Failed to get the items at {}: {}", location, e);
+            return false;
+        }
+    };
+    if items.is_empty() {
+        println!("Nothing is at {}", location);
+        return true;
+    }
+
+    for item in &items {
+        println!("{:>14}  {}", item["barcode"], json_text(&item["name"]));
+    }
+    let verb = if archive { "archive" } else { "delete" };
+    println!("{} items at {}", items.len(), location);
+    if dry_run {
+        return true;
+    }
+
+    let mut answer = String::new();
+    flush_print!("delete-location> type {} to {} all of them: ", location, verb);
+    std::io::stdin()
+        .read_line(&mut answer)
+        .expect("Failed to read input");
+    if !confirms_location(&answer, location) {
+        println!("Left alone");
+        return false;
+    }
+
+    let barcodes: Vec<u64> = items.iter().filter_map(|item| item["barcode"].as_u64()).collect();
+    let total = barcodes.len();
+    let done = if archive {
+        run_bulk(barcodes, verb, |barcode| async move { set_status(barcode, "retired").await }).await
+    } else {
+        run_bulk(barcodes, verb, delete_item).await
+    };
+
+    println!(
+        "{} {} of {} items, {} failed",
+        if archive { "Archived" } else { "Deleted" },
+        done,
+        total,
+        total - done
+    );
+    done == total
+}
+
+/// a name as `dedup` compares it: lowercase, runs of whitespace as one space, no trailing punctuation
+fn dedup_key(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+        .trim_end_matches(|c: char| c.is_ascii_punctuation() || c.is_whitespace())
+        .to_string()
+}
+
+/// items sharing a name (as `dedup_key` sees it), in groups of two or more ordered by name,
+/// each ordered by barcode
+fn duplicate_groups(items: &[serde_json::Value]) -> Vec<Vec<serde_json::Value>> {
+    let mut groups: std::collections::BTreeMap<String, Vec<serde_json::Value>> = std::collections::BTreeMap::new();
+    for item in items {
+        groups.entry(dedup_key(&json_text(&item["name"]))).or_default().push(item.clone());
+    }
+
+    groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|mut group| {
+            group.sort_by_key(|item| item["barcode"].as_u64());
+            group
+        })
+        .collect()
+}
+
+/// let `alias` be scanned as `barcode`
+async fn add_alias(barcode: u64, alias: u64) -> Result<u16, reqwest::Error> {
+    let url = format!("{}/item/{}/aliases", server(), barcode);
+    let body = serde_json::json!({ "alias": alias }).to_string();
+
+    if pretend(&format!("alias {} to barcode {}", alias, barcode), "POST", &url, Some(&body)) {
+        return Ok(200);
+    }
+
+    let res = send_idempotent(|client| client.post(&url).body(body.clone())).await?;
+
+    Ok(res.status().as_u16())
+}
+
+/// read one answer to a `dedup` question
+fn dedup_answer(question: &str) -> String {
+    let mut answer = String::new();
+    flush_print!("dedup> {} ", question);
+    std::io::stdin()
+        .read_line(&mut answer)
+        .expect("Failed to read input");
+    answer.trim().to_lowercase()
+}
+
+/// walk through every group of items with the same name, showing each in full and asking which
+/// to keep; each of the others can then be deleted, merged (deleted, with its barcode kept as an
+/// alias of the one kept so its labels still scan) or left alone
+///
+/// the server has no duplicates report, so the groups are worked out here from every item;
+/// returns whether every chosen action succeeded
+async fn dedup() -> bool {
+    let items = match fetch_items(None).await {
+        Ok(items) => items,
+        Err(e) => {
+            eprintln!("Failed to get the items: {}", e);
+            return false;
+        }
+    };
+    let groups = duplicate_groups(&items);
+    if groups.is_empty() {
+        println!("No duplicate names");
+        return true;
+    }
+
+    let mut ok = true;
+    let (mut deleted, mut merged) = (0, 0);
+    for (i, group) in groups.iter().enumerate() {
+        println!("\n{} of {}: {} items named like \"{}\"", i + 1, groups.len(), group.len(), json_text(&group[0]["name"]));
+        for (n, item) in group.iter().enumerate() {
+            flush_print!("[{}] ", n + 1);
+            print_item(item);
+        }
+
+        let keep = loop {
+            let answer = dedup_answer(&format!("keep which? [1-{}, s to skip, q to stop]", group.len()));
+            match answer.as_str() {
+                "s" | "" => break None,
+                "q" => {
+                    println!("Deleted {}, merged {}", deleted, merged);
+                    return ok;
+                }
+                n => match n.parse::<usize>() {
+                    Ok(n) if (1..=group.len()).contains(&n) => break Some(n - 1),
+                    _ => eprintln!("Enter a number from 1 to {}", group.len()),
+                },
+            }
+        };
+        let Some(keep) = keep else { continue };
+        let Some(kept) = group[keep]["barcode"].as_u64() else { continue };
+
+        for item in group.iter().filter(|item| item["barcode"].as_u64() != Some(kept)) {
+            let Some(barcode) = item["barcode"].as_u64() else { continue };
+            let answer = dedup_answer(&format!(
+                "{}: d to delete, m to merge into {} (keeping {} as an alias), anything else to leave it [d/m/N]",
+                barcode, kept, barcode
+            ));
+            let merge = match answer.as_str() {
+                "d" => false,
+                "m" => true,
+                _ => continue,
+            };
+
+            match delete_item(barcode).await {
+                Ok(200) => {}
+                Ok(status) => {
+                    eprintln!("Failed to delete {}: HTTP {}", barcode, status);
+                    ok = false;
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("Error deleting {}: {}", barcode, e);
+                    ok = false;
+                    continue;
+                }
+            }
+            if !merge {
+                println!("Deleted {}", barcode);
+                deleted += 1;
+                continue;
+            }
+            match add_alias(kept, barcode).await {
+                Ok(200) => {
+                    println!("Merged {} into {}", barcode, kept);
+                    merged += 1;
+                }
+                Ok(status) => {
+                    eprintln!("Deleted {} but couldn't make it an alias of {}: HTTP {}", barcode, kept, status);
+                    ok = false;
+                }
+                Err(e) => {
+                    eprintln!("Deleted {} but couldn't make it an alias of {}: {}", barcode, kept, e);
+                    ok = false;
+                }
+            }
+        }
+    }
 
-    Ok(res.send().await?.status().as_u16())
+    println!("Deleted {}, merged {}", deleted, merged);
+    ok
 }
 
 fn process_new_item(barcode: u64) -> Item {
@@ -170,13 +2568,7 @@ fn process_new_item(barcode: u64) -> Item {
         .read_line(&mut location)
         .expect("Failed to read input");
 
-    let actual_location = match location.trim() {
-        "l" => "Levi Fox Hall Tech Box",
-        "d" => "Drama Studio Tech Box",
-        "r" => "Rig",
-        "s" => "Storage outside Levi Fox Hall Tech Box",
-        _ => location.trim(),
-    };
+    let actual_location = expand_location(location.trim());
 
     let mut name = String::new();
     flush_print!("new>{}>name> ", barcode);
@@ -198,13 +2590,7 @@ fn process_modify_item(barcode: u64) -> Item {
         .read_line(&mut location)
         .expect("Failed to read input");
 
-    let actual_location = match location.trim() {
-        "l" => "Levi Fox Hall Tech Box",
-        "d" => "Drama Studio Tech Box",
-        "r" => "Rig",
-        "s" => "Storage outside Levi Fox Hall Tech Box",
-        _ => location.trim(),
-    };
+    let actual_location = expand_location(location.trim());
 
     let mut name = String::new();
     flush_print!("modify>{}>name> ", barcode);
@@ -230,65 +2616,476 @@ mod macros {
 }
 }
 
-fn get_args(s: String) -> Vec<u64> {
-    s.split_whitespace()
-        .skip(1) // skip the command
-        .map(|x| x.parse().expect("Failed to parse"))
-        .collect()
+/// settings other than the server, one `key = "value"` per line with `#` comments
+const CONFIG_FILE: &str = "barcode.toml";
+
+/// a setting kept in `CONFIG_FILE`, overridden by its environment variable
+struct Setting {
+    key: &'static str,
+    env: &'static str,
+    default: &'static str,
+    /// the only values it may take, or empty for anything
+    allowed: &'static [&'static str],
 }
 
-fn load_server_ip() {
-    // server ip will probably be in `barcode.cfg`
-    // if it is not, prompt the user for the server ip
-    // and write it to `barcode.cfg`
-    if std::fs::exists("barcode.cfg").unwrap() {
-        let mut file = std::fs::File::open("barcode.cfg").expect("Failed to open barcode.cfg");
-        let mut server = String::new();
-        file.read_to_string(&mut server)
-            .expect("Failed to read barcode.cfg");
-        SERVER
-            .lock()
-            .unwrap()
-            .set(server.trim().to_string())
-            .expect("Failed to set server");
-    } else {
+/// every setting but the server, which stays in barcode.cfg; all are read when they're used,
+/// so changing one takes effect straight away
+const SETTINGS: [Setting; 7] = [
+    Setting { key: "currency", env: "BARCODE_CURRENCY", default: "£", allowed: &[] },
+    Setting {
+        key: "on_conflict",
+        env: "BARCODE_ON_CONFLICT",
+        default: "ask",
+        allowed: &["ask", "update", "fail"],
+    },
+    Setting { key: "scan_prefix", env: "BARCODE_SCAN_PREFIX", default: "", allowed: &[] },
+    Setting { key: "scan_suffix", env: "BARCODE_SCAN_SUFFIX", default: "", allowed: &[] },
+    Setting {
+        key: "strip_check_digit",
+        env: "BARCODE_STRIP_CHECK_DIGIT",
+        default: "false",
+        allowed: &["true", "false"],
+    },
+    Setting {
+        key: "date_format",
+        env: "BARCODE_DATE_FORMAT",
+        default: "%Y-%m-%d %H:%M:%S",
+        allowed: &[],
+    },
+    Setting { key: "history_file", env: "BARCODE_HISTORY_FILE", default: ".barcode_history", allowed: &[] },
+];
+
+/// the key and value on one line of the config file, if it has them
+fn parse_config_line(line: &str) -> Option<(&str, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (key, value) = line.split_once('=')?;
+    let value = value.trim();
+
+    let value = match value.strip_prefix('"') {
+        // a quoted string, up to the closing quote, with \" and \\ escapes
+        Some(quoted) => {
+            let mut unquoted = String::new();
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => unquoted.extend(chars.next()),
+                    c => unquoted.push(c),
+                }
+            }
+            unquoted
+        }
+        // bare, up to any comment
+        None => value.split('#').next().unwrap_or_default().trim().to_string(),
+    };
+    Some((key.trim(), value))
+}
+
+/// a setting's value in the config file, if it's there
+fn config_file_value(key: &str) -> Option<String> {
+    std::fs::read_to_string(config_file())
+        .ok()?
+        .lines()
+        .filter_map(parse_config_line)
+        .find(|(k, _)| *k == key)
+        .map(|(_, value)| value)
+}
+
+/// a setting's value and where it came from: its environment variable, the config file or the default
+fn setting(key: &str) -> (String, &'static str) {
+    let setting = SETTINGS
+        .iter()
+        .find(|setting| setting.key == key)
+        .expect("Unknown setting");
+
+    if let Ok(value) = std::env::var(setting.env) {
+        return (value, "env");
+    }
+    match config_file_value(key) {
+        Some(value) => (value, "file"),
+        None => (setting.default.to_string(), "default"),
+    }
+}
+
+/// set (or with `None`, remove) a key in the config file, keeping every other line and comment as it was
+fn write_config_value(key: &str, value: Option<&str>) -> std::io::Result<()> {
+    let existing = match std::fs::read_to_string(config_file()) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let entry = value.map(|value| {
+        format!("{} = \"{}\"", key, value.replace('\\', "\\\\").replace('"', "\\\""))
+    });
+    let mut lines = Vec::new();
+    let mut written = false;
+    for line in existing.lines() {
+        if parse_config_line(line).is_some_and(|(k, _)| k == key) {
+            // the first line for the key is replaced, any repeats dropped
+            if let Some(entry) = entry.as_ref().filter(|_| !written) {
+                lines.push(entry.clone());
+            }
+            written = true;
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    if let Some(entry) = entry.filter(|_| !written) {
+        lines.push(entry);
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    std::fs::write(config_file(), contents)
+}
+
+/// a server address as it's used and saved: with an http:// scheme and no trailing slash
+///
+/// the same for what's typed and what's read back from barcode.cfg, so the file always holds what's used;
+/// it must parse as a URL with a host
+fn normalize_server(server: &str) -> Result<String, String> {
+    let server = match server.trim() {
+        "" => return Err("no address given".to_string()),
+        s if s.starts_with("http://") => s.to_string(),
+        s if s.starts_with("https://") => s.replace("https://", "http://"),
+        s => format!("http://{}", s),
+    };
+    // paths are added with a leading slash
+    let server = server.trim_end_matches('/').to_string();
+
+    match reqwest::Url::parse(&server) {
+        Ok(url) if url.host_str().is_some_and(|host| !host.is_empty()) => Ok(server),
+        Ok(_) => Err(format!("{} has no host", server)),
+        Err(e) => Err(format!("{} isn't a valid address: {}", server, e)),
+    }
+}
+
+/// where the server address is kept between runs
+const SERVER_FILE: &str = "barcode.cfg";
+
+/// the address saved in `path`, normalized; `None` if there's no file, or an error if what's in it is no good
+fn read_server(path: &str) -> Option<Result<String, String>> {
+    let saved = std::fs::read_to_string(path).ok()?;
+    Some(normalize_server(&saved))
+}
+
+/// save an address (already normalized) to `path`, exactly as it will be used
+fn write_server(path: &str, server: &str) -> std::io::Result<()> {
+    std::fs::write(path, server)
+}
+
+/// use an address from now on, saving it for next time; returns the normalized address, or why it's no good
+fn set_server(server: &str) -> Result<String, String> {
+    let server = normalize_server(server)?;
+    *SERVER.write().unwrap() = Some(server.clone());
+    if let Err(e) = write_server(server_file(), &server) {
+        eprintln!("Failed to save the server to {}: {}", server_file(), e);
+    }
+    Ok(server)
+}
+
+/// ask for the server address until a usable one is given, then use and save it
+///
+/// an empty answer keeps the current address, if there is one
+fn prompt_server() {
+    loop {
         let mut server = String::new();
         flush_print!("server addr> ");
-
         std::io::stdin()
             .read_line(&mut server)
             .expect("Failed to read input");
 
-        let server = match server {
-            s if s.starts_with("http://") => s,
-            s if s.starts_with("https://") => s.replace("https://", "http://"),
-            s => format!("http://{}", s),
-        };
+        if server.trim().is_empty() && SERVER.read().unwrap().is_some() {
+            return;
+        }
+        match set_server(&server) {
+            Ok(_) => return,
+            Err(e) => eprintln!("{}, try again", e),
+        }
+    }
+}
+
+/// run `config` with the words after it: no words shows the settings, `set` and `unset` change them;
+/// returns whether it worked
+fn config(args: &[&str]) -> bool {
+    let valid_keys = || {
+        std::iter::once("server")
+            .chain(SETTINGS.iter().map(|setting| setting.key))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    match args {
+        [] => {
+            let path = std::env::current_dir()
+                .map(|dir| dir.join(config_file()).display().to_string())
+                .unwrap_or_else(|_| config_file().to_string());
+            println!("config file: {}", path);
+
+            let server = SERVER.read().unwrap().clone();
+            match server {
+                Some(server) => {
+                    let source = match SERVER_OVERRIDE.get() {
+                        Some(source) => source.to_string(),
+                        None => format!("file ({})", server_file()),
+                    };
+                    println!("{:<14}{:<24}{}", "server", server, source)
+                }
+                None => println!("{:<14}{:<24}asked for on start", "server", "(not set)"),
+            }
+            for s in &SETTINGS {
+                let (value, source) = setting(s.key);
+                let source = match source {
+                    "env" => format!("env ({})", s.env),
+                    source => source.to_string(),
+                };
+                println!("{:<14}{:<24}{}", s.key, value, source);
+            }
+            true
+        }
+        ["set", "server", server] => match set_server(server) {
+            Ok(server) => {
+                println!("server = {}", server);
+                true
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                false
+            }
+        },
+        ["unset", "server"] => {
+            SERVER.write().unwrap().take();
+            match std::fs::remove_file(server_file()) {
+                Ok(()) => {
+                    println!("Removed the server, you'll be asked for it next time");
+                    true
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+                Err(e) => {
+                    eprintln!("Failed to remove {}: {}", server_file(), e);
+                    false
+                }
+            }
+        }
+        [action, key, rest @ ..]
+            if *key != "server" && matches!((*action, rest.len()), ("set", 1) | ("unset", 0)) =>
+        {
+            let Some(s) = SETTINGS.iter().find(|s| s.key == *key) else {
+                eprintln!("Unknown key {}, valid keys are: {}", key, valid_keys());
+                return false;
+            };
+            let value = rest.first().copied();
+            if let Some(value) = value {
+                if !s.allowed.is_empty() && !s.allowed.contains(&value) {
+                    eprintln!("Invalid value {} for {}, expected one of: {}", value, key, s.allowed.join(", "));
+                    return false;
+                }
+                if *key == "date_format" && !valid_date_format(value) {
+                    eprintln!("Invalid date_format {}, expected a strftime pattern like %d/%m/%Y", value);
+                    return false;
+                }
+            }
+
+            if let Err(e) = write_config_value(key, value) {
+                eprintln!("Failed to write {}: {}", config_file(), e);
+                return false;
+            }
+            let (now, source) = setting(key);
+            println!("{} = {} ({})", key, now, source);
+            if source == "env" {
+                println!("{} is set, and overrides {} until it's unset", s.env, config_file());
+            }
+            true
+        }
+        _ => {
+            eprintln!(
+                "Usage: config, config set <key> <value> or config unset <key>; valid keys are: {}",
+                valid_keys()
+            );
+            false
+        }
+    }
+}
+
+/// `\t`, `\r` and `\n` in a setting as the characters they stand for, so a scanner's TAB suffix can be configured
+fn unescape(setting: &str) -> String {
+    setting.replace("\\t", "\t").replace("\\r", "\r").replace("\\n", "\n")
+}
+
+/// the EAN-13 check digit for the first 12 digits of `code`
+fn ean13_check_digit(code: &str) -> Option<u32> {
+    let sum: u32 = code
+        .chars()
+        .take(12)
+        .enumerate()
+        .map(|(i, c)| c.to_digit(10).map(|d| if i % 2 == 0 { d } else { d * 3 }))
+        .sum::<Option<u32>>()?;
+    Some((10 - sum % 10) % 10)
+}
+
+/// a barcode as a scanner sent it, without `prefix` and `suffix` (when they're there) and, with
+/// `strip_check_digit`, without the check digit of an EAN-13, since items are stored by the 12-digit
+/// form; a check digit that doesn't verify is kept, with a warning to show
+fn clean_scan(raw: &str, prefix: &str, suffix: &str, strip_check_digit: bool) -> (String, Option<String>) {
+    // only the line ending goes before the suffix is looked for, in case the suffix is whitespace
+    let mut code = raw.trim_start().trim_end_matches(['\r', '\n']);
+    if !prefix.is_empty() {
+        code = code.strip_prefix(prefix).unwrap_or(code);
+    }
+    if !suffix.is_empty() {
+        code = code.strip_suffix(suffix).unwrap_or(code);
+    }
+    let code = code.trim();
+
+    if !strip_check_digit || code.len() != 13 || !code.chars().all(|c| c.is_ascii_digit()) {
+        return (code.to_string(), None);
+    }
+    let check = code[12..].parse::<u32>().ok();
+    if ean13_check_digit(code) == check {
+        (code[..12].to_string(), None)
+    } else {
+        (code.to_string(), Some(format!("{}'s check digit doesn't match, keeping all 13 digits", code)))
+    }
+}
 
-        SERVER
-            .lock()
-            .unwrap()
-            .set(server.trim().to_string())
-            .expect("Failed to set server");
+/// a scanned (or typed) barcode cleaned up by the scan_prefix, scan_suffix and strip_check_digit settings
+fn scanned(raw: &str) -> String {
+    let (cleaned, warning) = clean_scan(
+        raw,
+        &unescape(&setting("scan_prefix").0),
+        &unescape(&setting("scan_suffix").0),
+        setting("strip_check_digit").0 == "true",
+    );
+    if let Some(warning) = warning {
+        eprintln!("{}", warning);
+    }
+    if VERBOSE.load(Ordering::Relaxed) && cleaned != raw.trim() {
+        eprintln!("[SCAN] {:?} -> {}", raw.trim(), cleaned);
+    }
+    cleaned
+}
+
+fn get_args(s: String) -> Vec<u64> {
+    s.split_whitespace()
+        .skip(1) // skip the command
+        .map(|x| scanned(x).parse().expect("Failed to parse"))
+        .collect()
+}
 
-        let mut file = std::fs::File::create("barcode.cfg").expect("Failed to create barcode.cfg");
-        file.write_all(server.as_bytes())
-            .expect("Failed to write to barcode.cfg");
+fn load_server_ip() {
+    // server ip will probably be in `barcode.cfg`
+    // if it is not (or what's there is no good), prompt the user for the server ip
+    // and write it to `barcode.cfg`
+    // --server or BARCODE_SERVER, already in use
+    if SERVER.read().unwrap().is_some() {
+        return;
+    }
+    match read_server(server_file()) {
+        Some(Ok(server)) => *SERVER.write().unwrap() = Some(server),
+        Some(Err(e)) => {
+            eprintln!("The server in {} is no good: {}", server_file(), e);
+            prompt_server();
+        }
+        None => prompt_server(),
     }
 }
 
 #[tokio::main]
 async fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(pos) = args.iter().position(|arg| arg == "--pretend") {
+        args.remove(pos);
+        PRETEND.store(true, Ordering::Relaxed);
+        println!("[PRETEND] nothing will be changed on the server");
+    }
+    if let Some(pos) = args.iter().position(|arg| arg == "--verbose") {
+        args.remove(pos);
+        VERBOSE.store(true, Ordering::Relaxed);
+    }
+    if let Some(pos) = args.iter().position(|arg| arg == "--offline") {
+        args.remove(pos);
+        OFFLINE.store(true, Ordering::Relaxed);
+        println!("[OFFLINE] see and all use the cache from the last pull");
+    }
+    let overrides = match take_overrides(&mut args) {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(files) = overrides.files {
+        FILES.set(files).expect("files set twice");
+    }
+    if let Some(timeout) = overrides.timeout {
+        TIMEOUT.set(timeout).expect("timeout set twice");
+    }
+    if let Some(profile) = overrides.profile {
+        PROFILE.set(profile).expect("profile set twice");
+    }
+    match server_override(overrides.server, std::env::var("BARCODE_SERVER").ok()) {
+        Ok(Some((server, source))) => {
+            *SERVER.write().unwrap() = Some(server);
+            SERVER_OVERRIDE.set(source).expect("server override set twice");
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+    let (format, source) = setting("date_format");
+    if !valid_date_format(&format) {
+        eprintln!("Invalid date_format {} (from {}), showing dates as ISO-8601", format, source);
+    }
+    if !args.is_empty() {
+        std::process::exit(run_once(&args).await);
+    }
+
     load_server_ip();
+    if !OFFLINE.load(Ordering::Relaxed) {
+        check_server_version().await;
+    }
 
-    let mut input = String::new();
+    // keep the prompt's ✓/✗ honest while nothing else is being sent
+    if !OFFLINE.load(Ordering::Relaxed) {
+        tokio::spawn(async {
+            let mut interval = tokio::time::interval(LINK_CHECK_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let url = format!("{}/health", server());
+                let _ = track(http().head(url).timeout(Duration::from_secs(5)).send().await);
+            }
+        });
+    }
+
+    // up and down step through earlier commands, Ctrl-R searches them
+    let mut editor = rustyline::DefaultEditor::new().expect("Failed to start the line editor");
+    let history_file = setting("history_file").0;
+    if !history_file.is_empty() {
+        // there's no history the first time
+        let _ = editor.load_history(&history_file);
+    }
 
     loop {
-        flush_print!("> ");
-        input.clear();
-        std::io::stdin()
-            .read_line(&mut input)
-            .expect("Failed to read input");
+        let input = match editor.readline(&prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+            Err(e) => panic!("Failed to read input: {}", e),
+        };
+        if !input.trim().is_empty() {
+            let _ = editor.add_history_entry(input.as_str());
+            if !history_file.is_empty()
+                && let Err(e) = editor.save_history(&history_file)
+            {
+                eprintln!("Failed to save the command history to {}: {}", history_file, e);
+            }
+        }
         match input
             .trim()
             .split_whitespace()
@@ -297,14 +3094,13 @@ async fn main() {
         {
             "new" => {
                 let args = get_args(input.to_string());
+                let (mut created, mut updated) = (0, 0);
                 for barcode in args.clone() {
-                    match new_item(process_new_item(barcode)).await {
-                        Ok(status) if status == 200 => {},
-                        Ok(status) => eprintln!("Failed to create item with barcode {}: HTTP {}", barcode, status),
-                        Err(e) => eprintln!("Error creating item with barcode {}: {}", barcode, e),
-                    }
+                    let (c, u) = create_from_input(barcode).await;
+                    created += c;
+                    updated += u;
                 }
-                println!("Created {} items", args.len());
+                println!("Created {} items, updated {} items", created, updated);
             }
             "modify" => {
                 let args = get_args(input.to_string());
@@ -319,33 +3115,43 @@ async fn main() {
             }
             "delete" => {
                 let args = get_args(input.to_string());
-                for barcode in args.clone() {
-                    match delete_item(barcode).await {
-                        Ok(status) if status == 200 => {},
-                        Ok(status) => eprintln!("Failed to delete item with barcode {}: HTTP {}", barcode, status),
-                        Err(e) => eprintln!("Error deleting item with barcode {}: {}", barcode, e),
-                    }
-                }
-                println!("Deleted {} items", args.len());
+                let total = args.len();
+                let deleted = run_bulk(args, "delete", delete_item).await;
+                println!("Deleted {} of {} items", deleted, total);
             }
             "log" => {
                 let args = get_args(input.to_string());
-                for barcode in args.clone() {
-                    match log_item(barcode).await {
-                        Ok(status) if status == 200 => {},
-                        Ok(status) => eprintln!("Failed to log item with barcode {}: HTTP {}", barcode, status),
-                        Err(e) => eprintln!("Error logging item with barcode {}: {}", barcode, e),
-                    }
-                }
-                println!("Logged {} items", args.len());
+                let total = args.len();
+                let logged = run_bulk(args, "log", log_item).await;
+                println!("Logged {} of {} items", logged, total);
             }
             "all" => {
-                match get_all_items().await {
+                let page = match input.split_whitespace().nth(1).map(str::parse::<u64>) {
+                    None => None,
+                    Some(Ok(page)) if page > 0 => Some(page),
+                    Some(_) => {
+                        eprintln!("Usage: all [page], pages counting from 1");
+                        continue;
+                    }
+                };
+                match get_all_items(page).await {
                     Ok(status) if status == 200 => {}, // printing handled by get_all_items
                     Ok(status) => eprintln!("Failed to retrieve all items: HTTP {}", status),
                     Err(e) => eprintln!("Error retrieving all items: {}", e),
                 }
             }
+            "search" => {
+                let query = input.trim().strip_prefix("search").unwrap_or_default().trim();
+                if query.is_empty() {
+                    eprintln!("Usage: search <text>");
+                    continue;
+                }
+                match search_items(query).await {
+                    Ok(200) => {}
+                    Ok(status) => eprintln!("Failed to search for {}: HTTP {}", query, status),
+                    Err(e) => eprintln!("Error searching for {}: {}", query, e),
+                }
+            }
             "see" => {
                 let args = get_args(input.to_string());
                 for barcode in args.clone() {
@@ -359,37 +3165,111 @@ async fn main() {
             }
             "server" => {
                 // change the server ip
-                let mut server = String::new();
-                flush_print!("server addr> ");
-                std::io::stdin()
-                    .read_line(&mut server)
-                    .expect("Failed to read input");
-
-                SERVER.lock().unwrap().take();
-
-                SERVER
-                    .lock()
-                    .unwrap()
-                    .set(server.trim().to_string())
-                    .expect("Failed to set server");
-
-                let mut file =
-                    std::fs::File::create("barcode.cfg").expect("Failed to create barcode.cfg");
-                file.write_all(server.as_bytes())
-                    .expect("Failed to write to barcode.cfg");
+                prompt_server();
+            }
+            "config" => {
+                let args: Vec<&str> = input.split_whitespace().skip(1).collect();
+                config(&args);
+            }
+            "pull" => match pull().await {
+                Ok((refreshed, removed)) => println!("Refreshed {} items, removed {}", refreshed, removed),
+                Err(e) => eprintln!("Failed to pull: {}", e),
+            },
+            "backup" => {
+                let args: Vec<&str> = input.split_whitespace().skip(1).collect();
+                backup(&args).await;
+            }
+            "decode" => {
+                let path = input.split_whitespace().nth(1);
+                match path {
+                    Some(path) => match decode_file(path).await {
+                        Ok(barcodes) => {
+                            println!("Decoded {}", barcodes.join(", "));
+                            decode_follow_up(barcodes).await;
+                        }
+                        Err(e) => eprintln!("{}", e),
+                    },
+                    None => eprintln!("Usage: decode <image-file>"),
+                }
+            }
+            "selftest" => {
+                selftest().await;
+            }
+            "status" => {
+                let args: Vec<&str> = input.split_whitespace().skip(1).collect();
+                match args.as_slice() {
+                    [barcode, status] if barcode.parse::<u64>().is_ok() => {
+                        let barcode = barcode.parse::<u64>().unwrap();
+                        match set_status(barcode, status).await {
+                            Ok(200) => println!("Set status of {} to {}", barcode, status),
+                            Ok(code) => eprintln!("Failed to set status of {}: HTTP {}", barcode, code),
+                            Err(e) => eprintln!("Error setting status of {}: {}", barcode, e),
+                        }
+                    }
+                    [] => println!(
+                        "server {}: {}",
+                        server(),
+                        LINK.lock().unwrap().describe(chrono::Utc::now().timestamp())
+                    ),
+                    _ => eprintln!("Usage: status [<barcode> <status>]"),
+                }
+            }
+            "import" => {
+                let args: Vec<&str> = input.split_whitespace().skip(1).collect();
+                import_file(&args).await;
+            }
+            "diff" => {
+                let args: Vec<&str> = input.split_whitespace().skip(1).collect();
+                diff(&args).await;
+            }
+            "dedup" => {
+                dedup().await;
+            }
+            "rename-location" => {
+                let words = split_quoted(input.trim());
+                let args: Vec<&str> = words.iter().skip(1).map(String::as_str).collect();
+                rename_location(&args).await;
+            }
+            "delete-location" => {
+                let words = split_quoted(input.trim());
+                let args: Vec<&str> = words.iter().skip(1).map(String::as_str).collect();
+                delete_location(&args).await;
+            }
+            "history" => {
+                let args: Vec<&str> = input.split_whitespace().skip(1).collect();
+                history(&args).await;
+            }
+            "audit" => {
+                let args: Vec<&str> = input.split_whitespace().skip(1).collect();
+                audit(&args).await;
+            }
+            "report" => {
+                let args: Vec<&str> = input.split_whitespace().skip(1).collect();
+                report(&args).await;
+            }
+            "note" => {
+                // everything after the barcode is the note, so it needs no quotes
+                let mut words = input.split_whitespace().skip(1);
+                let barcode = words.next().map(str::parse::<u64>);
+                let text = words.collect::<Vec<_>>().join(" ");
+                match barcode {
+                    Some(Ok(barcode)) if !text.is_empty() => match add_note(barcode, &text).await {
+                        Ok(200) => println!("Noted on {}", barcode),
+                        Ok(status) => eprintln!("Failed to add a note to barcode {}: HTTP {}", barcode, status),
+                        Err(e) => eprintln!("Error adding a note to barcode {}: {}", barcode, e),
+                    },
+                    _ => eprintln!("Usage: note <barcode> <text>"),
+                }
             }
             "quit" => break,
-            inp => {
-                if inp.chars().all(char::is_numeric) {
+            _ => {
+                // a whole line that isn't a command is a scan, prefix and all
+                let barcode = scanned(&input);
+                if !barcode.is_empty() && barcode.chars().all(char::is_numeric) {
                     // create a new item
-                    let barcode: u64 = inp.parse().expect("Failed to parse barcode");
-
-                    match new_item(process_new_item(barcode)).await {
-                        Ok(status) if status == 200 => {},
-                        Ok(status) => eprintln!("Failed to create item with barcode {}: HTTP {}", barcode, status),
-                        Err(e) => eprintln!("Error creating item with barcode {}: {}", barcode, e),
-                    }
+                    let barcode: u64 = barcode.parse().expect("Failed to parse barcode");
 
+                    create_from_input(barcode).await;
                 } else {
                     println!("{}", HELP);
                 }
@@ -397,3 +3277,272 @@ async fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_entries() {
+        let item = serde_json::json!({
+            "barcode": 42,
+            "name": "Radio mic",
+            "last_seen": 300,
+            "notes": [{"noted_at": 250, "text": "new battery"}],
+        });
+        let trail = serde_json::json!([{"moved_at": 200, "from": "Store", "to": "Rig"}]);
+        let scans = serde_json::json!([
+            {"scanned_at": 100, "location": "Store"},
+            {"scanned_at": 300, "location": "Rig"},
+        ]);
+
+        let entries = history_entries(&item, Some(&trail), Some(&scans), 10);
+        let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_str()).collect();
+        // the last sighting is the latest scan, so it isn't listed twice
+        assert_eq!(
+            texts,
+            ["scanned at Rig", "note: new battery", "location: Store → Rig", "scanned at Store"]
+        );
+        assert_eq!(history_entries(&item, Some(&trail), Some(&scans), 2).len(), 2);
+
+        // servers without a scan log still show when it was last seen
+        let entries = history_entries(&item, None, None, 10);
+        let texts: Vec<&str> = entries.iter().map(|entry| entry.text.as_str()).collect();
+        assert_eq!(texts, ["last seen", "note: new battery"]);
+    }
+
+    #[test]
+    fn test_page_count() {
+        assert_eq!(page_count(0, 100), 1);
+        assert_eq!(page_count(100, 100), 1);
+        assert_eq!(page_count(101, 100), 2);
+        assert_eq!(page_count(2_500, 100), 25);
+    }
+
+    #[test]
+    fn test_link() {
+        let mut link = Link::default();
+        assert_eq!(link.marker(0), "");
+        assert_eq!(link.describe(0), "not contacted yet");
+
+        // the first success isn't news, later ones aren't either
+        assert_eq!(link.record(true, 100), None);
+        assert_eq!(link.record(true, 110), None);
+        assert_eq!(link.marker(110), "✓");
+
+        // going down is said once, however many requests fail
+        assert_eq!(
+            link.record(false, 400).as_deref(),
+            Some("Lost the server, it last answered 4 minutes ago")
+        );
+        assert_eq!(link.record(false, 410), None);
+        assert_eq!(link.marker(410), "✗ 5m");
+        assert!(link.describe(410).starts_with("unreachable, last answered 5 minutes ago"));
+
+        // and so is coming back
+        assert_eq!(link.record(true, 420).as_deref(), Some("Reached the server again"));
+        assert_eq!(link.record(true, 430), None);
+        assert_eq!(link.marker(430), "✓");
+
+        let mut never = Link::default();
+        assert_eq!(never.record(false, 0).as_deref(), Some("Can't reach the server"));
+        assert_eq!(never.marker(0), "✗");
+    }
+
+    #[test]
+    fn test_short_age() {
+        assert_eq!(short_age(-5), "0s");
+        assert_eq!(short_age(45), "45s");
+        assert_eq!(short_age(300), "5m");
+        assert_eq!(short_age(3 * 3_600 + 59), "3h");
+        assert_eq!(short_age(2 * 86_400), "2d");
+    }
+
+    #[test]
+    fn test_confirms_location() {
+        assert!(confirms_location("Old Store\n", "Old Store"));
+        assert!(confirms_location("  old store ", "Old Store"));
+        assert!(!confirms_location("y\n", "Old Store"));
+        assert!(!confirms_location("Old", "Old Store"));
+        assert!(!confirms_location("\n", " "));
+    }
+
+    #[test]
+    fn test_date_format() {
+        assert!(valid_date_format("%Y-%m-%d %H:%M:%S"));
+        assert!(valid_date_format("%d/%m/%Y"));
+        assert!(!valid_date_format("%Y-%m-%Q"));
+        assert!(!valid_date_format(""));
+        assert!(valid_date_format(ISO_DATE_FORMAT));
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_take_overrides() {
+        let mut rest = args(&["all", "--server", "10.0.0.5:3000", "--timeout=2.5", "--csv"]);
+        let overrides = take_overrides(&mut rest).unwrap();
+        assert_eq!(rest, args(&["all", "--csv"]));
+        assert_eq!(overrides.server.as_deref(), Some("http://10.0.0.5:3000"));
+        assert_eq!(overrides.timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(overrides.files, None);
+
+        let mut rest = args(&["--profile", "drama", "report"]);
+        let overrides = take_overrides(&mut rest).unwrap();
+        assert_eq!(rest, args(&["report"]));
+        assert_eq!(
+            overrides.files,
+            Some(("barcode-drama.toml".to_string(), "barcode-drama.cfg".to_string()))
+        );
+
+        let mut nothing = args(&["see", "42"]);
+        assert_eq!(take_overrides(&mut nothing).unwrap(), Overrides::default());
+        assert_eq!(nothing, args(&["see", "42"]));
+
+        for bad in [
+            &["--server"][..],
+            &["--server", ""],
+            &["--timeout", "soon"],
+            &["--timeout", "0"],
+            &["--profile", "../etc"],
+            &["--config", "no-such-file.toml"],
+            &["--config", "Cargo.toml", "--profile", "drama"],
+        ] {
+            assert!(take_overrides(&mut args(bad)).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn test_config_override_files() {
+        let dir = std::env::temp_dir().join(format!("termclient-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("stage.toml");
+        std::fs::write(&config, "").unwrap();
+
+        let overrides = take_overrides(&mut args(&["--config", config.to_str().unwrap()])).unwrap();
+        assert_eq!(
+            overrides.files,
+            Some((config.display().to_string(), dir.join("stage.cfg").display().to_string()))
+        );
+
+        // the server file can't be the settings file
+        let cfg = dir.join("stage.cfg");
+        std::fs::write(&cfg, "").unwrap();
+        assert!(take_overrides(&mut args(&["--config", cfg.to_str().unwrap()])).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_server_override() {
+        let flag = Some("http://10.0.0.5:3000".to_string());
+        let env = Some("10.0.0.6".to_string());
+
+        assert_eq!(
+            server_override(flag.clone(), env.clone()).unwrap(),
+            Some(("http://10.0.0.5:3000".to_string(), "flag (--server)"))
+        );
+        assert_eq!(
+            server_override(None, env).unwrap(),
+            Some(("http://10.0.0.6".to_string(), "env (BARCODE_SERVER)"))
+        );
+        // unset or empty falls through to barcode.cfg
+        assert_eq!(server_override(None, None).unwrap(), None);
+        assert_eq!(server_override(None, Some(" ".to_string())).unwrap(), None);
+        // a bad flag never gets this far, a bad variable is an error rather than ignored
+        assert_eq!(server_override(flag, Some("::".to_string())).unwrap().unwrap().1, "flag (--server)");
+    }
+
+    #[test]
+    fn test_duplicate_groups() {
+        assert_eq!(dedup_key("  XLR   Cable. "), "xlr cable");
+        assert_eq!(dedup_key("xlr cable!?"), "xlr cable");
+
+        let item = |name: &str, barcode: u64| serde_json::json!({ "name": name, "barcode": barcode, "location": "Rig" });
+        let items = [item("xlr  cable ", 3), item("Gobo", 2), item("XLR Cable", 1), item("Mic", 4), item("gobo.", 5)];
+        let barcodes: Vec<Vec<u64>> = duplicate_groups(&items)
+            .iter()
+            .map(|group| group.iter().map(|item| item["barcode"].as_u64().unwrap()).collect())
+            .collect();
+        assert_eq!(barcodes, [vec![2, 5], vec![1, 3]]);
+    }
+
+    #[test]
+    fn test_explain_rejection() {
+        let body = r#"{"errors": [{"field": "name", "code": "empty", "message": "name can't be empty"},
+            {"field": "value_pence", "code": "negative", "message": "value_pence can't be negative"}]}"#;
+        assert_eq!(explain_rejection(body), "name: name can't be empty\nvalue_pence: value_pence can't be negative");
+        // older servers (and other endpoints) send plain text
+        assert_eq!(explain_rejection("notes are at most 2000 characters"), "notes are at most 2000 characters");
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn test_normalize_server() {
+        assert_eq!(normalize_server("10.0.0.5:3000\n"), Ok("http://10.0.0.5:3000".to_string()));
+        assert_eq!(normalize_server("http://barcode.local/"), Ok("http://barcode.local".to_string()));
+        assert_eq!(normalize_server("https://barcode.local:3000"), Ok("http://barcode.local:3000".to_string()));
+        assert!(normalize_server("").is_err());
+        assert!(normalize_server("http://").is_err());
+        assert!(normalize_server("not an address").is_err());
+    }
+
+    #[test]
+    fn test_saved_server_is_the_one_used() {
+        let path = std::env::temp_dir().join(format!("termclient-test-{}.cfg", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let server = normalize_server(" barcode.local:3000/ \n").unwrap();
+        write_server(path, &server).unwrap();
+        assert_eq!(std::fs::read_to_string(path).unwrap(), server);
+        assert_eq!(read_server(path), Some(Ok(server)));
+
+        // files written by older versions, with the newline they were typed with
+        std::fs::write(path, "10.0.0.5:3000\n").unwrap();
+        assert_eq!(read_server(path), Some(Ok("http://10.0.0.5:3000".to_string())));
+
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(read_server(path), None);
+    }
+
+    #[test]
+    fn test_clean_scan_prefix() {
+        assert_eq!(clean_scan("]E0123456789012\n", "]E0", "", false), ("123456789012".to_string(), None));
+        // no prefix, nothing to take off
+        assert_eq!(clean_scan("123456789012", "]E0", "", false), ("123456789012".to_string(), None));
+    }
+
+    #[test]
+    fn test_clean_scan_suffix() {
+        assert_eq!(clean_scan("123456789012\t\n", "", "\t", false), ("123456789012".to_string(), None));
+        assert_eq!(clean_scan("123456789012#\r\n", "", "#", false), ("123456789012".to_string(), None));
+    }
+
+    #[test]
+    fn test_clean_scan_check_digit() {
+        assert_eq!(clean_scan("1234567890128", "", "", true), ("123456789012".to_string(), None));
+        // a wrong check digit is kept, with a warning
+        let (code, warning) = clean_scan("1234567890127", "", "", true);
+        assert_eq!(code, "1234567890127");
+        assert!(warning.is_some());
+        // only EAN-13s have one to take off
+        assert_eq!(clean_scan("12345678", "", "", true), ("12345678".to_string(), None));
+        assert_eq!(clean_scan("1234567890128", "", "", false), ("1234567890128".to_string(), None));
+    }
+
+    #[test]
+    fn test_clean_scan_combined() {
+        assert_eq!(
+            clean_scan("]E01234567890128\t\r\n", "]E0", "\t", true),
+            ("123456789012".to_string(), None)
+        );
+        assert_eq!(unescape("\\t"), "\t");
+    }
+}
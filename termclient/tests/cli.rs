@@ -0,0 +1,89 @@
+//! runs the built termclient to check which server and settings file a run ends up with
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// an empty directory to run in, so no barcode.cfg or barcode.toml is picked up by accident
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("termclient-cli-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn termclient(dir: &Path, args: &[&str], server_env: Option<&str>) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_termclient"));
+    command.current_dir(dir).args(args).env_remove("BARCODE_SERVER");
+    if let Some(server) = server_env {
+        command.env("BARCODE_SERVER", server);
+    }
+    command.output().unwrap()
+}
+
+/// the line `config` prints for the server
+fn server_line(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|line| line.starts_with("server "))
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[test]
+fn server_precedence() {
+    let dir = scratch_dir("precedence");
+    std::fs::write(dir.join("barcode.cfg"), "10.0.0.1:3000").unwrap();
+
+    let file = termclient(&dir, &["config"], None);
+    assert!(server_line(&file).contains("10.0.0.1:3000"), "{}", server_line(&file));
+    assert!(server_line(&file).contains("file (barcode.cfg)"));
+
+    let env = termclient(&dir, &["config"], Some("10.0.0.2:3000"));
+    assert!(server_line(&env).contains("10.0.0.2:3000"));
+    assert!(server_line(&env).contains("env (BARCODE_SERVER)"));
+
+    let flag = termclient(&dir, &["config", "--server", "10.0.0.3:3000"], Some("10.0.0.2:3000"));
+    assert!(server_line(&flag).contains("10.0.0.3:3000"));
+    assert!(server_line(&flag).contains("flag (--server)"));
+
+    // neither is saved
+    assert_eq!(std::fs::read_to_string(dir.join("barcode.cfg")).unwrap(), "10.0.0.1:3000");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn profile_files() {
+    let dir = scratch_dir("profile");
+    std::fs::write(dir.join("barcode.cfg"), "10.0.0.1:3000").unwrap();
+    std::fs::write(dir.join("barcode-drama.cfg"), "10.0.0.4:3000").unwrap();
+
+    let output = termclient(&dir, &["--profile", "drama", "config", "set", "currency", "$"], None);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(std::fs::read_to_string(dir.join("barcode-drama.toml")).unwrap().contains("currency"));
+    assert!(!dir.join("barcode.toml").exists());
+
+    let output = termclient(&dir, &["config", "--profile=drama"], None);
+    assert!(server_line(&output).contains("10.0.0.4:3000"));
+    assert!(server_line(&output).contains("file (barcode-drama.cfg)"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn bad_overrides_fail_clearly() {
+    let dir = scratch_dir("bad");
+
+    let missing = termclient(&dir, &["--config", "stage.toml", "config"], None);
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("stage.toml doesn't exist"));
+
+    let both = termclient(&dir, &["--config", "x.toml", "--profile", "x", "config"], None);
+    assert!(!both.status.success());
+
+    let env = termclient(&dir, &["config"], Some("http://"));
+    assert!(!env.status.success());
+    assert!(String::from_utf8_lossy(&env.stderr).contains("BARCODE_SERVER"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -1,7 +1,28 @@
+// the sub-path the server is mounted under, from config.js (empty at the root)
+const API_BASE = typeof BASE_PATH !== 'undefined' ? BASE_PATH : '';
+
+// the most items the server sends in one page of /all
+const PAGE_LIMIT = 1000;
+
+// every item /all lists with `query`, asked for a page at a time until there are as many as
+// X-Total-Count says
+function fetchAllItems(query, items = []) {
+    return fetch(`http://${SERVER}${API_BASE}/all?${query}&limit=${PAGE_LIMIT}&offset=${items.length}`)
+        .then(response => {
+            const total = Number(response.headers.get('X-Total-Count'));
+            return response.json().then(page => {
+                items = items.concat(page);
+                if (page.length < PAGE_LIMIT || !total || items.length >= total) {
+                    return items;
+                }
+                return fetchAllItems(query, items);
+            });
+        });
+}
+
 // get all items
 function getAllItems() {
-    fetch(`http://${SERVER}/all`)
-        .then(response => response.json())
+    fetchAllItems('barcode_as=string')
         .then(data => {
             console.log('All items:', data);
             data;
@@ -11,14 +32,15 @@ function getAllItems() {
 
 // add a new item
 function addItem(name, barcode, location) {
-    let int_barcode = parseInt(barcode);
-    if (isNaN(int_barcode)) {
+    // barcodes are sent as strings, numbers above 2^53 would lose precision
+    barcode = String(barcode).trim();
+    if (!/^\d+$/.test(barcode)) {
         console.error('Invalid barcode:', barcode);
         return;
     }
-    const item = { name, "barcode": int_barcode, location };
+    const item = { name, barcode, location };
     console.log(JSON.stringify(item));
-    fetch(`http://${SERVER}/new`, {
+    fetch(`http://${SERVER}${API_BASE}/new`, {
         method: 'POST',
         body: JSON.stringify(item)
     })
@@ -27,6 +49,28 @@ function addItem(name, barcode, location) {
         .catch(error => console.error('Error adding item:', error));
 }
 
+// whether the server wants a field: 'required', 'optional' or 'disabled', from /config.js
+function fieldPolicy(field) {
+    return (typeof FIELD_POLICY !== 'undefined' && FIELD_POLICY[field]) || 'required';
+}
+
+// the location input for a popup, left out when locations are disabled
+function locationInput(value) {
+    if (fieldPolicy('location') === 'disabled') {
+        return '';
+    }
+    const required = fieldPolicy('location') === 'required' ? 'required' : '';
+    return `
+        <label for="location">Location:</label>
+        <input type="text" id="location" value="${value}" ${required}>`;
+}
+
+// what was typed into the location input, empty when there isn't one
+function locationValue() {
+    const input = document.getElementById('location');
+    return input ? input.value : '';
+}
+
 function actualLocation(location) {
     switch (location) {
         case 'l':
@@ -44,13 +88,14 @@ function actualLocation(location) {
 
 // modify an item
 function modifyItem(name, barcode, location) {
-    let int_barcode = parseInt(barcode);
-    if (isNaN(int_barcode)) {
+    // barcodes are sent as strings, numbers above 2^53 would lose precision
+    barcode = String(barcode).trim();
+    if (!/^\d+$/.test(barcode)) {
         console.error('Invalid barcode:', barcode);
         return;
     }
-    const item = { name, "barcode": int_barcode, "location": actualLocation(location) };
-    fetch(`http://${SERVER}/modify`, {
+    const item = { name, barcode, "location": actualLocation(location) };
+    fetch(`http://${SERVER}${API_BASE}/modify`, {
         method: 'POST',
         body: JSON.stringify(item)
     })
@@ -61,7 +106,7 @@ function modifyItem(name, barcode, location) {
 
 // delete an item
 function deleteItem(barcode) {
-    fetch(`http://${SERVER}/delete/${barcode}`)
+    fetch(`http://${SERVER}${API_BASE}/delete/${barcode}`)
         .then(response => response.json())
         .then(data => console.log('Deleted item:', data))
         .catch(error => console.error('Error deleting item:', error));
@@ -69,7 +114,7 @@ function deleteItem(barcode) {
 
 // log an item (update its last_seen timestamp)
 function logItem(barcode) {
-    fetch(`http://${SERVER}/log/${barcode}`, {
+    fetch(`http://${SERVER}${API_BASE}/log/${barcode}`, {
         method: 'POST'
     })
         .then(response => response.json())
@@ -79,7 +124,7 @@ function logItem(barcode) {
 
 // get a specific item by barcode
 function getItem(barcode) {
-    fetch(`http://${SERVER}/item/${barcode}`)
+    fetch(`http://${SERVER}${API_BASE}/item/${barcode}?barcode_as=string`)
         .then(response => response.json())
         .then(data => {
             console.log('Item:', data);
@@ -90,8 +135,7 @@ function getItem(barcode) {
 
 // get all items and add to the DOM
 function getAllItemsDOM() {
-    fetch(`http://${SERVER}/all`)
-        .then(response => response.json())
+    fetchAllItems('barcode_as=string&fields=name,barcode,location,last_seen')
         .then(data => {
             const table = document.getElementById('table');
             table.innerHTML = ''; // Clear existing items
@@ -162,10 +206,8 @@ function addItemUI() {
         <label for="name">Name:</label>
         <input type="text" id="name" required>
         <label for="barcode">Barcode:</label>
-        <input type="text" id="barcode" required>
-        <label for="location">Location:</label>
-        <input type="text" id="location" required>
-        <button onclick="addItem(document.getElementById('name').value, document.getElementById('barcode').value, document.getElementById('location').value);closePopup();getAllItemsDOM()">Add</button>
+        <input type="text" id="barcode" required>${locationInput('')}
+        <button onclick="addItem(document.getElementById('name').value, document.getElementById('barcode').value, locationValue());closePopup();getAllItemsDOM()">Add</button>
         <button onclick="closePopup()">Close</button>
     `;
     document.body.appendChild(popup);
@@ -189,10 +231,8 @@ function modifyItemUI(barcode) {
     popup.innerHTML = `
         <h2>Modify Item (${barcode})</h2>
         <label for="name">Name:</label>
-        <input type="text" id="name" value="${name}" required>
-        <label for="location">Location:</label>
-        <input type="text" id="location" value="${location}" required>
-        <button onclick="modifyItem(document.getElementById('name').value, ${barcode}, document.getElementById('location').value);closePopup();getAllItemsDOM()">Modify</button>
+        <input type="text" id="name" value="${name}" required>${locationInput(location)}
+        <button onclick="modifyItem(document.getElementById('name').value, '${barcode}', locationValue());closePopup();getAllItemsDOM()">Modify</button>
         <button onclick="closePopup()">Close</button>
     `;
     document.body.appendChild(popup);
@@ -248,7 +288,7 @@ function checkForRun() {
         }
     }, 3000);
 
-    fetch(`http://${SERVER}/all`)
+    fetch(`http://${SERVER}${API_BASE}/all`)
         .then(response => {
             if (response.ok) {
                 clearTimeout(timer);